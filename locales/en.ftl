@@ -0,0 +1,95 @@
+# Messages printed by the simple-ca command line, see src/i18n.rs.
+
+replacing-file = Replacing { $path }
+confirm-ca-reset = Regenerating the CA breaks the chain of { $count } unexpired certificates issued by it. Continue?
+replacing-server-cert = Replacing the key and certificate of { $domain }, `renew { $domain }` keeps the key and SANs instead
+issued-devices = Issued { $count } device certificates
+saved-bundle = Saved PKCS#7 bundle at: { $path }
+saved-haproxy = Saved HAProxy PEM at: { $path }
+saved-der = Saved DER file at: { $path }
+saved-java-store = Saved Java store at: { $path }
+saved-android = Saved Android resource at: { $path }
+android-manifest-hint = Reference it from AndroidManifest.xml with android:networkSecurityConfig="@xml/network_security_config" on <application>
+saved-mobileconfig = Saved configuration profile at: { $path }
+mobileconfig-trust-hint = After installing it on iOS, enable full trust in Settings > General > About > Certificate Trust Settings
+saved-pfx = Saved PFX at: { $path }
+trusted-root = Added the root to the { $store }
+root-already-trusted = The root is already in the { $store }
+untrusted-root = Removed the root from the { $store }
+root-not-trusted = The root is not in the { $store }
+saved-man-page = Saved man page at: { $path }
+error = Error: { $error }
+saved-pool-key = Pre-generated key at: { $path }
+exposed-key = { $path } can be read by other users, restrict it with `chmod 600`
+exported-all = Exported { $count } server certificates to { $path }
+leaked-key = Found private key issued by simple-ca: { $path } (matches { $issued_as })
+auto-renew-skipped = Skipping automatic renewal: { $error }
+auto-renewed = Renewed expiring certificate for: { $domain }
+auto-renew-failed-with = Automatic renewal failed: { $error }
+verify-repair-hint = Issued by the archived intermediate { $issuer }: `repair` bundles the certificate with it, `repair --reissue` re-issues it from the current CA
+stale-certs = { $count } server certificates were issued by a previous intermediate, run `repair` to fix their chain bundles
+repair-nothing = Every server certificate chains to the current intermediate
+repair-rebundled = Re-bundled { $domain } with the archived intermediate { $issuer }
+repair-unrepairable = { $domain } was issued by an unknown intermediate, use --reissue
+ephemeral-store = WARNING: no writable home directory, XDG_STATE_HOME or SIMPLE_CA_HOME, using a throwaway CA in { $dir } that is deleted on exit; its private keys are on disk there until then. Copy issued files with --out-dir or set --home to keep them
+local-store-detected = Using the project-local CA in { $dir } found in the current directory, pass --home to use another one
+root-offline = Exported the root key to { $path } and removed it from the store, keep it offline. Only `ca resign-intermediate` needs it
+
+config-signer-trusted = Trusted { $fingerprint } to sign the config
+config-signed = The config is signed by { $signer }
+config-unsigned = The config is not signed
+hooks-trusted = The post_issue hook "{ $command }" of this config runs from now on
+hooks-none = The config has no post_issue hook
+hook-untrusted = Not running the post_issue hook "{ $command }" of { $path }, `config trust-hooks` approves it
+backup-written = Backed up { $count } files to { $path }
+backup-restored = Restored { $count } files into the store
+vault-pushed = Pushed { $count } files to { $path } in Vault
+vault-pulled = Restored { $count } files from { $path } in Vault
+ssh-signed = Wrote the SSH certificate to { $path }
+compose-issued = Issued { $count } service certificates into { $path }, mount it along with ca.cert.pem
+no-compose-file = No compose.yaml or docker-compose.yml in the current directory, pass the compose file
+
+passphrase-prompt = CA key passphrase:{" "}
+passphrase-repeat = Repeat the passphrase:{" "}
+
+list-serial = SERIAL
+list-not-after = NOT AFTER
+list-common-name = COMMON NAME
+list-sans = SANS
+list-file = FILE
+
+summary-subject = Subject
+summary-sans = SANs
+summary-valid = Valid
+summary-valid-range = { $not_before } to { $not_after }
+summary-sha256 = SHA-256
+summary-file-key = Key
+summary-file-cert = Cert
+summary-file-fullchain = Fullchain
+summary-file-chain = Chain
+summary-file-receipt = Receipt
+summary-file-precert = Precert
+summary-file-dhparam = DH params
+summary-file-ticket-key = Ticket key
+summary-file-pair = Pair
+summary-file-copy = Copy
+summary-file-csr = CSR
+summary-file-attestation = Attestation
+summary-file-haproxy = HAProxy PEM
+summary-file-cert-der = Cert (DER)
+summary-file-key-der = Key (DER)
+summary-file-p12 = PKCS#12
+summary-serve-hint = Serve { $fullchain } with the key; clients must trust { $ca }.
+summary-trust-hint = Add { $ca } to the trust store of your OS and browsers.
+
+status-store = Store
+status-certificates = Certificates
+status-counts = { $valid } valid, { $expiring } expiring, { $expired } expired, { $superseded } superseded
+status-revocations = Revocations
+status-revocations-value = { $pending } pending
+status-disk-usage = Disk usage
+status-disk-usage-value = { $bytes } bytes in { $files } files
+status-issued = Issued
+status-issued-range = { $oldest } to { $newest }
+status-issuer = Issuer
+status-issuer-value = { $subject } (until { $not_after }, { $issued } issued)
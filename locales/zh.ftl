@@ -0,0 +1,95 @@
+# simple-ca 命令行输出的中文消息，见 src/i18n.rs。
+
+replacing-file = 正在替换 { $path }
+confirm-ca-reset = 重新生成 CA 会使其签发的 { $count } 个未过期证书失去有效的证书链。是否继续？
+replacing-server-cert = 正在替换 { $domain } 的密钥和证书，`renew { $domain }` 则会保留原有密钥和 SAN
+issued-devices = 已签发 { $count } 个设备证书
+saved-bundle = PKCS#7 证书包已保存至：{ $path }
+saved-haproxy = HAProxy PEM 已保存至：{ $path }
+saved-der = DER 文件已保存至：{ $path }
+saved-java-store = Java 密钥库已保存至：{ $path }
+saved-android = Android 资源已保存至：{ $path }
+android-manifest-hint = 在 AndroidManifest.xml 的 <application> 上以 android:networkSecurityConfig="@xml/network_security_config" 引用它
+saved-mobileconfig = 描述文件已保存至：{ $path }
+mobileconfig-trust-hint = 在 iOS 上安装后，请在“设置 > 通用 > 关于本机 > 证书信任设置”中启用完全信任
+saved-pfx = PFX 已保存至：{ $path }
+trusted-root = 已将根证书添加到 { $store }
+root-already-trusted = 根证书已在 { $store } 中
+untrusted-root = 已从 { $store } 移除根证书
+root-not-trusted = 根证书不在 { $store } 中
+saved-man-page = 手册页已保存至：{ $path }
+error = 错误：{ $error }
+saved-pool-key = 预生成的密钥位于：{ $path }
+exposed-key = 其他用户可以读取 { $path }，请用 `chmod 600` 限制其权限
+exported-all = 已将 { $count } 个服务器证书导出到 { $path }
+leaked-key = 发现由 simple-ca 签发的私钥：{ $path }（对应 { $issued_as }）
+auto-renew-skipped = 跳过自动续期：{ $error }
+auto-renewed = 已续期即将过期的证书：{ $domain }
+auto-renew-failed-with = 自动续期失败：{ $error }
+verify-repair-hint = 由已归档的中间证书 { $issuer } 签发：`repair` 将证书与其重新打包，`repair --reissue` 由当前 CA 重新签发
+stale-certs = { $count } 个服务器证书由之前的中间证书签发，运行 `repair` 修复其证书链文件
+repair-nothing = 所有服务器证书都链接到当前的中间证书
+repair-rebundled = 已将 { $domain } 与已归档的中间证书 { $issuer } 重新打包
+repair-unrepairable = { $domain } 由未知的中间证书签发，请使用 --reissue
+ephemeral-store = 警告：没有可写的主目录、XDG_STATE_HOME 或 SIMPLE_CA_HOME，将在 { $dir } 中使用退出时删除的临时 CA，其私钥在此之前会保存在磁盘上。请使用 --out-dir 复制签发的文件，或设置 --home 以保留它们
+local-store-detected = 正在使用当前目录中的项目本地 CA { $dir }，可用 --home 指定其他 CA
+root-offline = 已将根密钥导出到 { $path } 并从存储中删除，请离线保管。只有 `ca resign-intermediate` 需要它
+
+config-signer-trusted = 已信任 { $fingerprint } 签名配置
+config-signed = 配置已由 { $signer } 签名
+config-unsigned = 配置未签名
+hooks-trusted = 此配置的 post_issue 钩子 "{ $command }" 从现在起会运行
+hooks-none = 配置中没有 post_issue 钩子
+hook-untrusted = 未运行 { $path } 的 post_issue 钩子 "{ $command }"，可用 `config trust-hooks` 批准它
+backup-written = 已将 { $count } 个文件备份到 { $path }
+backup-restored = 已将 { $count } 个文件恢复到存储中
+vault-pushed = 已将 { $count } 个文件推送到 Vault 的 { $path }
+vault-pulled = 已从 Vault 的 { $path } 恢复 { $count } 个文件
+ssh-signed = 已将 SSH 证书写入 { $path }
+compose-issued = 已将 { $count } 个服务证书签发到 { $path }，请连同 ca.cert.pem 一起挂载
+no-compose-file = 当前目录中没有 compose.yaml 或 docker-compose.yml，请指定 compose 文件
+
+passphrase-prompt = CA 密钥口令：
+passphrase-repeat = 再次输入口令：
+
+list-serial = 序列号
+list-not-after = 有效期至
+list-common-name = 通用名称
+list-sans = SAN
+list-file = 文件
+
+summary-subject = 主题
+summary-sans = SAN
+summary-valid = 有效期
+summary-valid-range = { $not_before } 至 { $not_after }
+summary-sha256 = SHA-256
+summary-file-key = 密钥
+summary-file-cert = 证书
+summary-file-fullchain = 完整链
+summary-file-chain = 证书链
+summary-file-receipt = 回执
+summary-file-precert = 预证书
+summary-file-dhparam = DH 参数
+summary-file-ticket-key = 会话票据密钥
+summary-file-pair = 证书对
+summary-file-copy = 副本
+summary-file-csr = 证书请求
+summary-file-attestation = 密钥证明
+summary-file-haproxy = HAProxy PEM
+summary-file-cert-der = 证书 (DER)
+summary-file-key-der = 密钥 (DER)
+summary-file-p12 = PKCS#12
+summary-serve-hint = 请将 { $fullchain } 与密钥一起部署；客户端需要信任 { $ca }。
+summary-trust-hint = 请将 { $ca } 添加到操作系统和浏览器的信任存储中。
+
+status-store = 存储
+status-certificates = 证书
+status-counts = { $valid } 个有效，{ $expiring } 个即将过期，{ $expired } 个已过期，{ $superseded } 个已被取代
+status-revocations = 吊销
+status-revocations-value = { $pending } 个待吊销
+status-disk-usage = 磁盘占用
+status-disk-usage-value = { $files } 个文件共 { $bytes } 字节
+status-issued = 签发时间
+status-issued-range = { $oldest } 至 { $newest }
+status-issuer = 签发者
+status-issuer-value = { $subject }（有效期至 { $not_after }，已签发 { $issued } 个）
@@ -0,0 +1,873 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use openssl::base64;
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::{hash, MessageDigest};
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Public};
+use openssl::rand::rand_bytes;
+use openssl::rsa::Rsa;
+use openssl::sign::Verifier;
+use openssl::x509::{X509Name, X509Req};
+
+use crate::commands::issue_public_key_cert;
+use crate::conf::ValidityConf;
+use crate::datetime::format_rfc3339;
+use crate::deterministic::Sources;
+use crate::err::{Result, SimpleCAError};
+use crate::guard::RepoGuard;
+use crate::http::{Request, Response};
+use crate::index::record_issued;
+
+const ORDER_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Outstanding nonces kept, the oldest are forgotten first and clients
+/// presenting one get `badNonce` and retry.
+const MAX_NONCES: usize = 1024;
+/// Accounts kept, further registrations get `rateLimited`.
+const MAX_ACCOUNTS: usize = 1024;
+/// Unexpired orders one account may have at a time.
+const MAX_ORDERS: usize = 64;
+const CHALLENGE_TYPES: [&str; 3] = ["http-01", "dns-01", "tls-alpn-01"];
+
+pub(crate) fn b64url_encode(data: &[u8]) -> String {
+    base64::encode_block(data)
+        .replace('+', "-")
+        .replace('/', "_")
+        .trim_end_matches('=')
+        .to_string()
+}
+
+pub(crate) fn b64url_decode(data: &str) -> Option<Vec<u8>> {
+    let mut data = data.replace('-', "+").replace('_', "/");
+    while !data.len().is_multiple_of(4) {
+        data.push('=');
+    }
+    base64::decode_block(&data).ok()
+}
+
+fn random_id() -> Result<String> {
+    let mut buf = [0u8; 16];
+    rand_bytes(&mut buf)?;
+    Ok(b64url_encode(&buf))
+}
+
+/// An ACME problem document (RFC 8555 section 6.7).
+#[derive(Debug)]
+struct Problem {
+    status: u16,
+    kind: &'static str,
+    detail: String,
+}
+
+impl Problem {
+    fn new(status: u16, kind: &'static str, detail: &str) -> Problem {
+        Problem {
+            status,
+            kind,
+            detail: detail.to_string(),
+        }
+    }
+
+    fn malformed(detail: &str) -> Problem {
+        Problem::new(400, "malformed", detail)
+    }
+
+    fn not_found() -> Problem {
+        Problem::new(404, "malformed", "No such resource")
+    }
+}
+
+impl From<SimpleCAError> for Problem {
+    fn from(err: SimpleCAError) -> Problem {
+        match err {
+            SimpleCAError::PolicyDenied { .. } => {
+                Problem::new(400, "rejectedIdentifier", &err.to_string())
+            }
+            _ => Problem::new(500, "serverInternal", &err.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Identifier {
+    #[serde(rename = "type")]
+    kind: String,
+    value: String,
+}
+
+struct Account {
+    id: String,
+    thumbprint: String,
+    key: PKey<Public>,
+    contact: Value,
+}
+
+struct Challenge {
+    id: String,
+    kind: &'static str,
+    token: String,
+    valid: bool,
+}
+
+struct Authz {
+    /// The order the authorization was created for, which owns it.
+    order: String,
+    identifier: Identifier,
+    wildcard: bool,
+    expires: SystemTime,
+    challenges: Vec<Challenge>,
+}
+
+impl Authz {
+    fn valid(&self) -> bool {
+        self.challenges.iter().any(|challenge| challenge.valid)
+    }
+}
+
+struct Order {
+    account: String,
+    expires: SystemTime,
+    identifiers: Vec<Identifier>,
+    authzs: Vec<String>,
+    cert: Option<Vec<u8>>,
+}
+
+#[derive(Default)]
+struct AcmeState {
+    nonces: VecDeque<String>,
+    accounts: HashMap<String, Account>,
+    orders: HashMap<String, Order>,
+    authzs: HashMap<String, Authz>,
+}
+
+impl AcmeState {
+    /// Forgets the expired orders along with their authorizations.
+    fn prune(&mut self, now: SystemTime) {
+        self.orders.retain(|_, order| order.expires > now);
+        let orders = &self.orders;
+        self.authzs
+            .retain(|_, authz| authz.expires > now && orders.contains_key(&authz.order));
+    }
+}
+
+#[derive(Deserialize)]
+struct Jws {
+    protected: String,
+    payload: String,
+    signature: String,
+}
+
+#[derive(Deserialize)]
+struct Protected {
+    alg: String,
+    nonce: Option<String>,
+    url: String,
+    jwk: Option<Value>,
+    kid: Option<String>,
+}
+
+/// A verified JWS request body.
+struct Signed {
+    payload: Vec<u8>,
+    jwk: Option<Value>,
+    account: Option<String>,
+}
+
+impl Signed {
+    fn json<T: for<'de> Deserialize<'de>>(&self) -> Result<T, Problem> {
+        serde_json::from_slice(&self.payload).map_err(|err| Problem::malformed(&err.to_string()))
+    }
+}
+
+fn jwk_field(jwk: &Value, field: &str) -> Result<BigNum, Problem> {
+    jwk[field]
+        .as_str()
+        .and_then(b64url_decode)
+        .and_then(|bytes| BigNum::from_slice(&bytes).ok())
+        .ok_or_else(|| Problem::new(400, "badPublicKey", &format!("Invalid JWK {}", field)))
+}
+
+fn jwk_to_pkey(jwk: &Value) -> Result<PKey<Public>, Problem> {
+    let bad_key = |_| Problem::new(400, "badPublicKey", "Unusable JWK");
+    match jwk["kty"].as_str() {
+        Some("RSA") => {
+            let rsa = Rsa::from_public_components(jwk_field(jwk, "n")?, jwk_field(jwk, "e")?)
+                .map_err(bad_key)?;
+            PKey::from_rsa(rsa).map_err(bad_key)
+        }
+        Some("EC") => {
+            let nid = match jwk["crv"].as_str() {
+                Some("P-256") => Nid::X9_62_PRIME256V1,
+                Some("P-384") => Nid::SECP384R1,
+                _ => return Err(Problem::new(400, "badPublicKey", "Unsupported curve")),
+            };
+            let group = EcGroup::from_curve_name(nid).map_err(bad_key)?;
+            let (x, y) = (jwk_field(jwk, "x")?, jwk_field(jwk, "y")?);
+            let ec = EcKey::from_public_key_affine_coordinates(&group, &x, &y).map_err(bad_key)?;
+            PKey::from_ec_key(ec).map_err(bad_key)
+        }
+        _ => Err(Problem::new(400, "badPublicKey", "Unsupported key type")),
+    }
+}
+
+/// JWK thumbprint (RFC 7638) identifying an account key.
+fn jwk_thumbprint(jwk: &Value) -> Result<String, Problem> {
+    let canonical = match jwk["kty"].as_str() {
+        Some("RSA") => json!({"e": jwk["e"], "kty": "RSA", "n": jwk["n"]}),
+        _ => json!({"crv": jwk["crv"], "kty": jwk["kty"], "x": jwk["x"], "y": jwk["y"]}),
+    };
+    let digest = hash(MessageDigest::sha256(), canonical.to_string().as_bytes())
+        .map_err(|err| Problem::from(SimpleCAError::from(err)))?;
+    Ok(b64url_encode(&digest))
+}
+
+fn verify_signature(alg: &str, key: &PKey<Public>, input: &[u8], signature: &[u8]) -> bool {
+    let (digest, signature) = match alg {
+        "RS256" => (MessageDigest::sha256(), signature.to_vec()),
+        "ES256" | "ES384" => {
+            let half = signature.len() / 2;
+            let der = BigNum::from_slice(&signature[..half])
+                .and_then(|r| Ok((r, BigNum::from_slice(&signature[half..])?)))
+                .and_then(|(r, s)| EcdsaSig::from_private_components(r, s))
+                .and_then(|sig| sig.to_der());
+            let digest = if alg == "ES256" {
+                MessageDigest::sha256()
+            } else {
+                MessageDigest::sha384()
+            };
+            match der {
+                Ok(der) => (digest, der),
+                Err(_) => return false,
+            }
+        }
+        _ => return false,
+    };
+    Verifier::new(digest, key)
+        .and_then(|mut verifier| verifier.verify_oneshot(&signature, input))
+        .unwrap_or(false)
+}
+
+/// A development ACME v2 (RFC 8555) server issuing from the intermediate CA.
+///
+/// Challenges are accepted as soon as the client responds to them, without
+/// any validation, so any client can obtain a certificate for any name.
+pub struct AcmeServer {
+    base_url: String,
+    validity: ValidityConf,
+    sources: Sources,
+    state: Mutex<AcmeState>,
+}
+
+impl AcmeServer {
+    pub fn new(base_url: &str, validity: ValidityConf, sources: Sources) -> AcmeServer {
+        AcmeServer {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            validity,
+            sources,
+            state: Mutex::new(AcmeState::default()),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    fn new_nonce(&self) -> Result<String> {
+        let nonce = random_id()?;
+        let nonces = &mut self.state.lock().unwrap().nonces;
+        if nonces.len() == MAX_NONCES {
+            nonces.pop_front();
+        }
+        nonces.push_back(nonce.clone());
+        Ok(nonce)
+    }
+
+    /// Adds the headers every ACME response carries; without a fresh nonce
+    /// the client retries with `new-nonce`.
+    fn finish(&self, response: Response) -> Response {
+        let response = match self.new_nonce() {
+            Ok(nonce) => response.header("Replay-Nonce", &nonce),
+            Err(_) => response,
+        };
+        response.header("Cache-Control", "no-store").header(
+            "Link",
+            &format!("<{}>;rel=\"index\"", self.url("/directory")),
+        )
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        let response = match self.route(request) {
+            Ok(response) => response,
+            Err(problem) => Response::json(
+                problem.status,
+                &json!({
+                    "type": format!("urn:ietf:params:acme:error:{}", problem.kind),
+                    "detail": problem.detail,
+                    "status": problem.status,
+                }),
+            )
+            .header("Content-Type", "application/problem+json"),
+        };
+        self.finish(response)
+    }
+
+    fn route(&self, request: &Request) -> Result<Response, Problem> {
+        let path = request.path.as_str();
+        match (request.method.as_str(), path) {
+            ("GET", "/directory") => Ok(Response::json(200, &self.directory())),
+            ("HEAD", "/new-nonce") => Ok(Response::new(200)),
+            ("GET", "/new-nonce") => Ok(Response::new(204)),
+            ("POST", _) => {
+                let signed = self.verify(request)?;
+                let path = path.strip_prefix('/').unwrap_or(path);
+                let (resource, id) = path.split_once('/').unwrap_or((path, ""));
+                match resource {
+                    "new-account" => self.new_account(&signed),
+                    "account" => self.account(&signed, id),
+                    "new-order" => self.new_order(&signed),
+                    "order" => self.order(&signed, id),
+                    "authz" => self.authz(&signed, id),
+                    "chall" => self.challenge(&signed, id),
+                    "finalize" => self.finalize(&signed, id),
+                    "cert" => self.cert(&signed, id),
+                    _ => Err(Problem::not_found()),
+                }
+            }
+            _ => Err(Problem::new(405, "malformed", "Method not allowed")),
+        }
+    }
+
+    fn directory(&self) -> Value {
+        json!({
+            "newNonce": self.url("/new-nonce"),
+            "newAccount": self.url("/new-account"),
+            "newOrder": self.url("/new-order"),
+            "meta": {
+                "externalAccountRequired": false,
+                "website": "https://github.com/grerlrr/simple_ca",
+            },
+        })
+    }
+
+    /// Checks the JWS envelope: nonce, target URL, key and signature.
+    fn verify(&self, request: &Request) -> Result<Signed, Problem> {
+        let jws: Jws = serde_json::from_slice(&request.body)
+            .map_err(|_| Problem::malformed("Request body is not a flattened JWS"))?;
+        let protected: Protected = b64url_decode(&jws.protected)
+            .and_then(|header| serde_json::from_slice(&header).ok())
+            .ok_or_else(|| Problem::malformed("Invalid protected header"))?;
+        let payload = b64url_decode(&jws.payload)
+            .ok_or_else(|| Problem::malformed("Invalid payload encoding"))?;
+        let signature = b64url_decode(&jws.signature)
+            .ok_or_else(|| Problem::malformed("Invalid signature encoding"))?;
+
+        let mut state = self.state.lock().unwrap();
+        state.prune(self.sources.now());
+        let nonce = protected.nonce.unwrap_or_default();
+        match state.nonces.iter().position(|known| *known == nonce) {
+            Some(index) => state.nonces.remove(index),
+            None => return Err(Problem::new(400, "badNonce", "Unknown or reused nonce")),
+        };
+        if protected.url != self.url(&request.path) {
+            return Err(Problem::new(401, "unauthorized", "JWS url does not match"));
+        }
+
+        let (key, account) = match (&protected.jwk, &protected.kid) {
+            (Some(jwk), None) => (jwk_to_pkey(jwk)?, None),
+            (None, Some(kid)) => {
+                let id = kid
+                    .strip_prefix(&self.url("/account/"))
+                    .ok_or_else(|| Problem::new(400, "accountDoesNotExist", "Unknown kid"))?;
+                let account = state
+                    .accounts
+                    .get(id)
+                    .ok_or_else(|| Problem::new(400, "accountDoesNotExist", "Unknown kid"))?;
+                (account.key.clone(), Some(id.to_string()))
+            }
+            _ => return Err(Problem::malformed("Exactly one of jwk and kid is required")),
+        };
+
+        let input = format!("{}.{}", jws.protected, jws.payload);
+        if !verify_signature(&protected.alg, &key, input.as_bytes(), &signature) {
+            return Err(Problem::new(400, "badSignatureAlgorithm", "Bad signature"));
+        }
+        Ok(Signed {
+            payload,
+            jwk: protected.jwk,
+            account,
+        })
+    }
+
+    fn require_account(signed: &Signed) -> Result<&str, Problem> {
+        signed
+            .account
+            .as_deref()
+            .ok_or_else(|| Problem::malformed("Request must be signed with an account kid"))
+    }
+
+    fn account_json(&self, account: &Account) -> Value {
+        json!({
+            "status": "valid",
+            "contact": account.contact,
+            "orders": self.url(&format!("/account/{}/orders", account.id)),
+        })
+    }
+
+    fn new_account(&self, signed: &Signed) -> Result<Response, Problem> {
+        let jwk = signed
+            .jwk
+            .as_ref()
+            .ok_or_else(|| Problem::malformed("newAccount must be signed with a jwk"))?;
+        let payload: Value = signed.json()?;
+        let thumbprint = jwk_thumbprint(jwk)?;
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(account) = state
+            .accounts
+            .values()
+            .find(|account| account.thumbprint == thumbprint)
+        {
+            let location = self.url(&format!("/account/{}", account.id));
+            return Ok(
+                Response::json(200, &self.account_json(account)).header("Location", &location)
+            );
+        }
+        if payload["onlyReturnExisting"].as_bool().unwrap_or(false) {
+            return Err(Problem::new(
+                400,
+                "accountDoesNotExist",
+                "No account for key",
+            ));
+        }
+        if state.accounts.len() >= MAX_ACCOUNTS {
+            return Err(Problem::new(429, "rateLimited", "Too many accounts"));
+        }
+
+        let account = Account {
+            id: random_id()?,
+            thumbprint,
+            key: jwk_to_pkey(jwk)?,
+            contact: payload["contact"].clone(),
+        };
+        let location = self.url(&format!("/account/{}", account.id));
+        let response =
+            Response::json(201, &self.account_json(&account)).header("Location", &location);
+        state.accounts.insert(account.id.clone(), account);
+        Ok(response)
+    }
+
+    fn account(&self, signed: &Signed, id: &str) -> Result<Response, Problem> {
+        let account_id = AcmeServer::require_account(signed)?;
+        if id != account_id {
+            return Err(Problem::new(403, "unauthorized", "Not your account"));
+        }
+        let state = self.state.lock().unwrap();
+        let account = state.accounts.get(id).ok_or_else(Problem::not_found)?;
+        Ok(Response::json(200, &self.account_json(account)))
+    }
+
+    fn new_order(&self, signed: &Signed) -> Result<Response, Problem> {
+        let account = AcmeServer::require_account(signed)?.to_string();
+        #[derive(Deserialize)]
+        struct NewOrder {
+            identifiers: Vec<Identifier>,
+        }
+        let new_order: NewOrder = signed.json()?;
+        if new_order.identifiers.is_empty() {
+            return Err(Problem::malformed("No identifiers requested"));
+        }
+
+        let expires = self.sources.now() + ORDER_LIFETIME;
+        let mut state = self.state.lock().unwrap();
+        let orders = state
+            .orders
+            .values()
+            .filter(|order| order.account == account)
+            .count();
+        if orders >= MAX_ORDERS {
+            return Err(Problem::new(429, "rateLimited", "Too many pending orders"));
+        }
+        let id = random_id()?;
+        let mut authzs = Vec::new();
+        for identifier in &new_order.identifiers {
+            if identifier.kind != "dns" {
+                return Err(Problem::new(
+                    400,
+                    "unsupportedIdentifier",
+                    "Only dns identifiers are supported",
+                ));
+            }
+            let (value, wildcard) = match identifier.value.strip_prefix("*.") {
+                Some(value) => (value.to_string(), true),
+                None => (identifier.value.clone(), false),
+            };
+            let challenges = CHALLENGE_TYPES
+                .iter()
+                .filter(|kind| !wildcard || **kind == "dns-01")
+                .map(|kind| {
+                    Ok(Challenge {
+                        id: random_id()?,
+                        kind,
+                        token: random_id()?,
+                        valid: false,
+                    })
+                })
+                .collect::<Result<_>>()?;
+            let authz_id = random_id()?;
+            state.authzs.insert(
+                authz_id.clone(),
+                Authz {
+                    order: id.clone(),
+                    identifier: Identifier {
+                        kind: "dns".to_string(),
+                        value,
+                    },
+                    wildcard,
+                    expires,
+                    challenges,
+                },
+            );
+            authzs.push(authz_id);
+        }
+
+        let order = Order {
+            account,
+            expires,
+            identifiers: new_order.identifiers,
+            authzs,
+            cert: None,
+        };
+        let body = self.order_json(&state, &id, &order);
+        state.orders.insert(id.clone(), order);
+        Ok(Response::json(201, &body).header("Location", &self.url(&format!("/order/{}", id))))
+    }
+
+    fn order_status(state: &AcmeState, order: &Order) -> &'static str {
+        if order.cert.is_some() {
+            "valid"
+        } else if order
+            .authzs
+            .iter()
+            .all(|id| state.authzs.get(id).map(Authz::valid).unwrap_or(false))
+        {
+            "ready"
+        } else {
+            "pending"
+        }
+    }
+
+    fn order_json(&self, state: &AcmeState, id: &str, order: &Order) -> Value {
+        let mut body = json!({
+            "status": AcmeServer::order_status(state, order),
+            "expires": format_rfc3339(order.expires),
+            "identifiers": order.identifiers,
+            "authorizations": order
+                .authzs
+                .iter()
+                .map(|authz| self.url(&format!("/authz/{}", authz)))
+                .collect::<Vec<String>>(),
+            "finalize": self.url(&format!("/finalize/{}", id)),
+        });
+        if order.cert.is_some() {
+            body["certificate"] = json!(self.url(&format!("/cert/{}", id)));
+        }
+        body
+    }
+
+    /// The unexpired order `id` of the signing account.
+    fn owned_order<'s>(
+        &self,
+        state: &'s AcmeState,
+        signed: &Signed,
+        id: &str,
+    ) -> Result<&'s Order, Problem> {
+        let account = AcmeServer::require_account(signed)?;
+        let order = state.orders.get(id).ok_or_else(Problem::not_found)?;
+        if order.account != account {
+            return Err(Problem::new(403, "unauthorized", "Not your order"));
+        }
+        if order.expires <= self.sources.now() {
+            return Err(Problem::not_found());
+        }
+        Ok(order)
+    }
+
+    /// The unexpired authorization `id`, owned through its order.
+    fn owned_authz<'s>(
+        &self,
+        state: &'s AcmeState,
+        signed: &Signed,
+        id: &str,
+    ) -> Result<&'s Authz, Problem> {
+        let authz = state.authzs.get(id).ok_or_else(Problem::not_found)?;
+        self.owned_order(state, signed, &authz.order)?;
+        if authz.expires <= self.sources.now() {
+            return Err(Problem::not_found());
+        }
+        Ok(authz)
+    }
+
+    fn order(&self, signed: &Signed, id: &str) -> Result<Response, Problem> {
+        let state = self.state.lock().unwrap();
+        let order = self.owned_order(&state, signed, id)?;
+        Ok(Response::json(200, &self.order_json(&state, id, order)))
+    }
+
+    fn challenge_json(&self, challenge: &Challenge) -> Value {
+        json!({
+            "type": challenge.kind,
+            "url": self.url(&format!("/chall/{}", challenge.id)),
+            "token": challenge.token,
+            "status": if challenge.valid { "valid" } else { "pending" },
+        })
+    }
+
+    fn authz(&self, signed: &Signed, id: &str) -> Result<Response, Problem> {
+        let state = self.state.lock().unwrap();
+        let authz = self.owned_authz(&state, signed, id)?;
+        let mut body = json!({
+            "status": if authz.valid() { "valid" } else { "pending" },
+            "expires": format_rfc3339(authz.expires),
+            "identifier": authz.identifier,
+            "challenges": authz
+                .challenges
+                .iter()
+                .map(|challenge| self.challenge_json(challenge))
+                .collect::<Vec<Value>>(),
+        });
+        if authz.wildcard {
+            body["wildcard"] = json!(true);
+        }
+        Ok(Response::json(200, &body))
+    }
+
+    /// Responding to a challenge immediately validates it.
+    fn challenge(&self, signed: &Signed, id: &str) -> Result<Response, Problem> {
+        let mut state = self.state.lock().unwrap();
+        let authz_id = state
+            .authzs
+            .iter()
+            .find(|(_, authz)| authz.challenges.iter().any(|c| c.id == id))
+            .map(|(authz_id, _)| authz_id.clone())
+            .ok_or_else(Problem::not_found)?;
+        self.owned_authz(&state, signed, &authz_id)?;
+        let authz_url = self.url(&format!("/authz/{}", authz_id));
+        let challenge = state
+            .authzs
+            .get_mut(&authz_id)
+            .ok_or_else(Problem::not_found)?
+            .challenges
+            .iter_mut()
+            .find(|challenge| challenge.id == id)
+            .ok_or_else(Problem::not_found)?;
+        if !signed.payload.is_empty() {
+            challenge.valid = true;
+        }
+        Ok(Response::json(200, &self.challenge_json(challenge))
+            .header("Link", &format!("<{}>;rel=\"up\"", authz_url)))
+    }
+
+    fn finalize(&self, signed: &Signed, id: &str) -> Result<Response, Problem> {
+        #[derive(Deserialize)]
+        struct Finalize {
+            csr: String,
+        }
+        let finalize: Finalize = signed.json()?;
+        let identifiers = {
+            let state = self.state.lock().unwrap();
+            let order = self.owned_order(&state, signed, id)?;
+            if AcmeServer::order_status(&state, order) != "ready" {
+                return Err(Problem::new(403, "orderNotReady", "Order is not ready"));
+            }
+            order.identifiers.clone()
+        };
+
+        let bad_csr = |_| Problem::new(400, "badCSR", "Unable to parse CSR");
+        let csr = b64url_decode(&finalize.csr)
+            .ok_or_else(|| Problem::new(400, "badCSR", "Invalid CSR encoding"))?;
+        let csr = X509Req::from_der(&csr).map_err(bad_csr)?;
+        let pkey = csr.public_key().map_err(bad_csr)?;
+        if !csr.verify(&pkey).map_err(bad_csr)? {
+            return Err(Problem::new(400, "badCSR", "CSR signature is invalid"));
+        }
+
+        let names: Vec<&str> = identifiers.iter().map(|id| id.value.as_str()).collect();
+        let mut name = X509Name::builder().map_err(SimpleCAError::from)?;
+        name.append_entry_by_nid(Nid::COMMONNAME, names[0])
+            .map_err(SimpleCAError::from)?;
+        let name = name.build();
+        let (cert, ca) = issue_public_key_cert(
+            &name,
+            &pkey,
+            &names,
+            &self.validity,
+            None,
+            &RepoGuard::default(),
+            &self.sources,
+            false,
+        )?;
+        record_issued(&cert, None, None, self.sources.now())?;
+        let mut chain = cert.to_pem().map_err(SimpleCAError::from)?;
+        chain.extend(ca.to_pem().map_err(SimpleCAError::from)?);
+
+        let mut state = self.state.lock().unwrap();
+        let order = state.orders.get_mut(id).ok_or_else(Problem::not_found)?;
+        order.cert = Some(chain);
+        let order = &state.orders[id];
+        Ok(Response::json(200, &self.order_json(&state, id, order))
+            .header("Location", &self.url(&format!("/order/{}", id))))
+    }
+
+    fn cert(&self, signed: &Signed, id: &str) -> Result<Response, Problem> {
+        let state = self.state.lock().unwrap();
+        let order = self.owned_order(&state, signed, id)?;
+        let chain = order.cert.as_ref().ok_or_else(Problem::not_found)?;
+        Ok(Response::text(
+            200,
+            "application/pem-certificate-chain",
+            chain,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::bn::BigNumContext;
+    use openssl::pkey::Private;
+    use openssl::sign::Signer;
+
+    fn es256_jwk(key: &EcKey<Private>) -> Value {
+        let mut ctx = BigNumContext::new().unwrap();
+        let (mut x, mut y) = (BigNum::new().unwrap(), BigNum::new().unwrap());
+        key.public_key()
+            .affine_coordinates(key.group(), &mut x, &mut y, &mut ctx)
+            .unwrap();
+        json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "x": b64url_encode(&x.to_vec_padded(32).unwrap()),
+            "y": b64url_encode(&y.to_vec_padded(32).unwrap()),
+        })
+    }
+
+    #[test]
+    fn test_verify_es256() {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = EcKey::generate(&group).unwrap();
+        let public = jwk_to_pkey(&es256_jwk(&key)).unwrap();
+
+        let pkey = PKey::from_ec_key(key).unwrap();
+        let mut signer = Signer::new(MessageDigest::sha256(), &pkey).unwrap();
+        let der = signer.sign_oneshot_to_vec(b"header.payload").unwrap();
+        let sig = EcdsaSig::from_der(&der).unwrap();
+        let mut raw = sig.r().to_vec_padded(32).unwrap();
+        raw.extend(sig.s().to_vec_padded(32).unwrap());
+
+        assert!(verify_signature("ES256", &public, b"header.payload", &raw));
+        assert!(!verify_signature("ES256", &public, b"header.other", &raw));
+        assert!(!verify_signature("none", &public, b"header.payload", &raw));
+        assert_eq!(b64url_decode(&b64url_encode(&raw)), Some(raw));
+    }
+
+    #[test]
+    fn test_nonces_are_bounded() {
+        let server = AcmeServer::new(
+            "https://ca.test",
+            ValidityConf::default(),
+            Sources::default(),
+        );
+        let first = server.new_nonce().unwrap();
+        for _ in 0..MAX_NONCES {
+            server.new_nonce().unwrap();
+        }
+        let nonces = &server.state.lock().unwrap().nonces;
+        assert_eq!(nonces.len(), MAX_NONCES);
+        assert!(!nonces.contains(&first));
+    }
+    fn signed(account: &str, payload: Value) -> Signed {
+        Signed {
+            payload: serde_json::to_vec(&payload).unwrap(),
+            jwk: None,
+            account: Some(account.to_string()),
+        }
+    }
+
+    fn new_order(server: &AcmeServer, account: &str) -> Result<Response, Problem> {
+        server.new_order(&signed(
+            account,
+            json!({"identifiers": [{"type": "dns", "value": "app.test"}]}),
+        ))
+    }
+
+    #[test]
+    fn test_authz_ownership() {
+        let server = AcmeServer::new(
+            "https://ca.test",
+            ValidityConf::default(),
+            Sources::default(),
+        );
+        new_order(&server, "alice").unwrap();
+        let (authz, challenge) = {
+            let state = server.state.lock().unwrap();
+            let (id, authz) = state.authzs.iter().next().unwrap();
+            (id.clone(), authz.challenges[0].id.clone())
+        };
+
+        let mallory = signed("mallory", json!({}));
+        assert_eq!(server.authz(&mallory, &authz).unwrap_err().status, 403);
+        assert_eq!(
+            server.challenge(&mallory, &challenge).unwrap_err().status,
+            403
+        );
+        assert!(!server.state.lock().unwrap().authzs[&authz].valid());
+
+        let alice = signed("alice", json!({}));
+        assert!(server.authz(&alice, &authz).is_ok());
+        server.challenge(&alice, &challenge).unwrap();
+        assert!(server.state.lock().unwrap().authzs[&authz].valid());
+    }
+
+    #[test]
+    fn test_orders_expire() {
+        let now = SystemTime::now();
+        let mut server = AcmeServer::new(
+            "https://ca.test",
+            ValidityConf::default(),
+            Sources {
+                clock: Some(now),
+                ..Sources::default()
+            },
+        );
+        for _ in 0..MAX_ORDERS {
+            new_order(&server, "alice").unwrap();
+        }
+        assert_eq!(new_order(&server, "alice").unwrap_err().kind, "rateLimited");
+        new_order(&server, "bob").unwrap();
+
+        let (order, authz) = {
+            let state = server.state.lock().unwrap();
+            let (id, order) = state.orders.iter().next().unwrap();
+            (id.clone(), order.authzs[0].clone())
+        };
+        let owner = server.state.lock().unwrap().orders[&order].account.clone();
+        let owner = signed(&owner, json!({"csr": ""}));
+
+        server.sources.clock = Some(now + ORDER_LIFETIME);
+        assert_eq!(server.order(&owner, &order).unwrap_err().status, 404);
+        assert_eq!(server.authz(&owner, &authz).unwrap_err().status, 404);
+        assert_eq!(server.finalize(&owner, &order).unwrap_err().status, 404);
+
+        let mut state = server.state.lock().unwrap();
+        state.prune(server.sources.now());
+        assert!(state.orders.is_empty());
+        assert!(state.authzs.is_empty());
+    }
+}
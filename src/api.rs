@@ -0,0 +1,230 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use openssl::base64;
+use openssl::memcmp;
+use openssl::sha::sha256;
+
+use crate::commands::{issue_public_key_cert, issue_server_cert, read_file};
+use crate::conf::{CertAuthConf, Conf, ValidityConf};
+use crate::csr::{check_attestation, csr_common_name, read_csr};
+use crate::deterministic::Sources;
+use crate::err::{Result, SimpleCAError};
+use crate::guard::RepoGuard;
+use crate::http::{Request, Response};
+use crate::index::{record_attested, record_issued};
+use crate::name::Name;
+
+/// Body of `POST /certs`.
+#[derive(Debug, Deserialize)]
+struct IssueRequest {
+    common_name: String,
+    #[serde(default)]
+    alt_names: Vec<String>,
+    days: Option<u32>,
+    ttl: Option<String>,
+}
+
+/// Response of `POST /certs`. The key is generated for the caller and is not
+/// kept in the store.
+#[derive(Debug, Serialize)]
+struct IssueResponse {
+    key: String,
+    cert: String,
+    chain: String,
+    fullchain: String,
+}
+
+/// Body of `POST /csr`, for keys held by the caller, e.g. in a TPM.
+#[derive(Debug, Deserialize)]
+struct CsrRequest {
+    /// PEM encoded CSR, its subject common name becomes the common name.
+    csr: String,
+    #[serde(default)]
+    alt_names: Vec<String>,
+    days: Option<u32>,
+    ttl: Option<String>,
+    /// Base64 attestation statement of the key, kept in the issuance index.
+    attestation: Option<String>,
+}
+
+/// Response of `POST /csr`.
+#[derive(Debug, Serialize)]
+struct CsrResponse {
+    cert: String,
+    chain: String,
+    fullchain: String,
+}
+
+/// REST API issuing server certificates from the intermediate CA.
+pub struct ApiServer {
+    token: Option<String>,
+    sources: Sources,
+}
+
+impl ApiServer {
+    pub fn new(token: Option<String>, sources: Sources) -> ApiServer {
+        ApiServer { token, sources }
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        if let Some(token) = &self.token {
+            let expected = format!("Bearer {}", token);
+            // Compared in constant time, over digests so the lengths match.
+            let given = request.header("Authorization").unwrap_or_default();
+            if !memcmp::eq(&sha256(given.as_bytes()), &sha256(expected.as_bytes())) {
+                return error(401, "Missing or invalid bearer token");
+            }
+        }
+        let path = request.path.split('?').next().unwrap_or_default();
+        let result = match (request.method.as_str(), path) {
+            ("GET", "/ca") => ca_cert(),
+            ("POST", "/certs") => match serde_json::from_slice(&request.body) {
+                Ok(issue) => issue_cert(&issue, &self.sources),
+                Err(err) => return error(400, &err.to_string()),
+            },
+            ("POST", "/csr") => match serde_json::from_slice(&request.body) {
+                Ok(csr) => sign_csr(&csr, &self.sources),
+                Err(err) => return error(400, &err.to_string()),
+            },
+            (_, "/ca") | (_, "/certs") | (_, "/csr") => return error(405, "Method not allowed"),
+            _ => return error(404, "Not found"),
+        };
+        result.unwrap_or_else(|err| match err {
+            SimpleCAError::InvalidTtl { .. }
+            | SimpleCAError::OutlivesIssuer { .. }
+            | SimpleCAError::InvalidCsr { .. }
+            | SimpleCAError::AttestationMismatch => error(400, &err.to_string()),
+            SimpleCAError::PolicyDenied { .. } => error(403, &err.to_string()),
+            _ => error(500, &err.to_string()),
+        })
+    }
+}
+
+fn error(status: u16, msg: &str) -> Response {
+    Response::json(status, &json!({ "error": msg }))
+}
+
+fn ca_cert() -> Result<Response> {
+    let pem = read_file(&CertAuthConf::ca_cert()?)?;
+    Ok(Response::text(200, "application/x-pem-file", &pem))
+}
+
+fn issue_cert(issue: &IssueRequest, sources: &Sources) -> Result<Response> {
+    let conf = Conf::load()?;
+    let name = Name::new(issue.common_name.clone()).to_x509_name_ordered(&conf.name().order())?;
+    let validity = ValidityConf {
+        server_days: issue.days,
+        server_ttl: issue.ttl.clone(),
+        ..ValidityConf::default()
+    };
+    let alt_names: Vec<&str> = issue.alt_names.iter().map(String::as_str).collect();
+    let pkey = sources.new_key(conf.keys().server_algorithm())?;
+    let (cert, intermediate) = issue_server_cert(
+        &name,
+        &pkey,
+        &alt_names,
+        &validity,
+        None,
+        &RepoGuard::default(),
+        sources,
+        false,
+    )?;
+    record_issued(&cert, None, None, sources.now())?;
+
+    let root = read_file(&CertAuthConf::ca_cert()?)?;
+    let cert = String::from_utf8(cert.to_pem()?)?;
+    let intermediate = String::from_utf8(intermediate.to_pem()?)?;
+    let chain = intermediate.clone() + &String::from_utf8(root)?;
+    let response = IssueResponse {
+        key: String::from_utf8(pkey.private_key_to_pem_pkcs8()?)?,
+        fullchain: cert.clone() + &intermediate,
+        cert,
+        chain,
+    };
+    Ok(Response::json(201, &response))
+}
+
+fn sign_csr(request: &CsrRequest, sources: &Sources) -> Result<Response> {
+    let conf = Conf::load()?;
+    let csr = read_csr(request.csr.as_bytes())?;
+    let pkey = csr.public_key()?;
+    let attestation = match &request.attestation {
+        Some(attestation) => {
+            Some(
+                base64::decode_block(attestation).map_err(|_| SimpleCAError::InvalidCsr {
+                    msg: "the attestation is not base64",
+                })?,
+            )
+        }
+        None => None,
+    };
+    if let Some(attestation) = &attestation {
+        check_attestation(attestation, &pkey)?;
+    }
+    let common_name = csr_common_name(&csr)?;
+    let name = Name::new(common_name).to_x509_name_ordered(&conf.name().order())?;
+    let validity = ValidityConf {
+        server_days: request.days,
+        server_ttl: request.ttl.clone(),
+        ..ValidityConf::default()
+    };
+    let alt_names: Vec<&str> = request.alt_names.iter().map(String::as_str).collect();
+    let (cert, intermediate) = issue_public_key_cert(
+        &name,
+        &pkey,
+        &alt_names,
+        &validity,
+        None,
+        &RepoGuard::default(),
+        sources,
+        false,
+    )?;
+    record_attested(&cert, None, attestation.as_deref(), sources.now())?;
+
+    let root = read_file(&CertAuthConf::ca_cert()?)?;
+    let cert = String::from_utf8(cert.to_pem()?)?;
+    let intermediate = String::from_utf8(intermediate.to_pem()?)?;
+    let response = CsrResponse {
+        fullchain: cert.clone() + &intermediate,
+        chain: intermediate + &String::from_utf8(root)?,
+        cert,
+    };
+    Ok(Response::json(201, &response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str, authorization: Option<&str>, body: &[u8]) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: authorization
+                .map(|value| ("Authorization".to_string(), value.to_string()))
+                .into_iter()
+                .collect(),
+            body: body.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_handle() {
+        let api = ApiServer::new(Some("s3cret".to_string()), Sources::default());
+        let status = |method, path, authorization, body: &[u8]| {
+            api.handle(&request(method, path, authorization, body))
+                .status
+        };
+        assert_eq!(status("GET", "/ca", None, b""), 401);
+        assert_eq!(status("GET", "/ca", Some("Bearer s3cre"), b""), 401);
+        assert_eq!(status("GET", "/ca", Some("Bearer s3cret!"), b""), 401);
+        assert_eq!(status("GET", "/ca", Some("s3cret"), b""), 401);
+
+        let token = Some("Bearer s3cret");
+        assert_eq!(status("GET", "/nope", token, b""), 404);
+        assert_eq!(status("DELETE", "/certs", token, b""), 405);
+        assert_eq!(status("POST", "/certs", token, b"{}"), 400);
+        assert_eq!(status("POST", "/csr?pretty", token, b"not json"), 400);
+    }
+}
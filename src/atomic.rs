@@ -0,0 +1,96 @@
+//! Writes that either replace a file completely or leave it as it was: the
+//! content goes to a temporary file in the same directory, is flushed to disk
+//! and then renamed over the target, so an interrupted run never leaves a
+//! truncated CA certificate or config behind.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::perms::{create_private, is_private};
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A name next to `path` no other writer uses, hidden from store listings.
+fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?;
+    Ok(path.with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        name.to_string_lossy(),
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::SeqCst)
+    )))
+}
+
+fn write_atomic(path: &Path, content: &[u8], private: bool) -> io::Result<()> {
+    let temp = temp_path(path)?;
+    let written = (|| {
+        let mut file = if private {
+            create_private(&temp)?
+        } else {
+            File::create(&temp)?
+        };
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written?;
+    sync_parent(path);
+    Ok(())
+}
+
+/// Flushes the rename itself, best effort as not every file system allows
+/// opening a directory.
+#[cfg(unix)]
+fn sync_parent(path: &Path) {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if let Ok(dir) = File::open(parent) {
+        let _ = dir.sync_all();
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) {}
+
+/// Replaces `path` with `content` atomically, readable by the owner only when
+/// it is named like a private key.
+pub(crate) fn write_file(path: impl AsRef<Path>, content: impl AsRef<[u8]>) -> io::Result<()> {
+    let path = path.as_ref();
+    write_atomic(path, content.as_ref(), is_private(path))
+}
+
+/// Replaces `path` with `content` atomically, readable by the owner only
+/// whatever its name, for keystores and other containers of keys.
+pub(crate) fn write_private(path: impl AsRef<Path>, content: impl AsRef<[u8]>) -> io::Result<()> {
+    write_atomic(path.as_ref(), content.as_ref(), true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_file() {
+        let dir = std::env::temp_dir().join(format!("simple-ca-atomic-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ca.cert.pem");
+        write_file(&path, b"first").unwrap();
+        write_file(&path, b"second").unwrap();
+
+        let content = fs::read(&path).unwrap();
+        let entries = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(content, b"second");
+        // No temporary file is left behind.
+        assert_eq!(entries, 1);
+    }
+}
@@ -0,0 +1,274 @@
+//! Where the private keys of the CA live: PEM files in the store, or keys in
+//! a PKCS#11 token such as SoftHSM or an HSM, which never leave it. Token
+//! keys are opened through an OpenSSL 3 provider, the pkcs11-provider unless
+//! `[pkcs11] provider` names another, and come back as opaque `PKey`s, so
+//! `create_cert` has the token sign without any change. The intermediate key
+//! can also live in a cloud KMS, see `kms`, or a TPM, whose keys the tpm2
+//! provider opens the same way as token keys.
+
+use std::path::PathBuf;
+
+use openssl::pkey::{PKey, Private};
+
+use crate::conf::{CertAuthConf, Conf, Pkcs11Conf};
+use crate::err::Result;
+use crate::kms::KmsKey;
+use crate::passphrase::read_private_key;
+use crate::tpm::{parse_handle, TPM2_PROVIDER};
+
+/// Where a CA private key is kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyBackend {
+    /// A PEM file, encrypted with the passphrase or not.
+    File(PathBuf),
+    /// A key in a PKCS#11 token, named by its RFC 7512 URI.
+    Pkcs11 {
+        uri: String,
+        provider: String,
+        module: Option<PathBuf>,
+    },
+    /// A key in AWS KMS or GCP Cloud KMS, named like `[kms] intermediate_key`.
+    Kms { key: String },
+    /// A key in a TPM 2.0 at a persistent handle.
+    Tpm { handle: u32 },
+}
+
+impl KeyBackend {
+    fn configured(pkcs11: &Pkcs11Conf, uri: Option<&String>, path: PathBuf) -> KeyBackend {
+        match uri {
+            Some(uri) => KeyBackend::Pkcs11 {
+                uri: uri.clone(),
+                provider: pkcs11.provider().to_string(),
+                module: pkcs11.module.clone(),
+            },
+            None => KeyBackend::File(path),
+        }
+    }
+
+    /// The key of the root CA, `[pkcs11] ca_key` when set.
+    pub fn ca(conf: &Conf) -> Result<KeyBackend> {
+        let pkcs11 = conf.pkcs11();
+        Ok(KeyBackend::configured(
+            &pkcs11,
+            pkcs11.ca_key.as_ref(),
+            CertAuthConf::ca_key()?,
+        ))
+    }
+
+    /// The key of the default intermediate, `[kms] intermediate_key`,
+    /// `[tpm] intermediate_handle` or `[pkcs11] intermediate_key` when set.
+    pub fn intermediate(conf: &Conf) -> Result<KeyBackend> {
+        if let Some(key) = conf.kms().intermediate_key {
+            return Ok(KeyBackend::Kms { key });
+        }
+        if let Some(handle) = conf.tpm().intermediate_handle {
+            return Ok(KeyBackend::Tpm {
+                handle: parse_handle(&handle)?,
+            });
+        }
+        let pkcs11 = conf.pkcs11();
+        Ok(KeyBackend::configured(
+            &pkcs11,
+            pkcs11.intermediate_key.as_ref(),
+            CertAuthConf::intermediate_key()?,
+        ))
+    }
+
+    /// The key file, `None` for a key in a token.
+    pub fn path(&self) -> Option<&PathBuf> {
+        match self {
+            KeyBackend::File(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Whether the key is there to sign with. Keys in a token, KMS or TPM
+    /// are created with their own tools and assumed to exist.
+    pub fn exists(&self) -> bool {
+        match self {
+            KeyBackend::File(path) => path.exists(),
+            _ => true,
+        }
+    }
+
+    /// Loads the key, for a token or KMS a handle that signs remotely.
+    pub fn load(&self) -> Result<PKey<Private>> {
+        match self {
+            KeyBackend::File(path) => read_private_key(path),
+            KeyBackend::Pkcs11 {
+                uri,
+                provider,
+                module,
+            } => token::load_key(uri, provider, module.as_ref()),
+            KeyBackend::Kms { key } => KmsKey::parse(key)?.load(),
+            KeyBackend::Tpm { handle } => {
+                token::load_key(&format!("handle:0x{:08x}", handle), TPM2_PROVIDER, None)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "pkcs11")]
+mod token {
+    use std::ffi::{c_char, c_int, c_void, CString};
+    use std::path::PathBuf;
+    use std::ptr;
+    use std::sync::Mutex;
+
+    use foreign_types::ForeignType;
+    use openssl::error::ErrorStack;
+    use openssl::pkey::{PKey, Private};
+    use openssl::provider::Provider;
+
+    use crate::err::{Result, SimpleCAError};
+
+    /// Environment variable the pkcs11-provider takes the module from.
+    const MODULE_ENV: &str = "PKCS11_PROVIDER_MODULE";
+    const OSSL_STORE_INFO_PKEY: c_int = 4;
+
+    #[allow(non_camel_case_types)]
+    enum OSSL_STORE_CTX {}
+    #[allow(non_camel_case_types)]
+    enum OSSL_STORE_INFO {}
+
+    extern "C" {
+        fn UI_OpenSSL() -> *const c_void;
+        fn OSSL_STORE_open(
+            uri: *const c_char,
+            ui_method: *const c_void,
+            ui_data: *mut c_void,
+            post_process: *const c_void,
+            post_process_data: *mut c_void,
+        ) -> *mut OSSL_STORE_CTX;
+        fn OSSL_STORE_load(ctx: *mut OSSL_STORE_CTX) -> *mut OSSL_STORE_INFO;
+        fn OSSL_STORE_eof(ctx: *mut OSSL_STORE_CTX) -> c_int;
+        fn OSSL_STORE_error(ctx: *mut OSSL_STORE_CTX) -> c_int;
+        fn OSSL_STORE_close(ctx: *mut OSSL_STORE_CTX) -> c_int;
+        fn OSSL_STORE_INFO_get_type(info: *const OSSL_STORE_INFO) -> c_int;
+        fn OSSL_STORE_INFO_get1_PKEY(info: *const OSSL_STORE_INFO) -> *mut openssl_sys::EVP_PKEY;
+        fn OSSL_STORE_INFO_free(info: *mut OSSL_STORE_INFO);
+    }
+
+    /// Providers loaded so far, kept loaded for the life of the process.
+    static PROVIDERS: Mutex<Vec<(String, Provider)>> = Mutex::new(Vec::new());
+
+    fn load_provider(name: &str, module: Option<&PathBuf>) -> Result<()> {
+        let mut providers = PROVIDERS.lock().unwrap();
+        if providers.iter().any(|(loaded, _)| loaded == name) {
+            return Ok(());
+        }
+        if let Some(module) = module {
+            if std::env::var_os(MODULE_ENV).is_none() {
+                std::env::set_var(MODULE_ENV, module);
+            }
+        }
+        // Keeps the default provider for everything but the token keys.
+        let provider = Provider::try_load(None, name, true)?;
+        providers.push((name.to_string(), provider));
+        Ok(())
+    }
+
+    fn token_error(uri: &str, msg: impl ToString) -> SimpleCAError {
+        SimpleCAError::TokenKey {
+            uri: uri.to_string(),
+            msg: msg.to_string(),
+        }
+    }
+
+    /// The first private key the store at `uri` holds. The PIN is asked for
+    /// on the terminal unless the URI carries a `pin-source`.
+    pub(crate) fn store_key(uri: &str) -> Result<PKey<Private>> {
+        let c_uri = CString::new(uri).map_err(|err| token_error(uri, err))?;
+        // SAFETY: the context is closed before returning, every loaded info
+        // is freed, and the key taken out of it is owned by the `PKey`.
+        unsafe {
+            let ctx = OSSL_STORE_open(
+                c_uri.as_ptr(),
+                UI_OpenSSL(),
+                ptr::null_mut(),
+                ptr::null(),
+                ptr::null_mut(),
+            );
+            if ctx.is_null() {
+                return Err(token_error(uri, ErrorStack::get()));
+            }
+            let mut key = None;
+            while key.is_none() && OSSL_STORE_eof(ctx) == 0 {
+                let info = OSSL_STORE_load(ctx);
+                if info.is_null() {
+                    if OSSL_STORE_error(ctx) != 0 {
+                        break;
+                    }
+                    continue;
+                }
+                if OSSL_STORE_INFO_get_type(info) == OSSL_STORE_INFO_PKEY {
+                    let pkey = OSSL_STORE_INFO_get1_PKEY(info);
+                    if !pkey.is_null() {
+                        key = Some(PKey::from_ptr(pkey));
+                    }
+                }
+                OSSL_STORE_INFO_free(info);
+            }
+            OSSL_STORE_close(ctx);
+            key.ok_or_else(|| {
+                let errors = ErrorStack::get();
+                if errors.errors().is_empty() {
+                    token_error(uri, "no private key at this URI")
+                } else {
+                    token_error(uri, errors)
+                }
+            })
+        }
+    }
+
+    pub(super) fn load_key(
+        uri: &str,
+        provider: &str,
+        module: Option<&PathBuf>,
+    ) -> Result<PKey<Private>> {
+        load_provider(provider, module).map_err(|err| token_error(uri, err))?;
+        store_key(uri)
+    }
+}
+
+#[cfg(not(feature = "pkcs11"))]
+mod token {
+    use std::path::PathBuf;
+
+    use openssl::pkey::{PKey, Private};
+
+    use crate::err::{Result, SimpleCAError};
+
+    pub(super) fn load_key(
+        uri: &str,
+        _provider: &str,
+        _module: Option<&PathBuf>,
+    ) -> Result<PKey<Private>> {
+        Err(SimpleCAError::TokenKey {
+            uri: uri.to_string(),
+            msg: "simple-ca was built without the pkcs11 feature".to_string(),
+        })
+    }
+}
+
+#[cfg(all(test, feature = "pkcs11"))]
+mod tests {
+    use super::*;
+    use crate::err::SimpleCAError;
+    use crate::keys::KeyAlgorithm;
+
+    #[test]
+    fn test_store_key() {
+        // The file: scheme of the default provider goes through the same
+        // store API as a token.
+        let path = std::env::temp_dir().join(format!("simple-ca-store-{}.pem", std::process::id()));
+        let key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        std::fs::write(&path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        let loaded = token::store_key(&format!("file:{}", path.display()));
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.unwrap().public_eq(&key));
+
+        let missing = token::store_key("file:/nonexistent/simple-ca.pem");
+        assert!(matches!(missing, Err(SimpleCAError::TokenKey { .. })));
+    }
+}
@@ -0,0 +1,282 @@
+//! Backups of the whole CA state in a single file, so a team can share one
+//! dev CA: every file of the manifest plus the config, issuance index, serial
+//! counter and usage stamps, optionally encrypted with the passphrase.
+
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use openssl::base64;
+use openssl::hash::MessageDigest;
+use openssl::pkcs5::pbkdf2_hmac;
+use openssl::rand::rand_bytes;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+
+use crate::atomic::{write_file, write_private};
+use crate::conf::{config_dir, with_config_dir};
+use crate::datetime::format_rfc3339;
+use crate::err::{Result, SimpleCAError};
+use crate::guard::RepoGuard;
+use crate::hooks::warn_unapproved_hook;
+use crate::index::INDEX_FILE;
+use crate::lock::StoreLock;
+use crate::passphrase::passphrase;
+use crate::serial::SERIALS_FILE;
+use crate::store::{check_replace, StoreManifest, CONFIG_FILE, STORE_MANIFEST};
+use crate::usage::USAGE_FILE;
+
+const BACKUP_FORMAT: &str = "simple-ca-backup";
+const BACKUP_VERSION: u32 = 1;
+const KDF: &str = "pbkdf2-hmac-sha256";
+const KDF_ITERATIONS: usize = 600_000;
+/// Iterations accepted from a backup, which may come from a remote vault: a
+/// crafted file can neither hang a restore nor make the key cheap to derive.
+const KDF_ITERATION_RANGE: RangeInclusive<usize> = 100_000..=10 * KDF_ITERATIONS;
+/// Length of the GCM tag, shorter ones would weaken the authentication.
+const TAG_LEN: usize = 16;
+/// Binds the ciphertext to the format, as additional authenticated data.
+const AAD: &[u8] = b"simple-ca-backup/1";
+
+/// A backup file, with the archived files as base64 encoded JSON in `payload`.
+#[derive(Debug, Deserialize, Serialize)]
+struct Backup {
+    format: String,
+    version: u32,
+    created_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encryption: Option<Encryption>,
+    payload: String,
+}
+
+/// AES-256-GCM with a key derived from the passphrase.
+#[derive(Debug, Deserialize, Serialize)]
+struct Encryption {
+    kdf: String,
+    iterations: usize,
+    salt: String,
+    nonce: String,
+    tag: String,
+}
+
+/// A file of the store, with its path relative to the config dir.
+#[derive(Debug, Deserialize, Serialize)]
+struct ArchivedFile {
+    path: String,
+    content: String,
+}
+
+fn derive_key(salt: &[u8], iterations: usize) -> Result<Vec<u8>> {
+    let mut key = vec![0; 32];
+    pbkdf2_hmac(
+        &passphrase(false)?,
+        salt,
+        iterations,
+        MessageDigest::sha256(),
+        &mut key,
+    )?;
+    Ok(key)
+}
+
+fn seal(payload: &[u8]) -> Result<(Encryption, Vec<u8>)> {
+    // Asks for the passphrase twice before it is cached for `derive_key`.
+    passphrase(true)?;
+    let mut salt = vec![0; 16];
+    let mut nonce = vec![0; 12];
+    rand_bytes(&mut salt)?;
+    rand_bytes(&mut nonce)?;
+    let key = derive_key(&salt, KDF_ITERATIONS)?;
+    let mut tag = vec![0; TAG_LEN];
+    let ciphertext = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        &key,
+        Some(&nonce),
+        AAD,
+        payload,
+        &mut tag,
+    )?;
+    let encryption = Encryption {
+        kdf: KDF.to_string(),
+        iterations: KDF_ITERATIONS,
+        salt: base64::encode_block(&salt),
+        nonce: base64::encode_block(&nonce),
+        tag: base64::encode_block(&tag),
+    };
+    Ok((encryption, ciphertext))
+}
+
+fn open(path: &Path, encryption: &Encryption, ciphertext: &[u8]) -> Result<Vec<u8>> {
+    let invalid = |msg| SimpleCAError::InvalidBackup {
+        path: path.to_path_buf(),
+        msg,
+    };
+    if encryption.kdf != KDF {
+        Err(invalid("unsupported key derivation"))?;
+    }
+    if !KDF_ITERATION_RANGE.contains(&encryption.iterations) {
+        Err(invalid("unsupported number of key derivation iterations"))?;
+    }
+    let decode = |value: &str| base64::decode_block(value).map_err(|_| invalid("bad encoding"));
+    let tag = decode(&encryption.tag)?;
+    if tag.len() != TAG_LEN {
+        Err(invalid("truncated authentication tag"))?;
+    }
+    let key = derive_key(&decode(&encryption.salt)?, encryption.iterations)?;
+    decrypt_aead(
+        Cipher::aes_256_gcm(),
+        &key,
+        Some(&decode(&encryption.nonce)?),
+        AAD,
+        ciphertext,
+        &tag,
+    )
+    .map_err(|_| SimpleCAError::WrongPassphrase {
+        path: path.to_path_buf(),
+    })
+}
+
+/// Writes the whole CA state to `dest`, encrypted with the passphrase when
+/// `encrypt` is set. Returns the number of archived files.
+pub fn backup_store(dest: &Path, encrypt: bool, guard: &RepoGuard) -> Result<usize> {
+    let _lock = StoreLock::acquire()?;
+    guard.check(&[dest])?;
+    let (backup, count) = backup_content(encrypt)?;
+    write_private(dest, backup)?;
+    Ok(count)
+}
+
+/// The backup of the whole CA state and the number of files it holds. The
+/// caller holds the store lock.
+pub(crate) fn backup_content(encrypt: bool) -> Result<(Vec<u8>, usize)> {
+    let dir = config_dir()?;
+    let manifest = StoreManifest::load()?.relocatable();
+    let mut files = vec![ArchivedFile {
+        path: STORE_MANIFEST.to_string(),
+        content: base64::encode_block(&serde_json::to_vec_pretty(&manifest)?),
+    }];
+    let state = [CONFIG_FILE, INDEX_FILE, SERIALS_FILE, USAGE_FILE];
+    let paths = manifest.files.iter().map(|entry| entry.path.as_str());
+    for path in paths.chain(state) {
+        if dir.join(path).exists() {
+            files.push(ArchivedFile {
+                path: path.to_string(),
+                content: base64::encode_block(&fs::read(dir.join(path))?),
+            });
+        }
+    }
+
+    let payload = serde_json::to_vec(&files)?;
+    let (encryption, payload) = if encrypt {
+        let (encryption, ciphertext) = seal(&payload)?;
+        (Some(encryption), ciphertext)
+    } else {
+        (None, payload)
+    };
+    let backup = Backup {
+        format: BACKUP_FORMAT.to_string(),
+        version: BACKUP_VERSION,
+        created_at: format_rfc3339(SystemTime::now()),
+        encryption,
+        payload: base64::encode_block(&payload),
+    };
+    Ok((serde_json::to_vec_pretty(&backup)?, files.len()))
+}
+
+/// Whether `path` stays inside the directory it is joined to.
+pub(crate) fn is_contained(path: &str) -> bool {
+    let path = Path::new(path);
+    path.components().count() > 0
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// Restores a backup written by `backup_store` into the current store. An
+/// existing CA is only replaced with `force`. Returns the restored files.
+pub fn restore_store(src: &Path, force: bool) -> Result<Vec<PathBuf>> {
+    let _lock = StoreLock::acquire()?;
+    restore_content(src, &fs::read(src)?, force)
+}
+
+/// Restores the backup `content`, read from `src`. The caller holds the
+/// store lock.
+pub(crate) fn restore_content(src: &Path, content: &[u8], force: bool) -> Result<Vec<PathBuf>> {
+    let invalid = |msg| SimpleCAError::InvalidBackup {
+        path: src.to_path_buf(),
+        msg,
+    };
+    let backup: Backup = serde_json::from_slice(content).map_err(|_| invalid("unreadable"))?;
+    if backup.format != BACKUP_FORMAT {
+        Err(invalid("unknown format"))?;
+    }
+    if backup.version > BACKUP_VERSION {
+        Err(invalid("written by a newer version of simple-ca"))?;
+    }
+    let payload = base64::decode_block(&backup.payload).map_err(|_| invalid("bad encoding"))?;
+    let payload = match &backup.encryption {
+        Some(encryption) => open(src, encryption, &payload)?,
+        None => payload,
+    };
+    let files: Vec<ArchivedFile> =
+        serde_json::from_slice(&payload).map_err(|_| invalid("unreadable"))?;
+    if !files.iter().all(|file| is_contained(&file.path)) {
+        Err(invalid("it contains paths outside the store"))?;
+    }
+    check_replace(force)?;
+
+    let restored = with_config_dir(|dir| {
+        let mut restored = Vec::with_capacity(files.len());
+        for file in &files {
+            let target = dir.join(&file.path);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let content =
+                base64::decode_block(&file.content).map_err(|_| invalid("bad encoding"))?;
+            write_file(&target, content)?;
+            restored.push(target);
+        }
+        Ok(restored)
+    })?;
+    warn_unapproved_hook();
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_contained() {
+        assert!(is_contained("ca.key.pem"));
+        assert!(is_contained("archive/ca.0123456789abcdef.cert.pem"));
+        assert!(!is_contained("../ca.key.pem"));
+        assert!(!is_contained("/etc/passwd"));
+        assert!(!is_contained("archive/../../ca.key.pem"));
+        assert!(!is_contained(""));
+    }
+    #[test]
+    fn test_open_checks_parameters() {
+        let path = Path::new("backup.json");
+        let encryption = |iterations, tag: &[u8]| Encryption {
+            kdf: KDF.to_string(),
+            iterations,
+            salt: base64::encode_block(&[0; 16]),
+            nonce: base64::encode_block(&[0; 12]),
+            tag: base64::encode_block(tag),
+        };
+        // Rejected before the passphrase is asked for.
+        for encryption in [
+            encryption(usize::MAX, &[0; TAG_LEN]),
+            encryption(0, &[0; TAG_LEN]),
+            encryption(KDF_ITERATIONS, &[0; 4]),
+        ] {
+            assert!(matches!(
+                open(path, &encryption, b"ciphertext"),
+                Err(SimpleCAError::InvalidBackup { .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! Batch issuance from a TOML manifest listing many server certificates, so a
+//! dev cluster can be provisioned in one run:
+//!
+//! ```toml
+//! [defaults]
+//! org = "Acme Dev"
+//! days = 30
+//! out_dir = "certs"
+//!
+//! [[cert]]
+//! common_name = "api.dev.test"
+//! sans = ["api.dev.test", "10.0.0.5"]
+//! key = "ecdsa-p256"
+//! ```
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::commands::{check_server_policy, generate_server_cert, ServerExtras};
+use crate::conf::{CertAuthConf, Conf, ValidityConf};
+use crate::deterministic::Sources;
+use crate::err::{Result, SimpleCAError};
+use crate::guard::RepoGuard;
+use crate::keys::KeyAlgorithm;
+use crate::lock::StoreLock;
+use crate::name::Name;
+use crate::out_dir::OutDir;
+use crate::summary::IssuanceSummary;
+
+/// A manifest file: the certificates to issue and the defaults they share.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CertManifest {
+    #[serde(default)]
+    pub defaults: ManifestCert,
+    #[serde(default, rename = "cert")]
+    pub certs: Vec<ManifestCert>,
+}
+
+/// One certificate of a manifest, fields left out are taken from `[defaults]`
+/// and then from the config.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestCert {
+    pub common_name: Option<String>,
+    /// DNS names and IP addresses besides the common name.
+    pub sans: Option<Vec<String>>,
+    pub country: Option<String>,
+    pub state: Option<String>,
+    pub locality: Option<String>,
+    pub org: Option<String>,
+    pub org_unit: Option<String>,
+    pub days: Option<u32>,
+    pub ttl: Option<String>,
+    pub not_before: Option<String>,
+    pub not_after: Option<String>,
+    pub key: Option<KeyAlgorithm>,
+    /// Named intermediate signing the certificate.
+    pub issuer: Option<String>,
+    /// Also copy the files into this directory, relative to the manifest.
+    pub out_dir: Option<PathBuf>,
+}
+
+impl ManifestCert {
+    /// This spec with its missing fields taken from `defaults`.
+    fn or(&self, defaults: &ManifestCert) -> ManifestCert {
+        let validity = self.validity().or(&defaults.validity());
+        ManifestCert {
+            common_name: self.common_name.clone(),
+            sans: self.sans.clone().or_else(|| defaults.sans.clone()),
+            country: self.country.clone().or_else(|| defaults.country.clone()),
+            state: self.state.clone().or_else(|| defaults.state.clone()),
+            locality: self.locality.clone().or_else(|| defaults.locality.clone()),
+            org: self.org.clone().or_else(|| defaults.org.clone()),
+            org_unit: self.org_unit.clone().or_else(|| defaults.org_unit.clone()),
+            days: validity.server_days,
+            ttl: validity.server_ttl,
+            not_before: validity.not_before,
+            not_after: validity.not_after,
+            key: self.key.or(defaults.key),
+            issuer: self.issuer.clone().or_else(|| defaults.issuer.clone()),
+            out_dir: self.out_dir.clone().or_else(|| defaults.out_dir.clone()),
+        }
+    }
+
+    fn validity(&self) -> ValidityConf {
+        ValidityConf {
+            server_days: self.days,
+            server_ttl: self.ttl.clone(),
+            not_before: self.not_before.clone(),
+            not_after: self.not_after.clone(),
+            ..ValidityConf::default()
+        }
+    }
+
+    fn name(&self, common_name: &str) -> Name {
+        Name {
+            country: self.country.clone(),
+            province: self.state.clone(),
+            locality: self.locality.clone(),
+            org: self.org.clone(),
+            org_unit: self.org_unit.clone(),
+            common_name: Some(common_name.to_string()),
+        }
+    }
+}
+
+/// Reads a manifest, checking that every certificate has a distinct common
+/// name.
+pub fn read_cert_manifest(path: &Path) -> Result<CertManifest> {
+    let invalid = |msg: String| SimpleCAError::InvalidCertManifest {
+        path: path.to_path_buf(),
+        msg,
+    };
+    let manifest: CertManifest =
+        toml::from_str(&fs::read_to_string(path)?).map_err(|err| invalid(err.to_string()))?;
+    if manifest.certs.is_empty() {
+        Err(invalid("it lists no [[cert]]".to_string()))?;
+    }
+    let mut seen = HashSet::new();
+    for (index, cert) in manifest.certs.iter().enumerate() {
+        match &cert.common_name {
+            Some(name) if !seen.insert(name) => {
+                Err(invalid(format!("{} is listed twice", name)))?;
+            }
+            Some(_) => {}
+            None => Err(invalid(format!("cert {} has no common_name", index + 1)))?,
+        }
+    }
+    Ok(manifest)
+}
+
+/// Issues every certificate of the manifest at `path`. The policy and
+/// repository checks of all of them run before the first one is issued.
+pub fn issue_manifest(
+    path: &Path,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<Vec<IssuanceSummary>> {
+    let _lock = StoreLock::acquire()?;
+    let manifest = read_cert_manifest(path)?;
+    let conf = Conf::load()?;
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    let specs: Vec<ManifestCert> = manifest
+        .certs
+        .iter()
+        .map(|cert| cert.or(&manifest.defaults))
+        .collect();
+    for spec in &specs {
+        let common_name = spec.common_name.as_deref().unwrap_or_default();
+        let name = spec
+            .name(common_name)
+            .to_x509_name_ordered(&conf.name().order())?;
+        let sans: Vec<&str> = spec.sans.iter().flatten().map(String::as_str).collect();
+        check_server_policy(
+            &conf,
+            &name,
+            &sans,
+            &spec.validity().or(&conf.validity()),
+            sources,
+        )?;
+        guard.check(&[&CertAuthConf::server_key(common_name)?])?;
+        if let Some(out_dir) = &spec.out_dir {
+            guard.check(&[&OutDir::new(&base.join(out_dir)).key_path(common_name)])?;
+        }
+    }
+
+    let mut summaries = Vec::with_capacity(specs.len());
+    for spec in &specs {
+        let common_name = spec.common_name.as_deref().unwrap_or_default();
+        let sans: Vec<&str> = spec.sans.iter().flatten().map(String::as_str).collect();
+        let out_dir = spec
+            .out_dir
+            .as_ref()
+            .map(|dir| OutDir::new(&base.join(dir)));
+        let extras = ServerExtras {
+            algorithm: spec.key,
+            ..ServerExtras::default()
+        };
+        summaries.push(generate_server_cert(
+            &spec.name(common_name),
+            &sans,
+            &spec.validity(),
+            spec.issuer.as_deref(),
+            guard,
+            out_dir.as_ref(),
+            &extras,
+            sources,
+            verbose,
+        )?);
+    }
+    Ok(summaries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cert_spec_defaults() {
+        let manifest: CertManifest = toml::from_str(
+            r#"
+            [defaults]
+            org = "Acme Dev"
+            ttl = "12h"
+            key = "ecdsa-p256"
+
+            [[cert]]
+            common_name = "api.dev.test"
+            days = 90
+
+            [[cert]]
+            common_name = "db.dev.test"
+            org = "Acme Data"
+            key = "rsa2048"
+            "#,
+        )
+        .unwrap();
+        let api = manifest.certs[0].or(&manifest.defaults);
+        assert_eq!(api.org.as_deref(), Some("Acme Dev"));
+        assert_eq!((api.days, api.ttl), (Some(90), None));
+        assert_eq!(api.key, Some(KeyAlgorithm::EcdsaP256));
+        let db = manifest.certs[1].or(&manifest.defaults);
+        assert_eq!(db.org.as_deref(), Some("Acme Data"));
+        assert_eq!((db.days, db.ttl.as_deref()), (None, Some("12h")));
+        assert_eq!(db.key, Some(KeyAlgorithm::Rsa2048));
+
+        assert!(toml::from_str::<CertManifest>("[[cert]]\nname = \"typo\"").is_err());
+    }
+}
@@ -2,117 +2,593 @@ use openssl::asn1::{Asn1Integer, Asn1Time};
 use openssl::bn::BigNum;
 use openssl::error::ErrorStack;
 use openssl::nid::Nid;
-use openssl::pkey::{PKey, Private};
-use openssl::x509::X509Name;
-use std::time::{SystemTime, UNIX_EPOCH};
-
-fn create_serial_number() -> BigNum {
-    let now = SystemTime::now();
-    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap();
-    let time = since_epoch.as_secs() * 1_000_000_000 as u64 + since_epoch.subsec_nanos() as u64;
-    let time = format!("{}", time);
-    BigNum::from_dec_str(&time).unwrap()
+use openssl::pkey::{HasPublic, PKey, Private, Public};
+use openssl::x509::{X509Extension, X509Name, X509NameRef};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::certs::ExtKeyUsage;
+use crate::conf::DEFAULT_SERVER_DAYS;
+use crate::constraints::NameConstraints;
+use crate::err::{Result, SimpleCAError};
+use crate::keys::SignatureDigest;
+use crate::serial::{random_serial, SerialSource};
+
+/// Parses a lifetime such as `90m`, `6h`, `30s` or `2d`.
+pub fn parse_ttl(ttl: &str) -> Result<Duration, SimpleCAError> {
+    let invalid = || SimpleCAError::InvalidTtl {
+        ttl: ttl.to_string(),
+    };
+    let ttl = ttl.trim();
+    let unit_pos = ttl.len() - ttl.chars().last().ok_or_else(invalid)?.len_utf8();
+    let (value, unit) = ttl.split_at(unit_pos);
+    let value: u64 = value.parse().map_err(|_| invalid())?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        _ => return Err(invalid()),
+    };
+    let secs = value.checked_mul(unit_secs).ok_or_else(invalid)?;
+    if secs == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// How far before issuance certificates start unless the config says
+/// otherwise, so a host whose clock is slightly behind the issuer's accepts
+/// a fresh certificate.
+pub const DEFAULT_BACKDATE: Duration = Duration::from_secs(60 * 60);
+
+/// Netscape comment of server certificates unless the config brands them.
+pub const DEFAULT_COMMENT: &str = "Simple CA Generated Server Certificate";
+
+pub struct Entity<T = Private> {
+    pub name: X509Name,
+    pub pkey: PKey<T>,
 }
 
-pub struct Entity<'a> {
-    pub name: &'a X509Name,
-    pub pkey: &'a PKey<Private>,
+impl<T> Entity<T> {
+    /// Copies a borrowed name and key; keys are reference counted, so only
+    /// the name is duplicated.
+    fn copy(name: &X509NameRef, pkey: &PKey<T>) -> Result<Entity<T>, ErrorStack> {
+        Ok(Entity {
+            name: name.to_owned()?,
+            pkey: pkey.clone(),
+        })
+    }
 }
 
-pub struct CertParams<'a> {
-    subject: Entity<'a>,
-    issuer: Option<Entity<'a>>,
+/// Parameters of a certificate to be signed by `issuer`.
+///
+/// The subject key is usually a generated private key, but may be a bare
+/// public key such as one taken from a CSR. Built with `CertParams::builder`,
+/// or from borrowed names and keys with the `*_params` constructors.
+pub struct CertParams<T = Private> {
+    subject: Entity<T>,
+    issuer: Entity,
     pub valid: u32,
+    pub ttl: Option<Duration>,
+    /// Explicit start as a Unix timestamp, the time of signing when `None`.
+    pub not_before: Option<i64>,
+    /// Explicit end as a Unix timestamp, overriding `valid` and `ttl`.
+    pub not_after: Option<i64>,
+    /// How far before the time of signing the certificate starts, unless
+    /// `not_before` is set. The lifetime still counts from the time of
+    /// signing.
+    pub backdate: Duration,
+    /// The time of signing, the system clock when `None`.
+    pub clock: Option<SystemTime>,
     serial: BigNum,
     pub sub_alt_names: Vec<String>,
+    /// Written into CA certificates only.
+    pub name_constraints: Option<NameConstraints>,
+    /// How many CA certificates may follow this CA certificate in a chain,
+    /// unlimited when `None`.
+    pub path_len: Option<u32>,
+    pub digest: SignatureDigest,
+    /// Netscape comment, written into server certificates only and left out
+    /// when `None`.
+    pub comment: Option<String>,
+    /// Extended key usages of client certificates, only clientAuth when empty.
+    pub ext_key_usage: Vec<ExtKeyUsage>,
+    /// Added after the extensions of the kind of certificate.
+    extensions: Vec<X509Extension>,
 }
 
-impl<'a> CertParams<'a> {
-    pub fn valid_from(&self) -> Asn1Time {
-        Asn1Time::days_from_now(0).unwrap()
+impl<T: HasPublic> CertParams<T> {
+    /// Starts owned parameters for a certificate of `name` and `pkey`.
+    pub fn builder(name: X509Name, pkey: PKey<T>) -> CertParamsBuilder<T> {
+        CertParamsBuilder::new(name, pkey)
+    }
+
+    /// Seconds since the epoch at signing, 0 for a clock set before it.
+    fn unix_now(&self) -> i64 {
+        self.clock
+            .unwrap_or_else(SystemTime::now)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64
     }
 
-    pub fn valid_to(&self) -> Asn1Time {
-        Asn1Time::days_from_now(self.valid).unwrap()
+    /// The start of validity, `backdate` before the time of signing unless
+    /// `not_before` is set.
+    pub fn valid_from(&self) -> Result<Asn1Time, ErrorStack> {
+        match self.not_before {
+            Some(not_before) => Asn1Time::from_unix(not_before as _),
+            None => {
+                let backdate = i64::try_from(self.backdate.as_secs()).unwrap_or(i64::MAX);
+                Asn1Time::from_unix(self.unix_now().saturating_sub(backdate) as _)
+            }
+        }
     }
 
-    pub fn subject(&self) -> &Entity {
+    /// The end of validity; a lifetime counts from `not_before` when set.
+    pub fn valid_to(&self) -> Result<Asn1Time, ErrorStack> {
+        if let Some(not_after) = self.not_after {
+            return Asn1Time::from_unix(not_after as _);
+        }
+        let start = match self.not_before {
+            Some(not_before) => not_before,
+            None => self.unix_now(),
+        };
+        let lifetime = match self.ttl {
+            Some(ttl) => i64::try_from(ttl.as_secs()).unwrap_or(i64::MAX),
+            None => i64::from(self.valid) * 86_400,
+        };
+        Asn1Time::from_unix(start.saturating_add(lifetime) as _)
+    }
+
+    pub fn subject(&self) -> &Entity<T> {
         &self.subject
     }
 
     pub fn issuer(&self) -> &Entity {
-        self.issuer.as_ref().unwrap_or(self.subject())
+        &self.issuer
+    }
+
+    /// Extra extensions, added after those of the kind of certificate.
+    pub fn extensions(&self) -> &[X509Extension] {
+        &self.extensions
     }
 
-    pub fn serial(&self) -> Asn1Integer {
-        self.serial.to_asn1_integer().unwrap()
+    pub fn serial(&self) -> Result<Asn1Integer, ErrorStack> {
+        self.serial.to_asn1_integer()
     }
 
+    /// Replaces the random serial.
+    pub fn set_serial(&mut self, serial: BigNum) {
+        self.serial = serial;
+    }
+
+    /// Replaces the random serial with the next one of `source`.
+    pub fn allocate_serial(&mut self, source: &dyn SerialSource) -> Result<()> {
+        self.serial = source.next_serial()?;
+        Ok(())
+    }
+}
+
+impl CertParams {
     pub fn root_ca_params(
-        name: &'a X509Name,
-        pkey: &'a PKey<Private>,
+        name: &X509Name,
+        pkey: &PKey<Private>,
         valid: u32,
-    ) -> Result<CertParams<'a>, ErrorStack> {
-        let subject = Entity { name, pkey };
+    ) -> Result<CertParams, ErrorStack> {
         Ok(CertParams {
-            subject,
-            issuer: None,
+            subject: Entity::copy(name, pkey)?,
+            issuer: Entity::copy(name, pkey)?,
             valid,
-            serial: BigNum::from_u32(1000)?,
+            ttl: None,
+            not_before: None,
+            not_after: None,
+            backdate: DEFAULT_BACKDATE,
+            clock: None,
+            serial: random_serial()?,
             sub_alt_names: Vec::with_capacity(0),
+            name_constraints: None,
+            path_len: None,
+            digest: SignatureDigest::default(),
+            comment: Some(DEFAULT_COMMENT.to_string()),
+            ext_key_usage: Vec::new(),
+            extensions: Vec::new(),
         })
     }
 
     pub fn intermediate_ca_params(
-        name: &'a X509Name,
-        pkey: &'a PKey<Private>,
-        root_name: &'a X509Name,
-        root_pkey: &'a PKey<Private>,
+        name: &X509Name,
+        pkey: &PKey<Private>,
+        issuer_name: &X509Name,
+        issuer_pkey: &PKey<Private>,
         valid: u32,
-    ) -> Result<CertParams<'a>, ErrorStack> {
-        let subject = Entity { name, pkey };
-        let issuer = Entity {
-            name: root_name,
-            pkey: root_pkey,
-        };
+    ) -> Result<CertParams, ErrorStack> {
+        let subject = Entity::copy(name, pkey)?;
+        let issuer = Entity::copy(issuer_name, issuer_pkey)?;
         Ok(CertParams {
             subject,
-            issuer: Some(issuer),
+            issuer,
             valid,
-            serial: BigNum::from_u32(10000)?,
+            ttl: None,
+            not_before: None,
+            not_after: None,
+            backdate: DEFAULT_BACKDATE,
+            clock: None,
+            serial: random_serial()?,
             sub_alt_names: Vec::with_capacity(0),
+            name_constraints: None,
+            path_len: None,
+            digest: SignatureDigest::default(),
+            comment: Some(DEFAULT_COMMENT.to_string()),
+            ext_key_usage: Vec::new(),
+            extensions: Vec::new(),
         })
     }
 
     pub fn server_cert_params(
-        name: &'a X509Name,
-        pkey: &'a PKey<Private>,
-        issuer_name: &'a X509Name,
-        issuer_pkey: &'a PKey<Private>,
+        name: &X509Name,
+        pkey: &PKey<Private>,
+        issuer_name: &X509Name,
+        issuer_pkey: &PKey<Private>,
         valid: u32,
-        sub_alt_names: &Vec<&'a str>,
-    ) -> Result<CertParams<'a>, ErrorStack> {
-        let common_name = format!(
-            "{}",
-            name.entries_by_nid(Nid::COMMONNAME)
-                .next()
-                .unwrap()
-                .data()
-                .as_utf8()?
-        );
-        let subject = Entity { name, pkey };
-        let issuer = Entity {
-            name: issuer_name,
-            pkey: issuer_pkey,
-        };
+        sub_alt_names: &Vec<&str>,
+    ) -> Result<CertParams> {
+        let common_name = name
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .ok_or(SimpleCAError::MissingCommonName)?
+            .data()
+            .to_string()?;
+        let subject = Entity::copy(name, pkey)?;
+        let issuer = Entity::copy(issuer_name, issuer_pkey)?;
         let mut sub_alt_names: Vec<String> = sub_alt_names.iter().map(|x| x.to_string()).collect();
         sub_alt_names.insert(0, common_name);
         Ok(CertParams {
             subject,
-            issuer: Some(issuer),
+            issuer,
             valid,
-            serial: create_serial_number(),
+            ttl: None,
+            not_before: None,
+            not_after: None,
+            backdate: DEFAULT_BACKDATE,
+            clock: None,
+            serial: random_serial()?,
             sub_alt_names,
+            name_constraints: None,
+            path_len: None,
+            digest: SignatureDigest::default(),
+            comment: Some(DEFAULT_COMMENT.to_string()),
+            ext_key_usage: Vec::new(),
+            extensions: Vec::new(),
+        })
+    }
+}
+
+impl CertParams {
+    /// Parameters for a device certificate, identified by its subject rather than SANs.
+    pub fn device_cert_params(
+        name: &X509Name,
+        pkey: &PKey<Private>,
+        issuer_name: &X509Name,
+        issuer_pkey: &PKey<Private>,
+        valid: u32,
+    ) -> Result<CertParams, ErrorStack> {
+        Ok(CertParams {
+            subject: Entity::copy(name, pkey)?,
+            issuer: Entity::copy(issuer_name, issuer_pkey)?,
+            valid,
+            ttl: None,
+            not_before: None,
+            not_after: None,
+            backdate: DEFAULT_BACKDATE,
+            clock: None,
+            serial: random_serial()?,
+            sub_alt_names: Vec::with_capacity(0),
+            name_constraints: None,
+            path_len: None,
+            digest: SignatureDigest::default(),
+            comment: Some(DEFAULT_COMMENT.to_string()),
+            ext_key_usage: Vec::new(),
+            extensions: Vec::new(),
+        })
+    }
+}
+
+impl CertParams<Public> {
+    /// Parameters for a server certificate whose private key is held elsewhere,
+    /// e.g. requested through a CSR.
+    pub fn csr_cert_params(
+        name: &X509Name,
+        pkey: &PKey<Public>,
+        issuer_name: &X509Name,
+        issuer_pkey: &PKey<Private>,
+        valid: u32,
+        sub_alt_names: &[&str],
+    ) -> Result<CertParams<Public>, ErrorStack> {
+        Ok(CertParams {
+            subject: Entity::copy(name, pkey)?,
+            issuer: Entity::copy(issuer_name, issuer_pkey)?,
+            valid,
+            ttl: None,
+            not_before: None,
+            not_after: None,
+            backdate: DEFAULT_BACKDATE,
+            clock: None,
+            serial: random_serial()?,
+            sub_alt_names: sub_alt_names.iter().map(|x| x.to_string()).collect(),
+            name_constraints: None,
+            path_len: None,
+            digest: SignatureDigest::default(),
+            comment: Some(DEFAULT_COMMENT.to_string()),
+            ext_key_usage: Vec::new(),
+            extensions: Vec::new(),
         })
     }
 }
+
+/// How the serial of a certificate is picked.
+pub enum SerialPolicy {
+    /// 159 random bits, the largest positive serial RFC 5280 allows.
+    Random,
+    /// A serial chosen by the caller, e.g. from a `SerialSource`.
+    Fixed(BigNum),
+}
+
+/// Owned, chainable parameters of a certificate, for callers that cannot keep
+/// the names and keys borrowed until it is signed:
+///
+/// ```no_run
+/// # use openssl::x509::X509NameBuilder;
+/// # use simple_ca::{create_root_ca, CertParams, KeyAlgorithm};
+/// let mut name = X509NameBuilder::new()?;
+/// name.append_entry_by_text("CN", "Example Root")?;
+/// let key = KeyAlgorithm::EcdsaP256.generate()?;
+/// let params = CertParams::builder(name.build(), key)
+///     .valid_days(3650)
+///     .path_len(1)
+///     .self_signed()?
+///     .build()?;
+/// let root = create_root_ca(&params)?;
+/// # Ok::<(), simple_ca::SimpleCAError>(())
+/// ```
+pub struct CertParamsBuilder<T = Private> {
+    subject: Entity<T>,
+    issuer: Option<Entity>,
+    valid: u32,
+    ttl: Option<Duration>,
+    not_before: Option<i64>,
+    not_after: Option<i64>,
+    backdate: Duration,
+    clock: Option<SystemTime>,
+    serial: SerialPolicy,
+    sub_alt_names: Vec<String>,
+    name_constraints: Option<NameConstraints>,
+    path_len: Option<u32>,
+    digest: SignatureDigest,
+    comment: Option<String>,
+    ext_key_usage: Vec<ExtKeyUsage>,
+    extensions: Vec<X509Extension>,
+}
+
+impl<T: HasPublic> CertParamsBuilder<T> {
+    pub fn new(name: X509Name, pkey: PKey<T>) -> CertParamsBuilder<T> {
+        CertParamsBuilder {
+            subject: Entity { name, pkey },
+            issuer: None,
+            valid: DEFAULT_SERVER_DAYS,
+            ttl: None,
+            not_before: None,
+            not_after: None,
+            backdate: DEFAULT_BACKDATE,
+            clock: None,
+            serial: SerialPolicy::Random,
+            sub_alt_names: Vec::new(),
+            name_constraints: None,
+            path_len: None,
+            digest: SignatureDigest::default(),
+            comment: Some(DEFAULT_COMMENT.to_string()),
+            ext_key_usage: Vec::new(),
+            extensions: Vec::new(),
+        }
+    }
+
+    /// The CA signing the certificate.
+    pub fn issuer(mut self, name: X509Name, pkey: PKey<Private>) -> Self {
+        self.issuer = Some(Entity { name, pkey });
+        self
+    }
+
+    pub fn valid_days(mut self, days: u32) -> Self {
+        self.valid = days;
+        self
+    }
+
+    /// A lifetime shorter than a day, overriding `valid_days`.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Explicit start as a Unix timestamp.
+    pub fn not_before(mut self, not_before: i64) -> Self {
+        self.not_before = Some(not_before);
+        self
+    }
+
+    /// Explicit end as a Unix timestamp, overriding the lifetime.
+    pub fn not_after(mut self, not_after: i64) -> Self {
+        self.not_after = Some(not_after);
+        self
+    }
+
+    /// How far before the time of signing the certificate starts, an hour
+    /// by default.
+    pub fn backdate(mut self, backdate: Duration) -> Self {
+        self.backdate = backdate;
+        self
+    }
+
+    /// The time of signing instead of the system clock.
+    pub fn clock(mut self, now: SystemTime) -> Self {
+        self.clock = Some(now);
+        self
+    }
+
+    pub fn serial(mut self, serial: SerialPolicy) -> Self {
+        self.serial = serial;
+        self
+    }
+
+    /// DNS names, IP addresses or, for client certificates, email addresses.
+    pub fn sub_alt_names<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.sub_alt_names = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn name_constraints(mut self, constraints: NameConstraints) -> Self {
+        self.name_constraints = Some(constraints);
+        self
+    }
+
+    pub fn path_len(mut self, path_len: u32) -> Self {
+        self.path_len = Some(path_len);
+        self
+    }
+
+    pub fn digest(mut self, digest: SignatureDigest) -> Self {
+        self.digest = digest;
+        self
+    }
+
+    /// Netscape comment of server certificates, left out when `None`.
+    pub fn comment(mut self, comment: Option<String>) -> Self {
+        self.comment = comment;
+        self
+    }
+
+    /// Extended key usages of a client certificate.
+    pub fn ext_key_usage(mut self, usages: impl IntoIterator<Item = ExtKeyUsage>) -> Self {
+        self.ext_key_usage = usages.into_iter().collect();
+        self
+    }
+
+    /// An extension added after those of the kind of certificate.
+    pub fn extension(mut self, extension: X509Extension) -> Self {
+        self.extensions.push(extension);
+        self
+    }
+
+    /// The parameters for `certs::create_*`; fails without an issuer.
+    pub fn build(self) -> Result<CertParams<T>> {
+        let issuer = self.issuer.ok_or(SimpleCAError::GenericError {
+            msg: "A certificate needs an issuer, or to be self-signed.",
+        })?;
+        let serial = match self.serial {
+            SerialPolicy::Random => random_serial()?,
+            SerialPolicy::Fixed(serial) => serial,
+        };
+        Ok(CertParams {
+            subject: self.subject,
+            issuer,
+            valid: self.valid,
+            ttl: self.ttl,
+            not_before: self.not_before,
+            not_after: self.not_after,
+            backdate: self.backdate,
+            clock: self.clock,
+            serial,
+            sub_alt_names: self.sub_alt_names,
+            name_constraints: self.name_constraints,
+            path_len: self.path_len,
+            digest: self.digest,
+            comment: self.comment,
+            ext_key_usage: self.ext_key_usage,
+            extensions: self.extensions,
+        })
+    }
+}
+
+impl CertParamsBuilder {
+    /// Signs the certificate with its own key, as for a root.
+    pub fn self_signed(mut self) -> Result<Self, ErrorStack> {
+        self.issuer = Some(Entity::copy(&self.subject.name, &self.subject.pkey)?);
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ttl() {
+        assert_eq!(parse_ttl("90m").unwrap(), Duration::from_secs(90 * 60));
+        assert_eq!(parse_ttl("6h").unwrap(), Duration::from_secs(6 * 60 * 60));
+        assert_eq!(
+            parse_ttl("2d").unwrap(),
+            Duration::from_secs(2 * 24 * 60 * 60)
+        );
+        assert!(parse_ttl("").is_err());
+        assert!(parse_ttl("0m").is_err());
+        assert!(parse_ttl("6w").is_err());
+        assert!(parse_ttl("999999999999999d").is_err());
+    }
+
+    #[test]
+    fn test_builder() {
+        use crate::certs::create_root_ca;
+        use crate::keys::KeyAlgorithm;
+        use openssl::x509::X509NameBuilder;
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "builder test").unwrap();
+        let key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let builder = CertParams::builder(name.build(), key)
+            .valid_days(10)
+            .serial(SerialPolicy::Fixed(BigNum::from_u32(42).unwrap()));
+        assert!(CertParams::builder(
+            builder.subject.name.to_owned().unwrap(),
+            builder.subject.pkey.clone()
+        )
+        .build()
+        .is_err());
+
+        let params = builder.self_signed().unwrap().build().unwrap();
+        let cert = create_root_ca(&params).unwrap();
+        assert_eq!(
+            cert.serial_number().to_bn().unwrap(),
+            BigNum::from_u32(42).unwrap()
+        );
+        assert!(cert.verify(&params.subject().pkey).unwrap());
+    }
+
+    #[test]
+    fn test_backdate() {
+        use crate::keys::KeyAlgorithm;
+        use openssl::x509::X509NameBuilder;
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "backdate test").unwrap();
+        let name = name.build();
+        let key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let mut params = CertParams::root_ca_params(&name, &key, 1).unwrap();
+        // Seconds from `valid_from` to `valid_to`, allowing the clock to tick
+        // between them.
+        let span = |params: &CertParams| {
+            let diff = params
+                .valid_from()
+                .unwrap()
+                .diff(&params.valid_to().unwrap())
+                .unwrap();
+            diff.days * 86_400 + diff.secs
+        };
+        let backdate = DEFAULT_BACKDATE.as_secs() as i32;
+        assert!((86_400 + backdate..=86_401 + backdate).contains(&span(&params)));
+
+        params.backdate = Duration::ZERO;
+        assert!((86_400..=86_401).contains(&span(&params)));
+    }
+}
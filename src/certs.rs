@@ -1,25 +1,40 @@
-use anyhow::Result;
+use std::net::IpAddr;
 
-use openssl::hash::MessageDigest;
+use openssl::hash::{hash, MessageDigest};
 use openssl::nid::Nid;
+use openssl::pkey::{HasPublic, PKeyRef};
 use openssl::x509::extension;
-use openssl::x509::{X509Builder, X509Extension, X509Ref, X509};
+use openssl::x509::{X509Builder, X509Extension, X509Ref, X509v3Context, X509};
 
 use crate::cert_params::CertParams;
+use crate::err::{Result, SimpleCAError};
 
-pub fn create_cert<EXT>(params: &CertParams, ext: EXT) -> Result<X509>
+/// Hex encoded SHA-256 digest of the DER encoded public key.
+pub fn pubkey_fingerprint<T: HasPublic>(pkey: &PKeyRef<T>) -> Result<String> {
+    let digest = hash(MessageDigest::sha256(), &pkey.public_key_to_der()?)?;
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Hex encoded SHA-256 digest of the DER encoded certificate.
+pub fn cert_fingerprint(cert: &X509Ref) -> Result<String> {
+    let digest = cert.digest(MessageDigest::sha256())?;
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+pub fn create_cert<T, EXT>(params: &CertParams<T>, ext: EXT) -> Result<X509>
 where
+    T: HasPublic,
     EXT: Fn(&X509Builder) -> Result<Vec<X509Extension>>,
 {
     let mut builder = X509Builder::new()?;
 
     builder.set_version(2)?;
 
-    let serial = params.serial();
+    let serial = params.serial()?;
     builder.set_serial_number(&serial)?;
 
-    builder.set_not_before(&params.valid_from())?;
-    builder.set_not_after(&params.valid_to())?;
+    builder.set_not_before(params.valid_from()?.as_ref())?;
+    builder.set_not_after(params.valid_to()?.as_ref())?;
 
     let subject = params.subject();
     builder.set_subject_name(&subject.name)?;
@@ -32,12 +47,94 @@ where
     for extension in extensions.drain(..) {
         builder.append_extension(extension)?;
     }
+    for extension in params.extensions() {
+        builder.append_extension2(extension)?;
+    }
 
-    builder.sign(&issuer.pkey, MessageDigest::sha256())?;
+    builder.sign(&issuer.pkey, params.digest.message_digest())?;
 
     Ok(builder.build())
 }
 
+/// Ensures a certificate built from `params` does not outlive `issuer`.
+fn check_issuer_validity<T: HasPublic>(params: &CertParams<T>, issuer: &X509Ref) -> Result<()> {
+    let not_after = params.valid_to()?;
+    if not_after.as_ref() > issuer.not_after() {
+        Err(SimpleCAError::OutlivesIssuer {
+            not_after: not_after.to_string(),
+            issuer_not_after: issuer.not_after().to_string(),
+        })?;
+    }
+    Ok(())
+}
+
+/// The authority key identifier of a certificate signed by a CA, holding only
+/// the CA's key ID. Leaving out the CA's issuer and serial lets a
+/// cross-signed copy of the CA validate the same certificates.
+fn authority_key_id(ctx: &X509v3Context) -> Result<X509Extension> {
+    Ok(extension::AuthorityKeyIdentifier::new()
+        .keyid(true)
+        .build(ctx)?)
+}
+
+/// An extended key usage, the purposes a certificate may be used for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtKeyUsage {
+    ServerAuth,
+    ClientAuth,
+    CodeSigning,
+    EmailProtection,
+    TimeStamping,
+    OcspSigning,
+    /// Any other purpose as a dotted OID, e.g. `1.3.6.1.5.5.7.3.17` for IKE.
+    Other(String),
+}
+
+fn extended_key_usage(usages: &[ExtKeyUsage], critical: bool) -> Result<X509Extension> {
+    let mut eku = extension::ExtendedKeyUsage::new();
+    if critical {
+        eku.critical();
+    }
+    for usage in usages {
+        match usage {
+            ExtKeyUsage::ServerAuth => eku.server_auth(),
+            ExtKeyUsage::ClientAuth => eku.client_auth(),
+            ExtKeyUsage::CodeSigning => eku.code_signing(),
+            ExtKeyUsage::EmailProtection => eku.email_protection(),
+            ExtKeyUsage::TimeStamping => eku.time_stamping(),
+            ExtKeyUsage::OcspSigning => eku.other("OCSPSigning"),
+            ExtKeyUsage::Other(oid) => eku.other(oid),
+        };
+    }
+    Ok(eku.build()?)
+}
+
+/// The SAN extension of `names`, telling IP addresses and email addresses
+/// from DNS names; `None` without names.
+fn sub_alt_names(names: &[String], ctx: &X509v3Context) -> Result<Option<X509Extension>> {
+    if names.is_empty() {
+        return Ok(None);
+    }
+    let mut sub_alt_name = extension::SubjectAlternativeName::new();
+    for name in names {
+        if name.parse::<IpAddr>().is_ok() {
+            sub_alt_name.ip(name);
+        } else if name.contains('@') {
+            sub_alt_name.email(name);
+        } else {
+            sub_alt_name.dns(name);
+        }
+    }
+    Ok(Some(sub_alt_name.build(ctx)?))
+}
+
+fn name_constraints<T>(params: &CertParams<T>) -> Result<Option<X509Extension>> {
+    match &params.name_constraints {
+        Some(constraints) if !constraints.is_empty() => Ok(Some(constraints.build()?)),
+        _ => Ok(None),
+    }
+}
+
 pub fn create_root_ca(params: &CertParams) -> Result<X509> {
     let cert = create_cert(params, |builder| {
         let ctx = builder.x509v3_context(None, None);
@@ -51,48 +148,62 @@ pub fn create_root_ca(params: &CertParams) -> Result<X509> {
         let auth_key_id = extension::AuthorityKeyIdentifier::new()
             .keyid(true)
             .build(&ctx)?;
-        let bc = extension::BasicConstraints::new().critical().ca().build()?;
+        let mut bc = extension::BasicConstraints::new();
+        bc.critical().ca();
+        if let Some(path_len) = params.path_len {
+            bc.pathlen(path_len);
+        }
+        let bc = bc.build()?;
         let key_usage = extension::KeyUsage::new()
             .digital_signature()
             .key_cert_sign()
             .crl_sign()
             .build()?;
-        Ok(vec![sub_key_id, auth_key_id, bc, key_usage])
+        let mut extensions = vec![sub_key_id, auth_key_id, bc, key_usage];
+        extensions.extend(name_constraints(params)?);
+        Ok(extensions)
     })
 }
 
-pub fn create_intermediate_ca(params: &CertParams, root_ca_cert: &X509Ref) -> Result<X509> {
+/// Creates a CA certificate signed by `issuer_cert`, which is the root or,
+/// for deeper hierarchies, another intermediate.
+pub fn create_intermediate_ca<T: HasPublic>(
+    params: &CertParams<T>,
+    issuer_cert: &X509Ref,
+) -> Result<X509> {
+    check_issuer_validity(params, issuer_cert)?;
     create_cert(params, |builder| {
-        let ctx = builder.x509v3_context(Some(root_ca_cert), None);
+        let ctx = builder.x509v3_context(Some(issuer_cert), None);
         let sub_key_id = extension::SubjectKeyIdentifier::new().build(&ctx)?;
-        let auth_key_id = extension::AuthorityKeyIdentifier::new()
-            .keyid(true)
-            .issuer(true)
-            .build(&ctx)?;
-        let bc = extension::BasicConstraints::new()
-            //.critical()
-            .ca()
-            //.pathlen(0)
-            .build()?;
+        let auth_key_id = authority_key_id(&ctx)?;
+        let mut bc = extension::BasicConstraints::new();
+        bc.ca();
+        if let Some(path_len) = params.path_len {
+            bc.pathlen(path_len);
+        }
+        let bc = bc.build()?;
         let key_usage = extension::KeyUsage::new()
             .digital_signature()
             .key_cert_sign()
             .crl_sign()
             .build()?;
-        Ok(vec![sub_key_id, auth_key_id, bc, key_usage])
+        let mut extensions = vec![sub_key_id, auth_key_id, bc, key_usage];
+        extensions.extend(name_constraints(params)?);
+        Ok(extensions)
     })
 }
 
-pub fn create_server_cert(params: &CertParams, intermediate_cert: &X509Ref) -> Result<X509> {
+pub fn create_server_cert<T: HasPublic>(
+    params: &CertParams<T>,
+    intermediate_cert: &X509Ref,
+) -> Result<X509> {
+    check_issuer_validity(params, intermediate_cert)?;
     create_cert(params, |builder| {
         let ctx = builder.x509v3_context(Some(intermediate_cert), None);
 
         let sub_key_id = extension::SubjectKeyIdentifier::new().build(&ctx)?;
 
-        let auth_key_id = extension::AuthorityKeyIdentifier::new()
-            .keyid(true)
-            .issuer(true)
-            .build(&ctx)?;
+        let auth_key_id = authority_key_id(&ctx)?;
 
         let bc = extension::BasicConstraints::new().build()?;
 
@@ -105,68 +216,146 @@ pub fn create_server_cert(params: &CertParams, intermediate_cert: &X509Ref) -> R
 
         let extended_key_usage = extension::ExtendedKeyUsage::new().server_auth().build()?;
 
+        #[allow(deprecated)]
         let netscape_cert_type =
             X509Extension::new_nid(None, None, Nid::NETSCAPE_CERT_TYPE, "SSL Server")?;
 
-        let netscape_comment = X509Extension::new_nid(
-            None,
-            None,
-            Nid::NETSCAPE_COMMENT,
-            "Simple CA Generated Server Certificate",
-        )?;
+        let mut v3_extensions = vec![sub_key_id, auth_key_id, bc, netscape_cert_type];
+
+        if let Some(comment) = &params.comment {
+            #[allow(deprecated)]
+            let netscape_comment =
+                X509Extension::new_nid(None, None, Nid::NETSCAPE_COMMENT, comment)?;
+            v3_extensions.push(netscape_comment);
+        }
+
+        v3_extensions.push(key_usage);
+        v3_extensions.push(extended_key_usage);
+
+        v3_extensions.extend(sub_alt_names(&params.sub_alt_names, &ctx)?);
+
+        Ok(v3_extensions)
+    })
+}
 
-        let mut v3_extensions = vec![
+/// Creates a client certificate signed by `issuer_cert`, for the extended key
+/// usages of `params` or only clientAuth when it has none. Its SANs may hold
+/// email addresses besides DNS names and IP addresses.
+pub fn create_client_cert<T: HasPublic>(
+    params: &CertParams<T>,
+    issuer_cert: &X509Ref,
+) -> Result<X509> {
+    check_issuer_validity(params, issuer_cert)?;
+    let usages = if params.ext_key_usage.is_empty() {
+        &[ExtKeyUsage::ClientAuth][..]
+    } else {
+        &params.ext_key_usage[..]
+    };
+    create_cert(params, |builder| {
+        let ctx = builder.x509v3_context(Some(issuer_cert), None);
+        let sub_key_id = extension::SubjectKeyIdentifier::new().build(&ctx)?;
+        let auth_key_id = authority_key_id(&ctx)?;
+        let bc = extension::BasicConstraints::new().build()?;
+        let key_usage = extension::KeyUsage::new()
+            .critical()
+            .digital_signature()
+            .key_encipherment()
+            .build()?;
+        let mut extensions = vec![
             sub_key_id,
             auth_key_id,
             bc,
-            netscape_cert_type,
-            netscape_comment,
             key_usage,
-            extended_key_usage,
+            extended_key_usage(usages, false)?,
         ];
+        extensions.extend(sub_alt_names(&params.sub_alt_names, &ctx)?);
+        Ok(extensions)
+    })
+}
 
-        if params.sub_alt_names.len() > 0 {
-            let mut sub_alt_name = extension::SubjectAlternativeName::new();
-            params.sub_alt_names.iter().for_each(|name| {
-                sub_alt_name.dns(name);
-            });
-            v3_extensions.push(sub_alt_name.build(&ctx)?);
-        }
-
-        Ok(v3_extensions)
+/// Creates the certificate of a signer like an OCSP responder, which only
+/// signs with its key, for the extended key usages of `params`. The profile
+/// adds its own extensions to `params`.
+pub fn create_signer_cert<T: HasPublic>(
+    params: &CertParams<T>,
+    issuer_cert: &X509Ref,
+    critical_usage: bool,
+) -> Result<X509> {
+    check_issuer_validity(params, issuer_cert)?;
+    create_cert(params, |builder| {
+        let ctx = builder.x509v3_context(Some(issuer_cert), None);
+        let sub_key_id = extension::SubjectKeyIdentifier::new().build(&ctx)?;
+        let auth_key_id = authority_key_id(&ctx)?;
+        let bc = extension::BasicConstraints::new().build()?;
+        let key_usage = extension::KeyUsage::new()
+            .critical()
+            .digital_signature()
+            .build()?;
+        Ok(vec![
+            sub_key_id,
+            auth_key_id,
+            bc,
+            key_usage,
+            extended_key_usage(&params.ext_key_usage, critical_usage)?,
+        ])
     })
 }
 
+/// Creates a client certificate for a device, identified by its subject.
+pub fn create_device_cert(params: &CertParams, intermediate_cert: &X509Ref) -> Result<X509> {
+    create_client_cert(params, intermediate_cert)
+}
+
+/// Issuers for tests that sign under a CA of their own.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use openssl::pkey::{PKey, Private};
+    use openssl::x509::{X509Name, X509};
+
+    use super::create_root_ca;
+    use crate::cert_params::CertParams;
+    use crate::keys::KeyAlgorithm;
+    use crate::name::Name;
+
+    /// A self-signed ECDSA root valid for 30 days, with its name and key.
+    pub(crate) fn test_root_ca() -> (X509Name, PKey<Private>, X509) {
+        test_root_ca_with(|_| {})
+    }
+
+    /// `test_root_ca` with its parameters adjusted by `configure`.
+    pub(crate) fn test_root_ca_with(
+        configure: impl FnOnce(&mut CertParams),
+    ) -> (X509Name, PKey<Private>, X509) {
+        let name = Name::new("Test CA").to_x509_name().unwrap();
+        let key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let mut params = CertParams::root_ca_params(&name, &key, 30).unwrap();
+        configure(&mut params);
+        let cert = create_root_ca(&params).unwrap();
+        (name, key, cert)
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
     use openssl::pkey::PKey;
     use openssl::rsa::Rsa;
-    use std::fs::File;
-    use std::io::Write;
 
+    use super::fixtures::{test_root_ca, test_root_ca_with};
     use super::*;
+    use crate::keys::KeyAlgorithm;
     use crate::CertParams;
     use crate::Name;
     use openssl::x509::X509;
 
-    macro_rules! write_file_unwrapped {
-        ($content:expr, $dest:expr) => {{
-            let mut file = File::create($dest).unwrap();
-            file.write_all($content).unwrap();
-        }};
-    }
-
     #[test]
     fn test_create_cert_authorities() {
-        let name = Name {
-            country: "AU".to_string(),
-            province: "TAS".to_string(),
-            locality: "Hobart".to_string(),
-            org: "".to_string(),
-            org_unit: "".to_string(),
-            common_name: "ROOT CA".to_string(),
-        };
+        let name = Name::builder()
+            .country("AU")
+            .province("TAS")
+            .locality("Hobart")
+            .common_name("ROOT CA")
+            .build();
 
         let root_rsa = Rsa::generate(4096).unwrap();
         let root_key = PKey::from_rsa(root_rsa).unwrap();
@@ -174,12 +363,6 @@ mod tests {
         let ca_params = CertParams::root_ca_params(&root_name, &root_key, 7200).unwrap();
         let root_ca: X509 = create_root_ca(&ca_params).unwrap();
 
-        write_file_unwrapped!(
-            &root_key.private_key_to_pem_pkcs8().unwrap(),
-            "target/ca.key.pem"
-        );
-        write_file_unwrapped!(&root_ca.to_pem().unwrap(), "target/ca.cert.pem");
-
         let intermediate_rsa = Rsa::generate(4096).unwrap();
         let intermediate_key = PKey::from_rsa(intermediate_rsa).unwrap();
         let intermediate_name = name.copy("Intermediate CA").to_x509_name().unwrap();
@@ -193,15 +376,6 @@ mod tests {
         .unwrap();
         let intermediate_ca = create_intermediate_ca(&intermediate_params, &root_ca).unwrap();
 
-        write_file_unwrapped!(
-            &intermediate_key.private_key_to_pem_pkcs8().unwrap(),
-            "target/intermediate.key.pem"
-        );
-        write_file_unwrapped!(
-            &intermediate_ca.to_pem().unwrap(),
-            "target/intermediate.cert.pem"
-        );
-
         let server_rsa = Rsa::generate(2048).unwrap();
         let server_key = PKey::from_rsa(server_rsa).unwrap();
         let server_name = name.copy("*.example.com").to_x509_name().unwrap();
@@ -218,12 +392,49 @@ mod tests {
         .unwrap();
         let server_cert = create_server_cert(&server_params, &intermediate_ca).unwrap();
         // let server_cert = create_server_cert(&server_params, &root_ca).unwrap();
+        assert!(server_cert.verify(&intermediate_key).unwrap());
+    }
 
-        write_file_unwrapped!(
-            &server_key.private_key_to_pem_pkcs8().unwrap(),
-            "target/server.key.pem"
-        );
-        write_file_unwrapped!(&server_cert.to_pem().unwrap(), "target/server.cert.pem");
+    #[test]
+    fn test_client_cert() {
+        let (ca_name, ca_key, ca) = test_root_ca();
+        let key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let params = CertParams::builder(Name::new("alice").to_x509_name().unwrap(), key)
+            .issuer(ca_name, ca_key.clone())
+            .valid_days(7)
+            .ext_key_usage([ExtKeyUsage::ClientAuth, ExtKeyUsage::EmailProtection])
+            .sub_alt_names(["alice@example.com"])
+            .build()
+            .unwrap();
+        let cert = create_client_cert(&params, &ca).unwrap();
+        assert!(cert.verify(&ca_key).unwrap());
+        let text = String::from_utf8(cert.to_text().unwrap()).unwrap();
+        assert!(text.contains("TLS Web Client Authentication, E-mail Protection"));
+        assert!(text.contains("email:alice@example.com"));
     }
 
+    #[test]
+    fn test_path_len() {
+        let (root_name, root_key, root_ca) = test_root_ca_with(|params| params.path_len = Some(1));
+        let text = String::from_utf8(root_ca.to_text().unwrap()).unwrap();
+        assert!(text.contains("CA:TRUE, pathlen:1"));
+
+        let name = Name::new("Intermediate CA").to_x509_name().unwrap();
+        let key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let mut params =
+            CertParams::intermediate_ca_params(&name, &key, &root_name, &root_key, 1).unwrap();
+        let text = String::from_utf8(
+            create_intermediate_ca(&params, &root_ca)
+                .unwrap()
+                .to_text()
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(text.contains("CA:TRUE\n"));
+
+        params.path_len = Some(0);
+        let intermediate_ca = create_intermediate_ca(&params, &root_ca).unwrap();
+        let text = String::from_utf8(intermediate_ca.to_text().unwrap()).unwrap();
+        assert!(text.contains("CA:TRUE, pathlen:0"));
+    }
 }
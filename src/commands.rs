@@ -1,21 +1,42 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io;
-use std::io::{Read, Write};
-use std::path::Path;
+use std::io::Read;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
-
-use openssl::pkey::{PKey, Private};
-use openssl::rsa::Rsa;
+use openssl::nid::Nid;
+use openssl::pkey::{HasPublic, PKey, Private, Public};
 use openssl::x509::{X509Name, X509};
 
+use crate::backend::KeyBackend;
 use crate::cert_params::CertParams;
 use crate::certs::{create_intermediate_ca, create_root_ca, create_server_cert};
-use crate::conf::{CertAuthConf, Conf};
+use crate::conf::{config_dir, file_in_conf, CertAuthConf, Conf, ValidityConf};
+use crate::ct::issue_precert_pair;
+use crate::datetime::{asn1_to_unix, format_rfc3339};
+use crate::deterministic::Sources;
+use crate::err::{Result, SimpleCAError};
+use crate::guard::RepoGuard;
+use crate::hardening::{generate_dhparam, generate_ticket_key, DHPARAM_BITS};
+use crate::hooks::run_post_issue_hooks;
+use crate::index::record_issued;
+use crate::inspect::{format_name, CertInfo};
+use crate::keys::KeyAlgorithm;
+use crate::lock::StoreLock;
+use crate::matching::key_matches_cert;
 use crate::name::Name;
+use crate::offline::is_root_offline;
+use crate::out_dir::OutDir;
+use crate::passphrase::{private_key_to_pem, read_private_key};
+use crate::paths::server_files;
+use crate::policy::{check_policy, normalize_name};
+use crate::receipt::sign_receipt;
 use crate::save_file;
+use crate::store::{self, Role};
+use crate::suggest::{did_you_mean, hint};
+use crate::summary::{IssuanceSummary, PairReceipt};
 
-fn read_file(path: &Path) -> Result<Vec<u8>, io::Error> {
+pub(crate) fn read_file(path: &Path) -> Result<Vec<u8>, io::Error> {
     let mut content = Vec::new();
     let mut f = File::open(path)?;
 
@@ -23,17 +44,48 @@ fn read_file(path: &Path) -> Result<Vec<u8>, io::Error> {
     Ok(content)
 }
 
-fn get_pkey(generate: bool, path: &Path, bits: u32) -> Result<PKey<Private>> {
+/// Path of the root certificate, failing with `CaMissing` before a CA was
+/// created.
+pub(crate) fn existing_ca_cert() -> Result<PathBuf> {
+    let path = CertAuthConf::ca_cert()?;
+    if !path.exists() {
+        Err(SimpleCAError::CaMissing { dir: config_dir()? })?;
+    }
+    Ok(path)
+}
+
+pub(crate) fn read_root_cert() -> Result<X509> {
+    Ok(X509::from_pem(&read_file(&existing_ca_cert()?)?)?)
+}
+
+fn get_pkey(
+    generate: bool,
+    path: &Path,
+    algorithm: KeyAlgorithm,
+    sources: &Sources,
+) -> Result<PKey<Private>> {
     let pkey = if generate {
-        let rsa = Rsa::generate(bits)?;
-        PKey::from_rsa(rsa)?
+        sources.new_key(algorithm)?
     } else {
-        let pem = read_file(path)?;
-        PKey::private_key_from_pem(&pem)?
+        read_private_key(path)?
     };
     Ok(pkey)
 }
 
+/// The key of a CA: the token key, or for a file a new key when `generate`
+/// is set, as a token key is never generated here.
+fn get_ca_pkey(
+    generate: bool,
+    backend: &KeyBackend,
+    algorithm: KeyAlgorithm,
+    sources: &Sources,
+) -> Result<PKey<Private>> {
+    match backend {
+        KeyBackend::File(_) if generate => sources.new_key(algorithm),
+        _ => backend.load(),
+    }
+}
+
 fn get_x509<T>(generate: bool, path: &Path, create: T) -> Result<X509>
 where
     T: Fn() -> Result<X509>,
@@ -49,37 +101,93 @@ where
     Ok(x509)
 }
 
-pub fn load_ca(reset: bool, verbose: bool) -> Result<(X509, PKey<Private>, X509Name)> {
+pub fn load_ca(
+    reset: bool,
+    validity: &ValidityConf,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<(X509, PKey<Private>, X509Name)> {
+    let _lock = StoreLock::acquire()?;
     let conf = Conf::load()?;
+    let validity = validity.or(&conf.validity());
+    let keys = conf.keys();
+    let strategy = conf.serials().strategy();
 
+    let ca_key = KeyBackend::ca(&conf)?;
     let ca_key_path = CertAuthConf::ca_key()?;
     let ca_cert_path = CertAuthConf::ca_cert()?;
 
+    let intermediate_key = KeyBackend::intermediate(&conf)?;
     let intermediate_key_path = CertAuthConf::intermediate_key()?;
     let intermediate_cert_path = CertAuthConf::intermediate_cert()?;
 
+    let offline = is_root_offline()?;
     let mut ca_create = false;
     let mut intermediate_create = false;
 
-    if reset || !ca_key_path.exists() || !ca_cert_path.exists() {
+    if reset || (!offline && !ca_key.exists()) || !ca_cert_path.exists() {
         ca_create = true;
         intermediate_create = true;
-    } else if !intermediate_key_path.exists() || !intermediate_cert_path.exists() {
+    } else if !intermediate_key.exists() || !intermediate_cert_path.exists() {
         intermediate_create = true;
     }
+    if offline && intermediate_create {
+        Err(SimpleCAError::RootOffline)?;
+    }
 
-    let ca_pkey = get_pkey(ca_create, &ca_key_path, 4096)?;
+    if reset {
+        guard.check_replace(&[
+            &ca_key_path,
+            &ca_cert_path,
+            &intermediate_key_path,
+            &intermediate_cert_path,
+        ])?;
+    }
     if ca_create {
+        guard.check(&[&ca_key_path])?;
+    }
+    if intermediate_create {
+        guard.check(&[&intermediate_key_path])?;
+    }
+
+    // The root key is only read to sign, so routine issuance never touches it.
+    let ca_pkey = if intermediate_create {
+        Some(get_ca_pkey(
+            ca_create,
+            &ca_key,
+            keys.ca_algorithm(),
+            sources,
+        )?)
+    } else {
+        None
+    };
+    if let (true, Some(ca_pkey), Some(_)) = (ca_create, &ca_pkey, ca_key.path()) {
         write_file!(
-            &ca_pkey.private_key_to_pem_pkcs8()?,
+            &private_key_to_pem(ca_pkey, keys.encrypt_ca())?,
             &ca_key_path,
             verbose,
             "Saved CA private key at: {:?}"
         );
+        store::record(Role::CaKey, None, &ca_key_path, None)?;
     }
-    let ca_name = conf.ca().ca_name().to_x509_name()?;
-    let ca_params = CertParams::root_ca_params(&ca_name, &ca_pkey, 7200)?;
-    let ca = get_x509(ca_create, &ca_cert_path, || create_root_ca(&ca_params))?;
+    let name_order = conf.name().order();
+    let ca_name = conf
+        .ca()
+        .ca_name(&conf.branding())
+        .to_x509_name_ordered(&name_order)?;
+    if ca_create {
+        store::archive_cert(Role::ArchivedCaCert, &ca_cert_path)?;
+    }
+    let ca = get_x509(ca_create, &ca_cert_path, || {
+        let ca_pkey = ca_pkey.as_ref().ok_or(SimpleCAError::RootOffline)?;
+        let mut ca_params = CertParams::root_ca_params(&ca_name, ca_pkey, validity.ca_days())?;
+        ca_params.name_constraints = conf.name_constraints();
+        ca_params.backdate = validity.backdate()?;
+        ca_params.digest = keys.digest();
+        sources.configure(&mut ca_params, strategy)?;
+        create_root_ca(&ca_params)
+    })?;
     if ca_create {
         write_file!(
             &ca.to_pem()?,
@@ -87,27 +195,53 @@ pub fn load_ca(reset: bool, verbose: bool) -> Result<(X509, PKey<Private>, X509N
             verbose,
             "Saved CA certificate at: {:?}"
         );
+        store::record(Role::CaCert, None, &ca_cert_path, Some(&ca))?;
+        record_issued(
+            &ca,
+            ca_key.path().map(PathBuf::as_path),
+            Some(&ca_cert_path),
+            sources.now(),
+        )?;
     }
+    // The stored CA may predate the current config, so its own subject is the issuer.
+    let ca_name = ca.subject_name().to_owned()?;
 
-    let intermediate_pkey = get_pkey(intermediate_create, &intermediate_key_path, 4096)?;
-    let intermediate_name = conf.ca().intermediate_name().to_x509_name()?;
+    let intermediate_pkey = get_ca_pkey(
+        intermediate_create,
+        &intermediate_key,
+        keys.ca_algorithm(),
+        sources,
+    )?;
+    let intermediate_name = conf
+        .ca()
+        .intermediate_name(&conf.branding())
+        .to_x509_name_ordered(&name_order)?;
     let intermediate = {
         if intermediate_create {
-            write_file!(
-                &intermediate_pkey.private_key_to_pem_pkcs8()?,
-                &intermediate_key_path,
-                verbose,
-                "Saved Intermediate private key at: {:?}"
-            );
+            if intermediate_key.path().is_some() {
+                write_file!(
+                    &private_key_to_pem(&intermediate_pkey, keys.encrypt_ca())?,
+                    &intermediate_key_path,
+                    verbose,
+                    "Saved Intermediate private key at: {:?}"
+                );
+                store::record(Role::IntermediateKey, None, &intermediate_key_path, None)?;
+            }
+            store::archive_cert(Role::ArchivedIntermediateCert, &intermediate_cert_path)?;
         }
-        let intermediate_params = CertParams::intermediate_ca_params(
-            &intermediate_name,
-            &intermediate_pkey,
-            &ca_name,
-            &ca_pkey,
-            3600,
-        )?;
         let intermediate = get_x509(intermediate_create, &intermediate_cert_path, || {
+            let mut intermediate_params = CertParams::intermediate_ca_params(
+                &intermediate_name,
+                &intermediate_pkey,
+                &ca_name,
+                ca_pkey.as_ref().ok_or(SimpleCAError::RootOffline)?,
+                validity.intermediate_days(),
+            )?;
+            intermediate_params.name_constraints = conf.name_constraints();
+            intermediate_params.path_len = conf.ca().intermediate_path_len();
+            intermediate_params.backdate = validity.backdate()?;
+            intermediate_params.digest = keys.digest();
+            sources.configure(&mut intermediate_params, strategy)?;
             create_intermediate_ca(&intermediate_params, &ca)
         })?;
         if intermediate_create {
@@ -117,32 +251,848 @@ pub fn load_ca(reset: bool, verbose: bool) -> Result<(X509, PKey<Private>, X509N
                 verbose,
                 "Saved intermediate certicate at: {:?}"
             );
+            store::record(
+                Role::IntermediateCert,
+                None,
+                &intermediate_cert_path,
+                Some(&ca),
+            )?;
+            record_issued(
+                &intermediate,
+                intermediate_key.path().map(PathBuf::as_path),
+                Some(&intermediate_cert_path),
+                sources.now(),
+            )?;
         }
         intermediate
     };
 
+    let intermediate_name = intermediate.subject_name().to_owned()?;
     Ok((intermediate, intermediate_pkey, intermediate_name))
 }
 
+/// Installs a user-provided root CA, e.g. a corporate test root, into the
+/// store and signs a fresh intermediate with it. Unless `force` is set, an
+/// existing CA is never replaced.
+pub fn import_ca(
+    cert_path: &Path,
+    key_path: &Path,
+    force: bool,
+    validity: &ValidityConf,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<()> {
+    let _lock = StoreLock::acquire()?;
+    let ca_key_path = CertAuthConf::ca_key()?;
+    let ca_cert_path = CertAuthConf::ca_cert()?;
+    if !force && (ca_key_path.exists() || ca_cert_path.exists()) {
+        Err(SimpleCAError::StoreExists { dir: config_dir()? })?;
+    }
+
+    let content = read_file(cert_path)?;
+    let ca = X509::from_pem(&content).or_else(|_| X509::from_der(&content))?;
+    let ca_pkey = read_private_key(key_path)?;
+    let invalid = |msg| SimpleCAError::InvalidRootCa {
+        path: cert_path.to_path_buf(),
+        msg,
+    };
+    if !CertInfo::from_cert(&ca)?.is_ca {
+        Err(invalid("it is not a CA certificate"))?;
+    }
+    let public_key = ca.public_key()?;
+    if !ca.verify(&public_key).unwrap_or(false) {
+        Err(invalid("it is not self-signed"))?;
+    }
+    if !key_matches_cert(&ca_pkey, &ca)? {
+        Err(invalid("the private key does not belong to it"))?;
+    }
+
+    let intermediate_key_path = CertAuthConf::intermediate_key()?;
+    let intermediate_cert_path = CertAuthConf::intermediate_cert()?;
+    guard.check(&[&ca_key_path, &intermediate_key_path])?;
+    let keys = Conf::load()?.keys();
+    write_file!(
+        &private_key_to_pem(&ca_pkey, keys.encrypt_ca())?,
+        &ca_key_path,
+        verbose,
+        "Saved imported CA private key at: {:?}"
+    );
+    store::record(Role::CaKey, None, &ca_key_path, None)?;
+    let offline_root = CertAuthConf::offline_root()?;
+    if offline_root.exists() {
+        fs::remove_file(&offline_root)?;
+        store::forget(Role::OfflineRoot, None)?;
+    }
+    store::archive_cert(Role::ArchivedCaCert, &ca_cert_path)?;
+    write_file!(
+        &ca.to_pem()?,
+        &ca_cert_path,
+        verbose,
+        "Saved imported CA certificate at: {:?}"
+    );
+    store::record(Role::CaCert, None, &ca_cert_path, Some(&ca))?;
+
+    // A missing intermediate is issued from the imported root by `load_ca`.
+    store::archive_cert(Role::ArchivedIntermediateCert, &intermediate_cert_path)?;
+    for path in [&intermediate_key_path, &intermediate_cert_path] {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    load_ca(false, validity, guard, sources, verbose)?;
+    Ok(())
+}
+
+/// Signs the public key and subject of the current intermediate with a second
+/// root, so certificates it issues also validate against that root. Writes
+/// the cross-signed intermediate and an alternate chain ending in the second
+/// root.
+pub fn cross_sign(
+    root_cert_path: &Path,
+    root_key_path: &Path,
+    days: Option<u32>,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<IssuanceSummary> {
+    let _lock = StoreLock::acquire()?;
+    let conf = Conf::load()?;
+    let content = read_file(root_cert_path)?;
+    let root = X509::from_pem(&content).or_else(|_| X509::from_der(&content))?;
+    let root_pkey = read_private_key(root_key_path)?;
+    if !key_matches_cert(&root_pkey, &root)? {
+        Err(SimpleCAError::InvalidRootCa {
+            path: root_cert_path.to_path_buf(),
+            msg: "the private key does not belong to it",
+        })?;
+    }
+
+    let validity = ValidityConf {
+        intermediate_days: days,
+        ..ValidityConf::default()
+    }
+    .or(&conf.validity());
+    let (_, intermediate_pkey, intermediate_name) =
+        load_ca(false, &validity, &RepoGuard::default(), sources, verbose)?;
+    let root_name = root.subject_name().to_owned()?;
+    let mut params = CertParams::intermediate_ca_params(
+        &intermediate_name,
+        &intermediate_pkey,
+        &root_name,
+        &root_pkey,
+        validity.intermediate_days(),
+    )?;
+    params.name_constraints = conf.name_constraints();
+    params.path_len = conf.ca().intermediate_path_len();
+    params.backdate = validity.backdate()?;
+    params.digest = conf.keys().digest();
+    sources.configure(&mut params, conf.serials().strategy())?;
+    let cross = create_intermediate_ca(&params, &root)?;
+
+    let cross_path = CertAuthConf::cross_intermediate_cert()?;
+    write_file!(
+        &cross.to_pem()?,
+        &cross_path,
+        verbose,
+        "Saved cross-signed intermediate certificate at: {:?}"
+    );
+    store::record(Role::CrossIntermediateCert, None, &cross_path, Some(&root))?;
+    record_issued(&cross, None, Some(&cross_path), sources.now())?;
+
+    let mut chain = cross.to_pem()?;
+    chain.extend(root.to_pem()?);
+    let chain_path = CertAuthConf::cross_chain()?;
+    write_file!(&chain, &chain_path, verbose, "Saved cross chain at: {:?}");
+    store::record(Role::CrossChain, None, &chain_path, Some(&root))?;
+
+    Ok(IssuanceSummary::new(&cross)?
+        .file("cert", &cross_path)
+        .file("chain", &chain_path))
+}
+
+/// Name under which `--parent` refers to the default intermediate.
+const DEFAULT_INTERMEDIATE: &str = "default";
+/// Names taken by the default intermediate and by the files of `intermediate
+/// create cross`.
+const RESERVED_INTERMEDIATE_NAMES: [&str; 2] = [DEFAULT_INTERMEDIATE, "cross"];
+
+/// Fails unless `name` can be used as the name of an intermediate, and so in
+/// its file names.
+fn check_intermediate_name(name: &str) -> Result<()> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if name.is_empty() || RESERVED_INTERMEDIATE_NAMES.contains(&name) || !name.chars().all(valid) {
+        Err(SimpleCAError::InvalidIntermediateName {
+            name: name.to_string(),
+        })?;
+    }
+    Ok(())
+}
+
+/// Creates an additional intermediate called `name` under the root, e.g. one
+/// for servers and one for clients, replacing an existing one of that name.
+/// Server certificates choose it with `--issuer <name>`.
+pub fn create_intermediate(
+    name: &str,
+    validity: &ValidityConf,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<IssuanceSummary> {
+    create_named_ca(name, None, validity, guard, sources, verbose)
+}
+
+/// Creates a subordinate CA called `name` issued by the intermediate named
+/// `parent`, or by the default intermediate for `default`, to reproduce
+/// hierarchies deeper than root and intermediate. It is used like any named
+/// intermediate.
+pub fn create_subordinate(
+    name: &str,
+    parent: &str,
+    validity: &ValidityConf,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<IssuanceSummary> {
+    if name == parent {
+        Err(SimpleCAError::GenericError {
+            msg: "A subordinate CA cannot be its own parent.",
+        })?;
+    }
+    create_named_ca(name, Some(parent), validity, guard, sources, verbose)
+}
+
+/// Signs a named intermediate with the root, or with the intermediate named
+/// `parent`, and writes it along with its chain up to the root.
+fn create_named_ca(
+    name: &str,
+    parent: Option<&str>,
+    validity: &ValidityConf,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<IssuanceSummary> {
+    check_intermediate_name(name)?;
+    let _lock = StoreLock::acquire()?;
+    let conf = Conf::load()?;
+    let validity = validity.or(&conf.validity());
+    let keys = conf.keys();
+    let mut path_len = conf.ca().intermediate_path_len();
+    let (issuer, issuer_pkey, issuer_name) = match parent {
+        Some(parent) => {
+            let parent = Some(parent).filter(|parent| *parent != DEFAULT_INTERMEDIATE);
+            let (issuer, issuer_pkey, issuer_name) =
+                load_issuer(parent, &validity, guard, sources, verbose)?;
+            match issuer.pathlen() {
+                Some(0) => Err(SimpleCAError::PathLenExceeded {
+                    parent: format_name(&issuer_name)?,
+                })?,
+                Some(parent_len) => {
+                    path_len = Some(path_len.unwrap_or(u32::MAX).min(parent_len - 1));
+                }
+                None => {}
+            }
+            (issuer, issuer_pkey, issuer_name)
+        }
+        None => {
+            if is_root_offline()? {
+                Err(SimpleCAError::RootOffline)?;
+            }
+            // The default intermediate is created along with the root if needed.
+            load_ca(false, &validity, guard, sources, verbose)?;
+            let ca = read_root_cert()?;
+            let ca_pkey = KeyBackend::ca(&Conf::load()?)?.load()?;
+            let ca_name = ca.subject_name().to_owned()?;
+            (ca, ca_pkey, ca_name)
+        }
+    };
+
+    let key_path = CertAuthConf::named_intermediate_key(name)?;
+    let cert_path = CertAuthConf::named_intermediate_cert(name)?;
+    guard.check(&[&key_path])?;
+    let pkey = sources.new_key(keys.ca_algorithm())?;
+    let subject = conf
+        .ca()
+        .named_intermediate_name(&conf.branding(), name)
+        .to_x509_name_ordered(&conf.name().order())?;
+    let mut params = CertParams::intermediate_ca_params(
+        &subject,
+        &pkey,
+        &issuer_name,
+        &issuer_pkey,
+        validity.intermediate_days(),
+    )?;
+    params.name_constraints = conf.name_constraints();
+    params.path_len = path_len;
+    params.backdate = validity.backdate()?;
+    params.clock = sources.clock;
+    if parent.is_some() && params.valid_to()?.as_ref() > issuer.not_after() {
+        // A subordinate is cut off when its parent expires.
+        params.not_after = Some(asn1_to_unix(issuer.not_after())?);
+    }
+    params.digest = keys.digest();
+    sources.configure(&mut params, conf.serials().strategy())?;
+    let intermediate = create_intermediate_ca(&params, &issuer)?;
+
+    write_file!(
+        &private_key_to_pem(&pkey, keys.encrypt_ca())?,
+        &key_path,
+        verbose,
+        "Saved Intermediate private key at: {:?}"
+    );
+    store::record(Role::NamedIntermediateKey, Some(name), &key_path, None)?;
+    store::archive_cert(Role::ArchivedIntermediateCert, &cert_path)?;
+    write_file!(
+        &intermediate.to_pem()?,
+        &cert_path,
+        verbose,
+        "Saved intermediate certicate at: {:?}"
+    );
+    store::record(
+        Role::NamedIntermediateCert,
+        Some(name),
+        &cert_path,
+        Some(&issuer),
+    )?;
+    record_issued(
+        &intermediate,
+        Some(&key_path),
+        Some(&cert_path),
+        sources.now(),
+    )?;
+
+    let mut chain = intermediate.to_pem()?;
+    for ca in ca_chain(&issuer)? {
+        chain.extend(ca.to_pem()?);
+    }
+    let chain_path = CertAuthConf::named_intermediate_chain(name)?;
+    write_file!(&chain, &chain_path, verbose, "Saved CA chain at: {:?}");
+    store::record(
+        Role::NamedIntermediateChain,
+        Some(name),
+        &chain_path,
+        Some(&issuer),
+    )?;
+
+    Ok(IssuanceSummary::new(&intermediate)?
+        .file("key", &key_path)
+        .file("cert", &cert_path)
+        .file("chain", &chain_path))
+}
+
+/// Has the root issue a new default intermediate for `public`, a key held
+/// outside the store, and replaces the stored intermediate certificate with
+/// it.
+pub(crate) fn replace_intermediate<T: HasPublic>(
+    conf: &Conf,
+    public: PKey<T>,
+    validity: &ValidityConf,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<X509> {
+    let cert_path = CertAuthConf::intermediate_cert()?;
+    let ca = read_root_cert()?;
+    let ca_pkey = KeyBackend::ca(conf)?.load()?;
+    let name = conf
+        .ca()
+        .intermediate_name(&conf.branding())
+        .to_x509_name_ordered(&conf.name().order())?;
+    let mut builder = CertParams::builder(name, public)
+        .issuer(ca.subject_name().to_owned()?, ca_pkey)
+        .valid_days(validity.intermediate_days())
+        .backdate(validity.backdate()?)
+        .digest(conf.keys().digest());
+    if let Some(constraints) = conf.name_constraints() {
+        builder = builder.name_constraints(constraints);
+    }
+    if let Some(path_len) = conf.ca().intermediate_path_len() {
+        builder = builder.path_len(path_len);
+    }
+    let mut params = builder.build()?;
+    sources.configure(&mut params, conf.serials().strategy())?;
+    let intermediate = create_intermediate_ca(&params, &ca)?;
+
+    store::archive_cert(Role::ArchivedIntermediateCert, &cert_path)?;
+    write_file!(
+        &intermediate.to_pem()?,
+        &cert_path,
+        verbose,
+        "Saved intermediate certicate at: {:?}"
+    );
+    store::record(Role::IntermediateCert, None, &cert_path, Some(&ca))?;
+    record_issued(&intermediate, None, Some(&cert_path), sources.now())?;
+    Ok(intermediate)
+}
+
+/// Deletes the intermediate key file once the key lives elsewhere.
+pub(crate) fn remove_intermediate_key(verbose: bool) -> Result<()> {
+    let key_path = CertAuthConf::intermediate_key()?;
+    if key_path.exists() {
+        fs::remove_file(&key_path)?;
+        if verbose {
+            tracing::info!("Removed intermediate private key from: {:?}", key_path);
+        }
+    }
+    store::forget(Role::IntermediateKey, None)
+}
+
+/// `issuer` followed by the stored CA certificates above it, up to and
+/// including the root.
+pub(crate) fn ca_chain(issuer: &X509) -> Result<Vec<X509>> {
+    let manifest = store::StoreManifest::load()?;
+    let mut cas = Vec::new();
+    for role in [
+        Role::CaCert,
+        Role::IntermediateCert,
+        Role::NamedIntermediateCert,
+    ] {
+        for entry in manifest.entries(role) {
+            if let Ok(pem) = read_file(&file_in_conf(&entry.path)?) {
+                cas.push(X509::from_pem(&pem)?);
+            }
+        }
+    }
+
+    let mut chain = vec![issuer.clone()];
+    loop {
+        let last = chain.last().unwrap();
+        if store::issued_by(last, last) {
+            return Ok(chain);
+        }
+        match cas.iter().find(|ca| store::issued_by(last, ca)) {
+            // Bounded by the number of CAs in case of a signature loop.
+            Some(ca) if chain.len() <= cas.len() => chain.push(ca.clone()),
+            _ => break,
+        }
+    }
+    chain.push(read_root_cert()?);
+    Ok(chain)
+}
+
+/// Loads the intermediate that signs server certificates: the named one for
+/// `Some(name)`, otherwise the default one of `load_ca`.
+pub(crate) fn load_issuer(
+    issuer: Option<&str>,
+    validity: &ValidityConf,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<(X509, PKey<Private>, X509Name)> {
+    let default = load_ca(false, validity, guard, sources, verbose)?;
+    let name = match issuer {
+        Some(name) => name,
+        None => return Ok(default),
+    };
+    let manifest = store::StoreManifest::load()?;
+    if manifest
+        .entry(Role::NamedIntermediateCert, Some(name))
+        .is_none()
+    {
+        Err(SimpleCAError::UnknownIntermediate {
+            name: name.to_string(),
+            hint: hint(did_you_mean(name, manifest.intermediate_names())),
+        })?;
+    }
+    let cert = X509::from_pem(&read_file(&CertAuthConf::named_intermediate_cert(name)?)?)?;
+    let pkey = read_private_key(&CertAuthConf::named_intermediate_key(name)?)?;
+    let subject = cert.subject_name().to_owned()?;
+    Ok((cert, pkey, subject))
+}
+
+/// The name of the named intermediate that issued `cert`, `None` when it
+/// was issued by the default intermediate or a replaced one.
+pub(crate) fn issuer_name(cert: &X509) -> Result<Option<String>> {
+    let manifest = store::StoreManifest::load()?;
+    for entry in manifest.entries(Role::NamedIntermediateCert) {
+        let intermediate = X509::from_pem(&read_file(&file_in_conf(&entry.path)?)?)?;
+        if store::issued_by(cert, &intermediate) {
+            return Ok(entry.name.clone());
+        }
+    }
+    Ok(None)
+}
+
+/// Checks a server certificate for `name` and `alt_names`, along with the
+/// `[sans] ips` every server certificate gets, against the issuance policy of
+/// the config.
+pub(crate) fn check_server_policy(
+    conf: &Conf,
+    name: &X509Name,
+    alt_names: &[&str],
+    validity: &ValidityConf,
+    sources: &Sources,
+) -> Result<()> {
+    let default_ips = conf.sans().default_ips()?;
+    let alt_names = with_default_ips(alt_names, &default_ips);
+    let common_name = match name.entries_by_nid(Nid::COMMONNAME).next() {
+        Some(entry) => entry.data().to_string()?,
+        None => String::new(),
+    };
+    let mut sans = vec![common_name.as_str()];
+    let normalized_cn = normalize_name(&common_name);
+    sans.extend(
+        alt_names
+            .iter()
+            .filter(|name| normalize_name(name) != normalized_cn),
+    );
+    check_policy(
+        &conf.policy().rules(),
+        &common_name,
+        &sans,
+        validity.server_lifetime_days(sources.now())?,
+    )
+}
+
+/// `alt_names` followed by the `[sans] ips` of the config it lacks.
+pub(crate) fn with_default_ips<'a>(
+    alt_names: &[&'a str],
+    default_ips: &'a [String],
+) -> Vec<&'a str> {
+    let mut alt_names = alt_names.to_vec();
+    for ip in default_ips {
+        let parsed = ip.parse::<IpAddr>().ok();
+        if !alt_names.iter().any(|name| name.parse().ok() == parsed) {
+            alt_names.push(ip);
+        }
+    }
+    alt_names
+}
+
+/// Applies the default IPs, lifetime, digest, branding and serial of the
+/// config, and the clock of `sources`, to the parameters of a server
+/// certificate.
+pub(crate) fn configure_server_params<T: HasPublic>(
+    params: &mut CertParams<T>,
+    conf: &Conf,
+    validity: &ValidityConf,
+    sources: &Sources,
+) -> Result<()> {
+    let default_ips = conf.sans().default_ips()?;
+    let alt_names: Vec<&str> = params.sub_alt_names.iter().map(String::as_str).collect();
+    params.sub_alt_names = with_default_ips(&alt_names, &default_ips)
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    params.ttl = validity.server_ttl()?;
+    (params.not_before, params.not_after) = validity.server_window()?;
+    params.backdate = validity.backdate()?;
+    params.digest = conf.keys().digest();
+    params.comment = conf.branding().server_comment();
+    sources.configure(params, conf.serials().strategy())
+}
+
+/// Signs a server certificate for `name` with the intermediate CA, or the
+/// named one of `issuer`, returning the certificate along with its issuer.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn issue_server_cert(
+    name: &X509Name,
+    pkey: &PKey<Private>,
+    alt_names: &[&str],
+    validity: &ValidityConf,
+    issuer: Option<&str>,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<(X509, X509)> {
+    let conf = Conf::load()?;
+    let validity = validity.or(&conf.validity());
+    check_server_policy(&conf, name, alt_names, &validity, sources)?;
+    let (ca, ca_pkey, ca_name) = load_issuer(issuer, &validity, guard, sources, verbose)?;
+
+    let mut params = CertParams::server_cert_params(
+        name,
+        pkey,
+        &ca_name,
+        &ca_pkey,
+        validity.server_days(),
+        &alt_names.to_vec(),
+    )?;
+    configure_server_params(&mut params, &conf, &validity, sources)?;
+    let cert = create_server_cert(&params, &ca)?;
+    Ok((cert, ca))
+}
+
+/// Signs a server certificate for a public key whose private key is held
+/// elsewhere, e.g. one taken from a CSR, returning it along with its issuer.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn issue_public_key_cert(
+    name: &X509Name,
+    pkey: &PKey<Public>,
+    alt_names: &[&str],
+    validity: &ValidityConf,
+    issuer: Option<&str>,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<(X509, X509)> {
+    let conf = Conf::load()?;
+    let validity = validity.or(&conf.validity());
+    check_server_policy(&conf, name, alt_names, &validity, sources)?;
+    let (ca, ca_pkey, ca_name) = load_issuer(issuer, &validity, guard, sources, verbose)?;
+
+    let mut params = CertParams::csr_cert_params(
+        name,
+        pkey,
+        &ca_name,
+        &ca_pkey,
+        validity.server_days(),
+        alt_names,
+    )?;
+    configure_server_params(&mut params, &conf, &validity, sources)?;
+    let cert = create_server_cert(&params, &ca)?;
+    Ok((cert, ca))
+}
+
+/// Writes the `fullchain` (leaf + intermediates) and `chain` (intermediates +
+/// root) bundles next to a server certificate.
+pub(crate) fn write_chain_files(
+    domain: &str,
+    cert: &X509,
+    intermediate: &X509,
+    verbose: bool,
+) -> Result<()> {
+    write_bundles(domain, cert, &ca_chain(intermediate)?, verbose)
+}
+
+/// Writes the chain bundles of a server certificate with the given issuers,
+/// from its issuer up to the root, which may be archived ones.
+pub(crate) fn write_bundles(
+    domain: &str,
+    cert: &X509,
+    issuers: &[X509],
+    verbose: bool,
+) -> Result<()> {
+    let (root, intermediates) = issuers.split_last().ok_or(SimpleCAError::GenericError {
+        msg: "A chain bundle needs at least the root.",
+    })?;
+    let mut fullchain = cert.to_pem()?;
+    for intermediate in intermediates {
+        fullchain.extend(intermediate.to_pem()?);
+    }
+    let fullchain_path = CertAuthConf::server_fullchain(domain)?;
+    write_file!(
+        &fullchain,
+        &fullchain_path,
+        verbose,
+        "Saved server certificate full chain at: {:?}"
+    );
+    store::record(
+        Role::ServerFullchain,
+        Some(domain),
+        &fullchain_path,
+        intermediates.first(),
+    )?;
+
+    let mut chain = Vec::new();
+    for issuer in issuers {
+        chain.extend(issuer.to_pem()?);
+    }
+    let chain_path = CertAuthConf::server_chain(domain)?;
+    write_file!(&chain, &chain_path, verbose, "Saved CA chain at: {:?}");
+    store::record(Role::ServerChain, Some(domain), &chain_path, Some(root))?;
+
+    // The combined file embeds the bundle, so it follows it.
+    if store::is_recorded(Role::ServerHaproxy, Some(domain))? {
+        write_haproxy_pem(domain, verbose)?;
+    }
+    if store::is_recorded(Role::ServerCertDer, Some(domain))? {
+        write_der_files(domain, verbose)?;
+    }
+    Ok(())
+}
+
+/// Writes the DER encodings of a server certificate and its key, the latter
+/// only while the key is in the store rather than in hardware.
+pub(crate) fn write_der_files(domain: &str, verbose: bool) -> Result<()> {
+    let cert = X509::from_pem(&read_file(&CertAuthConf::server_cert(domain)?)?)?;
+    let cert_der_path = CertAuthConf::server_cert_der(domain)?;
+    write_file!(
+        &cert.to_der()?,
+        &cert_der_path,
+        verbose,
+        "Saved DER server certificate at: {:?}"
+    );
+    store::record(Role::ServerCertDer, Some(domain), &cert_der_path, None)?;
+    if !store::is_recorded(Role::ServerKey, Some(domain))? {
+        return store::forget(Role::ServerKeyDer, Some(domain));
+    }
+    let pkey = PKey::private_key_from_pem(&read_file(&CertAuthConf::server_key(domain)?)?)?;
+    let key_der_path = CertAuthConf::server_key_der(domain)?;
+    write_file!(
+        &pkey.private_key_to_pkcs8()?,
+        &key_der_path,
+        verbose,
+        "Saved DER server key at: {:?}"
+    );
+    store::record(Role::ServerKeyDer, Some(domain), &key_der_path, None)
+}
+
+/// The key of the server certificate for `domain` followed by its full chain,
+/// the single file HAProxy's `crt` option takes.
+pub(crate) fn haproxy_pem(domain: &str) -> Result<Vec<u8>> {
+    let mut pem = read_file(&CertAuthConf::server_key(domain)?)?;
+    pem.extend(read_file(&CertAuthConf::server_fullchain(domain)?)?);
+    Ok(pem)
+}
+
+/// Writes the combined HAProxy PEM of a server certificate.
+pub(crate) fn write_haproxy_pem(domain: &str, verbose: bool) -> Result<()> {
+    let haproxy_path = CertAuthConf::server_haproxy(domain)?;
+    write_file!(
+        &haproxy_pem(domain)?,
+        &haproxy_path,
+        verbose,
+        "Saved HAProxy PEM at: {:?}"
+    );
+    store::record(Role::ServerHaproxy, Some(domain), &haproxy_path, None)
+}
+
+/// Writes a CMS receipt for a server certificate, signed by the intermediate CA.
+pub(crate) fn write_receipt(
+    domain: &str,
+    cert: &X509,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<()> {
+    let receipt = sign_receipt(cert, sources)?;
+    let receipt_path = CertAuthConf::server_receipt(domain)?;
+    write_file!(
+        &receipt,
+        &receipt_path,
+        verbose,
+        "Saved issuance receipt at: {:?}"
+    );
+    store::record(Role::ServerReceipt, Some(domain), &receipt_path, None)
+}
+
+/// Writes the CT precertificate of a server certificate.
+pub(crate) fn write_precert(
+    domain: &str,
+    precert: &X509,
+    intermediate: &X509,
+    verbose: bool,
+) -> Result<()> {
+    let precert_path = CertAuthConf::server_precert(domain)?;
+    write_file!(
+        &precert.to_pem()?,
+        &precert_path,
+        verbose,
+        "Saved CT precertificate at: {:?}"
+    );
+    store::record(
+        Role::ServerPrecert,
+        Some(domain),
+        &precert_path,
+        Some(intermediate),
+    )
+}
+
+/// Writes Diffie-Hellman parameters for a server certificate.
+pub(crate) fn write_dhparam(domain: &str, verbose: bool) -> Result<()> {
+    let dhparam_path = CertAuthConf::server_dhparam(domain)?;
+    write_file!(
+        &generate_dhparam(DHPARAM_BITS)?,
+        &dhparam_path,
+        verbose,
+        "Saved DH parameters at: {:?}"
+    );
+    store::record(Role::ServerDhparam, Some(domain), &dhparam_path, None)
+}
+
+/// Writes a session ticket key for a server certificate.
+pub(crate) fn write_ticket_key(domain: &str, verbose: bool) -> Result<()> {
+    let ticket_key_path = CertAuthConf::server_ticket_key(domain)?;
+    write_file!(
+        &generate_ticket_key()?,
+        &ticket_key_path,
+        verbose,
+        "Saved session ticket key at: {:?}"
+    );
+    store::record(Role::ServerTicketKey, Some(domain), &ticket_key_path, None)
+}
+
+/// Optional files written along with a server certificate, and the type of
+/// its key.
+#[derive(Debug, Default, Clone)]
+pub struct ServerExtras {
+    /// Overrides `server_algorithm` of the config.
+    pub algorithm: Option<KeyAlgorithm>,
+    /// A CMS receipt of the issuance signed by the CA.
+    pub receipt: bool,
+    /// A poisoned CT precertificate, with the certificate embedding a
+    /// simulated SCT for it.
+    pub precert: bool,
+    /// Diffie-Hellman parameters for DHE cipher suites.
+    pub dhparam: bool,
+    /// A session ticket key, which is private like the server key.
+    pub ticket_key: bool,
+    /// The key, certificate and intermediates in one file for HAProxy.
+    pub haproxy: bool,
+    /// DER encodings of the certificate and key.
+    pub der: bool,
+}
+
+/// Refuses to replace the files of an existing server certificate for
+/// `domain` unless `guard` allows it.
+fn check_replace_server(domain: &str, guard: &RepoGuard) -> Result<()> {
+    let files = server_files(domain)?;
+    guard.check_replace(&[&files.key, &files.cert, &files.fullchain, &files.chain])
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn generate_server_cert(
     name: &Name,
     alt_names: &Vec<&str>,
+    validity: &ValidityConf,
+    issuer: Option<&str>,
+    guard: &RepoGuard,
+    out_dir: Option<&OutDir>,
+    extras: &ServerExtras,
+    sources: &Sources,
     verbose: bool,
-) -> Result<()> {
-    let domain = &name.common_name;
-    let name = name.to_x509_name()?;
+) -> Result<IssuanceSummary> {
+    let _lock = StoreLock::acquire()?;
+    let domain = name.cn();
+    check_replace_server(domain, guard)?;
+    let conf = Conf::load()?;
+    let name = name.to_x509_name_ordered(&conf.name().order())?;
     let server_key_path = CertAuthConf::server_key(domain)?;
-    let pkey = get_pkey(true, &server_key_path, 2048)?;
+    let ticket_key_path = CertAuthConf::server_ticket_key(domain)?;
+    let haproxy_path = CertAuthConf::server_haproxy(domain)?;
+    let mut private_paths = vec![server_key_path.as_path()];
+    if extras.ticket_key {
+        private_paths.push(&ticket_key_path);
+    }
+    if extras.haproxy {
+        private_paths.push(&haproxy_path);
+    }
+    let key_der_path = CertAuthConf::server_key_der(domain)?;
+    if extras.der {
+        private_paths.push(&key_der_path);
+    }
+    guard.check(&private_paths)?;
+    let algorithm = extras
+        .algorithm
+        .unwrap_or_else(|| conf.keys().server_algorithm());
+    let pkey = get_pkey(true, &server_key_path, algorithm, sources)?;
+    let (mut cert, ca) = issue_server_cert(
+        &name, &pkey, alt_names, validity, issuer, guard, sources, verbose,
+    )?;
+    let mut precert = None;
+    if extras.precert {
+        let pair = issue_precert_pair(&cert, guard, sources, verbose)?;
+        cert = pair.cert;
+        precert = Some(pair.precert);
+    }
     write_file!(
         &pkey.private_key_to_pem_pkcs8()?,
         &server_key_path,
         verbose,
         "Saved server key at: {:?}"
     );
-    let (ca, ca_pkey, ca_name) = load_ca(false, verbose)?;
-
-    let params = CertParams::server_cert_params(&name, &pkey, &ca_name, &ca_pkey, 370, alt_names)?;
-    let cert = create_server_cert(&params, &ca)?;
+    store::record(Role::ServerKey, Some(domain), &server_key_path, None)?;
+    // A key generated here replaces one held in hardware.
+    store::forget(Role::ServerCsr, Some(domain))?;
+    store::forget(Role::ServerAttestation, Some(domain))?;
     let cert_path = CertAuthConf::server_cert(domain)?;
     write_file!(
         &cert.to_pem()?,
@@ -150,6 +1100,351 @@ pub fn generate_server_cert(
         verbose,
         "Saved server certificate at: {:?}"
     );
+    store::record(Role::ServerCert, Some(domain), &cert_path, Some(&ca))?;
+    record_issued(
+        &cert,
+        Some(&server_key_path),
+        Some(&cert_path),
+        sources.now(),
+    )?;
+    write_chain_files(domain, &cert, &ca, verbose)?;
+    let mut summary = IssuanceSummary::server(domain, &cert)?;
+    if extras.receipt {
+        write_receipt(domain, &cert, sources, verbose)?;
+        summary = summary.file("receipt", &CertAuthConf::server_receipt(domain)?);
+    }
+    if let Some(precert) = precert {
+        write_precert(domain, &precert, &ca, verbose)?;
+        summary = summary.file("precert", &CertAuthConf::server_precert(domain)?);
+    }
+    if extras.dhparam {
+        write_dhparam(domain, verbose)?;
+        summary = summary.file("dhparam", &CertAuthConf::server_dhparam(domain)?);
+    }
+    if extras.ticket_key {
+        write_ticket_key(domain, verbose)?;
+        summary = summary.file("ticket-key", &ticket_key_path);
+    }
+    // A recorded one was already rewritten along with the bundles.
+    if extras.haproxy && !store::is_recorded(Role::ServerHaproxy, Some(domain))? {
+        write_haproxy_pem(domain, verbose)?;
+    }
+    if store::is_recorded(Role::ServerHaproxy, Some(domain))? {
+        summary = summary.file("haproxy", &haproxy_path);
+    }
+    if extras.der && !store::is_recorded(Role::ServerCertDer, Some(domain))? {
+        write_der_files(domain, verbose)?;
+    }
+    if store::is_recorded(Role::ServerCertDer, Some(domain))? {
+        summary = summary
+            .file("cert-der", &CertAuthConf::server_cert_der(domain)?)
+            .file("key-der", &key_der_path);
+    }
 
-    Ok(())
+    if let Some(out_dir) = out_dir {
+        for copied in out_dir.copy_server_files(domain, guard, verbose)? {
+            summary = summary.file("copy", &copied);
+        }
+    }
+    run_post_issue_hooks(domain, out_dir.map(|out_dir| out_dir.dir.as_path()))?;
+
+    Ok(summary)
+}
+
+/// Issues `name` and `*.name` with separate keys but the same subject fields
+/// and validity, and writes a combined JSON receipt for both. The policy and
+/// repository checks of both run before either certificate is issued.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_server_pair(
+    name: &Name,
+    validity: &ValidityConf,
+    issuer: Option<&str>,
+    guard: &RepoGuard,
+    out_dir: Option<&OutDir>,
+    extras: &ServerExtras,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<Vec<IssuanceSummary>> {
+    let apex = name.cn();
+    if apex.starts_with("*.") || apex.parse::<std::net::IpAddr>().is_ok() {
+        Err(SimpleCAError::GenericError {
+            msg: "--pair needs an apex domain, not a wildcard or IP address.",
+        })?;
+    }
+    let _lock = StoreLock::acquire()?;
+    let conf = Conf::load()?;
+    let names = [name.copy(apex), name.copy(&format!("*.{}", apex))];
+    for name in &names {
+        let x509_name = name.to_x509_name_ordered(&conf.name().order())?;
+        check_server_policy(&conf, &x509_name, &[], validity, sources)?;
+        guard.check(&[&CertAuthConf::server_key(name.cn())?])?;
+        check_replace_server(name.cn(), guard)?;
+    }
+
+    let mut summaries = Vec::new();
+    for name in &names {
+        summaries.push(generate_server_cert(
+            name,
+            &Vec::new(),
+            validity,
+            issuer,
+            guard,
+            out_dir,
+            extras,
+            sources,
+            verbose,
+        )?);
+    }
+    let receipt = PairReceipt {
+        apex: apex.to_string(),
+        issued_at: format_rfc3339(sources.now()),
+        certificates: summaries.clone(),
+    };
+    let receipt_path = CertAuthConf::server_pair_receipt(apex)?;
+    write_file!(
+        &serde_json::to_vec_pretty(&receipt)?,
+        &receipt_path,
+        verbose,
+        "Saved pair receipt at: {:?}"
+    );
+    store::record(Role::ServerPairReceipt, Some(apex), &receipt_path, None)?;
+    Ok(summaries
+        .into_iter()
+        .map(|summary| summary.file("pair", &receipt_path))
+        .collect())
+}
+
+/// Issuance for tests that need server certificates in their store.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::{generate_server_cert, ServerExtras};
+    use crate::conf::ValidityConf;
+    use crate::deterministic::Sources;
+    use crate::guard::RepoGuard;
+    use crate::name::Name;
+    use crate::summary::IssuanceSummary;
+
+    /// Issues a server certificate for `domain` into the store of the test.
+    pub(crate) fn issue_test_server(
+        domain: &str,
+        alt_names: &[&str],
+        extras: &ServerExtras,
+    ) -> IssuanceSummary {
+        generate_server_cert(
+            &Name::new(domain),
+            &alt_names.to_vec(),
+            &ValidityConf::default(),
+            None,
+            &RepoGuard {
+                replace: true,
+                ..RepoGuard::default()
+            },
+            None,
+            extras,
+            &Sources::default(),
+            false,
+        )
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_intermediate_name() {
+        for name in ["servers", "clients-intermediate", "team_2"] {
+            assert!(check_intermediate_name(name).is_ok());
+        }
+        for name in ["", "a.b", "../ca", "with space", "default", "cross"] {
+            assert!(check_intermediate_name(name).is_err());
+        }
+    }
+
+    #[test]
+    fn test_validity_days() {
+        use crate::conf::with_test_store;
+        use openssl::asn1::Asn1Time;
+
+        with_test_store("validity", |_| {
+            let issue = |days: u32| {
+                let validity = ValidityConf {
+                    server_days: Some(days),
+                    ..ValidityConf::default()
+                };
+                generate_server_cert(
+                    &Name::new("app.test"),
+                    &vec![],
+                    &validity.or(&ValidityConf {
+                        server_days: Some(90),
+                        ..ValidityConf::default()
+                    }),
+                    None,
+                    &RepoGuard {
+                        replace: true,
+                        ..RepoGuard::default()
+                    },
+                    None,
+                    &ServerExtras::default(),
+                    &Sources::default(),
+                    false,
+                )
+            };
+            issue(30).unwrap();
+            let cert = X509::from_pem(
+                &read_file(&CertAuthConf::server_cert("app.test").unwrap()).unwrap(),
+            )
+            .unwrap();
+            let days = Asn1Time::days_from_now(0)
+                .unwrap()
+                .diff(cert.not_after())
+                .unwrap()
+                .days;
+            assert!((29..=30).contains(&days));
+
+            // A leaf may not outlive the intermediate, 3600 days by default.
+            assert!(matches!(
+                issue(5000),
+                Err(SimpleCAError::OutlivesIssuer { .. })
+            ));
+        });
+    }
+
+    #[test]
+    fn test_replace_guard() {
+        use crate::commands::fixtures::issue_test_server;
+        use crate::conf::with_test_store;
+
+        with_test_store("replace", |_| {
+            issue_test_server("app.test", &[], &ServerExtras::default());
+            let cert_path = CertAuthConf::server_cert("app.test").unwrap();
+            let cert = read_file(&cert_path).unwrap();
+            let ca = read_file(&CertAuthConf::ca_cert().unwrap()).unwrap();
+            let guard = RepoGuard::default();
+            let sources = Sources::default();
+
+            let err = generate_server_cert(
+                &Name::new("app.test"),
+                &vec![],
+                &ValidityConf::default(),
+                None,
+                &guard,
+                None,
+                &ServerExtras::default(),
+                &sources,
+                false,
+            )
+            .unwrap_err();
+            assert!(matches!(err, SimpleCAError::WouldReplace { paths } if paths.len() == 4));
+            assert_eq!(read_file(&cert_path).unwrap(), cert);
+
+            assert!(matches!(
+                load_ca(true, &ValidityConf::default(), &guard, &sources, false),
+                Err(SimpleCAError::WouldReplace { .. })
+            ));
+            assert_eq!(read_file(&CertAuthConf::ca_cert().unwrap()).unwrap(), ca);
+
+            let guard = RepoGuard {
+                replace: true,
+                ..RepoGuard::default()
+            };
+            load_ca(true, &ValidityConf::default(), &guard, &sources, false).unwrap();
+            assert_ne!(read_file(&CertAuthConf::ca_cert().unwrap()).unwrap(), ca);
+        });
+    }
+    #[test]
+    fn test_default_ips() {
+        use crate::conf::with_test_store;
+        use crate::context::CaContext;
+        use crate::keys::KeyAlgorithm;
+
+        fn ips(cert: &X509) -> Vec<Vec<u8>> {
+            cert.subject_alt_names()
+                .unwrap()
+                .iter()
+                .filter_map(|name| name.ipaddress().map(<[u8]>::to_vec))
+                .collect()
+        }
+
+        with_test_store("default-ips", |home| {
+            let config = home.join("config");
+            let mut conf = fs::read_to_string(&config).unwrap();
+            conf.push_str("[sans]\nips = [\"10.0.0.5\"]\n");
+            fs::write(&config, &conf).unwrap();
+            let sources = Sources::default();
+
+            let context = CaContext::load(None, sources.clone()).unwrap();
+            let issued = context.issue("ctx.test", &[]).unwrap();
+            assert_eq!(ips(&issued.cert), vec![vec![10, 0, 0, 5]]);
+
+            let key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+            let public = PKey::public_key_from_der(&key.public_key_to_der().unwrap()).unwrap();
+            let (cert, _) = issue_public_key_cert(
+                &Name::new("csr.test").to_x509_name().unwrap(),
+                &public,
+                &["csr.test", "10.0.0.5"],
+                &ValidityConf::default(),
+                None,
+                &RepoGuard::default(),
+                &sources,
+                false,
+            )
+            .unwrap();
+            assert_eq!(ips(&cert), vec![vec![10, 0, 0, 5]]);
+
+            conf.push_str("[policy]\nrules = ['deny when san == \"10.0.0.5\"']\n");
+            fs::write(&config, &conf).unwrap();
+            // The context holds the config it was loaded with.
+            let context = CaContext::load(None, sources.clone()).unwrap();
+            assert!(matches!(
+                context.issue("ctx.test", &[]),
+                Err(SimpleCAError::PolicyDenied { .. })
+            ));
+            assert!(matches!(
+                generate_server_pair(
+                    &Name::new("pair.test"),
+                    &ValidityConf::default(),
+                    None,
+                    &RepoGuard::default(),
+                    None,
+                    &ServerExtras::default(),
+                    &sources,
+                    false,
+                ),
+                Err(SimpleCAError::PolicyDenied { .. })
+            ));
+            assert!(!CertAuthConf::server_cert("pair.test").unwrap().exists());
+        });
+    }
+    #[test]
+    fn test_pair_policy_precheck() {
+        use crate::conf::with_test_store;
+
+        with_test_store("pair-policy", |home| {
+            let config = home.join("config");
+            let mut conf = fs::read_to_string(&config).unwrap();
+            conf.push_str("[policy]\nrules = ['deny when san.starts_with(\"*.\")']\n");
+            fs::write(&config, conf).unwrap();
+
+            assert!(matches!(
+                generate_server_pair(
+                    &Name::new("pair.test"),
+                    &ValidityConf::default(),
+                    None,
+                    &RepoGuard::default(),
+                    None,
+                    &ServerExtras::default(),
+                    &Sources::default(),
+                    false,
+                ),
+                Err(SimpleCAError::PolicyDenied { .. })
+            ));
+            let files = server_files("pair.test").unwrap();
+            for path in [&files.key, &files.cert, &files.fullchain, &files.chain] {
+                assert!(!path.exists());
+            }
+            assert!(!store::is_recorded(Role::ServerCert, Some("pair.test")).unwrap());
+        });
+    }
 }
@@ -0,0 +1,258 @@
+//! Certificates for the services of a docker-compose project: one per service,
+//! valid for the name the other containers reach it by and for the host, and
+//! copied into a directory the compose file can mount.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::commands::{generate_server_cert, ServerExtras};
+use crate::conf::{CertAuthConf, ValidityConf};
+use crate::deterministic::Sources;
+use crate::err::{Result, SimpleCAError};
+use crate::guard::RepoGuard;
+use crate::lock::StoreLock;
+use crate::name::Name;
+use crate::out_dir::OutDir;
+use crate::summary::IssuanceSummary;
+
+/// Looked up in this order when no compose file is given.
+pub const COMPOSE_FILES: [&str; 4] = [
+    "compose.yaml",
+    "compose.yml",
+    "docker-compose.yml",
+    "docker-compose.yaml",
+];
+
+/// File name of the root certificate copied next to the service certificates.
+const CA_CERT_NAME: &str = "ca.cert.pem";
+
+/// A service of a compose file and the `hostname` it sets, if any.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ComposeService {
+    pub name: String,
+    pub hostname: Option<String>,
+}
+
+impl ComposeService {
+    /// SANs besides the service name, which is its DNS name on the compose
+    /// network and as the common name always the first SAN: `<name>.localhost`
+    /// and `127.0.0.1` for published ports, then the hostname.
+    pub fn alt_names(&self) -> Vec<String> {
+        let mut names = vec![format!("{}.localhost", self.name), "127.0.0.1".to_string()];
+        if let Some(hostname) = &self.hostname {
+            if *hostname != self.name && !names.contains(hostname) {
+                names.push(hostname.clone());
+            }
+        }
+        names
+    }
+}
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+fn unquote(value: &str) -> &str {
+    value.trim().trim_matches(|c| c == '"' || c == '\'')
+}
+
+/// Splits a trimmed `key: value` line, dropping a trailing comment.
+fn key_value(line: &str) -> Option<(&str, &str)> {
+    if line.starts_with('-') {
+        return None;
+    }
+    let (key, value) = line.split_once(':')?;
+    let value = value.split(" #").next().unwrap_or_default();
+    Some((unquote(key), unquote(value)))
+}
+
+/// Finds the services of a compose file and their `hostname`. Only the block
+/// style compose files are written in is understood, going by indentation.
+pub fn parse_compose_services(yaml: &str) -> Vec<ComposeService> {
+    let mut services: Vec<ComposeService> = Vec::new();
+    let mut in_services = false;
+    let mut service_indent = None;
+    let mut field_indent = None;
+    for line in yaml.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let depth = indent(line);
+        if depth == 0 {
+            in_services = matches!(key_value(trimmed), Some(("services", _)));
+            service_indent = None;
+            continue;
+        }
+        if !in_services {
+            continue;
+        }
+        let service_depth = *service_indent.get_or_insert(depth);
+        if depth <= service_depth {
+            if let Some((name, _)) = key_value(trimmed) {
+                services.push(ComposeService {
+                    name: name.to_string(),
+                    hostname: None,
+                });
+            }
+            field_indent = None;
+            continue;
+        }
+        if *field_indent.get_or_insert(depth) != depth {
+            continue;
+        }
+        if let (Some(("hostname", hostname)), Some(service)) =
+            (key_value(trimmed), services.last_mut())
+        {
+            if !hostname.is_empty() {
+                service.hostname = Some(hostname.to_string());
+            }
+        }
+    }
+    services
+}
+
+/// The compose file of the project in `dir`.
+pub fn find_compose_file(dir: &Path) -> Option<PathBuf> {
+    COMPOSE_FILES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.exists())
+}
+
+/// Issues a server certificate for every service of `compose_file` and copies
+/// them into `dest`, usually `certs/` next to the compose file, along with the
+/// root certificate for the containers to trust.
+pub fn issue_compose_certs(
+    compose_file: &Path,
+    dest: &Path,
+    validity: &ValidityConf,
+    issuer: Option<&str>,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<Vec<IssuanceSummary>> {
+    let _lock = StoreLock::acquire()?;
+    let services = parse_compose_services(&fs::read_to_string(compose_file)?);
+    if services.is_empty() {
+        Err(SimpleCAError::NoComposeServices {
+            path: compose_file.to_path_buf(),
+        })?;
+    }
+    let out_dir = OutDir::new(dest);
+    for service in &services {
+        guard.check(&[&out_dir.key_path(&service.name)])?;
+    }
+
+    let mut summaries = Vec::with_capacity(services.len());
+    for service in &services {
+        let name = Name::new(service.name.clone());
+        let alt_names = service.alt_names();
+        summaries.push(generate_server_cert(
+            &name,
+            &alt_names.iter().map(String::as_str).collect(),
+            validity,
+            issuer,
+            guard,
+            Some(&out_dir),
+            &ServerExtras::default(),
+            sources,
+            verbose,
+        )?);
+    }
+    let ca_dest = dest.join(CA_CERT_NAME);
+    fs::copy(CertAuthConf::ca_cert()?, &ca_dest)?;
+    if verbose {
+        tracing::info!("Copied CA certificate to: {:?}", ca_dest);
+    }
+    Ok(summaries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::with_test_store;
+
+    #[test]
+    fn test_parse_compose_services() {
+        let yaml = r#"
+version: "3.8"
+services:
+  # the API
+  api:
+    image: example/api:1.2
+    hostname: api.dev.test
+    environment:
+      hostname: not-this-one
+  "db":
+    image: postgres:16
+    ports:
+      - "5432:5432"
+volumes:
+  data:
+"#;
+        let services = parse_compose_services(yaml);
+        assert_eq!(
+            services,
+            vec![
+                ComposeService {
+                    name: "api".to_string(),
+                    hostname: Some("api.dev.test".to_string()),
+                },
+                ComposeService {
+                    name: "db".to_string(),
+                    hostname: None,
+                },
+            ]
+        );
+        assert_eq!(services[1].alt_names(), ["db.localhost", "127.0.0.1"]);
+    }
+
+    #[test]
+    fn test_issue_compose_certs() {
+        with_test_store("compose", |home| {
+            let compose_file = home.join("compose.yml");
+            let yaml =
+                "services:\n  api:\n    hostname: api.dev.test\n  db:\n    image: postgres\n";
+            fs::write(&compose_file, yaml).unwrap();
+            let dest = home.join("certs");
+            let guard = RepoGuard::default();
+            let summaries = issue_compose_certs(
+                &compose_file,
+                &dest,
+                &ValidityConf::default(),
+                None,
+                &guard,
+                &Sources::default(),
+                false,
+            )
+            .unwrap();
+            assert_eq!(summaries.len(), 2);
+            assert_eq!(
+                summaries[0].sans,
+                ["api", "api.localhost", "127.0.0.1", "api.dev.test"]
+            );
+            for file in ["api.cert.pem", "api.key.pem", "db.fullchain.pem"] {
+                assert!(dest.join(file).exists(), "{}", file);
+            }
+            assert_eq!(
+                fs::read(dest.join(CA_CERT_NAME)).unwrap(),
+                fs::read(CertAuthConf::ca_cert().unwrap()).unwrap()
+            );
+
+            fs::write(&compose_file, "volumes:\n  data:\n").unwrap();
+            assert!(matches!(
+                issue_compose_certs(
+                    &compose_file,
+                    &dest,
+                    &ValidityConf::default(),
+                    None,
+                    &guard,
+                    &Sources::default(),
+                    false
+                ),
+                Err(SimpleCAError::NoComposeServices { .. })
+            ));
+        });
+    }
+}
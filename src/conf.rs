@@ -1,15 +1,69 @@
+use std::env;
 use std::fs::{self, File};
-use std::io::{Error as IOError, ErrorKind as IOErrorKind, Read, Write};
+use std::io::{Error as IOError, ErrorKind as IOErrorKind, Read};
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
 
-use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
-use crate::err::SimpleCAError;
+use crate::atomic::write_file;
+use crate::cert_params::{parse_ttl, DEFAULT_BACKDATE, DEFAULT_COMMENT};
+use crate::config_sig::verify_config;
+use crate::constraints::NameConstraints;
+use crate::datetime::{format_unix, parse_rfc3339};
+use crate::err::{Result, SimpleCAError};
+use crate::keypool::POOL_DIR;
+use crate::keys::{KeyAlgorithm, KeyProfile, SignatureDigest};
+use crate::name::{NameField, DEFAULT_NAME_ORDER};
+use crate::netif::local_ips;
+use crate::perms::create_private_dir;
+use crate::serial::SerialStrategy;
+use crate::store::{store_path, Role, ARCHIVE_DIR};
 use crate::Name;
 
-const CONFIG_DIR: &'static str = ".simple_ca";
-const CONFIG_FILE: &'static str = "config";
+const CONFIG_DIR: &str = ".simple_ca";
+const CONFIG_FILE: &str = "config";
+
+/// Environment variable overriding the config dir, like `--home`.
+pub const HOME_ENV: &str = "SIMPLE_CA_HOME";
+
+/// XDG base directory for state, holding the config dir when the home
+/// directory is missing or read-only.
+const XDG_STATE_ENV: &str = "XDG_STATE_HOME";
+const XDG_STATE_DIR: &str = "simple_ca";
+
+/// Name of the profile stored directly in the config dir.
+pub const DEFAULT_PROFILE: &str = "default";
+
+static PROFILE: RwLock<Option<String>> = RwLock::new(None);
+/// Project-local stores already used automatically, see `detect_local`.
+const KNOWN_LOCAL_STORES_FILE: &str = "local-stores";
+
+static LOCAL_STORE: RwLock<Option<PathBuf>> = RwLock::new(None);
+static CONFIG_HOME: RwLock<Option<PathBuf>> = RwLock::new(None);
+static DEFAULT_HOME: RwLock<Option<DefaultHome>> = RwLock::new(None);
+
+#[cfg(test)]
+thread_local! {
+    /// The config dir of the test running on this thread, see `with_test_home`.
+    static TEST_HOME: std::cell::RefCell<Option<PathBuf>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Where the config dir is when no other location was set.
+#[derive(Debug, Clone)]
+enum DefaultHome {
+    Dir(PathBuf),
+    /// A temporary dir used for a single run, as nothing else is writable.
+    Ephemeral(PathBuf),
+}
+
+const DEFAULT_CA_DAYS: u32 = 7200;
+const DEFAULT_INTERMEDIATE_DAYS: u32 = 3600;
+pub(crate) const DEFAULT_SERVER_DAYS: u32 = 370;
+const DEFAULT_RENEW_WITHIN_DAYS: u32 = 30;
 
 fn ensure_dir(dir: &PathBuf) -> Result<(), IOError> {
     if dir.exists() {
@@ -20,7 +74,7 @@ fn ensure_dir(dir: &PathBuf) -> Result<(), IOError> {
             ));
         }
     } else {
-        fs::create_dir(&dir)?;
+        fs::create_dir_all(dir)?;
     }
     Ok(())
 }
@@ -34,9 +88,241 @@ pub fn home_dir() -> Result<PathBuf, SimpleCAError> {
     }
 }
 
-fn file_in_conf(name: &str) -> Result<PathBuf> {
-    let mut path = home_dir()?;
-    path.push(CONFIG_DIR);
+/// The file `name` of simple-ca in the user's config dir, e.g.
+/// `~/.config/simple-ca/<name>` on Linux. It lies outside every store, so
+/// nothing copied into a store can change it. `None` without a config dir.
+pub(crate) fn user_file(name: &str) -> Option<PathBuf> {
+    // Tests keep it in their store, away from the real user config dir.
+    #[cfg(test)]
+    if let Some(dir) = TEST_HOME.with(|home| home.borrow().clone()) {
+        return Some(dir.join(".user").join(name));
+    }
+    dirs::config_dir().map(|dir| dir.join("simple-ca").join(name))
+}
+
+/// Whether the text file `path` has the line `line`, false when it is missing.
+pub(crate) fn has_line(path: &Path, line: &str) -> bool {
+    fs::read_to_string(path)
+        .map(|content| content.lines().any(|known| known == line))
+        .unwrap_or(false)
+}
+
+/// Appends `line` to the text file `path` unless it has it already.
+pub(crate) fn add_line(path: &Path, line: &str) -> Result<()> {
+    if has_line(path, line) {
+        return Ok(());
+    }
+    let mut content = fs::read_to_string(path).unwrap_or_default();
+    content.push_str(line);
+    content.push('\n');
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    Ok(write_file(path, content)?)
+}
+
+/// Selects the CA profile used by every following operation in the process.
+///
+/// The default profile lives directly in `~/.simple_ca`, any other one in
+/// `~/.simple_ca/<profile>` with its own root, intermediate and config.
+/// Names of the directories the default profile keeps there are refused.
+pub fn set_profile(profile: &str) -> Result<()> {
+    if profile.is_empty()
+        || profile.starts_with('.')
+        || profile.contains(|c: char| std::path::is_separator(c))
+        || [ARCHIVE_DIR, POOL_DIR].contains(&profile)
+    {
+        Err(SimpleCAError::InvalidProfile {
+            profile: profile.to_string(),
+        })?;
+    }
+    *PROFILE.write().unwrap() = Some(profile.to_string()).filter(|p| p != DEFAULT_PROFILE);
+    Ok(())
+}
+
+/// The selected profile.
+pub fn profile() -> String {
+    PROFILE
+        .read()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| DEFAULT_PROFILE.to_string())
+}
+
+/// Uses `dir` in place of `~/.simple_ca`, taking precedence over `SIMPLE_CA_HOME`
+/// and any project-local store.
+pub fn set_config_home(dir: &Path) -> Result<()> {
+    *CONFIG_HOME.write().unwrap() = Some(env::current_dir()?.join(dir));
+    Ok(())
+}
+
+/// Runs `test` with `dir` as the config dir of the current thread only, so
+/// tests running in parallel each keep their own store.
+#[cfg(test)]
+pub(crate) fn with_test_home<R>(dir: &Path, test: impl FnOnce() -> R) -> R {
+    TEST_HOME.with(|home| *home.borrow_mut() = Some(dir.to_path_buf()));
+    let result = test();
+    TEST_HOME.with(|home| *home.borrow_mut() = None);
+    result
+}
+
+/// Runs `test` with a new store of its own named after `name`, configured
+/// for fast ECDSA keys, and removes the store afterwards.
+#[cfg(test)]
+pub(crate) fn with_test_store<R>(name: &str, test: impl FnOnce(&Path) -> R) -> R {
+    let home = env::temp_dir().join(format!("simple-ca-{}-{}", name, process::id()));
+    fs::create_dir_all(&home).unwrap();
+    let result = with_test_home(&home, || {
+        Conf::load().unwrap();
+        let mut conf = fs::read_to_string(home.join(CONFIG_FILE)).unwrap();
+        conf.push_str("[keys]\nprofile = \"fast\"\n");
+        fs::write(home.join(CONFIG_FILE), conf).unwrap();
+        test(&home)
+    });
+    fs::remove_dir_all(&home).unwrap();
+    result
+}
+
+/// The config dir set with `set_config_home` or `SIMPLE_CA_HOME`, if any.
+fn config_home() -> Result<Option<PathBuf>> {
+    #[cfg(test)]
+    if let Some(dir) = TEST_HOME.with(|home| home.borrow().clone()) {
+        return Ok(Some(dir));
+    }
+    if let Some(dir) = CONFIG_HOME.read().unwrap().clone() {
+        return Ok(Some(dir));
+    }
+    match env::var_os(HOME_ENV) {
+        Some(dir) if !dir.is_empty() => Ok(Some(env::current_dir()?.join(dir))),
+        _ => Ok(None),
+    }
+}
+
+/// Keeps the store in `<project>/.simple_ca` instead of the home directory,
+/// so a project can carry its own throwaway CA.
+pub fn set_local(project: &Path) {
+    let store = project.join(CONFIG_DIR);
+    // Chosen explicitly, so `detect_local` need not point it out later.
+    if let Some(known) = user_file(KNOWN_LOCAL_STORES_FILE) {
+        let _ = add_line(&known, &store.display().to_string());
+    }
+    *LOCAL_STORE.write().unwrap() = Some(store);
+}
+
+/// Switches to the project-local store when the current directory has one,
+/// returning whether it did. The first time a project's store is used this
+/// way a warning names it, as it may have come with a checkout.
+pub fn detect_local() -> Result<bool> {
+    let cwd = env::current_dir()?;
+    let store = cwd.join(CONFIG_DIR);
+    let found =
+        store.is_dir() && home_dir().ok().as_ref() != Some(&cwd) && config_home()?.is_none();
+    if found {
+        let known = user_file(KNOWN_LOCAL_STORES_FILE)
+            .is_some_and(|known| has_line(&known, &store.display().to_string()));
+        if !known {
+            tracing::warn!(
+                "{}",
+                crate::tr!("local-store-detected", dir = store.display().to_string())
+            );
+        }
+        set_local(&cwd);
+    }
+    Ok(found)
+}
+
+/// The project-local store, if one is in use.
+pub(crate) fn local_store() -> Option<PathBuf> {
+    match config_home() {
+        Ok(None) => LOCAL_STORE.read().unwrap().clone(),
+        _ => None,
+    }
+}
+
+/// Whether files can be created in `dir`, or in the closest existing parent
+/// that it would be created in.
+fn is_writable(dir: &Path) -> bool {
+    let existing = match dir.ancestors().find(|dir| dir.exists()) {
+        Some(existing) if existing.is_dir() => existing,
+        _ => return false,
+    };
+    let probe = existing.join(format!(".simple_ca-probe-{}", process::id()));
+    File::create(&probe).is_ok() && fs::remove_file(&probe).is_ok()
+}
+
+/// The config dir used without `--home`, `SIMPLE_CA_HOME` or a project-local
+/// store: `~/.simple_ca`, else `$XDG_STATE_HOME/simple_ca` when the home
+/// directory is missing or read-only, else an ephemeral dir in the temp dir,
+/// e.g. in containers and sandboxes. Resolved once per process.
+fn default_home() -> Result<DefaultHome> {
+    let mut default_home = DEFAULT_HOME.write().unwrap();
+    if let Some(home) = default_home.as_ref() {
+        return Ok(home.clone());
+    }
+    let xdg_state = env::var_os(XDG_STATE_ENV)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .map(|dir| dir.join(XDG_STATE_DIR));
+    let candidates = [home_dir().ok().map(|dir| dir.join(CONFIG_DIR)), xdg_state];
+    let home = match candidates
+        .into_iter()
+        .flatten()
+        .find(|dir| is_writable(dir))
+    {
+        Some(dir) => DefaultHome::Dir(dir),
+        None => {
+            let dir = ephemeral_dir()?;
+            tracing::warn!(
+                "{}",
+                crate::tr!("ephemeral-store", dir = dir.display().to_string())
+            );
+            DefaultHome::Ephemeral(dir)
+        }
+    };
+    *default_home = Some(home.clone());
+    Ok(home)
+}
+
+/// Creates a new dir in the temp dir that only the current user can access,
+/// under a name others cannot guess in advance.
+fn ephemeral_dir() -> Result<PathBuf> {
+    loop {
+        let mut bytes = [0u8; 8];
+        openssl::rand::rand_bytes(&mut bytes)?;
+        let suffix: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let dir = env::temp_dir().join(format!("simple_ca-ephemeral-{}", suffix));
+        match create_private_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(err) if err.kind() == IOErrorKind::AlreadyExists => continue,
+            Err(err) => Err(err)?,
+        }
+    }
+}
+
+/// The temporary config dir of this run when no writable location was found,
+/// which should be removed when the process is done with it.
+pub fn ephemeral_store() -> Option<PathBuf> {
+    match DEFAULT_HOME.read().unwrap().as_ref() {
+        Some(DefaultHome::Ephemeral(dir)) => Some(dir.clone()),
+        _ => None,
+    }
+}
+
+pub(crate) fn config_dir() -> Result<PathBuf> {
+    let mut path = match (config_home()?, local_store()) {
+        (Some(dir), _) | (None, Some(dir)) => dir,
+        (None, None) => match default_home()? {
+            DefaultHome::Dir(dir) | DefaultHome::Ephemeral(dir) => dir,
+        },
+    };
+    if let Some(profile) = PROFILE.read().unwrap().as_ref() {
+        path.push(profile);
+    }
+    Ok(path)
+}
+
+pub(crate) fn file_in_conf(name: &str) -> Result<PathBuf> {
+    let mut path = config_dir()?;
     path.push(name);
     Ok(path)
 }
@@ -45,121 +331,635 @@ pub fn with_config_dir<T, RT>(process: T) -> Result<RT>
 where
     T: Fn(PathBuf) -> Result<RT>,
 {
-    let mut home_path = home_dir()?;
-    home_path.push(CONFIG_DIR);
-    let config_dir_path = home_path;
+    let config_dir_path = config_dir()?;
     ensure_dir(&config_dir_path)?;
     process(config_dir_path)
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CertAuthConf {
     country: Option<String>,
     state_or_province: Option<String>,
     locality: Option<String>,
     organization: Option<String>,
     organization_unit: Option<String>,
+    /// BasicConstraints pathlen of the intermediate CA, 0 to forbid it
+    /// from issuing further CAs.
+    intermediate_path_len: Option<u32>,
 }
 
 macro_rules! file_name_getter {
-    ($fn_name:ident, $f_name:expr) => {
+    ($fn_name:ident, $role:expr) => {
         pub fn $fn_name() -> Result<PathBuf> {
-            file_in_conf($f_name)
+            store_path($role, None)
         }
     };
 }
 
-fn reversed_domain(domain: &str) -> String {
-    if domain.parse::<std::net::SocketAddr>().is_ok() {
-        domain.to_owned()
-    } else {
-        let port_pos = domain.find(':');
-        let port = port_pos.map(|i| (&domain[i..])).unwrap_or("");
-        let domain = port_pos.map(|i| &domain[..i]).unwrap_or(domain);
-
-        let mut result = domain
-            .split(".")
-            .collect::<Vec<&str>>()
-            .into_iter()
-            .rev()
-            .collect::<Vec<&str>>()
-            .join(".");
-
-        result.push_str(port);
-        result
-    }
-}
-
-fn opt_value(opt: &Option<String>, default: &str) -> String {
-    opt.as_ref()
-        .map(|x| x.to_string())
-        .unwrap_or_else(|| default.to_string())
-}
-
-impl CertAuthConf {
-    pub fn default() -> CertAuthConf {
+impl Default for CertAuthConf {
+    fn default() -> CertAuthConf {
         CertAuthConf {
             country: None,
             state_or_province: None,
             locality: None,
-            organization: Some("Simple CA".to_string()),
+            organization: Some(DEFAULT_ORG.to_string()),
             organization_unit: None,
+            intermediate_path_len: None,
         }
     }
+}
 
-    file_name_getter!(ca_key, "ca.key.pem");
-    file_name_getter!(ca_cert, "ca.cert.pem");
-    file_name_getter!(intermediate_key, "intermediate.key.pem");
-    file_name_getter!(intermediate_cert, "intermediate.cert.pem");
+impl CertAuthConf {
+    file_name_getter!(ca_key, Role::CaKey);
+    file_name_getter!(ca_cert, Role::CaCert);
+    file_name_getter!(intermediate_key, Role::IntermediateKey);
+    file_name_getter!(intermediate_cert, Role::IntermediateCert);
+    file_name_getter!(cross_intermediate_cert, Role::CrossIntermediateCert);
+    file_name_getter!(cross_chain, Role::CrossChain);
+    file_name_getter!(offline_root, Role::OfflineRoot);
+    file_name_getter!(config_signature, Role::ConfigSignature);
+    file_name_getter!(config_signers, Role::ConfigSigners);
+    file_name_getter!(config_signers_signature, Role::ConfigSignersSignature);
 
     pub fn server_key(domain: &str) -> Result<PathBuf> {
-        file_in_conf(&format!("{}.key.pem", reversed_domain(domain)))
+        store_path(Role::ServerKey, Some(domain))
     }
 
     pub fn server_cert(domain: &str) -> Result<PathBuf> {
-        file_in_conf(&format!("{}.cert.pem", reversed_domain(domain)))
+        store_path(Role::ServerCert, Some(domain))
+    }
+
+    pub fn server_fullchain(domain: &str) -> Result<PathBuf> {
+        store_path(Role::ServerFullchain, Some(domain))
+    }
+
+    pub fn server_chain(domain: &str) -> Result<PathBuf> {
+        store_path(Role::ServerChain, Some(domain))
+    }
+
+    pub fn server_receipt(domain: &str) -> Result<PathBuf> {
+        store_path(Role::ServerReceipt, Some(domain))
+    }
+
+    pub fn server_precert(domain: &str) -> Result<PathBuf> {
+        store_path(Role::ServerPrecert, Some(domain))
+    }
+
+    pub fn server_csr(domain: &str) -> Result<PathBuf> {
+        store_path(Role::ServerCsr, Some(domain))
+    }
+
+    pub fn server_attestation(domain: &str) -> Result<PathBuf> {
+        store_path(Role::ServerAttestation, Some(domain))
+    }
+
+    pub fn server_pair_receipt(domain: &str) -> Result<PathBuf> {
+        store_path(Role::ServerPairReceipt, Some(domain))
+    }
+
+    pub fn server_dhparam(domain: &str) -> Result<PathBuf> {
+        store_path(Role::ServerDhparam, Some(domain))
+    }
+
+    pub fn server_ticket_key(domain: &str) -> Result<PathBuf> {
+        store_path(Role::ServerTicketKey, Some(domain))
+    }
+
+    pub fn server_haproxy(domain: &str) -> Result<PathBuf> {
+        store_path(Role::ServerHaproxy, Some(domain))
     }
 
-    pub fn ca_name(&self) -> Name {
-        let org = opt_value(&self.organization, "Simple CA");
+    pub fn server_cert_der(domain: &str) -> Result<PathBuf> {
+        store_path(Role::ServerCertDer, Some(domain))
+    }
+
+    pub fn server_key_der(domain: &str) -> Result<PathBuf> {
+        store_path(Role::ServerKeyDer, Some(domain))
+    }
+
+    pub fn named_intermediate_key(name: &str) -> Result<PathBuf> {
+        store_path(Role::NamedIntermediateKey, Some(name))
+    }
+
+    pub fn named_intermediate_cert(name: &str) -> Result<PathBuf> {
+        store_path(Role::NamedIntermediateCert, Some(name))
+    }
+
+    pub fn named_intermediate_chain(name: &str) -> Result<PathBuf> {
+        store_path(Role::NamedIntermediateChain, Some(name))
+    }
+
+    pub fn intermediate_path_len(&self) -> Option<u32> {
+        self.intermediate_path_len
+    }
+
+    pub fn ca_name(&self, branding: &BrandingConf) -> Name {
+        let org = self
+            .organization
+            .clone()
+            .or_else(|| branding.organization.clone());
+        let common_name = branding.root_common_name(org.as_deref().unwrap_or(DEFAULT_ORG));
         Name {
-            country: opt_value(&self.country, ""),
-            province: opt_value(&self.state_or_province, ""),
-            locality: opt_value(&self.locality, ""),
-            org: opt_value(&self.organization, ""),
-            org_unit: opt_value(&self.organization_unit, ""),
-            common_name: format!("{} Root CA", org),
+            country: self.country.clone(),
+            province: self.state_or_province.clone(),
+            locality: self.locality.clone(),
+            org,
+            org_unit: self.organization_unit.clone(),
+            common_name: Some(common_name),
+        }
+    }
+
+    pub fn intermediate_name(&self, branding: &BrandingConf) -> Name {
+        let ca_name = self.ca_name(branding);
+        ca_name.copy(&branding.intermediate_common_name(ca_name.org.as_deref().unwrap_or_default()))
+    }
+
+    /// Subject of the intermediate created with `intermediate create <name>`.
+    pub fn named_intermediate_name(&self, branding: &BrandingConf, name: &str) -> Name {
+        let ca_name = self.ca_name(branding);
+        ca_name.copy(
+            &branding
+                .named_intermediate_common_name(ca_name.org.as_deref().unwrap_or_default(), name),
+        )
+    }
+}
+
+/// Validity periods in days, each falling back to the built-in default.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct ValidityConf {
+    pub ca_days: Option<u32>,
+    pub intermediate_days: Option<u32>,
+    pub server_days: Option<u32>,
+    pub server_ttl: Option<String>,
+    /// Explicit RFC 3339 start of server certificates, the issuance time by
+    /// default.
+    pub not_before: Option<String>,
+    /// Explicit RFC 3339 end of server certificates, taking precedence over
+    /// `server_days` and `server_ttl`.
+    pub not_after: Option<String>,
+    /// How far before issuance certificates start, like `1h`, or `0` to start
+    /// them at issuance.
+    pub backdate: Option<String>,
+}
+
+impl ValidityConf {
+    /// Fills every unset period from `other`.
+    pub fn or(&self, other: &ValidityConf) -> ValidityConf {
+        let server = if self.server_days.is_some() || self.server_ttl.is_some() {
+            self
+        } else {
+            other
+        };
+        ValidityConf {
+            ca_days: self.ca_days.or(other.ca_days),
+            intermediate_days: self.intermediate_days.or(other.intermediate_days),
+            server_days: server.server_days,
+            server_ttl: server.server_ttl.clone(),
+            not_before: self.not_before.clone().or_else(|| other.not_before.clone()),
+            not_after: self.not_after.clone().or_else(|| other.not_after.clone()),
+            backdate: self.backdate.clone().or_else(|| other.backdate.clone()),
+        }
+    }
+
+    pub fn ca_days(&self) -> u32 {
+        self.ca_days.unwrap_or(DEFAULT_CA_DAYS)
+    }
+
+    pub fn intermediate_days(&self) -> u32 {
+        self.intermediate_days.unwrap_or(DEFAULT_INTERMEDIATE_DAYS)
+    }
+
+    pub fn server_days(&self) -> u32 {
+        self.server_days.unwrap_or(DEFAULT_SERVER_DAYS)
+    }
+
+    /// Server certificate lifetime in whole days, rounding a TTL or an
+    /// explicit window up. A window without a start starts at `now`.
+    pub fn server_lifetime_days(&self, now: SystemTime) -> Result<i64> {
+        let (not_before, not_after) = self.server_window()?;
+        if let Some(not_after) = not_after {
+            let not_before = match not_before {
+                Some(not_before) => not_before,
+                None => now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs() as i64,
+            };
+            return Ok(((not_after - not_before).max(0) as u64).div_ceil(86_400) as i64);
+        }
+        Ok(match self.server_ttl()? {
+            Some(ttl) => ttl.as_secs().div_ceil(86_400) as i64,
+            None => self.server_days() as i64,
+        })
+    }
+
+    /// The explicit `not_before` and `not_after` of server certificates as
+    /// Unix timestamps.
+    pub fn server_window(&self) -> Result<(Option<i64>, Option<i64>)> {
+        let not_before = self.not_before.as_deref().map(parse_rfc3339).transpose()?;
+        let not_after = self.not_after.as_deref().map(parse_rfc3339).transpose()?;
+        if let (Some(start), Some(end)) = (not_before, not_after) {
+            if start >= end {
+                Err(SimpleCAError::EmptyValidity {
+                    not_before: format_unix(start),
+                    not_after: format_unix(end),
+                })?;
+            }
+        }
+        Ok((not_before, not_after))
+    }
+
+    /// How far before issuance certificates start, an hour by default.
+    pub fn backdate(&self) -> Result<Duration> {
+        match self.backdate.as_deref().map(str::trim) {
+            Some("0") => Ok(Duration::ZERO),
+            Some(backdate) => Ok(parse_ttl(backdate)?),
+            None => Ok(DEFAULT_BACKDATE),
+        }
+    }
+
+    /// Sub-day server lifetime, taking precedence over `server_days`.
+    pub fn server_ttl(&self) -> Result<Option<Duration>> {
+        match &self.server_ttl {
+            Some(ttl) => Ok(Some(parse_ttl(ttl)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Opt-in renewal of expiring server certificates whenever a command runs.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct RenewConf {
+    pub auto: Option<bool>,
+    pub within_days: Option<u32>,
+}
+
+impl RenewConf {
+    pub fn auto(&self) -> bool {
+        self.auto.unwrap_or(false)
+    }
+
+    pub fn within_days(&self) -> u32 {
+        self.within_days.unwrap_or(DEFAULT_RENEW_WITHIN_DAYS)
+    }
+}
+
+/// Algorithms used when generating new private keys.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct KeysConf {
+    /// Algorithms used unless set one by one below.
+    pub profile: Option<KeyProfile>,
+    pub ca_algorithm: Option<KeyAlgorithm>,
+    pub server_algorithm: Option<KeyAlgorithm>,
+    /// Encrypts newly generated CA keys with a passphrase.
+    pub encrypt_ca: Option<bool>,
+    /// Digest every new certificate is signed with.
+    pub digest: Option<SignatureDigest>,
+    /// Keys of each algorithm `serve` keeps pre-generated, none when unset.
+    pub pool_size: Option<usize>,
+}
+
+impl KeysConf {
+    pub fn digest(&self) -> SignatureDigest {
+        self.digest.unwrap_or_default()
+    }
+
+    pub fn encrypt_ca(&self) -> bool {
+        self.encrypt_ca.unwrap_or(false)
+    }
+
+    pub fn ca_algorithm(&self) -> KeyAlgorithm {
+        self.ca_algorithm
+            .unwrap_or_else(|| self.profile().ca_algorithm())
+    }
+
+    pub fn server_algorithm(&self) -> KeyAlgorithm {
+        self.server_algorithm
+            .unwrap_or_else(|| self.profile().server_algorithm())
+    }
+
+    pub fn profile(&self) -> KeyProfile {
+        self.profile.unwrap_or_default()
+    }
+
+    pub fn pool_size(&self) -> usize {
+        self.pool_size.unwrap_or(0)
+    }
+
+    /// The algorithms of the keys the CA generates, each once.
+    pub fn algorithms(&self) -> Vec<KeyAlgorithm> {
+        let mut algorithms = vec![self.ca_algorithm()];
+        if self.server_algorithm() != self.ca_algorithm() {
+            algorithms.push(self.server_algorithm());
         }
+        algorithms
+    }
+}
+
+/// Provider reaching PKCS#11 tokens unless `[pkcs11] provider` names another.
+const DEFAULT_PKCS11_PROVIDER: &str = "pkcs11";
+
+/// CA keys held in a PKCS#11 token, e.g. SoftHSM or an HSM, instead of PEM
+/// files in the store. The keys are named by RFC 7512 URIs.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct Pkcs11Conf {
+    /// OpenSSL provider reaching the token.
+    pub provider: Option<String>,
+    /// PKCS#11 module of the token, unless the provider is configured with one.
+    pub module: Option<PathBuf>,
+    pub ca_key: Option<String>,
+    pub intermediate_key: Option<String>,
+}
+
+impl Pkcs11Conf {
+    pub fn provider(&self) -> &str {
+        self.provider.as_deref().unwrap_or(DEFAULT_PKCS11_PROVIDER)
+    }
+}
+
+/// The intermediate key in a cloud KMS instead of a PEM file in the store:
+/// an AWS KMS key ID, alias or ARN, or a Cloud KMS key version name.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct KmsConf {
+    pub intermediate_key: Option<String>,
+}
+
+/// The intermediate key in a TPM 2.0, at a persistent handle like
+/// `0x81010001`, used through the tpm2 OpenSSL provider.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct TpmConf {
+    pub intermediate_handle: Option<String>,
+}
+
+/// Order of the RDNs in issued subject names, for validators comparing DNs
+/// order-sensitively.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct NameConf {
+    pub order: Option<Vec<NameField>>,
+}
+
+impl NameConf {
+    pub fn order(&self) -> Vec<NameField> {
+        self.order
+            .clone()
+            .unwrap_or_else(|| DEFAULT_NAME_ORDER.to_vec())
+    }
+}
+
+/// Organization of a new CA when neither `[ca]` nor `[branding]` names one.
+const DEFAULT_ORG: &str = "Simple CA";
+
+/// Strings identifying the tool in CA subjects and server certificates, for
+/// products shipping simple-ca under their own name. In the common name
+/// templates `{org}` is the organization and `{name}` the intermediate name.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct BrandingConf {
+    /// Organization of the CA when `[ca]` sets none.
+    pub organization: Option<String>,
+    pub root_common_name: Option<String>,
+    pub intermediate_common_name: Option<String>,
+    pub named_intermediate_common_name: Option<String>,
+    /// Netscape comment of server certificates, left out when empty.
+    pub server_comment: Option<String>,
+}
+
+fn fill_template(template: &Option<String>, default: &str, org: &str, name: &str) -> String {
+    template
+        .as_deref()
+        .unwrap_or(default)
+        .replace("{org}", org)
+        .replace("{name}", name)
+}
+
+impl BrandingConf {
+    pub fn root_common_name(&self, org: &str) -> String {
+        fill_template(&self.root_common_name, "{org} Root CA", org, "")
+    }
+
+    pub fn intermediate_common_name(&self, org: &str) -> String {
+        fill_template(
+            &self.intermediate_common_name,
+            "{org} Intermediate CA",
+            org,
+            "",
+        )
+    }
+
+    pub fn named_intermediate_common_name(&self, org: &str, name: &str) -> String {
+        fill_template(
+            &self.named_intermediate_common_name,
+            "{org} Intermediate CA {name}",
+            org,
+            name,
+        )
+    }
+
+    pub fn server_comment(&self) -> Option<String> {
+        match self.server_comment.as_deref() {
+            Some("") => None,
+            Some(comment) => Some(comment.to_string()),
+            None => Some(DEFAULT_COMMENT.to_string()),
+        }
+    }
+}
+
+/// Commands run after a server certificate was written, see the `hooks` module.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct HooksConf {
+    pub post_issue: Option<String>,
+}
+
+/// Issuance policy rules, see the `policy` module.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct PolicyConf {
+    pub rules: Option<Vec<String>>,
+}
+
+impl PolicyConf {
+    pub fn rules(&self) -> Vec<String> {
+        self.rules.clone().unwrap_or_default()
     }
+}
+
+/// How serial numbers of new certificates are allocated.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct SerialConf {
+    pub strategy: Option<SerialStrategy>,
+}
 
-    pub fn intermediate_name(&self) -> Name {
-        let ca_name = self.ca_name();
-        ca_name.copy(&format!("{} Intermediate CA", ca_name.org))
+impl SerialConf {
+    pub fn strategy(&self) -> SerialStrategy {
+        self.strategy.unwrap_or_default()
+    }
+}
+
+/// IP SANs added to every server certificate.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct SansConf {
+    pub ips: Option<Vec<String>>,
+    /// Also add the addresses of the local network interfaces.
+    pub auto_ip: Option<bool>,
+}
+
+impl SansConf {
+    pub fn auto_ip(&self) -> bool {
+        self.auto_ip.unwrap_or(false)
+    }
+
+    /// The configured IPs followed by the detected ones when `auto_ip` is set.
+    pub fn default_ips(&self) -> Result<Vec<String>> {
+        let mut ips = Vec::new();
+        for ip in self.ips.iter().flatten() {
+            let ip: IpAddr = ip
+                .parse()
+                .map_err(|_| SimpleCAError::InvalidIpAddress { value: ip.clone() })?;
+            ips.push(ip);
+        }
+        if self.auto_ip() {
+            ips.extend(local_ips()?);
+        }
+        let mut unique: Vec<String> = Vec::new();
+        for ip in ips.iter().map(|ip| ip.to_string()) {
+            if !unique.contains(&ip) {
+                unique.push(ip);
+            }
+        }
+        Ok(unique)
     }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Conf {
     ca: Option<CertAuthConf>,
+    validity: Option<ValidityConf>,
+    renew: Option<RenewConf>,
+    keys: Option<KeysConf>,
+    pkcs11: Option<Pkcs11Conf>,
+    kms: Option<KmsConf>,
+    tpm: Option<TpmConf>,
+    name: Option<NameConf>,
+    policy: Option<PolicyConf>,
+    name_constraints: Option<NameConstraints>,
+    sans: Option<SansConf>,
+    serials: Option<SerialConf>,
+    branding: Option<BrandingConf>,
+    hooks: Option<HooksConf>,
 }
 
-impl Conf {
-    pub fn default() -> Conf {
+impl Default for Conf {
+    fn default() -> Conf {
         Conf {
             ca: Some(CertAuthConf::default()),
+            validity: None,
+            renew: None,
+            keys: None,
+            pkcs11: None,
+            kms: None,
+            tpm: None,
+            name: None,
+            policy: None,
+            name_constraints: None,
+            sans: None,
+            serials: None,
+            branding: None,
+            hooks: None,
         }
     }
+}
 
+impl Conf {
     pub fn load() -> Result<Conf> {
         with_config_dir(|mut dir| {
             dir.push(CONFIG_FILE);
             let config_path = dir;
+            verify_config(&config_path)?;
             Conf::load_config(&config_path)
         })
     }
 
-    pub fn ca(&self) -> &CertAuthConf {
-        self.ca.as_ref().unwrap()
+    /// Path of the config file, which may not exist yet.
+    pub fn path() -> Result<PathBuf> {
+        file_in_conf(CONFIG_FILE)
+    }
+
+    pub fn ca(&self) -> CertAuthConf {
+        self.ca.clone().unwrap_or_default()
+    }
+
+    pub fn validity(&self) -> ValidityConf {
+        self.validity.clone().unwrap_or_default()
+    }
+
+    pub fn renew(&self) -> RenewConf {
+        self.renew.clone().unwrap_or_default()
+    }
+
+    pub fn keys(&self) -> KeysConf {
+        self.keys.clone().unwrap_or_default()
+    }
+
+    pub fn pkcs11(&self) -> Pkcs11Conf {
+        self.pkcs11.clone().unwrap_or_default()
+    }
+
+    pub fn set_pkcs11(&mut self, pkcs11: Pkcs11Conf) {
+        self.pkcs11 = Some(pkcs11);
+    }
+
+    pub fn kms(&self) -> KmsConf {
+        self.kms.clone().unwrap_or_default()
+    }
+
+    pub fn set_kms(&mut self, kms: KmsConf) {
+        self.kms = Some(kms);
+    }
+
+    pub fn tpm(&self) -> TpmConf {
+        self.tpm.clone().unwrap_or_default()
+    }
+
+    pub fn set_tpm(&mut self, tpm: TpmConf) {
+        self.tpm = Some(tpm);
+    }
+
+    /// Drops the intermediate key from the `[pkcs11]`, `[kms]` and `[tpm]`
+    /// sections, before the key is moved somewhere else.
+    pub fn clear_intermediate_key(&mut self) {
+        if let Some(pkcs11) = &mut self.pkcs11 {
+            pkcs11.intermediate_key = None;
+        }
+        if let Some(kms) = &mut self.kms {
+            kms.intermediate_key = None;
+        }
+        if let Some(tpm) = &mut self.tpm {
+            tpm.intermediate_handle = None;
+        }
+    }
+
+    pub fn name(&self) -> NameConf {
+        self.name.clone().unwrap_or_default()
+    }
+
+    pub fn policy(&self) -> PolicyConf {
+        self.policy.clone().unwrap_or_default()
+    }
+
+    pub fn sans(&self) -> SansConf {
+        self.sans.clone().unwrap_or_default()
+    }
+
+    pub fn serials(&self) -> SerialConf {
+        self.serials.clone().unwrap_or_default()
+    }
+
+    pub fn branding(&self) -> BrandingConf {
+        self.branding.clone().unwrap_or_default()
+    }
+
+    pub fn hooks(&self) -> HooksConf {
+        self.hooks.clone().unwrap_or_default()
+    }
+
+    /// Name constraints of newly created CA certificates.
+    pub fn name_constraints(&self) -> Option<NameConstraints> {
+        self.name_constraints.clone()
     }
 
     pub fn load_config(path: &Path) -> Result<Conf> {
@@ -168,7 +968,11 @@ impl Conf {
             let mut f = File::open(path)?;
             f.read_to_string(&mut config_str)?;
 
-            let conf: Conf = toml::from_str(&config_str)?;
+            let conf: Conf =
+                toml::from_str(&config_str).map_err(|err| SimpleCAError::InvalidConfig {
+                    path: path.to_path_buf(),
+                    msg: err.to_string(),
+                })?;
             Ok(conf)
         } else {
             let conf = Conf::default();
@@ -179,8 +983,23 @@ impl Conf {
 
     pub fn save(&self, path: &Path) -> Result<()> {
         let content = toml::to_string_pretty(self)?;
-        let mut f = File::create(path)?;
-        f.write_all(content.as_bytes())?;
+        write_file(path, content)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserved_profiles() {
+        for profile in ["", ".hidden", "a/b", "archive", "key-pool"] {
+            assert!(matches!(
+                set_profile(profile),
+                Err(SimpleCAError::InvalidProfile { .. })
+            ));
+        }
+        assert_eq!(profile(), DEFAULT_PROFILE);
+    }
+}
@@ -0,0 +1,345 @@
+//! Signed config for shared deployments: once the config is signed, by the
+//! root key or an ops key the root trusts, it is verified on every load so an
+//! issuance policy distributed to a shared CA cannot be edited unnoticed.
+//!
+//! Signing pins the root key in the user's config dir, outside the store, so
+//! replacing the root certificate or deleting the signature files of the
+//! store does not turn verification off.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use openssl::base64;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{HasPrivate, PKey, PKeyRef, Public};
+use openssl::sign::{Signer, Verifier};
+
+use crate::certs::pubkey_fingerprint;
+use crate::commands::{read_file, read_root_cert};
+use crate::conf::{add_line, file_in_conf, user_file, CertAuthConf, Conf};
+use crate::err::{Result, SimpleCAError};
+use crate::lock::StoreLock;
+use crate::matching::key_matches_cert;
+use crate::passphrase::read_private_key;
+use crate::store::{self, is_recorded, Role};
+
+const PUBLIC_KEY_END: &str = "-----END PUBLIC KEY-----";
+/// Lines of `<root key fingerprint> <config path>` in the user's config dir.
+const PINNED_ROOTS_FILE: &str = "config-roots";
+
+/// Detached signature written next to the signed file.
+#[derive(Debug, Deserialize, Serialize)]
+struct Signature {
+    /// SHA-256 fingerprint of the public key that made the signature.
+    signer: String,
+    signature: String,
+}
+
+impl Signature {
+    fn create<T: HasPrivate>(pkey: &PKeyRef<T>, data: &[u8]) -> Result<Signature> {
+        let mut signer = Signer::new(MessageDigest::sha256(), pkey)?;
+        signer.update(data)?;
+        Ok(Signature {
+            signer: pubkey_fingerprint(pkey)?,
+            signature: base64::encode_block(&signer.sign_to_vec()?),
+        })
+    }
+
+    fn read(path: &Path) -> Option<Signature> {
+        serde_json::from_slice(&read_file(path).ok()?).ok()
+    }
+
+    /// Checks the signature against whichever of `keys` made it.
+    fn verify(&self, keys: &[PKey<Public>], data: &[u8]) -> Result<bool> {
+        let signature = match base64::decode_block(&self.signature) {
+            Ok(signature) => signature,
+            Err(_) => return Ok(false),
+        };
+        for key in keys {
+            if pubkey_fingerprint(key)? != self.signer {
+                continue;
+            }
+            let mut verifier = Verifier::new(MessageDigest::sha256(), key)?;
+            verifier.update(data)?;
+            return Ok(verifier.verify(&signature).unwrap_or(false));
+        }
+        Ok(false)
+    }
+}
+
+/// Fingerprint of the root key pinned for the config at `config` when it
+/// was first signed.
+fn pinned_root(config: &Path) -> Option<String> {
+    let pins = fs::read_to_string(user_file(PINNED_ROOTS_FILE)?).ok()?;
+    let config = config.display().to_string();
+    pins.lines()
+        .filter_map(|line| line.split_once(' '))
+        .find(|(_, path)| *path == config)
+        .map(|(fingerprint, _)| fingerprint.to_string())
+}
+
+/// Pins `root` for the config at `config`, keeping an earlier pin.
+fn pin_root(config: &Path, root: &PKey<Public>) -> Result<()> {
+    if pinned_root(config).is_some() {
+        return Ok(());
+    }
+    let pins = user_file(PINNED_ROOTS_FILE).ok_or(SimpleCAError::GenericError {
+        msg: "Unable to locate the user config directory.",
+    })?;
+    add_line(
+        &pins,
+        &format!("{} {}", pubkey_fingerprint(root)?, config.display()),
+    )
+}
+
+/// The key of the root certificate in the store, which must be the pinned
+/// one once the config was signed.
+fn root_public_key() -> Result<PKey<Public>> {
+    let ca = read_root_cert()?;
+    let root = ca.public_key()?;
+    if let Some(pinned) = pinned_root(&Conf::path()?) {
+        if pubkey_fingerprint(&root)? != pinned {
+            Err(SimpleCAError::UntrustedConfig {
+                path: CertAuthConf::ca_cert()?,
+                reason: "its key is not the root key the config was signed under",
+            })?;
+        }
+    }
+    Ok(root)
+}
+
+fn parse_public_keys(pem: &str) -> Result<Vec<PKey<Public>>> {
+    pem.split_inclusive(PUBLIC_KEY_END)
+        .filter(|block| block.contains(PUBLIC_KEY_END))
+        .map(|block| Ok(PKey::public_key_from_pem(block.trim_start().as_bytes())?))
+        .collect()
+}
+
+/// Whether the file of `role` is in the store, recorded in the manifest or
+/// just lying at its default path, so that dropping its manifest entry does
+/// not turn config signing off.
+fn has_file(role: Role) -> Result<bool> {
+    Ok(is_recorded(role, None)? || file_in_conf(&role.default_file(None))?.exists())
+}
+
+/// Public keys allowed to sign the config: the root key and the signers the
+/// root added with `trust_config_signer`.
+fn trusted_signers() -> Result<Vec<PKey<Public>>> {
+    let root = root_public_key()?;
+    let mut keys = Vec::new();
+    if has_file(Role::ConfigSigners)? {
+        let signers_path = CertAuthConf::config_signers()?;
+        let signers = read_file(&signers_path).unwrap_or_default();
+        let trusted = Signature::read(&CertAuthConf::config_signers_signature()?)
+            .map(|signature| signature.verify(std::slice::from_ref(&root), &signers))
+            .transpose()?
+            .unwrap_or(false);
+        if !trusted {
+            Err(SimpleCAError::UntrustedConfig {
+                path: signers_path,
+                reason: "it was not signed by the root key",
+            })?;
+        }
+        keys = parse_public_keys(&String::from_utf8_lossy(&signers))?;
+    }
+    keys.insert(0, root);
+    Ok(keys)
+}
+
+/// Verifies the config at `path` once it was signed, unsigned configs load as
+/// before. A config stays signed once a root is pinned for it, and while
+/// `config.sig` or the trusted signers exist, whatever the manifest says.
+/// Called by `Conf::load`.
+pub(crate) fn verify_config(path: &Path) -> Result<()> {
+    if pinned_root(path).is_none()
+        && !has_file(Role::ConfigSignature)?
+        && !has_file(Role::ConfigSigners)?
+    {
+        return Ok(());
+    }
+    let untrusted = |reason| SimpleCAError::UntrustedConfig {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let config =
+        read_file(path).map_err(|_| untrusted("it is missing while its signature exists"))?;
+    let signature = Signature::read(&CertAuthConf::config_signature()?).ok_or_else(|| {
+        untrusted("its signature is missing, `config sign --key <file>` signs it again")
+    })?;
+    if !signature.verify(&trusted_signers()?, &config)? {
+        Err(untrusted(
+            "it changed since it was signed, `config sign --key <file>` signs it again",
+        ))?;
+    }
+    Ok(())
+}
+
+/// Fingerprint of the key that signed the config, `None` while it is unsigned.
+/// Fails when the signature does not match.
+pub fn config_signer() -> Result<Option<String>> {
+    verify_config(&Conf::path()?)?;
+    if !has_file(Role::ConfigSignature)? {
+        return Ok(None);
+    }
+    Ok(Signature::read(&CertAuthConf::config_signature()?).map(|signature| signature.signer))
+}
+
+/// Signs the config with the private key at `key`, which must be the root key
+/// or one of the trusted signers. From then on the config is only loaded
+/// while the signature matches.
+pub fn sign_config(key: &Path, verbose: bool) -> Result<PathBuf> {
+    let _lock = StoreLock::acquire()?;
+    let pkey = read_private_key(key)?;
+    let fingerprint = pubkey_fingerprint(&pkey)?;
+    let mut trusted = false;
+    for signer in trusted_signers()? {
+        trusted |= pubkey_fingerprint(&signer)? == fingerprint;
+    }
+    if !trusted {
+        Err(SimpleCAError::UntrustedConfigSigner {
+            path: key.to_path_buf(),
+        })?;
+    }
+
+    // Creates the default config when there is none yet, without checking
+    // the signature that is about to be replaced.
+    let config_path = Conf::path()?;
+    Conf::load_config(&config_path)?;
+    let signature = Signature::create(&pkey, &read_file(&config_path)?)?;
+    let signature_path = CertAuthConf::config_signature()?;
+    write_file!(
+        &serde_json::to_vec_pretty(&signature)?,
+        &signature_path,
+        verbose,
+        "Saved config signature at: {:?}"
+    );
+    store::record(Role::ConfigSignature, None, &signature_path, None)?;
+    pin_root(&config_path, &root_public_key()?)?;
+    Ok(signature_path)
+}
+
+/// Trusts the public key at `public_key` to sign the config. The list of
+/// signers is signed with the root key at `root_key`, so it also works while
+/// the root is offline. Returns the fingerprint of the added key.
+pub fn trust_config_signer(public_key: &Path, root_key: &Path, verbose: bool) -> Result<String> {
+    let _lock = StoreLock::acquire()?;
+    let ca = read_root_cert()?;
+    let root = root_public_key()?;
+    let root_pkey = read_private_key(root_key)?;
+    if !key_matches_cert(&root_pkey, &ca)? {
+        Err(SimpleCAError::WrongRootKey {
+            path: root_key.to_path_buf(),
+        })?;
+    }
+    let signer = PKey::public_key_from_pem(&read_file(public_key)?)?;
+    let fingerprint = pubkey_fingerprint(&signer)?;
+
+    let signers_path = CertAuthConf::config_signers()?;
+    let mut signers = if has_file(Role::ConfigSigners)? {
+        trusted_signers()?.split_off(1)
+    } else {
+        Vec::new()
+    };
+    let mut known = false;
+    for key in &signers {
+        known |= pubkey_fingerprint(key)? == fingerprint;
+    }
+    if !known {
+        signers.push(signer);
+    }
+    let mut pem = Vec::new();
+    for key in &signers {
+        pem.extend(key.public_key_to_pem()?);
+    }
+    write_file!(
+        &pem,
+        &signers_path,
+        verbose,
+        "Saved trusted config signers at: {:?}"
+    );
+    store::record(Role::ConfigSigners, None, &signers_path, Some(&ca))?;
+
+    let signature_path = CertAuthConf::config_signers_signature()?;
+    write_file!(
+        &serde_json::to_vec_pretty(&Signature::create(&root_pkey, &pem)?)?,
+        &signature_path,
+        verbose,
+        "Saved config signers signature at: {:?}"
+    );
+    store::record(
+        Role::ConfigSignersSignature,
+        None,
+        &signature_path,
+        Some(&ca),
+    )?;
+    pin_root(&Conf::path()?, &root)?;
+    Ok(fingerprint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_verify() {
+        let rsa = openssl::rsa::Rsa::generate(2048).unwrap();
+        let pkey = PKey::from_rsa(rsa).unwrap();
+        let public = PKey::public_key_from_pem(&pkey.public_key_to_pem().unwrap()).unwrap();
+        let signature = Signature::create(&pkey, b"[policy]\nrules = []\n").unwrap();
+        assert!(signature
+            .verify(std::slice::from_ref(&public), b"[policy]\nrules = []\n")
+            .unwrap());
+        assert!(!signature
+            .verify(
+                std::slice::from_ref(&public),
+                b"[policy]\nrules = ['deny when true']\n"
+            )
+            .unwrap());
+
+        let pem = String::from_utf8(pkey.public_key_to_pem().unwrap().repeat(2)).unwrap();
+        assert_eq!(parse_public_keys(&pem).unwrap().len(), 2);
+    }
+    #[test]
+    fn test_signed_config_fails_closed() {
+        use crate::certs::fixtures::test_root_ca;
+        use crate::commands::fixtures::issue_test_server;
+        use crate::commands::ServerExtras;
+        use crate::conf::with_test_store;
+        use crate::store::STORE_MANIFEST;
+
+        with_test_store("config-sig", |home| {
+            issue_test_server("app.test", &[], &ServerExtras::default());
+            let root_key = CertAuthConf::ca_key().unwrap();
+            sign_config(&root_key, false).unwrap();
+            assert!(Conf::load().is_ok());
+            let untrusted = || matches!(Conf::load(), Err(SimpleCAError::UntrustedConfig { .. }));
+
+            // Dropping the signature and the manifest does not unsign it.
+            let signature = CertAuthConf::config_signature().unwrap();
+            let signed = read_file(&signature).unwrap();
+            fs::remove_file(&signature).unwrap();
+            fs::remove_file(home.join(STORE_MANIFEST)).unwrap();
+            assert!(untrusted());
+            fs::write(&signature, &signed).unwrap();
+            assert!(Conf::load().is_ok());
+
+            // Nor does a root of one's own, re-signing with its key.
+            let (_, other_key, other) = test_root_ca();
+            fs::write(CertAuthConf::ca_cert().unwrap(), other.to_pem().unwrap()).unwrap();
+            let other_key_path = home.join("other.key.pem");
+            fs::write(
+                &other_key_path,
+                other_key.private_key_to_pem_pkcs8().unwrap(),
+            )
+            .unwrap();
+            assert!(untrusted());
+            assert!(matches!(
+                sign_config(&other_key_path, false),
+                Err(SimpleCAError::UntrustedConfig { .. })
+            ));
+            assert!(untrusted());
+        });
+    }
+}
@@ -0,0 +1,190 @@
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+
+use openssl::asn1::{Asn1Object, Asn1OctetString};
+use openssl::x509::X509Extension;
+
+use crate::der;
+use crate::err::{Result, SimpleCAError};
+
+/// GeneralName tags of a dNSName and an iPAddress.
+const TAG_DNS_NAME: u8 = 0x82;
+const TAG_IP_ADDRESS: u8 = 0x87;
+
+/// DNS and IP subtrees the CA certificates restrict issuance to, written as
+/// a critical NameConstraints extension.
+///
+/// A DNS entry like `corp.test` covers the domain and its subdomains, while
+/// `*.corp.test` covers only the subdomains. IP entries are addresses or
+/// CIDR ranges such as `10.0.0.0/8`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct NameConstraints {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub permitted_dns: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excluded_dns: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub permitted_ip: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excluded_ip: Vec<String>,
+}
+
+fn invalid(value: &str) -> SimpleCAError {
+    SimpleCAError::InvalidNameConstraint {
+        value: value.to_string(),
+    }
+}
+
+fn dns_subtree(domain: &str) -> Result<Vec<u8>> {
+    let base = match domain.strip_prefix('*') {
+        Some(subdomains) if subdomains.starts_with('.') => subdomains,
+        Some(_) => Err(invalid(domain))?,
+        None => domain,
+    };
+    if base.is_empty() || !base.is_ascii() || base.contains(['*', '/', ' ']) {
+        Err(invalid(domain))?;
+    }
+    Ok(der::sequence(&[der::tlv(TAG_DNS_NAME, base.as_bytes())]))
+}
+
+/// Encodes an address or CIDR range as the address followed by its netmask.
+fn ip_subtree(range: &str) -> Result<Vec<u8>> {
+    let (addr, prefix) = match range.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (range, None),
+    };
+    let mut bytes = match addr.parse::<IpAddr>().map_err(|_| invalid(range))? {
+        IpAddr::V4(addr) => addr.octets().to_vec(),
+        IpAddr::V6(addr) => addr.octets().to_vec(),
+    };
+    let bits = bytes.len() * 8;
+    let prefix = match prefix {
+        Some(prefix) => prefix.parse::<usize>().map_err(|_| invalid(range))?,
+        None => bits,
+    };
+    if prefix > bits {
+        Err(invalid(range))?;
+    }
+    let mask: Vec<u8> = (0..bytes.len())
+        .map(|i| {
+            let ones = prefix.saturating_sub(i * 8).min(8);
+            (0xff00u16 >> ones) as u8
+        })
+        .collect();
+    for (byte, mask) in bytes.iter_mut().zip(&mask) {
+        *byte &= mask;
+    }
+    bytes.extend(mask);
+    Ok(der::sequence(&[der::tlv(TAG_IP_ADDRESS, &bytes)]))
+}
+
+impl NameConstraints {
+    pub fn is_empty(&self) -> bool {
+        self.permitted_dns.is_empty()
+            && self.excluded_dns.is_empty()
+            && self.permitted_ip.is_empty()
+            && self.excluded_ip.is_empty()
+    }
+
+    /// DER encoding of the NameConstraints value.
+    pub(crate) fn to_der(&self) -> Result<Vec<u8>> {
+        let subtrees = |dns: &[String], ip: &[String]| -> Result<Vec<Vec<u8>>> {
+            let mut subtrees = Vec::new();
+            for domain in dns {
+                subtrees.push(dns_subtree(domain)?);
+            }
+            for range in ip {
+                subtrees.push(ip_subtree(range)?);
+            }
+            Ok(subtrees)
+        };
+        let mut fields = Vec::new();
+        let permitted = subtrees(&self.permitted_dns, &self.permitted_ip)?;
+        if !permitted.is_empty() {
+            fields.push(der::context(0, &permitted.concat()));
+        }
+        let excluded = subtrees(&self.excluded_dns, &self.excluded_ip)?;
+        if !excluded.is_empty() {
+            fields.push(der::context(1, &excluded.concat()));
+        }
+        Ok(der::sequence(&fields))
+    }
+
+    pub(crate) fn build(&self) -> Result<X509Extension> {
+        let oid = Asn1Object::from_str("2.5.29.30")?;
+        let value = Asn1OctetString::new_from_bytes(&self.to_der()?)?;
+        Ok(X509Extension::new_from_der(&oid, true, &value)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cert_params::CertParams;
+    use crate::certs::fixtures::test_root_ca_with;
+    use crate::certs::{create_intermediate_ca, create_server_cert};
+    use crate::keys::KeyAlgorithm;
+    use crate::Name;
+
+    use openssl::stack::Stack;
+    use openssl::x509::store::X509StoreBuilder;
+    use openssl::x509::X509StoreContext;
+
+    #[test]
+    fn test_name_constraints() {
+        assert_eq!(
+            ip_subtree("10.1.2.3/12").unwrap(),
+            vec![0x30, 0x0a, 0x87, 0x08, 10, 0, 0, 0, 0xff, 0xf0, 0, 0]
+        );
+        assert!(dns_subtree("*corp.test").is_err());
+        assert!(ip_subtree("10.0.0.0/33").is_err());
+
+        let name = Name::new("constrained root");
+        let (root_name, root_key, root) = test_root_ca_with(|params| {
+            params.name_constraints = Some(NameConstraints {
+                permitted_dns: vec!["corp.test".to_string(), "*.localhost".to_string()],
+                ..NameConstraints::default()
+            });
+        });
+        let intermediate_name = name.copy("intermediate").to_x509_name().unwrap();
+        let intermediate_key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let intermediate_params = CertParams::intermediate_ca_params(
+            &intermediate_name,
+            &intermediate_key,
+            &root_name,
+            &root_key,
+            1,
+        )
+        .unwrap();
+        let intermediate = create_intermediate_ca(&intermediate_params, &root).unwrap();
+
+        let verifies = |domain: &str| {
+            let server_name = name.copy(domain).to_x509_name().unwrap();
+            let server_key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+            let params = CertParams::server_cert_params(
+                &server_name,
+                &server_key,
+                &intermediate_name,
+                &intermediate_key,
+                1,
+                &vec![],
+            )
+            .unwrap();
+            let cert = create_server_cert(&params, &intermediate).unwrap();
+            let mut store = X509StoreBuilder::new().unwrap();
+            store.add_cert(root.clone()).unwrap();
+            let store = store.build();
+            let mut chain = Stack::new().unwrap();
+            chain.push(intermediate.clone()).unwrap();
+            let mut context = X509StoreContext::new().unwrap();
+            context
+                .init(&store, &cert, &chain, |context| context.verify_cert())
+                .unwrap()
+        };
+        assert!(verifies("api.corp.test"));
+        assert!(verifies("app.localhost"));
+        assert!(!verifies("localhost"));
+        assert!(!verifies("example.test"));
+    }
+}
@@ -0,0 +1,153 @@
+//! The issuing CA loaded once, for proxies and servers minting many
+//! certificates: every `issue` signs in memory, without reading the CA from
+//! the store again.
+
+use openssl::pkey::{PKey, Private};
+use openssl::x509::{X509Name, X509};
+
+use crate::cert_params::CertParams;
+use crate::certs::create_server_cert;
+use crate::commands::{ca_chain, check_server_policy, configure_server_params, load_issuer};
+use crate::conf::{Conf, ValidityConf};
+use crate::deterministic::Sources;
+use crate::err::Result;
+use crate::guard::RepoGuard;
+use crate::memory::EphemeralCert;
+use crate::name::Name;
+
+/// The config and the issuing CA of the store, loaded by `CaContext::load`.
+/// Issued certificates are not written to the store.
+pub struct CaContext {
+    conf: Conf,
+    validity: ValidityConf,
+    sources: Sources,
+    issuer: X509,
+    issuer_key: PKey<Private>,
+    issuer_name: X509Name,
+    /// The issuer up to the root.
+    chain: Vec<X509>,
+}
+
+impl CaContext {
+    /// Loads the intermediate, or the named one of `issuer`, creating the CA
+    /// when the store has none yet. Certificates are issued with `sources`.
+    pub fn load(issuer: Option<&str>, sources: Sources) -> Result<CaContext> {
+        let conf = Conf::load()?;
+        let validity = ValidityConf::default().or(&conf.validity());
+        let (issuer, issuer_key, issuer_name) =
+            load_issuer(issuer, &validity, &RepoGuard::default(), &sources, false)?;
+        let chain = ca_chain(&issuer)?;
+        Ok(CaContext {
+            conf,
+            validity,
+            issuer,
+            issuer_key,
+            issuer_name,
+            chain,
+            sources,
+        })
+    }
+
+    /// The issuer up to the root.
+    pub fn chain(&self) -> &[X509] {
+        &self.chain
+    }
+
+    /// Issues a server certificate for `common_name`, which is also its first
+    /// SAN, followed by `alt_names`, under the policy, validity and key
+    /// algorithm of the config.
+    pub fn issue(&self, common_name: &str, alt_names: &[&str]) -> Result<EphemeralCert> {
+        let name = Name::new(common_name).to_x509_name_ordered(&self.conf.name().order())?;
+        check_server_policy(&self.conf, &name, alt_names, &self.validity, &self.sources)?;
+        let key = self.sources.new_key(self.conf.keys().server_algorithm())?;
+        let mut params = CertParams::server_cert_params(
+            &name,
+            &key,
+            &self.issuer_name,
+            &self.issuer_key,
+            self.validity.server_days(),
+            &alt_names.to_vec(),
+        )?;
+        configure_server_params(&mut params, &self.conf, &self.validity, &self.sources)?;
+        let cert = create_server_cert(&params, &self.issuer)?;
+        Ok(EphemeralCert {
+            cert,
+            key,
+            chain: self.chain.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use crate::commands::read_file;
+    use crate::conf::{with_test_store, CertAuthConf};
+    use crate::err::SimpleCAError;
+    use crate::serial::SERIALS_FILE;
+
+    /// Every file under `dir` with its content, except the serial counter
+    /// that keeps the serials of issued certificates unique.
+    fn snapshot(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir).unwrap().flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                files.extend(snapshot(&path));
+            } else if !path.ends_with(SERIALS_FILE) {
+                files.push((path.clone(), fs::read(&path).unwrap()));
+            }
+        }
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_issue() {
+        with_test_store("context", |home| {
+            let context = CaContext::load(None, Sources::default()).unwrap();
+            let intermediate =
+                X509::from_pem(&read_file(&CertAuthConf::intermediate_cert().unwrap()).unwrap())
+                    .unwrap();
+            let root =
+                X509::from_pem(&read_file(&CertAuthConf::ca_cert().unwrap()).unwrap()).unwrap();
+            let store = snapshot(home);
+
+            let issued = context.issue("app.test", &["api.app.test"]).unwrap();
+            assert!(issued
+                .cert
+                .verify(&intermediate.public_key().unwrap())
+                .unwrap());
+            assert_eq!(
+                issued.cert.issuer_name().to_der().unwrap(),
+                intermediate.subject_name().to_der().unwrap()
+            );
+            let chain: Vec<Vec<u8>> = issued
+                .chain
+                .iter()
+                .map(|cert| cert.to_der().unwrap())
+                .collect();
+            assert_eq!(
+                chain,
+                vec![intermediate.to_der().unwrap(), root.to_der().unwrap()]
+            );
+            assert_eq!(snapshot(home), store);
+
+            let config = home.join("config");
+            let mut conf = fs::read_to_string(&config).unwrap();
+            conf.push_str("[policy]\nrules = ['deny when san.starts_with(\"api.\")']\n");
+            fs::write(&config, conf).unwrap();
+            let context = CaContext::load(None, Sources::default()).unwrap();
+            let store = snapshot(home);
+            assert!(matches!(
+                context.issue("app.test", &["api.app.test"]),
+                Err(SimpleCAError::PolicyDenied { .. })
+            ));
+            assert!(context.issue("app.test", &[]).is_ok());
+            assert_eq!(snapshot(home), store);
+        });
+    }
+}
@@ -0,0 +1,254 @@
+//! Issuance for keys that never leave the client, e.g. generated on a TPM or
+//! a YubiKey: only a CSR reaches the CA. An attestation statement of the key
+//! can be passed along and is kept with the certificate and in the index.
+
+use std::net::IpAddr;
+
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Public};
+use openssl::x509::{X509Req, X509};
+
+use crate::commands::{issue_public_key_cert, read_file, write_chain_files};
+use crate::conf::{CertAuthConf, Conf, ValidityConf};
+use crate::deterministic::Sources;
+use crate::err::{Result, SimpleCAError};
+use crate::guard::RepoGuard;
+use crate::hooks::run_post_issue_hooks;
+use crate::index::record_attested;
+use crate::lock::StoreLock;
+use crate::name::Name;
+use crate::store::{self, Role};
+use crate::summary::IssuanceSummary;
+use crate::tr;
+
+/// Parses a PEM or DER encoded CSR and checks that it was signed by the key
+/// it requests a certificate for.
+pub fn read_csr(data: &[u8]) -> Result<X509Req> {
+    let csr = X509Req::from_pem(data)
+        .or_else(|_| X509Req::from_der(data))
+        .map_err(|_| SimpleCAError::InvalidCsr {
+            msg: "it is neither PEM nor DER",
+        })?;
+    let pkey = csr.public_key()?;
+    if !csr.verify(&pkey)? {
+        Err(SimpleCAError::InvalidCsr {
+            msg: "its signature does not match its key",
+        })?;
+    }
+    Ok(csr)
+}
+
+pub(crate) fn csr_common_name(csr: &X509Req) -> Result<String> {
+    let common_name = match csr.subject_name().entries_by_nid(Nid::COMMONNAME).next() {
+        Some(entry) => entry.data().to_string()?,
+        None => Err(SimpleCAError::InvalidCsr {
+            msg: "its subject has no common name",
+        })?,
+    };
+    // The common name names the store files, so the client must not be able
+    // to pick a path with it.
+    if !is_host_name(&common_name) {
+        Err(SimpleCAError::InvalidCsr {
+            msg: "its common name is neither a DNS name nor an IP address",
+        })?;
+    }
+    Ok(common_name)
+}
+
+/// Whether `name` is an IP address or a DNS name, possibly a wildcard.
+fn is_host_name(name: &str) -> bool {
+    if name.parse::<IpAddr>().is_ok() {
+        return true;
+    }
+    let host = name.strip_prefix("*.").unwrap_or(name);
+    name.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+/// Checks that an attestation statement is about `pkey` when it is an X.509
+/// attestation certificate, like the one a YubiKey issues for a PIV slot.
+/// Other statements, e.g. from a TPM, are passed through unchecked.
+pub(crate) fn check_attestation(attestation: &[u8], pkey: &PKey<Public>) -> Result<()> {
+    let cert = match X509::from_pem(attestation).or_else(|_| X509::from_der(attestation)) {
+        Ok(cert) => cert,
+        Err(_) => return Ok(()),
+    };
+    if !cert.public_key()?.public_eq(pkey) {
+        Err(SimpleCAError::AttestationMismatch)?;
+    }
+    Ok(())
+}
+
+/// Signs a server certificate for the key of `csr`, named after the common
+/// name of its subject which is also the only SAN when `alt_names` is empty,
+/// and writes it to the store along with the CSR, so
+/// renewing re-signs the same hardware-bound key, and the attestation.
+#[allow(clippy::too_many_arguments)]
+pub fn issue_csr_cert(
+    csr: &[u8],
+    alt_names: &[&str],
+    validity: &ValidityConf,
+    issuer: Option<&str>,
+    attestation: Option<&[u8]>,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<IssuanceSummary> {
+    let _lock = StoreLock::acquire()?;
+    let request = read_csr(csr)?;
+    let pkey = request.public_key()?;
+    if let Some(attestation) = attestation {
+        check_attestation(attestation, &pkey)?;
+    }
+    let domain = csr_common_name(&request)?;
+    let name = Name::new(domain.clone()).to_x509_name_ordered(&Conf::load()?.name().order())?;
+    let alt_names = if alt_names.is_empty() {
+        vec![domain.as_str()]
+    } else {
+        alt_names.to_vec()
+    };
+    let (cert, ca) = issue_public_key_cert(
+        &name, &pkey, &alt_names, validity, issuer, guard, sources, verbose,
+    )?;
+
+    let csr_path = CertAuthConf::server_csr(&domain)?;
+    write_file!(
+        &request.to_pem()?,
+        &csr_path,
+        verbose,
+        "Saved server CSR at: {:?}"
+    );
+    store::record(Role::ServerCsr, Some(&domain), &csr_path, None)?;
+    // The key of a previous certificate no longer belongs to this one.
+    store::forget(Role::ServerKey, Some(&domain))?;
+    store::forget(Role::ServerHaproxy, Some(&domain))?;
+    let attestation_path = CertAuthConf::server_attestation(&domain)?;
+    match attestation {
+        Some(attestation) => {
+            write_file!(
+                attestation,
+                &attestation_path,
+                verbose,
+                "Saved key attestation at: {:?}"
+            );
+            store::record(
+                Role::ServerAttestation,
+                Some(&domain),
+                &attestation_path,
+                None,
+            )?;
+        }
+        None => store::forget(Role::ServerAttestation, Some(&domain))?,
+    }
+    let cert_path = CertAuthConf::server_cert(&domain)?;
+    write_file!(
+        &cert.to_pem()?,
+        &cert_path,
+        verbose,
+        "Saved server certificate at: {:?}"
+    );
+    store::record(Role::ServerCert, Some(&domain), &cert_path, Some(&ca))?;
+    record_attested(&cert, Some(&cert_path), attestation, sources.now())?;
+    write_chain_files(&domain, &cert, &ca, verbose)?;
+    run_post_issue_hooks(&domain, None)?;
+    csr_summary(&domain, &cert, attestation.is_some())
+}
+
+fn csr_summary(domain: &str, cert: &X509, attested: bool) -> Result<IssuanceSummary> {
+    let fullchain = CertAuthConf::server_fullchain(domain)?;
+    let mut summary = IssuanceSummary::new(cert)?
+        .file("csr", &CertAuthConf::server_csr(domain)?)
+        .file("cert", &CertAuthConf::server_cert(domain)?)
+        .file("fullchain", &fullchain)
+        .file("chain", &CertAuthConf::server_chain(domain)?);
+    if attested {
+        summary = summary.file("attestation", &CertAuthConf::server_attestation(domain)?);
+    }
+    Ok(summary.chain_hint(tr!(
+        "summary-serve-hint",
+        fullchain = format!("{:?}", fullchain),
+        ca = format!("{:?}", CertAuthConf::ca_cert()?)
+    )))
+}
+
+/// Whether the key of the server certificate for `domain` is held in hardware.
+pub(crate) fn is_hardware_bound(domain: &str) -> Result<bool> {
+    store::is_recorded(Role::ServerCsr, Some(domain))
+}
+
+/// Renews the certificate of a hardware-bound key by re-signing its stored
+/// CSR with the same SANs and attestation.
+pub(crate) fn renew_csr_cert(
+    domain: &str,
+    alt_names: &[&str],
+    issuer: Option<&str>,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<IssuanceSummary> {
+    let csr = read_file(&CertAuthConf::server_csr(domain)?)?;
+    let attestation = if store::is_recorded(Role::ServerAttestation, Some(domain))? {
+        Some(read_file(&CertAuthConf::server_attestation(domain)?)?)
+    } else {
+        None
+    };
+    issue_csr_cert(
+        &csr,
+        alt_names,
+        &ValidityConf::default(),
+        issuer,
+        attestation.as_deref(),
+        guard,
+        sources,
+        verbose,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::x509::{X509NameBuilder, X509ReqBuilder};
+
+    #[test]
+    fn test_read_csr() {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let pkey = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "hsm.test")
+            .unwrap();
+        let mut builder = X509ReqBuilder::new().unwrap();
+        builder.set_subject_name(&name.build()).unwrap();
+        builder.set_pubkey(&pkey).unwrap();
+        builder.sign(&pkey, MessageDigest::sha256()).unwrap();
+        let der = builder.build().to_der().unwrap();
+
+        let csr = read_csr(&der).unwrap();
+        assert_eq!(csr_common_name(&csr).unwrap(), "hsm.test");
+        assert!(read_csr(b"not a csr").is_err());
+        for name in ["*.hsm.test", "10.0.0.1", "fe80::1", "hsm"] {
+            assert!(is_host_name(name), "{}", name);
+        }
+        for name in [
+            "",
+            ".hsm.test",
+            "hsm..test",
+            "../hsm",
+            "a/b",
+            "a\\b",
+            "hsm.*.test",
+        ] {
+            assert!(!is_host_name(name), "{}", name);
+        }
+
+        let public = csr.public_key().unwrap();
+        assert!(check_attestation(b"opaque TPM statement", &public).is_ok());
+    }
+}
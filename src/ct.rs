@@ -0,0 +1,264 @@
+//! Simulated certificate transparency: a poisoned precertificate and the final
+//! certificate embedding an SCT from a local stand-in log, without real CT logs.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkey::{Id, PKey, PKeyRef, Private};
+use openssl::sign::Signer;
+use openssl::x509::{X509Ref, X509};
+
+use crate::commands::{load_ca, read_file};
+use crate::conf::ValidityConf;
+use crate::der::{self, OID_CT_POISON, OID_CT_SCT_LIST};
+use crate::deterministic::Sources;
+use crate::err::{Result, SimpleCAError};
+use crate::guard::RepoGuard;
+use crate::keys::KeyAlgorithm;
+use crate::store::{self, store_path, Role};
+
+const SCT_VERSION_V1: u8 = 0;
+const SIGNATURE_TYPE_CERTIFICATE_TIMESTAMP: u8 = 0;
+const ENTRY_TYPE_PRECERT: u16 = 1;
+const HASH_ALGORITHM_SHA256: u8 = 4;
+const SIGNATURE_ALGORITHM_ECDSA: u8 = 3;
+
+/// A precertificate and the final certificate issued from it.
+pub struct PrecertPair {
+    pub precert: X509,
+    pub cert: X509,
+}
+
+fn malformed() -> SimpleCAError {
+    SimpleCAError::GenericError {
+        msg: "Malformed certificate DER.",
+    }
+}
+
+/// Loads the key of the simulated log, generating it on first use.
+pub(crate) fn log_key(guard: &RepoGuard, verbose: bool) -> Result<PKey<Private>> {
+    let path = store_path(Role::CtLogKey, None)?;
+    if path.exists() {
+        return Ok(PKey::private_key_from_pem(&read_file(&path)?)?);
+    }
+    guard.check(&[&path])?;
+    let pkey = KeyAlgorithm::EcdsaP256.generate()?;
+    write_file!(
+        &pkey.private_key_to_pem_pkcs8()?,
+        &path,
+        verbose,
+        "Saved simulated CT log key at: {:?}"
+    );
+    store::record(Role::CtLogKey, None, &path, None)?;
+    Ok(pkey)
+}
+
+/// The RFC 6962 log ID, the SHA-256 digest of the log's public key.
+pub fn log_id(log_key: &PKeyRef<Private>) -> Result<Vec<u8>> {
+    Ok(hash(MessageDigest::sha256(), &log_key.public_key_to_der()?)?.to_vec())
+}
+
+fn extension(oid: &[u8], critical: bool, value: &[u8]) -> Vec<u8> {
+    let mut items = vec![der::oid(oid)];
+    if critical {
+        items.push(der::boolean(true));
+    }
+    items.push(der::octet_string(value));
+    der::sequence(&items)
+}
+
+/// Splits a certificate into its TBSCertificate and signature algorithm.
+fn split_cert(cert: &X509Ref) -> Result<(Vec<u8>, Vec<u8>)> {
+    let cert = cert.to_der()?;
+    let (_, content, _) = der::read_tlv(&cert).ok_or_else(malformed)?;
+    match der::children(content).ok_or_else(malformed)?.as_slice() {
+        [tbs, algorithm, _] => Ok((tbs.to_vec(), algorithm.to_vec())),
+        _ => Err(malformed())?,
+    }
+}
+
+/// Appends the DER encoded `extension` to the extensions of `tbs`.
+fn append_extension(tbs: &[u8], extension: &[u8]) -> Result<Vec<u8>> {
+    let (_, content, _) = der::read_tlv(tbs).ok_or_else(malformed)?;
+    let mut fields: Vec<Vec<u8>> = der::children(content)
+        .ok_or_else(malformed)?
+        .into_iter()
+        .map(|field| field.to_vec())
+        .collect();
+    let extensions = fields
+        .iter_mut()
+        .find(|field| field[0] == 0xa3)
+        .ok_or_else(malformed)?;
+    let (_, wrapped, _) = der::read_tlv(extensions).ok_or_else(malformed)?;
+    let (_, list, _) = der::read_tlv(wrapped).ok_or_else(malformed)?;
+    let mut list = list.to_vec();
+    list.extend_from_slice(extension);
+    *extensions = der::context(3, &der::tlv(der::TAG_SEQUENCE, &list));
+    Ok(der::sequence(&fields))
+}
+
+/// The digest `cert` was signed with.
+fn signature_digest(cert: &X509Ref) -> Result<MessageDigest> {
+    let nid = cert.signature_algorithm().object().nid();
+    let digest = nid
+        .signature_algorithms()
+        .and_then(|algorithms| MessageDigest::from_nid(algorithms.digest));
+    digest.ok_or(SimpleCAError::GenericError {
+        msg: "Unsupported certificate signature algorithm.",
+    })
+}
+
+/// Signs `tbs` with the issuer key, using the signature algorithm it names.
+fn sign_tbs(
+    tbs: Vec<u8>,
+    algorithm: Vec<u8>,
+    digest: MessageDigest,
+    issuer_key: &PKeyRef<Private>,
+) -> Result<X509> {
+    let mut signer = Signer::new(digest, issuer_key)?;
+    signer.update(&tbs)?;
+    let mut signature = vec![0];
+    signature.extend(signer.sign_to_vec()?);
+    let cert = der::sequence(&[tbs, algorithm, der::tlv(0x03, &signature)]);
+    Ok(X509::from_der(&cert)?)
+}
+
+/// Creates a v1 SCT over the precertificate entry for `tbs`, the final
+/// TBSCertificate without any SCT list.
+pub(crate) fn sign_sct(
+    tbs: &[u8],
+    issuer: &X509Ref,
+    log_key: &PKeyRef<Private>,
+    timestamp: u64,
+) -> Result<Vec<u8>> {
+    if log_key.id() != Id::EC {
+        Err(SimpleCAError::GenericError {
+            msg: "The simulated CT log key must be an ECDSA key.",
+        })?;
+    }
+    let issuer_key_hash = hash(
+        MessageDigest::sha256(),
+        &issuer.public_key()?.public_key_to_der()?,
+    )?;
+    let mut signed = vec![SCT_VERSION_V1, SIGNATURE_TYPE_CERTIFICATE_TIMESTAMP];
+    signed.extend(timestamp.to_be_bytes());
+    signed.extend(ENTRY_TYPE_PRECERT.to_be_bytes());
+    signed.extend_from_slice(&issuer_key_hash);
+    signed.extend(&(tbs.len() as u32).to_be_bytes()[1..]);
+    signed.extend_from_slice(tbs);
+    signed.extend(0u16.to_be_bytes());
+
+    let mut signer = Signer::new(MessageDigest::sha256(), log_key)?;
+    signer.update(&signed)?;
+    let signature = signer.sign_to_vec()?;
+
+    let mut sct = vec![SCT_VERSION_V1];
+    sct.extend(log_id(log_key)?);
+    sct.extend(timestamp.to_be_bytes());
+    sct.extend(0u16.to_be_bytes());
+    sct.extend([HASH_ALGORITHM_SHA256, SIGNATURE_ALGORITHM_ECDSA]);
+    sct.extend((signature.len() as u16).to_be_bytes());
+    sct.extend(signature);
+    Ok(sct)
+}
+
+/// Re-issues `cert` as a precertificate carrying the poison extension and as
+/// a final certificate embedding an SCT for it from the simulated log.
+pub(crate) fn precert_pair(
+    cert: &X509Ref,
+    issuer: &X509Ref,
+    issuer_key: &PKeyRef<Private>,
+    log_key: &PKeyRef<Private>,
+    now: SystemTime,
+) -> Result<PrecertPair> {
+    let (tbs, algorithm) = split_cert(cert)?;
+    let digest = signature_digest(cert)?;
+    let poison = extension(OID_CT_POISON, true, &[0x05, 0x00]);
+    let precert = sign_tbs(
+        append_extension(&tbs, &poison)?,
+        algorithm.clone(),
+        digest,
+        issuer_key,
+    )?;
+
+    let timestamp = now.duration_since(UNIX_EPOCH)?.as_millis() as u64;
+    let sct = sign_sct(&tbs, issuer, log_key, timestamp)?;
+    let mut list = (sct.len() as u16).to_be_bytes().to_vec();
+    list.extend(sct);
+    let mut sct_list = (list.len() as u16).to_be_bytes().to_vec();
+    sct_list.extend(list);
+    let sct_extension = extension(OID_CT_SCT_LIST, false, &der::octet_string(&sct_list));
+    let cert = sign_tbs(
+        append_extension(&tbs, &sct_extension)?,
+        algorithm,
+        digest,
+        issuer_key,
+    )?;
+    Ok(PrecertPair { precert, cert })
+}
+
+/// Issues the precertificate pair for `cert` with the intermediate CA and the
+/// simulated log of the store.
+pub(crate) fn issue_precert_pair(
+    cert: &X509Ref,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<PrecertPair> {
+    let (intermediate, pkey, _) =
+        load_ca(false, &ValidityConf::default(), guard, sources, verbose)?;
+    let log_key = log_key(guard, verbose)?;
+    precert_pair(cert, &intermediate, &pkey, &log_key, sources.now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certs::create_server_cert;
+    use crate::certs::fixtures::test_root_ca;
+    use crate::keys::SignatureDigest;
+    use crate::{CertParams, Name};
+
+    #[test]
+    fn test_precert_pair() {
+        let (ca_name, ca_key, ca) = test_root_ca();
+        let server_name = Name::new("ct.test").to_x509_name().unwrap();
+        let server_key = KeyAlgorithm::Rsa2048.generate().unwrap();
+        let mut params = CertParams::server_cert_params(
+            &server_name,
+            &server_key,
+            &ca_name,
+            &ca_key,
+            1,
+            &vec!["ct.test"],
+        )
+        .unwrap();
+        params.digest = SignatureDigest::Sha384;
+        let cert = create_server_cert(&params, &ca).unwrap();
+        let log_key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+
+        let pair = precert_pair(&cert, &ca, &ca_key, &log_key, SystemTime::now()).unwrap();
+        let ca_public = ca.public_key().unwrap();
+        assert!(pair.precert.verify(&ca_public).unwrap());
+        assert!(pair.cert.verify(&ca_public).unwrap());
+        assert_eq!(
+            pair.cert.signature_algorithm().object().nid(),
+            cert.signature_algorithm().object().nid()
+        );
+        assert_eq!(
+            pair.cert.serial_number().to_bn().unwrap(),
+            cert.serial_number().to_bn().unwrap()
+        );
+        let precert = pair.precert.to_der().unwrap();
+        let final_cert = pair.cert.to_der().unwrap();
+        let contains = |haystack: &[u8], needle: &[u8]| {
+            haystack
+                .windows(needle.len())
+                .any(|window| window == needle)
+        };
+        assert!(contains(&precert, OID_CT_POISON));
+        assert!(!contains(&precert, OID_CT_SCT_LIST));
+        assert!(contains(&final_cert, OID_CT_SCT_LIST));
+        assert!(!contains(&final_cert, OID_CT_POISON));
+    }
+}
@@ -0,0 +1,156 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use openssl::asn1::{Asn1Time, Asn1TimeRef};
+
+use crate::err::{Result, SimpleCAError};
+
+/// Converts days since the Unix epoch into a (year, month, day) civil date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Converts a (year, month, day) civil date into days since the Unix epoch.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (i64::from(month) + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Parses a fixed width run of ASCII digits.
+fn digits(value: &str) -> Option<u32> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+/// Parses an RFC 3339 date-time such as `2024-01-31T08:00:00Z` or
+/// `2024-01-31T16:00:00.5+08:00` into a Unix timestamp, dropping fractions
+/// of a second.
+pub fn parse_rfc3339(value: &str) -> Result<i64, SimpleCAError> {
+    let invalid = || SimpleCAError::InvalidTimestamp {
+        value: value.to_string(),
+    };
+    let s = value.trim();
+    if s.len() < 20 || !s.is_ascii() {
+        return Err(invalid());
+    }
+    let field = |range: std::ops::Range<usize>| digits(&s[range]).ok_or_else(invalid);
+    let (year, month, day) = (field(0..4)?, field(5..7)?, field(8..10)?);
+    let (hour, minute, second) = (field(11..13)?, field(14..16)?, field(17..19)?);
+    let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+    if separators.iter().any(|(i, sep)| s.as_bytes()[*i] != *sep)
+        || !matches!(s.as_bytes()[10], b'T' | b't' | b' ')
+    {
+        return Err(invalid());
+    }
+
+    let mut offset = &s[19..];
+    if let Some(fraction) = offset.strip_prefix('.') {
+        let end = fraction
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(fraction.len());
+        if end == 0 {
+            return Err(invalid());
+        }
+        offset = &fraction[end..];
+    }
+    let offset_secs = match offset {
+        "Z" | "z" => 0,
+        _ if offset.len() == 6 && offset.as_bytes()[3] == b':' => {
+            let hours = digits(&offset[1..3]).ok_or_else(invalid)?;
+            let minutes = digits(&offset[4..6]).ok_or_else(invalid)?;
+            let secs = i64::from(hours * 3600 + minutes * 60);
+            match offset.as_bytes()[0] {
+                b'+' => secs,
+                b'-' => -secs,
+                _ => return Err(invalid()),
+            }
+        }
+        _ => return Err(invalid()),
+    };
+
+    let days = days_from_civil(i64::from(year), month, day);
+    if !(1..=12).contains(&month)
+        || civil_from_days(days) != (i64::from(year), month, day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return Err(invalid());
+    }
+    let secs = i64::from(hour * 3600 + minute * 60 + second.min(59));
+    Ok(days * 86_400 + secs - offset_secs)
+}
+
+/// Formats a Unix timestamp as an RFC 3339 UTC date-time, e.g. `2024-01-31T08:00:00Z`.
+pub fn format_unix(secs: i64) -> String {
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let time = secs.rem_euclid(86_400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    };
+    format_unix(secs)
+}
+
+/// Converts an ASN.1 time, such as a certificate's notAfter, into a Unix timestamp.
+pub fn asn1_to_unix(time: &Asn1TimeRef) -> Result<i64> {
+    let diff = Asn1Time::from_unix(0)?.diff(time)?;
+    Ok(i64::from(diff.days) * 86_400 + i64::from(diff.secs))
+}
+
+/// Formats an ASN.1 time as an RFC 3339 UTC date-time.
+pub fn format_asn1(time: &Asn1TimeRef) -> Result<String> {
+    Ok(format_unix(asn1_to_unix(time)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rfc3339() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z").unwrap(), 0);
+        let secs = parse_rfc3339("2024-02-29T08:30:15Z").unwrap();
+        assert_eq!(format_unix(secs), "2024-02-29T08:30:15Z");
+        assert_eq!(parse_rfc3339("2024-02-29T16:30:15.25+08:00").unwrap(), secs);
+        assert_eq!(parse_rfc3339("1969-12-31T23:00:00-01:00").unwrap(), 0);
+        for invalid in [
+            "2024-02-29",
+            "2023-02-29T00:00:00Z",
+            "2024-13-01T00:00:00Z",
+            "2024-01-01T24:00:00Z",
+            "2024-01-01T00:00:00",
+            "2024-01-01T00:00:00+0800",
+            "2024-01-01T00:00:00.Z",
+        ] {
+            assert!(parse_rfc3339(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! Minimal DER encoding for the few structures the openssl crate cannot build.
+
+pub(crate) const TAG_BOOLEAN: u8 = 0x01;
+pub(crate) const TAG_INTEGER: u8 = 0x02;
+pub(crate) const TAG_OCTET_STRING: u8 = 0x04;
+pub(crate) const TAG_SEQUENCE: u8 = 0x30;
+pub(crate) const TAG_SET: u8 = 0x31;
+pub(crate) const TAG_NULL: u8 = 0x05;
+pub(crate) const TAG_BMP_STRING: u8 = 0x1e;
+
+/// `1.2.840.113549.1.7.1`, PKCS#7 data.
+pub(crate) const OID_PKCS7_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01];
+/// `1.2.840.113549.1.7.2`, PKCS#7 signedData.
+pub(crate) const OID_PKCS7_SIGNED_DATA: &[u8] =
+    &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+/// `1.2.840.113549.1.9.20`, PKCS#9 friendlyName.
+pub(crate) const OID_FRIENDLY_NAME: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x14];
+/// `1.2.840.113549.1.9.22.1`, PKCS#9 x509Certificate.
+pub(crate) const OID_X509_CERTIFICATE: &[u8] =
+    &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x16, 0x01];
+/// `1.2.840.113549.1.12.10.1.3`, PKCS#12 certBag.
+pub(crate) const OID_PKCS12_CERT_BAG: &[u8] = &[
+    0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x0c, 0x0a, 0x01, 0x03,
+];
+/// `2.16.840.1.101.3.4.2.1`, SHA-256.
+pub(crate) const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+/// `2.5.29.15`, keyUsage.
+pub(crate) const OID_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x0f];
+/// `2.5.29.19`, basicConstraints.
+pub(crate) const OID_BASIC_CONSTRAINTS: &[u8] = &[0x55, 0x1d, 0x13];
+/// `2.5.29.37`, extKeyUsage.
+pub(crate) const OID_EXTENDED_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+/// `2.5.29.37.0`, anyExtendedKeyUsage.
+pub(crate) const OID_ANY_EXTENDED_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25, 0x00];
+/// `2.16.840.1.113894.746875.1.1`, the bag attribute Java reads to treat a
+/// PKCS#12 certificate as a trusted certificate entry.
+pub(crate) const OID_ORACLE_TRUSTED_KEY_USAGE: &[u8] = &[
+    0x60, 0x86, 0x48, 0x01, 0x86, 0xf9, 0x66, 0xad, 0xca, 0x7b, 0x01, 0x01,
+];
+/// `1.3.6.1.4.1.42.2.17.1.1`, the proprietary key protection of Sun's JKS.
+pub(crate) const OID_JKS_KEY_PROTECTOR: &[u8] =
+    &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x2a, 0x02, 0x11, 0x01, 0x01];
+/// `1.3.6.1.4.1.11129.2.4.2`, embedded SCT list (RFC 6962).
+pub(crate) const OID_CT_SCT_LIST: &[u8] =
+    &[0x2b, 0x06, 0x01, 0x04, 0x01, 0xd6, 0x79, 0x02, 0x04, 0x02];
+/// `1.3.6.1.4.1.11129.2.4.3`, precertificate poison (RFC 6962).
+pub(crate) const OID_CT_POISON: &[u8] =
+    &[0x2b, 0x06, 0x01, 0x04, 0x01, 0xd6, 0x79, 0x02, 0x04, 0x03];
+
+/// Encodes a tag-length-value triple.
+pub(crate) fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len
+            .to_be_bytes()
+            .iter()
+            .copied()
+            .skip_while(|b| *b == 0)
+            .collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend(bytes);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+/// Splits the first element off `input`, returning its tag, its content and
+/// the total length of the element.
+pub(crate) fn read_tlv(input: &[u8]) -> Option<(u8, &[u8], usize)> {
+    let tag = *input.first()?;
+    let first = *input.get(1)? as usize;
+    let (len, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let count = first & 0x7f;
+        let bytes = input.get(2..2 + count)?;
+        let len = bytes.iter().fold(0usize, |len, b| (len << 8) | *b as usize);
+        (len, 2 + count)
+    };
+    let content = input.get(header..header + len)?;
+    Some((tag, content, header + len))
+}
+
+/// Splits the content of a constructed element into its raw child elements.
+pub(crate) fn children(mut content: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut children = Vec::new();
+    while !content.is_empty() {
+        let (_, _, len) = read_tlv(content)?;
+        children.push(&content[..len]);
+        content = &content[len..];
+    }
+    Some(children)
+}
+
+pub(crate) fn sequence(items: &[Vec<u8>]) -> Vec<u8> {
+    tlv(TAG_SEQUENCE, &items.concat())
+}
+
+pub(crate) fn set(items: &[Vec<u8>]) -> Vec<u8> {
+    tlv(TAG_SET, &items.concat())
+}
+
+pub(crate) fn oid(encoded: &[u8]) -> Vec<u8> {
+    tlv(0x06, encoded)
+}
+
+pub(crate) fn octet_string(content: &[u8]) -> Vec<u8> {
+    tlv(TAG_OCTET_STRING, content)
+}
+
+pub(crate) fn boolean(value: bool) -> Vec<u8> {
+    tlv(TAG_BOOLEAN, &[if value { 0xff } else { 0 }])
+}
+
+pub(crate) fn small_integer(value: u8) -> Vec<u8> {
+    tlv(TAG_INTEGER, &[value])
+}
+
+/// A non-negative INTEGER in its minimal encoding.
+pub(crate) fn integer(value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let start = bytes
+        .iter()
+        .position(|b| *b != 0)
+        .unwrap_or(bytes.len() - 1);
+    let mut content = bytes[start..].to_vec();
+    if content[0] & 0x80 != 0 {
+        content.insert(0, 0);
+    }
+    tlv(TAG_INTEGER, &content)
+}
+
+pub(crate) fn null() -> Vec<u8> {
+    tlv(TAG_NULL, &[])
+}
+
+pub(crate) fn bmp_string(value: &str) -> Vec<u8> {
+    let content: Vec<u8> = value.encode_utf16().flat_map(u16::to_be_bytes).collect();
+    tlv(TAG_BMP_STRING, &content)
+}
+
+/// Context-specific constructed tag `[n]`.
+pub(crate) fn context(n: u8, content: &[u8]) -> Vec<u8> {
+    tlv(0xa0 | n, content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tlv_lengths() {
+        assert_eq!(tlv(0x04, &[1, 2]), vec![0x04, 0x02, 1, 2]);
+        let long = tlv(0x04, &[0; 200]);
+        assert_eq!(&long[..3], &[0x04, 0x81, 200]);
+        let longer = tlv(0x04, &[0; 300]);
+        assert_eq!(&longer[..4], &[0x04, 0x82, 0x01, 0x2c]);
+
+        let (tag, content, len) = read_tlv(&longer).unwrap();
+        assert_eq!((tag, content.len(), len), (0x04, 300, 304));
+        let seq = sequence(&[small_integer(1), longer.clone()]);
+        let (_, content, _) = read_tlv(&seq).unwrap();
+        assert_eq!(
+            children(content).unwrap(),
+            vec![&small_integer(1)[..], &longer[..]]
+        );
+    }
+}
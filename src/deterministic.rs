@@ -0,0 +1,207 @@
+//! Fixed inputs for reproducible issuance, for golden-file tests and snapshot
+//! comparisons: the clock, the serials and the generated keys are passed to
+//! the commands in `Sources`. With RSA keys the certificates are then byte
+//! for byte the same on every run; ECDSA signatures stay randomized.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::pkey::{HasPublic, PKey, Private};
+
+use crate::cert_params::CertParams;
+use crate::err::{Result, SimpleCAError};
+use crate::keypool::take_key;
+use crate::keys::KeyAlgorithm;
+use crate::serial::{SerialSource, SerialStrategy};
+
+/// Hands out the keys of new certificates.
+pub trait KeySource {
+    fn next_key(&self, algorithm: KeyAlgorithm) -> Result<PKey<Private>>;
+}
+
+/// The clock, serials and keys of issuance. The default is the system clock,
+/// the `[serials]` strategy of the config and keys from the pool or freshly
+/// generated.
+#[derive(Clone, Default)]
+pub struct Sources {
+    /// The time of issuance, the system clock when `None`.
+    pub clock: Option<SystemTime>,
+    /// Allocates serials instead of the `[serials]` strategy.
+    pub serials: Option<Arc<dyn SerialSource + Send + Sync>>,
+    /// Hands out the keys of new certificates instead of generating them.
+    pub keys: Option<Arc<dyn KeySource + Send + Sync>>,
+}
+
+impl Sources {
+    /// The time of issuance.
+    pub fn now(&self) -> SystemTime {
+        self.clock.unwrap_or_else(SystemTime::now)
+    }
+
+    /// The time `days` from the time of issuance.
+    pub(crate) fn days_from_now(&self, days: u32) -> Result<Asn1Time> {
+        let now = self.now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        Ok(Asn1Time::from_unix(now + days as i64 * 86_400)?)
+    }
+
+    /// The injected serial source, else the source of `strategy`.
+    pub(crate) fn serials(&self, strategy: SerialStrategy) -> Box<dyn SerialSource + Send + Sync> {
+        match &self.serials {
+            Some(source) => Box::new(source.clone()),
+            None => strategy.source(self.clock),
+        }
+    }
+
+    /// Signs `params` at the time of issuance, with a serial from the injected
+    /// source or else from `strategy`.
+    pub(crate) fn configure<T: HasPublic>(
+        &self,
+        params: &mut CertParams<T>,
+        strategy: SerialStrategy,
+    ) -> Result<()> {
+        params.clock = self.clock;
+        params.allocate_serial(self.serials(strategy).as_ref())
+    }
+
+    /// A new key of `algorithm`, from the injected source when set, else from
+    /// the key pool or freshly generated.
+    pub(crate) fn new_key(&self, algorithm: KeyAlgorithm) -> Result<PKey<Private>> {
+        if let Some(source) = &self.keys {
+            return source.next_key(algorithm);
+        }
+        match take_key(algorithm)? {
+            Some(key) => Ok(key),
+            None => algorithm.generate(),
+        }
+    }
+}
+
+/// 1, 2, 3, ... counted in memory, starting over with every value.
+#[derive(Default)]
+pub struct CountingSerial {
+    last: AtomicU64,
+}
+
+impl SerialSource for CountingSerial {
+    fn next_serial(&self) -> Result<BigNum> {
+        let counter = self.last.fetch_add(1, Ordering::SeqCst) + 1;
+        Ok(BigNum::from_slice(&counter.to_be_bytes())?)
+    }
+}
+
+/// Pre-generated keys, handed out in order whatever the algorithm asked for.
+pub struct FixedKeys {
+    keys: Mutex<VecDeque<PKey<Private>>>,
+}
+
+impl FixedKeys {
+    pub fn new(keys: Vec<PKey<Private>>) -> FixedKeys {
+        FixedKeys {
+            keys: Mutex::new(keys.into()),
+        }
+    }
+}
+
+impl KeySource for FixedKeys {
+    fn next_key(&self, _algorithm: KeyAlgorithm) -> Result<PKey<Private>> {
+        self.keys
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or(SimpleCAError::GenericError {
+                msg: "The fixed keys are used up.",
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_sources() {
+        let serials = CountingSerial::default();
+        assert_eq!(serials.next_serial().unwrap(), BigNum::from_u32(1).unwrap());
+        assert_eq!(serials.next_serial().unwrap(), BigNum::from_u32(2).unwrap());
+
+        let key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let keys = FixedKeys::new(vec![key.clone()]);
+        let next = keys.next_key(KeyAlgorithm::Rsa2048).unwrap();
+        assert!(next.public_eq(&key));
+        assert!(keys.next_key(KeyAlgorithm::Rsa2048).is_err());
+
+        // Fixed inputs make RSA-signed certificates identical.
+        let key = KeyAlgorithm::Rsa2048.generate().unwrap();
+        let ders: Vec<Vec<u8>> = (0..2)
+            .map(|_| {
+                let name = crate::Name::new("golden").to_x509_name().unwrap();
+                let params = crate::CertParams::builder(name, key.clone())
+                    .not_before(1_700_000_000)
+                    .serial(crate::SerialPolicy::Fixed(
+                        CountingSerial::default().next_serial().unwrap(),
+                    ))
+                    .self_signed()
+                    .unwrap()
+                    .build()
+                    .unwrap();
+                crate::create_root_ca(&params).unwrap().to_der().unwrap()
+            })
+            .collect();
+        assert_eq!(ders[0], ders[1]);
+    }
+
+    #[test]
+    fn test_injected_sources() {
+        use crate::commands::{generate_server_cert, load_ca, read_file, ServerExtras};
+        use crate::conf::{with_test_store, CertAuthConf, ValidityConf};
+        use crate::guard::RepoGuard;
+        use crate::name::Name;
+        use openssl::x509::X509;
+
+        // The root, intermediate and server keys.
+        let keys: Vec<PKey<Private>> = (0..3)
+            .map(|_| KeyAlgorithm::Rsa2048.generate().unwrap())
+            .collect();
+        let issue = |store: &str| {
+            with_test_store(store, |_| {
+                let sources = Sources {
+                    clock: Some(UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000)),
+                    serials: Some(Arc::new(CountingSerial::default())),
+                    keys: Some(Arc::new(FixedKeys::new(keys.clone()))),
+                };
+                let validity = ValidityConf::default();
+                let guard = RepoGuard::default();
+                load_ca(true, &validity, &guard, &sources, false).unwrap();
+                generate_server_cert(
+                    &Name::new("app.test"),
+                    &vec![],
+                    &validity,
+                    None,
+                    &guard,
+                    None,
+                    &ServerExtras::default(),
+                    &sources,
+                    false,
+                )
+                .unwrap();
+                [
+                    CertAuthConf::ca_cert().unwrap(),
+                    CertAuthConf::intermediate_cert().unwrap(),
+                    CertAuthConf::server_cert("app.test").unwrap(),
+                ]
+                .map(|path| read_file(&path).unwrap())
+            })
+        };
+        let first = issue("injected-1");
+        assert_eq!(first, issue("injected-2"));
+        let root = X509::from_pem(&first[0]).unwrap();
+        assert_eq!(
+            root.serial_number().to_bn().unwrap(),
+            BigNum::from_u32(1).unwrap()
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use openssl::nid::Nid;
+use openssl::x509::X509NameBuilder;
+
+use crate::atomic::write_file;
+use crate::cert_params::CertParams;
+use crate::certs::{cert_fingerprint, create_device_cert};
+use crate::commands::{load_ca, read_file};
+use crate::conf::{Conf, ValidityConf};
+use crate::datetime::format_asn1;
+use crate::deterministic::Sources;
+use crate::err::{Result, SimpleCAError};
+use crate::guard::RepoGuard;
+use crate::index::record_issued;
+use crate::lock::StoreLock;
+
+const DEVICE_MANIFEST: &str = "manifest.json";
+
+/// A row of the device list: the device ID and an optional value for the
+/// custom OID attribute.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Device {
+    pub id: String,
+    pub attribute: Option<String>,
+}
+
+/// One provisioned device in the `manifest.json` written next to the keys.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeviceEntry {
+    pub id: String,
+    pub serial: String,
+    pub fingerprint: String,
+    pub not_after: String,
+    pub key: String,
+    pub cert: String,
+}
+
+/// Parses a CSV of `id[,attribute]` rows. Blank lines, `#` comments and an
+/// `id` header row are skipped.
+pub fn parse_devices(csv: &str) -> Result<Vec<Device>> {
+    let mut devices = Vec::new();
+    let mut seen = HashSet::new();
+    for (index, line) in csv.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut columns = line.split(',').map(str::trim);
+        let id = columns.next().unwrap_or_default();
+        if index == 0 && id.eq_ignore_ascii_case("id") {
+            continue;
+        }
+        let invalid = |msg: &str| SimpleCAError::InvalidDeviceList {
+            line: index + 1,
+            msg: msg.to_string(),
+        };
+        if id.is_empty() {
+            Err(invalid("missing device ID"))?;
+        }
+        if id.contains(['/', '\\']) || id.starts_with('.') {
+            Err(invalid("device ID must be usable as a file name"))?;
+        }
+        if !seen.insert(id.to_string()) {
+            Err(invalid("duplicate device ID"))?;
+        }
+        let attribute = columns
+            .next()
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_string());
+        devices.push(Device {
+            id: id.to_string(),
+            attribute,
+        });
+    }
+    Ok(devices)
+}
+
+/// Issues a key pair and client certificate for every device in `csv` into
+/// `out_dir`, along with a `manifest.json` describing them.
+///
+/// Each ID goes into the subject serialNumber; when `oid` is given, the
+/// second CSV column (or the ID) is added as an attribute with that OID.
+pub fn issue_devices(
+    csv: &Path,
+    out_dir: &Path,
+    oid: Option<&str>,
+    validity: &ValidityConf,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<Vec<DeviceEntry>> {
+    let devices = parse_devices(&String::from_utf8(read_file(csv)?)?)?;
+    let _lock = StoreLock::acquire()?;
+    fs::create_dir_all(out_dir)?;
+    let key_paths: Vec<_> = devices
+        .iter()
+        .map(|device| out_dir.join(format!("{}.key.pem", device.id)))
+        .collect();
+    guard.check(
+        &key_paths
+            .iter()
+            .map(|path| path.as_path())
+            .collect::<Vec<_>>(),
+    )?;
+
+    let (ca, ca_pkey, ca_name) = load_ca(false, validity, guard, sources, verbose)?;
+    let conf = Conf::load()?;
+    let validity = validity.or(&conf.validity());
+    let algorithm = conf.keys().server_algorithm();
+
+    let mut entries = Vec::new();
+    for (device, key_path) in devices.iter().zip(&key_paths) {
+        let mut name = X509NameBuilder::new()?;
+        name.append_entry_by_nid(Nid::SERIALNUMBER, &device.id)?;
+        if let Some(oid) = oid {
+            let value = device.attribute.as_deref().unwrap_or(&device.id);
+            name.append_entry_by_text(oid, value)?;
+        }
+        let name = name.build();
+
+        let pkey = sources.new_key(algorithm)?;
+        let mut params = CertParams::device_cert_params(
+            &name,
+            &pkey,
+            &ca_name,
+            &ca_pkey,
+            validity.server_days(),
+        )?;
+        params.ttl = validity.server_ttl()?;
+        (params.not_before, params.not_after) = validity.server_window()?;
+        params.backdate = validity.backdate()?;
+        params.digest = conf.keys().digest();
+        sources.configure(&mut params, conf.serials().strategy())?;
+        let cert = create_device_cert(&params, &ca)?;
+
+        let cert_path = out_dir.join(format!("{}.cert.pem", device.id));
+        write_file!(
+            &pkey.private_key_to_pem_pkcs8()?,
+            key_path,
+            verbose,
+            "Saved device key at: {:?}"
+        );
+        write_file!(
+            &cert.to_pem()?,
+            &cert_path,
+            verbose,
+            "Saved device certificate at: {:?}"
+        );
+        record_issued(&cert, Some(key_path), Some(&cert_path), sources.now())?;
+        entries.push(DeviceEntry {
+            id: device.id.clone(),
+            serial: cert.serial_number().to_bn()?.to_hex_str()?.to_string(),
+            fingerprint: cert_fingerprint(&cert)?,
+            not_after: format_asn1(cert.not_after())?,
+            key: format!("{}.key.pem", device.id),
+            cert: format!("{}.cert.pem", device.id),
+        });
+    }
+
+    write_file(
+        out_dir.join(DEVICE_MANIFEST),
+        serde_json::to_vec_pretty(&entries)?,
+    )?;
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_devices() {
+        let devices = parse_devices("id,model\n# spare\nSN-001,thermo-v2\n\nSN-002\n").unwrap();
+        assert_eq!(
+            devices,
+            vec![
+                Device {
+                    id: "SN-001".to_string(),
+                    attribute: Some("thermo-v2".to_string()),
+                },
+                Device {
+                    id: "SN-002".to_string(),
+                    attribute: None,
+                },
+            ]
+        );
+        assert!(parse_devices("SN-001\nSN-001").is_err());
+        assert!(parse_devices(",x").is_err());
+        assert!(parse_devices("../etc").is_err());
+    }
+}
@@ -0,0 +1,129 @@
+use std::fs;
+use std::path::Path;
+
+use openssl::nid::Nid;
+use openssl::x509::X509NameBuilder;
+
+use crate::atomic::write_private;
+use crate::cert_params::CertParams;
+use crate::certs::{create_client_cert, ExtKeyUsage};
+use crate::commands::{ca_chain, load_issuer};
+use crate::conf::{Conf, ValidityConf};
+use crate::deterministic::Sources;
+use crate::err::{Result, SimpleCAError};
+use crate::export::pfx;
+use crate::guard::RepoGuard;
+use crate::index::record_issued;
+use crate::lock::StoreLock;
+use crate::summary::IssuanceSummary;
+
+/// Checks that `address` looks like `local@domain`, enough to tell it from a
+/// typo before it goes into a certificate.
+pub fn check_email(address: &str) -> Result<()> {
+    let valid = match address.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.is_empty()
+                && !domain.contains('@')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !address.contains(char::is_whitespace)
+        }
+        None => false,
+    };
+    if !valid {
+        Err(SimpleCAError::InvalidEmail {
+            address: address.to_string(),
+        })?;
+    }
+    Ok(())
+}
+
+/// Issues an S/MIME certificate for `address` from the intermediate CA, or
+/// the named one of `issuer`, with the emailProtection extended key usage
+/// and the address as rfc822Name SAN and subject emailAddress. The subject
+/// CN is `name`, the address without one.
+///
+/// The key and certificate go into `out_dir` as `<address>.key.pem` and
+/// `<address>.cert.pem`, and both along with the chain into `<address>.p12`
+/// protected by `password`, which mail clients like Thunderbird and Outlook
+/// import.
+#[allow(clippy::too_many_arguments)]
+pub fn issue_email_cert(
+    address: &str,
+    name: Option<&str>,
+    out_dir: &Path,
+    password: &str,
+    validity: &ValidityConf,
+    issuer: Option<&str>,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<IssuanceSummary> {
+    check_email(address)?;
+    let key_path = out_dir.join(format!("{}.key.pem", address));
+    let cert_path = out_dir.join(format!("{}.cert.pem", address));
+    let p12_path = out_dir.join(format!("{}.p12", address));
+    guard.check(&[&key_path, &p12_path])?;
+
+    let _lock = StoreLock::acquire()?;
+    let conf = Conf::load()?;
+    let validity = validity.or(&conf.validity());
+    let (ca, ca_pkey, ca_name) = load_issuer(issuer, &validity, guard, sources, verbose)?;
+
+    let mut subject = X509NameBuilder::new()?;
+    subject.append_entry_by_nid(Nid::COMMONNAME, name.unwrap_or(address))?;
+    subject.append_entry_by_nid(Nid::PKCS9_EMAILADDRESS, address)?;
+    let pkey = sources.new_key(conf.keys().server_algorithm())?;
+    let mut params = CertParams::builder(subject.build(), pkey.clone())
+        .issuer(ca_name, ca_pkey)
+        .valid_days(validity.server_days())
+        .sub_alt_names([address])
+        .ext_key_usage([ExtKeyUsage::EmailProtection])
+        .digest(conf.keys().digest())
+        .build()?;
+    params.ttl = validity.server_ttl()?;
+    (params.not_before, params.not_after) = validity.server_window()?;
+    params.backdate = validity.backdate()?;
+    sources.configure(&mut params, conf.serials().strategy())?;
+    let cert = create_client_cert(&params, &ca)?;
+
+    fs::create_dir_all(out_dir)?;
+    write_file!(
+        &pkey.private_key_to_pem_pkcs8()?,
+        &key_path,
+        verbose,
+        "Saved email key at: {:?}"
+    );
+    write_file!(
+        &cert.to_pem()?,
+        &cert_path,
+        verbose,
+        "Saved email certificate at: {:?}"
+    );
+    let mut chain = vec![cert.clone()];
+    chain.extend(ca_chain(&ca)?);
+    write_private(&p12_path, pfx(address, &pkey, &chain, password)?)?;
+    record_issued(&cert, Some(&key_path), Some(&cert_path), sources.now())?;
+
+    Ok(IssuanceSummary::new(&cert)?
+        .file("key", &key_path)
+        .file("cert", &cert_path)
+        .file("p12", &p12_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_email() {
+        assert!(check_email("alice@example.test").is_ok());
+        assert!(check_email("alice+smime@mail.example.test").is_ok());
+        assert!(check_email("alice").is_err());
+        assert!(check_email("@example.test").is_err());
+        assert!(check_email("alice@").is_err());
+        assert!(check_email("alice@bob@example.test").is_err());
+        assert!(check_email("alice @example.test").is_err());
+    }
+}
@@ -1,7 +1,232 @@
+use std::io;
+use std::path::{PathBuf, StripPrefixError};
+use std::string::FromUtf8Error;
+use std::time::SystemTimeError;
+
+#[cfg(feature = "backend-openssl")]
+use openssl::error::ErrorStack;
 use thiserror::Error;
 
+/// Result of every fallible function of the crate.
+pub type Result<T, E = SimpleCAError> = std::result::Result<T, E>;
+
+/// Errors of simple-ca. Failures of the file system, OpenSSL and the
+/// encodings of the store are wrapped, the rest tell what was wrong with a
+/// request, a certificate or the store so callers can match on them.
 #[derive(Debug, Error)]
 pub enum SimpleCAError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[cfg(feature = "backend-openssl")]
+    #[error(transparent)]
+    OpenSsl(#[from] ErrorStack),
+    #[cfg(feature = "backend-rcgen")]
+    #[error("rcgen could not build the certificate: {0}")]
+    Rcgen(#[from] rcgen::Error),
+    #[error("Could not parse TOML: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("Could not write TOML: {0}")]
+    TomlWrite(#[from] toml::ser::Error),
+    #[error("Could not parse JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Text is not UTF-8: {0}")]
+    Utf8(#[from] FromUtf8Error),
+    #[error("The system clock is before 1970: {0}")]
+    Clock(#[from] SystemTimeError),
+    #[error(transparent)]
+    StripPrefix(#[from] StripPrefixError),
+    #[error("TLS handshake failed: {msg}")]
+    TlsHandshake { msg: String },
     #[error("{msg}")]
     GenericError { msg: &'static str },
+    #[error(
+        "Refusing to write private keys into git repository {}, use --allow-in-repo to override.",
+        repo.display()
+    )]
+    KeyInRepo { repo: PathBuf },
+    #[error("Store layout version {version} is newer than this version of simple-ca supports.")]
+    UnsupportedStoreVersion { version: u32 },
+    #[error("{} is not a simple-ca store: {msg}.", dir.display())]
+    InvalidStore { dir: PathBuf, msg: String },
+    #[error("A CA already exists in {}, use --force to replace it.", dir.display())]
+    StoreExists { dir: PathBuf },
+    #[error("{} cannot be imported as the root CA: {msg}.", path.display())]
+    InvalidRootCa { path: PathBuf, msg: &'static str },
+    #[error(
+        "The root CA key is offline, `ca resign-intermediate --root-key <file>` re-issues the intermediate and `ca import --force` brings the root back."
+    )]
+    RootOffline,
+    #[error("{} is not the private key of the stored root CA.", path.display())]
+    WrongRootKey { path: PathBuf },
+    #[error("{} is not trusted, {reason}.", path.display())]
+    UntrustedConfig { path: PathBuf, reason: &'static str },
+    #[error(
+        "{} is neither the root key nor a trusted config signer, `config trust` adds its public key.",
+        path.display()
+    )]
+    UntrustedConfigSigner { path: PathBuf },
+    #[error("The store is locked by another simple-ca process (pid {pid}).")]
+    StoreLocked { pid: u32 },
+    #[error("Invalid lifetime {ttl:?}, expected a number followed by s, m, h or d.")]
+    InvalidTtl { ttl: String },
+    #[error("Invalid number of days {value:?}, expected a positive whole number.")]
+    InvalidDays { value: String },
+    #[error("Invalid number of keys {value:?}, expected a positive whole number.")]
+    InvalidCount { value: String },
+    #[error("Invalid time {value:?}, expected an RFC 3339 date-time like 2024-01-31T08:00:00Z.")]
+    InvalidTimestamp { value: String },
+    #[error("Certificate would start at {not_before}, after it ends at {not_after}.")]
+    EmptyValidity {
+        not_before: String,
+        not_after: String,
+    },
+    #[error(
+        "Certificate would expire at {not_after}, after its issuer expires at {issuer_not_after}."
+    )]
+    OutlivesIssuer {
+        not_after: String,
+        issuer_not_after: String,
+    },
+    #[error("No server certificate for {domain} in the store.{hint}")]
+    UnknownDomain { domain: String, hint: String },
+    #[error(
+        "Invalid intermediate name {name:?}, use letters, digits, - and _, other than default and cross."
+    )]
+    InvalidIntermediateName { name: String },
+    #[error("No intermediate named {name} in the store.{hint}")]
+    UnknownIntermediate { name: String, hint: String },
+    #[error("{parent} has a path length of 0 and cannot issue a subordinate CA.")]
+    PathLenExceeded { parent: String },
+    #[error("{} is not an OpenSSH public key.", path.display())]
+    InvalidSshKey { path: PathBuf },
+    #[error(
+        "An SSH certificate needs at least one principal, without any it is valid for everyone."
+    )]
+    NoSshPrincipals,
+    #[error("{target:?} is both a file and a domain in the store, write ./{target} for the file.")]
+    AmbiguousTarget { target: String },
+    #[error("Invalid profile name {profile:?}.")]
+    InvalidProfile { profile: String },
+    #[error("{} is not a simple-ca backup: {msg}.", path.display())]
+    InvalidBackup { path: PathBuf, msg: &'static str },
+    #[error("Wrong passphrase for {}.", path.display())]
+    WrongPassphrase { path: PathBuf },
+    #[error("Invalid policy rule {rule:?}: {msg}")]
+    InvalidPolicy { rule: String, msg: String },
+    #[error("Issuing {name} is denied by policy rule {rule:?}.")]
+    PolicyDenied { name: String, rule: String },
+    #[error("Invalid CSR: {msg}.")]
+    InvalidCsr { msg: &'static str },
+    #[error("The attestation statement is for a different key than the CSR.")]
+    AttestationMismatch,
+    #[error(
+        "The key of {domain} is held in hardware and cannot be replaced here, sign a new CSR with `csr` instead."
+    )]
+    HardwareKey { domain: String },
+    #[error(
+        "A passphrase is required but there is no terminal to prompt on, set {env} or use --passphrase-file."
+    )]
+    PassphraseRequired { env: &'static str },
+    #[error("Could not read the passphrase from {}: {msg}", path.display())]
+    UnreadablePassphraseFile { path: PathBuf, msg: String },
+    #[error("Invalid name constraint {value:?}, expected a domain, IP address or CIDR range.")]
+    InvalidNameConstraint { value: String },
+    #[error("Invalid IP address {value:?}.")]
+    InvalidIpAddress { value: String },
+    #[error("Invalid email address {address:?}.")]
+    InvalidEmail { address: String },
+    #[error("Invalid device list at line {line}: {msg}")]
+    InvalidDeviceList { line: usize, msg: String },
+    #[error("{} defines no services under `services:`.", path.display())]
+    NoComposeServices { path: PathBuf },
+    #[error("Invalid certificate manifest {}: {msg}.", path.display())]
+    InvalidCertManifest { path: PathBuf, msg: String },
+    #[error("The hook {command:?} failed with {status}, the certificate was written nonetheless.")]
+    HookFailed { command: String, status: String },
+    #[error("{} already exists, use --force to replace it.", path.display())]
+    FileExists { path: PathBuf },
+    #[error(
+        "{} would be replaced, use --force to replace them.",
+        join_paths(paths)
+    )]
+    WouldReplace { paths: Vec<PathBuf> },
+    #[error(
+        "No supported trust store was found on {platform}, install the root certificate by hand."
+    )]
+    TrustUnsupported { platform: &'static str },
+    #[error("Could not update the {store}: {msg}")]
+    TrustStoreFailed { store: String, msg: String },
+    #[error("Invalid config {}: {msg}.", path.display())]
+    InvalidConfig { path: PathBuf, msg: String },
+    #[error("The subject has no common name.")]
+    MissingCommonName,
+    #[error("No CA in {}, `simple-ca ca` creates one.", dir.display())]
+    CaMissing { dir: PathBuf },
+    #[error("No SSH CA in {}, `simple-ca ssh-ca` creates one.", dir.display())]
+    SshCaMissing { dir: PathBuf },
+    #[error("Could not load {uri} from the PKCS#11 token: {msg}")]
+    TokenKey { uri: String, msg: String },
+    #[error("Could not move the key to the YubiKey: {msg}")]
+    YubiKeyFailed { msg: String },
+    #[error("Could not use {key} in the cloud KMS: {msg}")]
+    KmsFailed { key: String, msg: String },
+    #[error("Vault failed on {path}: {msg}")]
+    VaultFailed { path: String, msg: String },
+    #[error("The TPM failed: {msg}")]
+    TpmFailed { msg: String },
+}
+
+fn join_paths(paths: &[PathBuf]) -> String {
+    let paths: Vec<String> = paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    paths.join(", ")
+}
+
+/// Exit status of the CLI for errors not listed below.
+pub const EXIT_FAILURE: i32 = 1;
+/// The CA, or the certificate or intermediate named, is not in the store.
+pub const EXIT_CA_MISSING: i32 = 2;
+/// A certificate, key, CSR or config failed validation, or policy denied it.
+pub const EXIT_INVALID: i32 = 3;
+/// Refusing to overwrite a file or a CA, or to write keys into a repository.
+pub const EXIT_EXISTS: i32 = 4;
+/// Another simple-ca process holds the store lock.
+pub const EXIT_LOCKED: i32 = 5;
+/// The command line is invalid, `EX_USAGE` of sysexits.
+pub const EXIT_USAGE: i32 = 64;
+
+impl SimpleCAError {
+    /// Exit status of the CLI for the error, so scripts can tell failures
+    /// apart without parsing messages.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SimpleCAError::CaMissing { .. }
+            | SimpleCAError::SshCaMissing { .. }
+            | SimpleCAError::UnknownDomain { .. }
+            | SimpleCAError::UnknownIntermediate { .. } => EXIT_CA_MISSING,
+            SimpleCAError::InvalidRootCa { .. }
+            | SimpleCAError::WrongRootKey { .. }
+            | SimpleCAError::UntrustedConfig { .. }
+            | SimpleCAError::UntrustedConfigSigner { .. }
+            | SimpleCAError::EmptyValidity { .. }
+            | SimpleCAError::OutlivesIssuer { .. }
+            | SimpleCAError::PathLenExceeded { .. }
+            | SimpleCAError::PolicyDenied { .. }
+            | SimpleCAError::InvalidCsr { .. }
+            | SimpleCAError::InvalidEmail { .. }
+            | SimpleCAError::AttestationMismatch => EXIT_INVALID,
+            SimpleCAError::KeyInRepo { .. }
+            | SimpleCAError::StoreExists { .. }
+            | SimpleCAError::FileExists { .. }
+            | SimpleCAError::WouldReplace { .. } => EXIT_EXISTS,
+            SimpleCAError::StoreLocked { .. } => EXIT_LOCKED,
+            SimpleCAError::InvalidDays { .. }
+            | SimpleCAError::InvalidCount { .. }
+            | SimpleCAError::NoSshPrincipals
+            | SimpleCAError::AmbiguousTarget { .. } => EXIT_USAGE,
+            _ => EXIT_FAILURE,
+        }
+    }
 }
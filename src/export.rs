@@ -0,0 +1,568 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use openssl::asn1::Asn1Time;
+use openssl::base64;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::{PKey, Private};
+use openssl::stack::Stack;
+use openssl::x509::X509;
+
+use crate::atomic::{write_file, write_private};
+use crate::certs::cert_fingerprint;
+use crate::commands::{existing_ca_cert, haproxy_pem, read_file, read_root_cert};
+use crate::conf::{file_in_conf, CertAuthConf};
+use crate::der;
+use crate::err::{Result, SimpleCAError};
+use crate::guard::RepoGuard;
+use crate::index::issued_certs;
+use crate::keystore::JavaStoreFormat;
+use crate::lock::StoreLock;
+use crate::out_dir::OutDir;
+use crate::passphrase::read_private_key;
+use crate::store::{self, Role, StoreManifest};
+
+/// Traefik file provider config written by `export_all`.
+const TRAEFIK_CONFIG: &str = "tls.yml";
+/// Raw resource the root is written to by `export_android`, referenced as
+/// `@raw/simple_ca_root`.
+const ANDROID_ROOT: &str = "res/raw/simple_ca_root.crt";
+const ANDROID_NETWORK_CONFIG: &str = "res/xml/network_security_config.xml";
+/// Prefix of the payload identifiers of `mobileconfig_profile`.
+const MOBILECONFIG_IDENTIFIER: &str = "dev.simple-ca.root";
+
+/// Encodes `certs` as a PKCS#7 SignedData without signers, the "certs-only"
+/// `.p7b` bundle understood by Windows and Java tooling.
+pub fn pkcs7_certs_only(certs: &[X509]) -> Result<Vec<u8>> {
+    let certs = certs
+        .iter()
+        .map(|cert| cert.to_der())
+        .collect::<Result<Vec<_>, _>>()?;
+    let signed_data = der::sequence(&[
+        der::small_integer(1),
+        der::set(&[]),
+        der::sequence(&[der::oid(der::OID_PKCS7_DATA)]),
+        der::context(0, &certs.concat()),
+        der::set(&[]),
+    ]);
+    Ok(der::sequence(&[
+        der::oid(der::OID_PKCS7_SIGNED_DATA),
+        der::context(0, &signed_data),
+    ]))
+}
+
+/// The chain of `domain`'s server certificate up to the root, or the CA chain
+/// (intermediate and root) without a domain.
+pub fn chain_certs(domain: Option<&str>) -> Result<Vec<X509>> {
+    let mut certs = Vec::new();
+    if let Some(domain) = domain {
+        StoreManifest::load()?.check_domain(domain)?;
+        certs.push(X509::from_pem(&read_file(&CertAuthConf::server_cert(
+            domain,
+        )?)?)?);
+    }
+    let root = read_root_cert()?;
+    certs.push(X509::from_pem(&read_file(
+        &CertAuthConf::intermediate_cert()?,
+    )?)?);
+    certs.push(root);
+    Ok(certs)
+}
+
+/// Writes the chain of `domain` (or the CA chain) to `dest` as a DER `.p7b`.
+pub fn export_p7b(domain: Option<&str>, dest: &Path) -> Result<()> {
+    write_file(dest, pkcs7_certs_only(&chain_certs(domain)?)?)?;
+    Ok(())
+}
+
+/// Writes the key and full chain of `domain`'s server certificate to `dest`
+/// as the single PEM file HAProxy takes.
+pub fn export_haproxy(domain: &str, dest: &Path, guard: &RepoGuard) -> Result<()> {
+    StoreManifest::load()?.check_domain(domain)?;
+    guard.check(&[dest])?;
+    write_private(dest, haproxy_pem(domain)?)?;
+    Ok(())
+}
+
+/// Writes `domain`'s server certificate, or the root certificate without a
+/// domain, to `dest` in DER. The key of a server certificate is written next
+/// to it as DER encoded PKCS#8 with a `.key` extension. Returns the written
+/// files.
+pub fn export_der(domain: Option<&str>, dest: &Path, guard: &RepoGuard) -> Result<Vec<PathBuf>> {
+    let cert_path = match domain {
+        Some(domain) => {
+            StoreManifest::load()?.check_domain(domain)?;
+            CertAuthConf::server_cert(domain)?
+        }
+        None => existing_ca_cert()?,
+    };
+    let key_dest = dest.with_extension("key");
+    let key = match domain {
+        Some(domain) if store::is_recorded(Role::ServerKey, Some(domain))? => {
+            guard.check(&[&key_dest])?;
+            let pem = read_file(&CertAuthConf::server_key(domain)?)?;
+            Some(PKey::private_key_from_pem(&pem)?.private_key_to_pkcs8()?)
+        }
+        _ => None,
+    };
+    write_file(dest, X509::from_pem(&read_file(&cert_path)?)?.to_der()?)?;
+    let mut written = vec![dest.to_path_buf()];
+    if let Some(key) = key {
+        write_private(&key_dest, key)?;
+        written.push(key_dest);
+    }
+    Ok(written)
+}
+
+/// Writes a Java truststore with the root to `dest`. With a domain whose key
+/// is in the store, a keystore with the key and chain of its server
+/// certificate is written next to it as `<domain>.keystore.<ext>`. Returns
+/// the written files.
+pub fn export_java_stores(
+    domain: Option<&str>,
+    format: JavaStoreFormat,
+    dest: &Path,
+    password: &str,
+    guard: &RepoGuard,
+) -> Result<Vec<PathBuf>> {
+    let root = read_root_cert()?;
+    let keystore = match domain {
+        Some(domain) => {
+            let chain = chain_certs(Some(domain))?;
+            if store::is_recorded(Role::ServerKey, Some(domain))? {
+                let keystore_dest = dest.with_file_name(format!(
+                    "{}.keystore.{}",
+                    domain.replace('*', "_"),
+                    format.extension()
+                ));
+                guard.check(&[&keystore_dest])?;
+                let key = read_private_key(&CertAuthConf::server_key(domain)?)?;
+                Some((
+                    keystore_dest,
+                    format.keystore(domain, &key, &chain, password)?,
+                ))
+            } else {
+                None
+            }
+        }
+        None => None,
+    };
+    write_file(
+        dest,
+        format.truststore(&[("simple-ca-root", &root)], password)?,
+    )?;
+    let mut written = vec![dest.to_path_buf()];
+    if let Some((keystore_dest, keystore)) = keystore {
+        write_private(&keystore_dest, keystore)?;
+        written.push(keystore_dest);
+    }
+    Ok(written)
+}
+
+/// A network security config trusting the root in debuggable builds only,
+/// next to the system CAs.
+pub fn android_network_security_config() -> String {
+    "<?xml version=\"1.0\" encoding=\"utf-8\"?>
+<network-security-config>
+    <debug-overrides>
+        <trust-anchors>
+            <certificates src=\"@raw/simple_ca_root\" />
+        </trust-anchors>
+    </debug-overrides>
+</network-security-config>
+"
+    .to_string()
+}
+
+/// Writes the root in DER as a raw resource and a network security config
+/// referencing it below `dest`, an Android source set like `app/src/debug`.
+/// An existing network security config is only replaced with `force`.
+/// Returns the written files.
+pub fn export_android(dest: &Path, force: bool) -> Result<Vec<PathBuf>> {
+    let root_path = dest.join(ANDROID_ROOT);
+    let config_path = dest.join(ANDROID_NETWORK_CONFIG);
+    if config_path.exists() && !force {
+        Err(SimpleCAError::FileExists {
+            path: config_path.clone(),
+        })?;
+    }
+    let root = read_root_cert()?;
+    for path in [&root_path, &config_path] {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    write_file(&root_path, root.to_der()?)?;
+    write_file(&config_path, android_network_security_config())?;
+    Ok(vec![root_path, config_path])
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A UUID (version 8, RFC 9562) made of 16 bytes of a digest.
+fn digest_uuid(bytes: &[u8]) -> String {
+    let mut bytes = bytes[..16].to_vec();
+    bytes[6] = (bytes[6] & 0x0f) | 0x80;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// An unsigned iOS/macOS configuration profile with `root` as its only
+/// payload. Identifiers and UUIDs derive from the certificate, so installing
+/// the profile again replaces the earlier copy instead of adding another.
+pub fn mobileconfig_profile(root: &X509) -> Result<String> {
+    let digest = root.digest(MessageDigest::sha256())?;
+    let fingerprint = cert_fingerprint(root)?;
+    let identifier = format!("{}.{}", MOBILECONFIG_IDENTIFIER, &fingerprint[..16]);
+    let name = match root.subject_name().entries_by_nid(Nid::COMMONNAME).next() {
+        Some(entry) => xml_escape(&entry.data().to_string()?),
+        None => "Simple CA".to_string(),
+    };
+    Ok(format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>PayloadContent</key>
+    <array>
+        <dict>
+            <key>PayloadCertificateFileName</key>
+            <string>simple-ca-root.cer</string>
+            <key>PayloadContent</key>
+            <data>{cert}</data>
+            <key>PayloadDescription</key>
+            <string>Adds the root certificate of the development CA</string>
+            <key>PayloadDisplayName</key>
+            <string>{name}</string>
+            <key>PayloadIdentifier</key>
+            <string>{identifier}.cert</string>
+            <key>PayloadType</key>
+            <string>com.apple.security.root</string>
+            <key>PayloadUUID</key>
+            <string>{cert_uuid}</string>
+            <key>PayloadVersion</key>
+            <integer>1</integer>
+        </dict>
+    </array>
+    <key>PayloadDescription</key>
+    <string>Trusts certificates issued by the development CA {name}, SHA-256 fingerprint {fingerprint}</string>
+    <key>PayloadDisplayName</key>
+    <string>{name}</string>
+    <key>PayloadIdentifier</key>
+    <string>{identifier}</string>
+    <key>PayloadRemovalDisallowed</key>
+    <false/>
+    <key>PayloadType</key>
+    <string>Configuration</string>
+    <key>PayloadUUID</key>
+    <string>{profile_uuid}</string>
+    <key>PayloadVersion</key>
+    <integer>1</integer>
+</dict>
+</plist>
+"#,
+        cert = base64::encode_block(&root.to_der()?),
+        name = name,
+        identifier = identifier,
+        fingerprint = fingerprint,
+        cert_uuid = digest_uuid(&digest[..16]),
+        profile_uuid = digest_uuid(&digest[16..]),
+    ))
+}
+
+/// Writes a configuration profile installing the root to `dest`.
+pub fn export_mobileconfig(dest: &Path) -> Result<()> {
+    let root = read_root_cert()?;
+    write_file(dest, mobileconfig_profile(&root)?)?;
+    Ok(())
+}
+
+/// A PKCS#12 of `key` and `chain`, the certificate first, shown as `name`.
+/// It is encrypted with 3DES and a SHA-1 MAC, the algorithms every Windows
+/// release reads.
+pub(crate) fn pfx(
+    name: &str,
+    key: &PKey<Private>,
+    chain: &[X509],
+    password: &str,
+) -> Result<Vec<u8>> {
+    let mut ca = Stack::new()?;
+    for cert in &chain[1..] {
+        ca.push(cert.clone())?;
+    }
+    let pfx = Pkcs12::builder()
+        .name(name)
+        .pkey(key)
+        .cert(&chain[0])
+        .ca(ca)
+        .key_algorithm(Nid::PBE_WITHSHA1AND3_KEY_TRIPLEDES_CBC)
+        .cert_algorithm(Nid::PBE_WITHSHA1AND3_KEY_TRIPLEDES_CBC)
+        .mac_md(MessageDigest::sha1())
+        .build2(password)?;
+    Ok(pfx.to_der()?)
+}
+
+/// Writes the key and chain of `domain`'s server certificate to `dest` as a
+/// PFX for IIS and the Windows certificate import wizard.
+pub fn export_pfx(domain: &str, dest: &Path, password: &str, guard: &RepoGuard) -> Result<()> {
+    let chain = chain_certs(Some(domain))?;
+    guard.check(&[dest])?;
+    let key = read_private_key(&CertAuthConf::server_key(domain)?)?;
+    write_private(dest, pfx(domain, &key, &chain, password)?)?;
+    Ok(())
+}
+
+/// Name of the Kubernetes Secret for `domain`, a DNS-1123 label like
+/// `www-example-test-tls` or `wildcard-example-test-tls`.
+pub fn k8s_secret_name(domain: &str) -> String {
+    let name: String = domain
+        .to_ascii_lowercase()
+        .replace('*', "wildcard")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    format!("{}-tls", name.trim_matches('-'))
+}
+
+/// A `kubernetes.io/tls` Secret manifest with the key and full chain of
+/// `domain`'s server certificate and the root as `ca.crt`, ready for
+/// `kubectl apply -f -`.
+pub fn k8s_tls_secret(domain: &str, name: Option<&str>, namespace: Option<&str>) -> Result<String> {
+    StoreManifest::load()?.check_domain(domain)?;
+    let encode = |path| -> Result<String> { Ok(base64::encode_block(&read_file(path)?)) };
+    let mut manifest = format!(
+        "apiVersion: v1\nkind: Secret\nmetadata:\n  name: {}\n",
+        name.map(str::to_string)
+            .unwrap_or_else(|| k8s_secret_name(domain))
+    );
+    if let Some(namespace) = namespace {
+        manifest.push_str(&format!("  namespace: {}\n", namespace));
+    }
+    manifest.push_str(&format!(
+        "type: kubernetes.io/tls\ndata:\n  tls.crt: {}\n  tls.key: {}\n  ca.crt: {}\n",
+        encode(&CertAuthConf::server_fullchain(domain)?)?,
+        encode(&CertAuthConf::server_key(domain)?)?,
+        encode(&CertAuthConf::ca_cert()?)?,
+    ));
+    Ok(manifest)
+}
+
+/// Directory conventions `export_all` writes server files in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportLayout {
+    /// `certs/<domain>.crt` (full chain) and `certs/<domain>.key`, listed in
+    /// a `tls.yml` for the Traefik file provider.
+    Traefik,
+    /// A directory per domain with `fullchain.pem`, `privkey.pem`,
+    /// `cert.pem` and `chain.pem`, like certbot's `live` directory.
+    Nginx,
+    /// Every file in one directory, named as by `server --out-dir`.
+    Flat,
+}
+
+impl ExportLayout {
+    pub fn from_name(name: &str) -> Option<ExportLayout> {
+        match name {
+            "traefik" => Some(ExportLayout::Traefik),
+            "nginx" => Some(ExportLayout::Nginx),
+            "flat" => Some(ExportLayout::Flat),
+            _ => None,
+        }
+    }
+
+    fn out_dir(&self, dest: &Path) -> OutDir {
+        let mut out_dir = OutDir::new(dest);
+        let names = match self {
+            ExportLayout::Traefik => [
+                "certs/{domain}.key",
+                "certs/{domain}.cert.crt",
+                "certs/{domain}.crt",
+                "certs/{domain}.chain.crt",
+                "certs/{domain}.dhparam.pem",
+                "certs/{domain}.ticket.key",
+                "certs/{domain}.haproxy.pem",
+                "certs/{domain}.der.crt",
+                "certs/{domain}.der.key",
+            ],
+            ExportLayout::Nginx => [
+                "{domain}/privkey.pem",
+                "{domain}/cert.pem",
+                "{domain}/fullchain.pem",
+                "{domain}/chain.pem",
+                "{domain}/dhparam.pem",
+                "{domain}/ticket.key",
+                "{domain}/haproxy.pem",
+                "{domain}/cert.der",
+                "{domain}/privkey.der",
+            ],
+            ExportLayout::Flat => return out_dir,
+        };
+        [
+            out_dir.key_name,
+            out_dir.cert_name,
+            out_dir.fullchain_name,
+            out_dir.chain_name,
+            out_dir.dhparam_name,
+            out_dir.ticket_key_name,
+            out_dir.haproxy_name,
+            out_dir.cert_der_name,
+            out_dir.key_der_name,
+        ] = names.map(str::to_string);
+        out_dir
+    }
+}
+
+/// Domains of the server certificates in the index that are still current,
+/// i.e. not replaced by a later one nor expired.
+fn current_server_domains() -> Result<Vec<String>> {
+    let manifest = StoreManifest::load()?;
+    let now = Asn1Time::days_from_now(0)?;
+    let mut domains: Vec<String> = Vec::new();
+    for issued in issued_certs()?.iter().rev() {
+        let cert_path = match issued.cert_path()? {
+            Some(path) => path,
+            None => continue,
+        };
+        let domain = manifest.entries(Role::ServerCert).find_map(|entry| {
+            match (&entry.name, file_in_conf(&entry.path)) {
+                (Some(name), Ok(path)) if path == cert_path => Some(name.clone()),
+                _ => None,
+            }
+        });
+        let domain = match domain {
+            Some(domain) if !domains.contains(&domain) => domain,
+            _ => continue,
+        };
+        let cert = match read_file(&cert_path).map(|pem| X509::from_pem(&pem)) {
+            Ok(Ok(cert)) => cert,
+            _ => continue,
+        };
+        let serial = cert.serial_number().to_bn()?.to_hex_str()?.to_string();
+        if serial == issued.serial && cert.not_after() > now {
+            domains.push(domain);
+        }
+    }
+    domains.sort();
+    Ok(domains)
+}
+
+/// Copies the key, certificate and chains of every current server
+/// certificate in the index into `dest`, laid out as `layout` expects.
+/// Returns the exported domains.
+pub fn export_all(
+    layout: ExportLayout,
+    dest: &Path,
+    guard: &RepoGuard,
+    verbose: bool,
+) -> Result<Vec<String>> {
+    let _lock = StoreLock::acquire()?;
+    let domains = current_server_domains()?;
+    fs::create_dir_all(dest)?;
+    // Absolute paths, as the Traefik config must name the files.
+    let out_dir = layout.out_dir(&dest.canonicalize()?);
+    for domain in &domains {
+        out_dir.copy_server_files(domain, guard, verbose)?;
+    }
+    if layout == ExportLayout::Traefik {
+        let mut config = String::from("tls:\n  certificates:\n");
+        for domain in &domains {
+            let key = out_dir.key_path(domain);
+            // Traefik cannot serve certificates whose key is held in hardware.
+            if !key.exists() {
+                continue;
+            }
+            config.push_str(&format!(
+                "    - certFile: {:?}\n      keyFile: {:?}\n",
+                out_dir.fullchain_path(domain),
+                key,
+            ));
+        }
+        let config_path = out_dir.dir.join(TRAEFIK_CONFIG);
+        write_file(&config_path, config)?;
+        if verbose {
+            tracing::info!("Saved Traefik TLS config at: {:?}", config_path);
+        }
+    }
+    Ok(domains)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certs::fixtures::test_root_ca;
+    use crate::commands::fixtures::issue_test_server;
+    use crate::commands::ServerExtras;
+    use crate::conf::with_test_store;
+    use openssl::pkcs7::Pkcs7;
+
+    #[test]
+    fn test_k8s_secret_name() {
+        assert_eq!(k8s_secret_name("www.Example.test"), "www-example-test-tls");
+        assert_eq!(
+            k8s_secret_name("*.example.test"),
+            "wildcard-example-test-tls"
+        );
+        assert_eq!(k8s_secret_name("db.test:5432"), "db-test-5432-tls");
+    }
+
+    #[test]
+    fn test_digest_uuid() {
+        let uuid = digest_uuid(&[0xff; 16]);
+        assert_eq!(uuid, "FFFFFFFF-FFFF-8FFF-BFFF-FFFFFFFFFFFF");
+        assert_eq!(xml_escape("R&D <CA>"), "R&amp;D &lt;CA&gt;");
+    }
+
+    #[test]
+    fn test_pkcs7_certs_only() {
+        let (_, _, cert) = test_root_ca();
+
+        let p7b = pkcs7_certs_only(&[cert.clone(), cert.clone()]).unwrap();
+        let pkcs7 = Pkcs7::from_der(&p7b).unwrap();
+        let certs = pkcs7.signed().unwrap().certificates().unwrap();
+        assert_eq!(certs.len(), 2);
+        assert_eq!(certs[0].to_der().unwrap(), cert.to_der().unwrap());
+    }
+
+    #[test]
+    fn test_export_all() {
+        with_test_store("export-all", |home| {
+            for domain in ["app.test", "*.api.test"] {
+                issue_test_server(domain, &[], &ServerExtras::default());
+            }
+            let dest = home.join("traefik");
+            let mut domains =
+                export_all(ExportLayout::Traefik, &dest, &RepoGuard::default(), false).unwrap();
+            domains.sort();
+            assert_eq!(domains, ["*.api.test", "app.test"]);
+            let dest = dest.canonicalize().unwrap();
+            let config = fs::read_to_string(dest.join(TRAEFIK_CONFIG)).unwrap();
+            for name in ["app.test", "_.api.test"] {
+                let key = dest.join(format!("certs/{}.key", name));
+                assert!(key.exists());
+                assert!(config.contains(&format!("keyFile: {:?}", key)));
+            }
+
+            let dest = home.join("nginx");
+            export_all(ExportLayout::Nginx, &dest, &RepoGuard::default(), false).unwrap();
+            assert!(dest.join("app.test/fullchain.pem").exists());
+            assert!(dest.join("app.test/privkey.pem").exists());
+            assert!(!dest.join(TRAEFIK_CONFIG).exists());
+        });
+    }
+}
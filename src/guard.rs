@@ -0,0 +1,133 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::conf::local_store;
+use crate::err::{Result, SimpleCAError};
+
+/// Guardrails applied before private keys are written into a directory.
+#[derive(Debug, Default, Clone)]
+pub struct RepoGuard {
+    pub allow_in_repo: bool,
+    pub gitignore: bool,
+    /// Replace an existing CA or server certificate instead of refusing to.
+    pub replace: bool,
+}
+
+/// Finds the root of the git work tree containing `path`, if any.
+pub fn git_root(path: &Path) -> Option<PathBuf> {
+    let path = path.canonicalize().ok()?;
+    path.ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .map(|dir| dir.to_path_buf())
+}
+
+fn append_gitignore(root: &Path, entries: &[String]) -> Result<()> {
+    let gitignore = root.join(".gitignore");
+    let existing = if gitignore.exists() {
+        fs::read_to_string(&gitignore)?
+    } else {
+        String::new()
+    };
+    let missing: Vec<&String> = entries
+        .iter()
+        .filter(|entry| !existing.lines().any(|line| line.trim() == entry.as_str()))
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&gitignore)?;
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        f.write_all(b"\n")?;
+    }
+    for entry in missing {
+        writeln!(f, "{}", entry)?;
+    }
+    Ok(())
+}
+
+impl RepoGuard {
+    /// Checks that `keys` may be written, refusing when they would land inside
+    /// a git work tree unless explicitly allowed, and optionally ignoring them.
+    /// Keys of a project-local store are meant to live in the project.
+    pub fn check(&self, keys: &[&Path]) -> Result<()> {
+        let local = local_store().and_then(|dir| dir.canonicalize().ok());
+        for key in keys {
+            let dir = match key.parent() {
+                Some(dir) if dir.exists() => dir,
+                _ => continue,
+            };
+            if let (Some(local), Ok(dir)) = (&local, dir.canonicalize()) {
+                if dir.starts_with(local) {
+                    continue;
+                }
+            }
+            let root = match git_root(dir) {
+                Some(root) => root,
+                None => continue,
+            };
+            if !self.allow_in_repo {
+                Err(SimpleCAError::KeyInRepo { repo: root.clone() })?;
+            }
+            if self.gitignore {
+                let dir = dir.canonicalize()?;
+                let file_name = key.file_name().unwrap_or_default();
+                let relative = dir.strip_prefix(&root)?.join(file_name);
+                let entry = format!("/{}", relative.to_string_lossy().replace('\\', "/"));
+                append_gitignore(&root, &[entry])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Refuses to write over the existing files among `paths` unless
+    /// replacing is allowed, and warns about each one it replaces. Callers
+    /// hold the store lock, so no other process creates them in between.
+    pub fn check_replace(&self, paths: &[&Path]) -> Result<()> {
+        let existing: Vec<PathBuf> = paths
+            .iter()
+            .filter(|path| path.exists())
+            .map(|path| path.to_path_buf())
+            .collect();
+        if !existing.is_empty() && !self.replace {
+            return Err(SimpleCAError::WouldReplace { paths: existing });
+        }
+        for path in &existing {
+            tracing::warn!(
+                "{}",
+                crate::tr!("replacing-file", path = path.display().to_string())
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guard_in_repo() {
+        let repo = Path::new("target/guard_repo");
+        let _ = fs::remove_dir_all(repo);
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        fs::create_dir_all(repo.join("certs")).unwrap();
+        let key = repo.join("certs/example.com.key.pem");
+
+        assert!(RepoGuard::default().check(&[&key]).is_err());
+
+        let guard = RepoGuard {
+            allow_in_repo: true,
+            gitignore: true,
+            ..RepoGuard::default()
+        };
+        guard.check(&[&key]).unwrap();
+        guard.check(&[&key]).unwrap();
+        let gitignore = fs::read_to_string(repo.join(".gitignore")).unwrap();
+        assert_eq!(gitignore, "/certs/example.com.key.pem\n");
+    }
+}
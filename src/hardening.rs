@@ -0,0 +1,44 @@
+//! TLS hardening files that server configs often ask for next to the
+//! certificate, so they do not need extra `openssl` invocations.
+
+use crate::err::Result;
+
+use openssl::dh::Dh;
+use openssl::rand::rand_bytes;
+
+/// Size of the generated Diffie-Hellman prime, as `openssl dhparam 2048`.
+pub const DHPARAM_BITS: u32 = 2048;
+
+/// Length of a session ticket key: 16 bytes of key name, 32 of HMAC secret and
+/// 32 of AES-256 key, the layout nginx and HAProxy read.
+pub const TICKET_KEY_LEN: usize = 80;
+
+/// Generates PEM encoded Diffie-Hellman parameters with a `bits` long prime.
+pub fn generate_dhparam(bits: u32) -> Result<Vec<u8>> {
+    let dh = Dh::generate_params(bits, 2)?;
+    Ok(dh.params_to_pem()?)
+}
+
+/// Generates a random session ticket key, for `ssl_session_ticket_key` in
+/// nginx or `tls-ticket-keys` in HAProxy.
+pub fn generate_ticket_key() -> Result<Vec<u8>> {
+    let mut key = vec![0u8; TICKET_KEY_LEN];
+    rand_bytes(&mut key)?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_hardening_files() {
+        let pem = generate_dhparam(512).unwrap();
+        let dh = Dh::params_from_pem(&pem).unwrap();
+        assert_eq!(dh.prime_p().num_bits(), 512);
+
+        let first = generate_ticket_key().unwrap();
+        assert_eq!(first.len(), TICKET_KEY_LEN);
+        assert_ne!(first, generate_ticket_key().unwrap());
+    }
+}
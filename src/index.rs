@@ -0,0 +1,217 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::err::Result;
+use serde::{Deserialize, Serialize};
+
+use openssl::base64;
+use openssl::nid::Nid;
+use openssl::x509::X509Ref;
+
+use crate::atomic::write_file;
+use crate::conf::{config_dir, file_in_conf, CertAuthConf};
+use crate::datetime::{format_asn1, format_rfc3339};
+use crate::lock::StoreLock;
+
+pub(crate) const INDEX_FILE: &str = "issued.json";
+
+/// A certificate issued by this CA, as recorded in `issued.json`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct IssuedCert {
+    /// Serial number in hex.
+    pub serial: String,
+    /// Common name, or the subject serialNumber of device certificates.
+    pub common_name: String,
+    #[serde(default)]
+    pub sans: Vec<String>,
+    pub not_before: String,
+    pub not_after: String,
+    /// When the certificate was recorded, missing from entries written by
+    /// older versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issued_at: Option<String>,
+    /// Key file, relative to the config dir when inside it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Certificate file, relative to the config dir when inside it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cert: Option<String>,
+    /// Attestation statement of a hardware-bound key, base64 encoded as it
+    /// was submitted along with the CSR.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<String>,
+}
+
+pub(crate) fn stored_path(path: &Path) -> Result<String> {
+    let dir = config_dir()?;
+    Ok(path
+        .strip_prefix(&dir)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string())
+}
+
+impl IssuedCert {
+    pub fn from_cert(
+        cert: &X509Ref,
+        key: Option<&Path>,
+        path: Option<&Path>,
+    ) -> Result<IssuedCert> {
+        let subject = cert.subject_name();
+        let common_name = match subject
+            .entries_by_nid(Nid::COMMONNAME)
+            .chain(subject.entries_by_nid(Nid::SERIALNUMBER))
+            .next()
+        {
+            Some(entry) => entry.data().to_string()?,
+            None => String::new(),
+        };
+        let sans = cert
+            .subject_alt_names()
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| name.dnsname().map(|dns| dns.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(IssuedCert {
+            serial: cert.serial_number().to_bn()?.to_hex_str()?.to_string(),
+            common_name,
+            sans,
+            not_before: format_asn1(cert.not_before())?,
+            not_after: format_asn1(cert.not_after())?,
+            issued_at: None,
+            key: key.map(stored_path).transpose()?,
+            cert: path.map(stored_path).transpose()?,
+            attestation: None,
+        })
+    }
+
+    /// Absolute path of the key file, if it was written to disk.
+    pub fn key_path(&self) -> Result<Option<std::path::PathBuf>> {
+        self.key.as_deref().map(file_in_conf).transpose()
+    }
+
+    /// Absolute path of the certificate file, if it was written to disk.
+    pub fn cert_path(&self) -> Result<Option<std::path::PathBuf>> {
+        self.cert.as_deref().map(file_in_conf).transpose()
+    }
+}
+
+/// Every certificate issued so far, oldest first.
+pub fn issued_certs() -> Result<Vec<IssuedCert>> {
+    let path = file_in_conf(INDEX_FILE)?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// The unexpired certificates issued since the current root was created,
+/// each file counted once and the root itself left out. Regenerating the
+/// root leaves all of them without a trusted chain. Certificates expired at
+/// `now` are left out too.
+pub fn issued_under_root(now: SystemTime) -> Result<Vec<IssuedCert>> {
+    let certs = issued_certs()?;
+    let root = Some(stored_path(&CertAuthConf::ca_cert()?)?);
+    let start = certs
+        .iter()
+        .rposition(|cert| cert.cert == root)
+        .map_or(0, |index| index + 1);
+    let now = format_rfc3339(now);
+    let mut seen = HashSet::new();
+    let mut issued = Vec::new();
+    for cert in certs[start..].iter().rev() {
+        // Later issuances for the same name overwrote the files of earlier ones.
+        if let Some(path) = &cert.cert {
+            if !seen.insert(path) {
+                continue;
+            }
+        }
+        if cert.not_after > now {
+            issued.push(cert.clone());
+        }
+    }
+    issued.reverse();
+    Ok(issued)
+}
+
+/// Appends `cert` to the issuance index.
+pub(crate) fn record_issued(
+    cert: &X509Ref,
+    key: Option<&Path>,
+    path: Option<&Path>,
+    issued_at: SystemTime,
+) -> Result<()> {
+    push(IssuedCert::from_cert(cert, key, path)?, issued_at)
+}
+
+/// Appends `cert`, whose key is held in hardware, to the issuance index
+/// along with the attestation statement of the key.
+pub(crate) fn record_attested(
+    cert: &X509Ref,
+    path: Option<&Path>,
+    attestation: Option<&[u8]>,
+    issued_at: SystemTime,
+) -> Result<()> {
+    let mut issued = IssuedCert::from_cert(cert, None, path)?;
+    issued.attestation = attestation.map(base64::encode_block);
+    push(issued, issued_at)
+}
+
+fn push(mut issued: IssuedCert, issued_at: SystemTime) -> Result<()> {
+    issued.issued_at = Some(format_rfc3339(issued_at));
+    let _lock = StoreLock::acquire()?;
+    let mut certs = issued_certs()?;
+    certs.push(issued);
+    write_file(
+        file_in_conf(INDEX_FILE)?,
+        serde_json::to_vec_pretty(&certs)?,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fixtures::issue_test_server;
+    use crate::commands::ServerExtras;
+    use crate::conf::with_test_store;
+
+    fn issue(domain: &str) -> String {
+        issue_test_server(domain, &[], &ServerExtras::default()).serial
+    }
+    #[test]
+    fn test_issued_certs() {
+        with_test_store("index", |_| {
+            issue("app.test");
+            issue("api.test");
+            let serial = issue("app.test");
+
+            let servers: Vec<IssuedCert> = issued_certs()
+                .unwrap()
+                .into_iter()
+                .filter(|cert| cert.common_name.ends_with(".test"))
+                .collect();
+            let names: Vec<&str> = servers.iter().map(|c| c.common_name.as_str()).collect();
+            assert_eq!(names, ["app.test", "api.test", "app.test"]);
+            // Paths inside the store are kept relative to it.
+            assert!(servers[0]
+                .cert
+                .as_deref()
+                .is_some_and(|p| !p.starts_with('/')));
+            assert!(servers[0].cert_path().unwrap().unwrap().exists());
+
+            let current: Vec<IssuedCert> = issued_under_root(SystemTime::now())
+                .unwrap()
+                .into_iter()
+                .filter(|cert| cert.common_name == "app.test")
+                .collect();
+            assert_eq!(current.len(), 1);
+            assert_eq!(current[0].serial, serial);
+        });
+    }
+}
@@ -0,0 +1,372 @@
+use std::fmt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::err::{Result, SimpleCAError};
+use serde::Serialize;
+
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{HasPublic, Id, PKeyRef};
+use openssl::x509::{X509NameRef, X509Ref, X509};
+
+use crate::commands::read_file;
+use crate::conf::CertAuthConf;
+use crate::datetime::{asn1_to_unix, format_unix};
+use crate::der;
+use crate::store::{Role, StoreManifest};
+
+/// The bits of keyUsage in the order of RFC 5280, most significant first.
+const KEY_USAGES: [&str; 9] = [
+    "Digital Signature",
+    "Non Repudiation",
+    "Key Encipherment",
+    "Data Encipherment",
+    "Key Agreement",
+    "Certificate Sign",
+    "CRL Sign",
+    "Encipher Only",
+    "Decipher Only",
+];
+
+/// `1.3.6.1.5.5.7.3`, the arc of the key purposes of RFC 5280.
+const OID_KEY_PURPOSE: [u8; 7] = [0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03];
+
+/// Key purposes by their last arc under `OID_KEY_PURPOSE`.
+const EXTENDED_KEY_USAGES: [(u8, &str); 7] = [
+    (1, "TLS Web Server Authentication"),
+    (2, "TLS Web Client Authentication"),
+    (4, "E-mail Protection"),
+    (3, "Code Signing"),
+    (9, "OCSP Signing"),
+    (8, "Time Stamping"),
+    (10, "DVCS"),
+];
+
+/// Human readable details of a certificate, as printed by `inspect`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CertInfo {
+    pub subject: String,
+    pub issuer: String,
+    pub serial: String,
+    pub not_before: String,
+    pub not_after: String,
+    pub sans: Vec<String>,
+    pub public_key: String,
+    pub is_ca: bool,
+    pub key_usage: Vec<String>,
+    pub extended_key_usage: Vec<String>,
+    pub sha256_fingerprint: String,
+    pub sha1_fingerprint: String,
+    #[serde(skip)]
+    expires_in: i64,
+}
+
+/// Formats a name like `O=Simple CA, CN=Simple CA Root CA`.
+pub(crate) fn format_name(name: &X509NameRef) -> Result<String> {
+    let mut parts = Vec::new();
+    for entry in name.entries() {
+        let object = entry.object();
+        let key = match object.nid().short_name() {
+            Ok(short) if object.nid() != Nid::UNDEF => short.to_string(),
+            _ => object.to_string(),
+        };
+        parts.push(format!("{}={}", key, entry.data().to_string()?));
+    }
+    Ok(parts.join(", "))
+}
+
+/// Describes a key like `RSA 2048`.
+pub(crate) fn describe_key<T: HasPublic>(pkey: &PKeyRef<T>) -> String {
+    let algorithm = match pkey.id() {
+        Id::RSA => "RSA",
+        Id::EC => "EC",
+        Id::ED25519 => "Ed25519",
+        _ => "Unknown",
+    };
+    format!("{} {}", algorithm, pkey.bits())
+}
+
+fn fingerprint(cert: &X509Ref, digest: MessageDigest) -> Result<String> {
+    Ok(cert
+        .digest(digest)?
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+fn malformed() -> SimpleCAError {
+    SimpleCAError::GenericError {
+        msg: "Malformed certificate DER.",
+    }
+}
+
+/// The OID and the value of each extension in the DER encoded `cert`.
+fn extensions(cert: &[u8]) -> Option<Vec<(&[u8], &[u8])>> {
+    let (_, cert, _) = der::read_tlv(cert)?;
+    let (_, tbs, _) = der::read_tlv(cert)?;
+    let wrapped = match der::children(tbs)?
+        .into_iter()
+        .find(|field| field[0] == 0xa3)
+    {
+        Some(wrapped) => wrapped,
+        None => return Some(Vec::new()),
+    };
+    let (_, list, _) = der::read_tlv(wrapped)?;
+    let (_, list, _) = der::read_tlv(list)?;
+    der::children(list)?
+        .into_iter()
+        .map(|extension| {
+            let (_, extension, _) = der::read_tlv(extension)?;
+            let fields = der::children(extension)?;
+            let (_, oid, _) = der::read_tlv(fields.first()?)?;
+            // The value is last, after the optional critical flag.
+            let (_, value, _) = der::read_tlv(fields.last()?)?;
+            Some((oid, value))
+        })
+        .collect()
+}
+
+/// Whether the basicConstraints `value` has the cA flag set.
+fn basic_constraints_ca(value: &[u8]) -> Option<bool> {
+    let (_, content, _) = der::read_tlv(value)?;
+    Some(match der::children(content)?.first() {
+        Some(first) => {
+            let (tag, flag, _) = der::read_tlv(first)?;
+            tag == der::TAG_BOOLEAN && flag.iter().any(|b| *b != 0)
+        }
+        None => false,
+    })
+}
+
+/// The names of the bits set in the keyUsage `value`.
+fn key_usages(value: &[u8]) -> Option<Vec<String>> {
+    let (_, bits, _) = der::read_tlv(value)?;
+    // The first byte counts the unused bits of the last one.
+    let bits = bits.get(1..)?;
+    Some(
+        KEY_USAGES
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| {
+                bits.get(bit / 8)
+                    .is_some_and(|b| b & (0x80 >> (bit % 8)) != 0)
+            })
+            .map(|(_, name)| name.to_string())
+            .collect(),
+    )
+}
+
+/// The names of the known key purposes in the extKeyUsage `value`.
+fn extended_key_usages(value: &[u8]) -> Option<Vec<String>> {
+    let (_, content, _) = der::read_tlv(value)?;
+    let mut oids = Vec::new();
+    for purpose in der::children(content)? {
+        oids.push(der::read_tlv(purpose)?.1);
+    }
+    let mut usages: Vec<String> = EXTENDED_KEY_USAGES
+        .iter()
+        .filter(|(arc, _)| {
+            oids.iter()
+                .any(|oid| oid.strip_prefix(&OID_KEY_PURPOSE[..]) == Some(&[*arc][..]))
+        })
+        .map(|(_, name)| name.to_string())
+        .collect();
+    if oids.contains(&der::OID_ANY_EXTENDED_KEY_USAGE) {
+        usages.push("Any Extended Key Usage".to_string());
+    }
+    Some(usages)
+}
+
+impl CertInfo {
+    pub fn from_cert(cert: &X509Ref) -> Result<CertInfo> {
+        let pkey = cert.public_key()?;
+        let der = cert.to_der()?;
+        let (mut is_ca, mut key_usage, mut extended_key_usage) = (false, Vec::new(), Vec::new());
+        for (oid, value) in extensions(&der).ok_or_else(malformed)? {
+            if oid == der::OID_BASIC_CONSTRAINTS {
+                is_ca = basic_constraints_ca(value).ok_or_else(malformed)?;
+            } else if oid == der::OID_KEY_USAGE {
+                key_usage = key_usages(value).ok_or_else(malformed)?;
+            } else if oid == der::OID_EXTENDED_KEY_USAGE {
+                extended_key_usage = extended_key_usages(value).ok_or_else(malformed)?;
+            }
+        }
+        let sans = cert
+            .subject_alt_names()
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| {
+                        name.dnsname()
+                            .map(|dns| dns.to_string())
+                            .or_else(|| name.ipaddress().map(format_ip))
+                            .or_else(|| name.email().map(|email| email.to_string()))
+                            .or_else(|| name.uri().map(|uri| uri.to_string()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let not_after = asn1_to_unix(cert.not_after())?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+
+        Ok(CertInfo {
+            subject: format_name(cert.subject_name())?,
+            issuer: format_name(cert.issuer_name())?,
+            serial: cert.serial_number().to_bn()?.to_hex_str()?.to_string(),
+            not_before: format_unix(asn1_to_unix(cert.not_before())?),
+            not_after: format_unix(not_after),
+            sans,
+            public_key: describe_key(&pkey),
+            is_ca,
+            key_usage,
+            extended_key_usage,
+            sha256_fingerprint: fingerprint(cert, MessageDigest::sha256())?,
+            sha1_fingerprint: fingerprint(cert, MessageDigest::sha1())?,
+            expires_in: not_after - now,
+        })
+    }
+}
+
+pub(crate) fn format_ip(ip: &[u8]) -> String {
+    match ip.len() {
+        4 => format!("{}.{}.{}.{}", ip[0], ip[1], ip[2], ip[3]),
+        16 => ip
+            .chunks(2)
+            .map(|pair| format!("{:x}", u16::from_be_bytes([pair[0], pair[1]])))
+            .collect::<Vec<_>>()
+            .join(":"),
+        _ => String::new(),
+    }
+}
+
+fn or_none(values: &[String]) -> String {
+    if values.is_empty() {
+        "-".to_string()
+    } else {
+        values.join(", ")
+    }
+}
+
+impl fmt::Display for CertInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let expiry = if self.expires_in < 0 {
+            "expired".to_string()
+        } else {
+            format!("expires in {} days", self.expires_in / 86_400)
+        };
+        writeln!(f, "Subject:            {}", self.subject)?;
+        writeln!(f, "Issuer:             {}", self.issuer)?;
+        writeln!(f, "Serial:             {}", self.serial)?;
+        writeln!(f, "Not before:         {}", self.not_before)?;
+        writeln!(f, "Not after:          {} ({})", self.not_after, expiry)?;
+        writeln!(f, "SANs:               {}", or_none(&self.sans))?;
+        writeln!(f, "Public key:         {}", self.public_key)?;
+        writeln!(
+            f,
+            "CA:                 {}",
+            if self.is_ca { "yes" } else { "no" }
+        )?;
+        writeln!(f, "Key usage:          {}", or_none(&self.key_usage))?;
+        writeln!(
+            f,
+            "Extended key usage: {}",
+            or_none(&self.extended_key_usage)
+        )?;
+        writeln!(f, "SHA-256:            {}", self.sha256_fingerprint)?;
+        write!(f, "SHA-1:              {}", self.sha1_fingerprint)
+    }
+}
+
+/// Loads the certificates in the file at `target` (PEM or DER), or the stored
+/// server certificate of the domain `target`. A name that is both asks for
+/// `./` in front of the file.
+pub(crate) fn load_certs(target: &str) -> Result<Vec<X509>> {
+    let path = Path::new(target);
+    if path.is_file() {
+        let is_domain = StoreManifest::load()
+            .is_ok_and(|manifest| manifest.entry(Role::ServerCert, Some(target)).is_some());
+        if is_domain {
+            Err(SimpleCAError::AmbiguousTarget {
+                target: target.to_string(),
+            })?;
+        }
+        let content = read_file(path)?;
+        match X509::stack_from_pem(&content) {
+            Ok(certs) if !certs.is_empty() => Ok(certs),
+            _ => Ok(vec![X509::from_der(&content)?]),
+        }
+    } else {
+        StoreManifest::load()?.check_domain(target)?;
+        Ok(X509::stack_from_pem(&read_file(
+            &CertAuthConf::server_cert(target)?,
+        )?)?)
+    }
+}
+
+/// Details of the certificates at `target`, see `load_certs`.
+pub fn inspect(target: &str) -> Result<Vec<CertInfo>> {
+    load_certs(target)?
+        .iter()
+        .map(|cert| CertInfo::from_cert(cert))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cert_params::CertParams;
+    use crate::certs::create_server_cert;
+    use crate::certs::fixtures::test_root_ca;
+    use crate::conf::with_test_store;
+    use crate::keys::KeyAlgorithm;
+    use crate::name::Name;
+    use crate::store;
+
+    #[test]
+    fn test_cert_info() {
+        let (ca_name, ca_key, ca) = test_root_ca();
+        let info = CertInfo::from_cert(&ca).unwrap();
+        assert!(info.is_ca);
+        assert_eq!(
+            info.key_usage,
+            ["Digital Signature", "Certificate Sign", "CRL Sign"]
+        );
+        assert!(info.extended_key_usage.is_empty());
+
+        let name = Name::new("app.test").to_x509_name().unwrap();
+        let key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let params =
+            CertParams::server_cert_params(&name, &key, &ca_name, &ca_key, 1, &vec!["app.test"])
+                .unwrap();
+        let info = CertInfo::from_cert(&create_server_cert(&params, &ca).unwrap()).unwrap();
+        assert!(!info.is_ca);
+        assert_eq!(
+            info.key_usage,
+            ["Digital Signature", "Non Repudiation", "Key Encipherment"]
+        );
+        assert_eq!(info.extended_key_usage, ["TLS Web Server Authentication"]);
+    }
+
+    #[test]
+    fn test_load_certs_ambiguous() {
+        with_test_store("inspect", |home| {
+            // Cargo.toml is a file in the directory tests run in.
+            store::record(
+                Role::ServerCert,
+                Some("Cargo.toml"),
+                &home.join("cert.pem"),
+                None,
+            )
+            .unwrap();
+            assert!(matches!(
+                load_certs("Cargo.toml"),
+                Err(SimpleCAError::AmbiguousTarget { .. })
+            ));
+            assert!(!matches!(
+                load_certs("./Cargo.toml"),
+                Err(SimpleCAError::AmbiguousTarget { .. })
+            ));
+        });
+    }
+}
@@ -0,0 +1,162 @@
+//! Keys generated ahead of issuance, so a dev certificate comes out at once
+//! instead of after seconds of RSA key generation. `prewarm` fills the pool in
+//! the store, `serve` keeps it topped up in the background, and every
+//! issuance takes a key of its algorithm from the pool before generating one.
+
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use openssl::pkey::{PKey, Private};
+
+use crate::atomic::write_file;
+use crate::conf::{file_in_conf, Conf};
+use crate::err::{Result, SimpleCAError};
+use crate::keys::KeyAlgorithm;
+
+pub(crate) const POOL_DIR: &str = "key-pool";
+/// Keys of each algorithm `prewarm` leaves in the pool unless told otherwise.
+pub const DEFAULT_POOL_SIZE: usize = 4;
+/// How often `serve` tops up the pool.
+const REFILL_INTERVAL: Duration = Duration::from_secs(30);
+
+fn pool_dir(algorithm: KeyAlgorithm) -> Result<PathBuf> {
+    Ok(file_in_conf(POOL_DIR)?.join(algorithm.name()))
+}
+
+fn pooled(algorithm: KeyAlgorithm) -> Result<Vec<PathBuf>> {
+    let mut keys: Vec<PathBuf> = match fs::read_dir(pool_dir(algorithm)?) {
+        Ok(entries) => entries.flatten().map(|entry| entry.path()).collect(),
+        Err(_) => Vec::new(),
+    };
+    keys.sort();
+    Ok(keys)
+}
+
+/// Tops up the pool of each of `algorithms` to `size` keys, generating them
+/// in parallel. Returns the written keys.
+pub fn prewarm(algorithms: &[KeyAlgorithm], size: usize) -> Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    for &algorithm in algorithms {
+        let dir = pool_dir(algorithm)?;
+        fs::create_dir_all(&dir)?;
+        let missing = size.saturating_sub(pooled(algorithm)?.len());
+        let keys = thread::scope(|scope| {
+            let handles: Vec<_> = (0..missing)
+                .map(|_| scope.spawn(move || algorithm.generate()))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle.join().unwrap_or(Err(SimpleCAError::GenericError {
+                        msg: "Key generation panicked.",
+                    }))
+                })
+                .collect::<Result<Vec<_>>>()
+        })?;
+        for key in keys {
+            let path = dir.join(format!("{}.key.pem", random_id()?));
+            write_file(&path, key.private_key_to_pem_pkcs8()?)?;
+            written.push(path);
+        }
+    }
+    Ok(written)
+}
+
+fn random_id() -> Result<String> {
+    let mut bytes = [0u8; 8];
+    openssl::rand::rand_bytes(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Takes a key of `algorithm` out of the pool, `None` when it is empty.
+/// Concurrent processes never get the same key: only the one that removes
+/// the file keeps it.
+pub(crate) fn take_key(algorithm: KeyAlgorithm) -> Result<Option<PKey<Private>>> {
+    for path in pooled(algorithm)? {
+        let pem = match fs::read(&path) {
+            Ok(pem) => pem,
+            Err(_) => continue,
+        };
+        if fs::remove_file(&path).is_ok() {
+            tracing::debug!("Took pre-generated key {:?}", path);
+            return Ok(Some(PKey::private_key_from_pem(&pem)?));
+        }
+    }
+    Ok(None)
+}
+
+/// Keeps the pool of the configured algorithms at `[keys] pool_size` keys
+/// while the process runs, when set.
+pub fn spawn_refill() -> Result<()> {
+    let keys = Conf::load()?.keys();
+    let size = keys.pool_size();
+    if size == 0 {
+        return Ok(());
+    }
+    let algorithms = keys.algorithms();
+    thread::spawn(move || loop {
+        if let Err(err) = prewarm(&algorithms, size) {
+            tracing::warn!("Could not refill the key pool: {}", err);
+        }
+        thread::sleep(REFILL_INTERVAL);
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::{with_test_home, with_test_store};
+
+    #[test]
+    fn test_prewarm_and_take() {
+        with_test_store("keypool", |_| {
+            let algorithm = KeyAlgorithm::EcdsaP256;
+            let mut written = prewarm(&[algorithm], 3).unwrap();
+            written.sort();
+            assert_eq!(written.len(), 3);
+            assert_eq!(pooled(algorithm).unwrap(), written);
+            #[cfg(unix)]
+            for path in &written {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = fs::metadata(path).unwrap().permissions().mode();
+                assert_eq!(mode & 0o777, 0o600);
+            }
+            assert!(prewarm(&[algorithm], 3).unwrap().is_empty());
+
+            for left in (0..3).rev() {
+                assert!(take_key(algorithm).unwrap().is_some());
+                assert_eq!(pooled(algorithm).unwrap().len(), left);
+            }
+            assert!(take_key(algorithm).unwrap().is_none());
+            assert!(take_key(KeyAlgorithm::EcdsaP384).unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn test_concurrent_take() {
+        with_test_store("keypool-concurrent", |home| {
+            let algorithm = KeyAlgorithm::EcdsaP256;
+            prewarm(&[algorithm], 8).unwrap();
+            let keys = thread::scope(|scope| {
+                let handles: Vec<_> = (0..8)
+                    .map(|_| scope.spawn(|| with_test_home(home, || take_key(algorithm))))
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().unwrap().unwrap().unwrap())
+                    .collect::<Vec<_>>()
+            });
+            let mut public_keys: Vec<Vec<u8>> = keys
+                .iter()
+                .map(|key| key.public_key_to_der().unwrap())
+                .collect();
+            public_keys.sort();
+            public_keys.dedup();
+            assert_eq!(public_keys.len(), 8);
+            assert!(take_key(algorithm).unwrap().is_none());
+        });
+    }
+}
@@ -0,0 +1,143 @@
+use crate::err::Result;
+use serde::{Deserialize, Serialize};
+
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+
+/// Key types that can be generated for CA and server certificates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum KeyAlgorithm {
+    #[serde(rename = "rsa2048")]
+    Rsa2048,
+    #[serde(rename = "rsa3072")]
+    Rsa3072,
+    #[serde(rename = "rsa4096")]
+    Rsa4096,
+    #[serde(rename = "ecdsa-p256")]
+    EcdsaP256,
+    #[serde(rename = "ecdsa-p384")]
+    EcdsaP384,
+}
+
+impl KeyAlgorithm {
+    pub const ALL: [KeyAlgorithm; 5] = [
+        KeyAlgorithm::Rsa2048,
+        KeyAlgorithm::Rsa3072,
+        KeyAlgorithm::Rsa4096,
+        KeyAlgorithm::EcdsaP256,
+        KeyAlgorithm::EcdsaP384,
+    ];
+
+    /// The name used in the config, e.g. `rsa4096`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyAlgorithm::Rsa2048 => "rsa2048",
+            KeyAlgorithm::Rsa3072 => "rsa3072",
+            KeyAlgorithm::Rsa4096 => "rsa4096",
+            KeyAlgorithm::EcdsaP256 => "ecdsa-p256",
+            KeyAlgorithm::EcdsaP384 => "ecdsa-p384",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<KeyAlgorithm> {
+        KeyAlgorithm::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.name() == name)
+    }
+
+    pub fn generate(&self) -> Result<PKey<Private>> {
+        let pkey = match self {
+            KeyAlgorithm::Rsa2048 => PKey::from_rsa(Rsa::generate(2048)?)?,
+            KeyAlgorithm::Rsa3072 => PKey::from_rsa(Rsa::generate(3072)?)?,
+            KeyAlgorithm::Rsa4096 => PKey::from_rsa(Rsa::generate(4096)?)?,
+            KeyAlgorithm::EcdsaP256 => ec_key(Nid::X9_62_PRIME256V1)?,
+            KeyAlgorithm::EcdsaP384 => ec_key(Nid::SECP384R1)?,
+        };
+        Ok(pkey)
+    }
+}
+
+fn ec_key(curve: Nid) -> Result<PKey<Private>> {
+    let group = EcGroup::from_curve_name(curve)?;
+    Ok(PKey::from_ec_key(EcKey::generate(&group)?)?)
+}
+
+/// Sets of algorithms chosen with `profile` in the `[keys]` config, which
+/// `ca_algorithm` and `server_algorithm` override.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum KeyProfile {
+    /// RSA 4096 for the CAs and RSA 2048 for servers.
+    #[default]
+    #[serde(rename = "rsa")]
+    Rsa,
+    /// P-384 for the CAs and P-256 for servers, generated in milliseconds
+    /// rather than the seconds RSA 4096 takes.
+    #[serde(rename = "fast")]
+    Fast,
+}
+
+impl KeyProfile {
+    pub fn ca_algorithm(&self) -> KeyAlgorithm {
+        match self {
+            KeyProfile::Rsa => KeyAlgorithm::Rsa4096,
+            KeyProfile::Fast => KeyAlgorithm::EcdsaP384,
+        }
+    }
+
+    pub fn server_algorithm(&self) -> KeyAlgorithm {
+        match self {
+            KeyProfile::Rsa => KeyAlgorithm::Rsa2048,
+            KeyProfile::Fast => KeyAlgorithm::EcdsaP256,
+        }
+    }
+}
+
+/// Digests certificates can be signed with.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SignatureDigest {
+    #[default]
+    #[serde(rename = "sha256")]
+    Sha256,
+    #[serde(rename = "sha384")]
+    Sha384,
+    #[serde(rename = "sha512")]
+    Sha512,
+}
+
+impl SignatureDigest {
+    pub fn message_digest(&self) -> MessageDigest {
+        match self {
+            SignatureDigest::Sha256 => MessageDigest::sha256(),
+            SignatureDigest::Sha384 => MessageDigest::sha384(),
+            SignatureDigest::Sha512 => MessageDigest::sha512(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::pkey::Id;
+
+    #[test]
+    fn test_key_algorithms() {
+        for algorithm in KeyAlgorithm::ALL {
+            assert_eq!(KeyAlgorithm::from_name(algorithm.name()), Some(algorithm));
+        }
+        assert_eq!(KeyAlgorithm::from_name("rsa1024"), None);
+
+        let key = KeyAlgorithm::EcdsaP384.generate().unwrap();
+        assert_eq!((key.id(), key.bits()), (Id::EC, 384));
+        let key = KeyAlgorithm::Rsa2048.generate().unwrap();
+        assert_eq!((key.id(), key.bits()), (Id::RSA, 2048));
+
+        assert_eq!(KeyProfile::Fast.ca_algorithm(), KeyAlgorithm::EcdsaP384);
+        assert_eq!(
+            KeyProfile::default().server_algorithm(),
+            KeyAlgorithm::Rsa2048
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! Java truststores and keystores, so JVM services can take the CA without
+//! keytool: JKS is encoded by hand, and so are PKCS#12 truststores as Java only
+//! trusts certificates carrying an Oracle bag attribute openssl cannot add.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::{PKey, Private};
+use openssl::rand::rand_bytes;
+use openssl::sign::Signer;
+use openssl::stack::Stack;
+use openssl::x509::X509;
+
+use crate::der;
+use crate::err::{Result, SimpleCAError};
+
+/// Password of the stores unless another one is given, the JDK's default.
+pub const DEFAULT_STORE_PASSWORD: &str = "changeit";
+
+const JKS_MAGIC: u32 = 0xfeed_feed;
+const JKS_VERSION: u32 = 2;
+const JKS_PRIVATE_KEY_TAG: u32 = 1;
+const JKS_TRUSTED_CERT_TAG: u32 = 2;
+/// Salt of the keyed digest at the end of a JKS file.
+const JKS_WHITENER: &[u8] = b"Mighty Aphrodite";
+const PKCS12_MAC_ITERATIONS: u64 = 10_000;
+
+/// Container format of the Java stores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JavaStoreFormat {
+    Jks,
+    Pkcs12,
+}
+
+impl JavaStoreFormat {
+    /// Parses the `export --format` values.
+    pub fn from_name(name: &str) -> Option<JavaStoreFormat> {
+        match name {
+            "jks" => Some(JavaStoreFormat::Jks),
+            "pkcs12-truststore" => Some(JavaStoreFormat::Pkcs12),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            JavaStoreFormat::Jks => "jks",
+            JavaStoreFormat::Pkcs12 => "p12",
+        }
+    }
+
+    /// A truststore with `certs` as trusted certificate entries.
+    pub fn truststore(&self, certs: &[(&str, &X509)], password: &str) -> Result<Vec<u8>> {
+        match self {
+            JavaStoreFormat::Jks => jks(certs, None, password),
+            JavaStoreFormat::Pkcs12 => pkcs12_truststore(certs, password),
+        }
+    }
+
+    /// A keystore with `key` and its certificate `chain` under `alias`.
+    pub fn keystore(
+        &self,
+        alias: &str,
+        key: &PKey<Private>,
+        chain: &[X509],
+        password: &str,
+    ) -> Result<Vec<u8>> {
+        if chain.is_empty() {
+            Err(SimpleCAError::GenericError {
+                msg: "A keystore needs the certificate of its key.",
+            })?;
+        }
+        match self {
+            JavaStoreFormat::Jks => jks(&[], Some((alias, key, chain)), password),
+            JavaStoreFormat::Pkcs12 => {
+                let mut ca = Stack::new()?;
+                for cert in &chain[1..] {
+                    ca.push(cert.clone())?;
+                }
+                let pkcs12 = Pkcs12::builder()
+                    .name(alias)
+                    .pkey(key)
+                    .cert(&chain[0])
+                    .ca(ca)
+                    .build2(password)?;
+                Ok(pkcs12.to_der()?)
+            }
+        }
+    }
+}
+
+/// The password as the big-endian UTF-16 Java hashes.
+fn utf16_password(password: &str) -> Vec<u8> {
+    password.encode_utf16().flat_map(u16::to_be_bytes).collect()
+}
+
+/// `DataOutputStream.writeUTF`, for the ASCII aliases and type names used here.
+fn write_utf(out: &mut Vec<u8>, value: &str) {
+    out.extend((value.len() as u16).to_be_bytes());
+    out.extend(value.as_bytes());
+}
+
+fn write_cert(out: &mut Vec<u8>, cert: &X509) -> Result<()> {
+    let der = cert.to_der()?;
+    write_utf(out, "X.509");
+    out.extend((der.len() as u32).to_be_bytes());
+    out.extend(der);
+    Ok(())
+}
+
+/// Encrypts a PKCS#8 key the way Sun's `KeyProtector` does: XOR with a
+/// SHA-1 keystream of the password, followed by a SHA-1 check value.
+fn protect_jks_key(key: &PKey<Private>, password: &str) -> Result<Vec<u8>> {
+    let plain = key.private_key_to_pkcs8()?;
+    let password = utf16_password(password);
+    let mut salt = vec![0; 20];
+    rand_bytes(&mut salt)?;
+
+    let mut keystream = Vec::with_capacity(plain.len() + 20);
+    let mut digest = salt.clone();
+    while keystream.len() < plain.len() {
+        digest = hash(MessageDigest::sha1(), &[&password[..], &digest].concat())?.to_vec();
+        keystream.extend(&digest);
+    }
+    let mut protected = salt;
+    protected.extend(plain.iter().zip(&keystream).map(|(p, k)| p ^ k));
+    protected.extend(hash(MessageDigest::sha1(), &[&password[..], &plain].concat())?.to_vec());
+    Ok(der::sequence(&[
+        der::sequence(&[der::oid(der::OID_JKS_KEY_PROTECTOR), der::null()]),
+        der::octet_string(&protected),
+    ]))
+}
+
+fn jks(
+    trusted: &[(&str, &X509)],
+    key_entry: Option<(&str, &PKey<Private>, &[X509])>,
+    password: &str,
+) -> Result<Vec<u8>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+    let count = trusted.len() + key_entry.iter().count();
+    let mut out = Vec::new();
+    out.extend(JKS_MAGIC.to_be_bytes());
+    out.extend(JKS_VERSION.to_be_bytes());
+    out.extend((count as u32).to_be_bytes());
+    if let Some((alias, key, chain)) = key_entry {
+        let protected = protect_jks_key(key, password)?;
+        out.extend(JKS_PRIVATE_KEY_TAG.to_be_bytes());
+        write_utf(&mut out, &alias.to_lowercase());
+        out.extend(now.to_be_bytes());
+        out.extend((protected.len() as u32).to_be_bytes());
+        out.extend(protected);
+        out.extend((chain.len() as u32).to_be_bytes());
+        for cert in chain {
+            write_cert(&mut out, cert)?;
+        }
+    }
+    for (alias, cert) in trusted {
+        out.extend(JKS_TRUSTED_CERT_TAG.to_be_bytes());
+        write_utf(&mut out, &alias.to_lowercase());
+        out.extend(now.to_be_bytes());
+        write_cert(&mut out, cert)?;
+    }
+    let digest = hash(
+        MessageDigest::sha1(),
+        &[&utf16_password(password)[..], JKS_WHITENER, &out].concat(),
+    )?;
+    out.extend(digest.to_vec());
+    Ok(out)
+}
+
+/// The PKCS#12 key derivation of RFC 7292 appendix B for the MAC key (ID 3),
+/// with SHA-256 whose output is exactly the one block needed.
+fn pkcs12_mac_key(password: &str, salt: &[u8], iterations: u64) -> Result<Vec<u8>> {
+    const BLOCK: usize = 64;
+    let fill = |data: &[u8]| -> Vec<u8> {
+        let len = data.len().div_ceil(BLOCK) * BLOCK;
+        data.iter().copied().cycle().take(len).collect()
+    };
+    let mut password = utf16_password(password);
+    password.extend([0, 0]);
+    let mut input = vec![3; BLOCK];
+    input.extend(fill(salt));
+    input.extend(fill(&password));
+    let mut digest = hash(MessageDigest::sha256(), &input)?.to_vec();
+    for _ in 1..iterations {
+        digest = hash(MessageDigest::sha256(), &digest)?.to_vec();
+    }
+    Ok(digest)
+}
+
+/// A PKCS#12 file of unencrypted certificate bags marked as trusted for any
+/// purpose, with a SHA-256 MAC.
+fn pkcs12_truststore(certs: &[(&str, &X509)], password: &str) -> Result<Vec<u8>> {
+    let mut bags = Vec::new();
+    for (alias, cert) in certs {
+        let cert_bag = der::sequence(&[
+            der::oid(der::OID_X509_CERTIFICATE),
+            der::context(0, &der::octet_string(&cert.to_der()?)),
+        ]);
+        let attributes = der::set(&[
+            der::sequence(&[
+                der::oid(der::OID_FRIENDLY_NAME),
+                der::set(&[der::bmp_string(alias)]),
+            ]),
+            der::sequence(&[
+                der::oid(der::OID_ORACLE_TRUSTED_KEY_USAGE),
+                der::set(&[der::oid(der::OID_ANY_EXTENDED_KEY_USAGE)]),
+            ]),
+        ]);
+        bags.push(der::sequence(&[
+            der::oid(der::OID_PKCS12_CERT_BAG),
+            der::context(0, &cert_bag),
+            attributes,
+        ]));
+    }
+    let safe_contents = der::sequence(&bags);
+    let auth_safe = der::sequence(&[der::sequence(&[
+        der::oid(der::OID_PKCS7_DATA),
+        der::context(0, &der::octet_string(&safe_contents)),
+    ])]);
+
+    let mut salt = vec![0; 16];
+    rand_bytes(&mut salt)?;
+    let mac_key = PKey::hmac(&pkcs12_mac_key(password, &salt, PKCS12_MAC_ITERATIONS)?)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &mac_key)?;
+    signer.update(&auth_safe)?;
+    let mac_data = der::sequence(&[
+        der::sequence(&[
+            der::sequence(&[der::oid(der::OID_SHA256), der::null()]),
+            der::octet_string(&signer.sign_to_vec()?),
+        ]),
+        der::octet_string(&salt),
+        der::integer(PKCS12_MAC_ITERATIONS),
+    ]);
+    Ok(der::sequence(&[
+        der::small_integer(3),
+        der::sequence(&[
+            der::oid(der::OID_PKCS7_DATA),
+            der::context(0, &der::octet_string(&auth_safe)),
+        ]),
+        mac_data,
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certs::fixtures::test_root_ca;
+
+    #[test]
+    fn test_pkcs12_truststore() {
+        let (_, _, cert) = test_root_ca();
+
+        let store = JavaStoreFormat::Pkcs12
+            .truststore(&[("root", &cert)], "changeit")
+            .unwrap();
+        // openssl verifies the MAC before it hands out the certificates.
+        let parsed = Pkcs12::from_der(&store)
+            .unwrap()
+            .parse2("changeit")
+            .unwrap();
+        let ca = parsed.ca.unwrap();
+        assert_eq!(ca.len(), 1);
+        assert_eq!(ca[0].to_der().unwrap(), cert.to_der().unwrap());
+        assert!(Pkcs12::from_der(&store).unwrap().parse2("wrong").is_err());
+
+        let jks = JavaStoreFormat::Jks
+            .truststore(&[("root", &cert)], "changeit")
+            .unwrap();
+        assert_eq!(&jks[..8], &[0xfe, 0xed, 0xfe, 0xed, 0, 0, 0, 2]);
+    }
+}
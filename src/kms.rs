@@ -0,0 +1,543 @@
+//! The intermediate key in a cloud KMS, AWS KMS or GCP Cloud KMS, so a shared
+//! team CA never has its issuing key on a laptop. The key never leaves the
+//! KMS: its public key is wrapped in a `PKey` whose signing operations are
+//! sent to the KMS, through the `aws` CLI or the Cloud KMS REST API with a
+//! `gcloud` access token. Every signature is a blocking round trip, using the
+//! credentials those tools are logged in with.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use openssl::base64;
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Private, Public};
+use serde_json::{json, Value};
+
+use crate::commands::{remove_intermediate_key, replace_intermediate};
+use crate::conf::{CertAuthConf, Conf, ValidityConf};
+use crate::deterministic::Sources;
+use crate::err::{Result, SimpleCAError};
+use crate::lock::StoreLock;
+use crate::summary::IssuanceSummary;
+
+const CLOUD_KMS_API: &str = "https://cloudkms.googleapis.com/v1";
+
+/// A signing key in a cloud KMS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KmsKey {
+    /// An AWS KMS key ID, alias or ARN.
+    Aws(String),
+    /// A Cloud KMS key version,
+    /// `projects/*/locations/*/keyRings/*/cryptoKeys/*/cryptoKeyVersions/*`.
+    Gcp(String),
+}
+
+impl KmsKey {
+    /// Cloud KMS key versions are told apart by their `projects/` prefix,
+    /// anything else is handed to AWS KMS.
+    pub fn parse(key: &str) -> Result<KmsKey> {
+        if !key.starts_with("projects/") {
+            return Ok(KmsKey::Aws(key.to_string()));
+        }
+        let labels = [
+            "projects",
+            "locations",
+            "keyRings",
+            "cryptoKeys",
+            "cryptoKeyVersions",
+        ];
+        let parts: Vec<&str> = key.split('/').collect();
+        let valid = parts.len() == 2 * labels.len()
+            && parts
+                .chunks(2)
+                .zip(labels)
+                .all(|(pair, label)| pair[0] == label && !pair[1].is_empty());
+        if !valid {
+            Err(kms_error(key, "not a Cloud KMS key version name"))?;
+        }
+        Ok(KmsKey::Gcp(key.to_string()))
+    }
+
+    pub fn id(&self) -> &str {
+        match self {
+            KmsKey::Aws(key) | KmsKey::Gcp(key) => key,
+        }
+    }
+
+    /// Fetches the public half of the key.
+    pub fn public_key(&self) -> Result<PKey<Public>> {
+        match self {
+            KmsKey::Aws(key) => {
+                let output = run(
+                    self,
+                    "aws",
+                    &[
+                        "kms",
+                        "get-public-key",
+                        "--key-id",
+                        key,
+                        "--query",
+                        "PublicKey",
+                        "--output",
+                        "text",
+                    ],
+                    None,
+                )?;
+                Ok(PKey::public_key_from_der(&decode(self, &output)?)?)
+            }
+            KmsKey::Gcp(key) => {
+                let response = cloud_kms(self, &format!("{}/publicKey", key), None)?;
+                let pem = response["pem"]
+                    .as_str()
+                    .ok_or_else(|| kms_error(key, "no public key in the response"))?;
+                Ok(PKey::public_key_from_pem(pem.as_bytes())?)
+            }
+        }
+    }
+
+    /// Has the KMS sign `digest`, made with the digest `digest_nid`, with a
+    /// key of type `id`. The signature comes back as DER for ECDSA and raw
+    /// for RSA, as OpenSSL expects it.
+    fn sign(&self, id: Id, digest_nid: Nid, digest: &[u8]) -> Result<Vec<u8>> {
+        let bits = match digest_nid {
+            Nid::SHA256 => 256,
+            Nid::SHA384 => 384,
+            Nid::SHA512 => 512,
+            _ => Err(kms_error(self.id(), "unsupported signature digest"))?,
+        };
+        match self {
+            KmsKey::Aws(key) => {
+                let algorithm = match id {
+                    Id::EC => format!("ECDSA_SHA_{}", bits),
+                    _ => format!("RSASSA_PKCS1_V1_5_SHA_{}", bits),
+                };
+                // Blob arguments are read from files the same way by v1 and
+                // v2 of the CLI, the digest is handed over on its stdin.
+                let output = run(
+                    self,
+                    "aws",
+                    &[
+                        "kms",
+                        "sign",
+                        "--key-id",
+                        key,
+                        "--message",
+                        "fileb:///dev/stdin",
+                        "--message-type",
+                        "DIGEST",
+                        "--signing-algorithm",
+                        &algorithm,
+                        "--query",
+                        "Signature",
+                        "--output",
+                        "text",
+                    ],
+                    Some(digest),
+                )?;
+                decode(self, &output)
+            }
+            KmsKey::Gcp(key) => {
+                let body = json!({
+                    "digest": { format!("sha{}", bits): base64::encode_block(digest) }
+                });
+                let response = cloud_kms(self, &format!("{}:asymmetricSign", key), Some(&body))?;
+                let signature = response["signature"]
+                    .as_str()
+                    .ok_or_else(|| kms_error(key, "no signature in the response"))?;
+                decode(self, signature.as_bytes())
+            }
+        }
+    }
+
+    /// A handle to the key that signs through the KMS.
+    pub fn load(&self) -> Result<PKey<Private>> {
+        let public = self.public_key()?;
+        let id = public.id();
+        let key = self.clone();
+        remote::remote_key(
+            &public,
+            Box::new(move |digest_nid, digest| key.sign(id, digest_nid, digest)),
+        )
+        .map_err(|err| kms_error(self.id(), err))
+    }
+}
+
+fn kms_error(key: &str, msg: impl ToString) -> SimpleCAError {
+    SimpleCAError::KmsFailed {
+        key: key.to_string(),
+        msg: msg.to_string(),
+    }
+}
+
+fn decode(key: &KmsKey, encoded: &[u8]) -> Result<Vec<u8>> {
+    let text = String::from_utf8_lossy(encoded);
+    base64::decode_block(text.trim()).map_err(|err| kms_error(key.id(), err))
+}
+
+/// Runs `program` with `input` on its stdin and returns its stdout.
+fn run(key: &KmsKey, program: &str, args: &[&str], input: Option<&[u8]>) -> Result<Vec<u8>> {
+    let failed = |msg: String| kms_error(key.id(), msg);
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| failed(format!("{} could not be run: {}", program, err)))?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        Err(failed(format!(
+            "{} failed with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))?;
+    }
+    Ok(output.stdout)
+}
+
+/// Calls the Cloud KMS REST API on `resource`, POSTing `body` when given.
+/// The access token goes to curl on stdin, never on its command line.
+fn cloud_kms(key: &KmsKey, resource: &str, body: Option<&Value>) -> Result<Value> {
+    let token = run(key, "gcloud", &["auth", "print-access-token"], None)?;
+    let config = format!(
+        "header = \"Authorization: Bearer {}\"\n",
+        String::from_utf8_lossy(&token).trim()
+    );
+    let url = format!("{}/{}", CLOUD_KMS_API, resource);
+    let body = body.map(Value::to_string);
+    let mut args = vec!["-sS", "--fail", "-K", "-"];
+    if let Some(body) = &body {
+        args.extend([
+            "-H",
+            "Content-Type: application/json",
+            "--data-binary",
+            body,
+        ]);
+    }
+    args.push(&url);
+    let output = run(key, "curl", &args, Some(config.as_bytes()))?;
+    serde_json::from_slice(&output).map_err(|err| kms_error(key.id(), err))
+}
+
+/// Moves the default intermediate into the KMS key `key`: the root issues a
+/// new intermediate for it, the key file is deleted and the config points
+/// at the KMS key.
+pub fn intermediate_to_kms(
+    key: &str,
+    validity: &ValidityConf,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<IssuanceSummary> {
+    let _lock = StoreLock::acquire()?;
+    let mut conf = Conf::load()?;
+    let validity = validity.or(&conf.validity());
+    let kms_key = KmsKey::parse(key)?;
+    let intermediate =
+        replace_intermediate(&conf, kms_key.public_key()?, &validity, sources, verbose)?;
+    remove_intermediate_key(verbose)?;
+
+    conf.clear_intermediate_key();
+    let mut kms = conf.kms();
+    kms.intermediate_key = Some(key.to_string());
+    conf.set_kms(kms);
+    conf.save(&Conf::path()?)?;
+
+    let cert_path = CertAuthConf::intermediate_cert()?;
+    IssuanceSummary::new(&intermediate).map(|summary| summary.file("cert", &cert_path))
+}
+
+/// Keys whose private half is elsewhere: OpenSSL calls back into Rust to
+/// sign, through an EC_KEY_METHOD or RSA_METHOD that replaces the local
+/// signing of the key.
+#[cfg(feature = "kms")]
+mod remote {
+    use std::ffi::{c_int, c_uchar, c_uint, c_void};
+    use std::mem::ManuallyDrop;
+    use std::slice;
+    use std::sync::{Arc, Mutex, OnceLock};
+
+    use foreign_types::{ForeignType, ForeignTypeRef};
+    use openssl::ec::{EcKey, EcKeyRef};
+    use openssl::error::ErrorStack;
+    use openssl::nid::Nid;
+    use openssl::pkey::{Id, PKey, Private, Public};
+    use openssl::rsa::{Rsa, RsaRef};
+    use openssl_sys::{BIGNUM, EC_KEY, RSA};
+
+    use crate::err::{Result, SimpleCAError};
+
+    /// Signs a digest made with the given digest algorithm.
+    pub(crate) type Sign = dyn Fn(Nid, &[u8]) -> Result<Vec<u8>> + Send + Sync;
+
+    #[allow(non_camel_case_types)]
+    enum EC_KEY_METHOD {}
+    #[allow(non_camel_case_types)]
+    enum RSA_METHOD {}
+
+    type EcSign = unsafe extern "C" fn(
+        c_int,
+        *const c_uchar,
+        c_int,
+        *mut c_uchar,
+        *mut c_uint,
+        *const BIGNUM,
+        *const BIGNUM,
+        *mut EC_KEY,
+    ) -> c_int;
+    type RsaSign = unsafe extern "C" fn(
+        c_int,
+        *const c_uchar,
+        c_uint,
+        *mut c_uchar,
+        *mut c_uint,
+        *const RSA,
+    ) -> c_int;
+
+    extern "C" {
+        fn EC_KEY_OpenSSL() -> *const EC_KEY_METHOD;
+        fn EC_KEY_METHOD_new(meth: *const EC_KEY_METHOD) -> *mut EC_KEY_METHOD;
+        fn EC_KEY_METHOD_get_sign(
+            meth: *const EC_KEY_METHOD,
+            sign: *mut Option<EcSign>,
+            sign_setup: *mut *const c_void,
+            sign_sig: *mut *const c_void,
+        );
+        fn EC_KEY_METHOD_set_sign(
+            meth: *mut EC_KEY_METHOD,
+            sign: Option<EcSign>,
+            sign_setup: *const c_void,
+            sign_sig: *const c_void,
+        );
+        fn EC_KEY_set_method(key: *mut EC_KEY, meth: *const EC_KEY_METHOD) -> c_int;
+        fn ECDSA_size(key: *const EC_KEY) -> c_int;
+        fn RSA_PKCS1_OpenSSL() -> *const RSA_METHOD;
+        fn RSA_meth_dup(meth: *const RSA_METHOD) -> *mut RSA_METHOD;
+        fn RSA_meth_set_sign(meth: *mut RSA_METHOD, sign: Option<RsaSign>) -> c_int;
+        fn RSA_set_method(rsa: *mut RSA, meth: *const RSA_METHOD) -> c_int;
+        fn RSA_size(rsa: *const RSA) -> c_int;
+    }
+
+    /// Signers of the remote keys, by the DER of their public key, which the
+    /// callbacks get back from the key they are handed.
+    static SIGNERS: Mutex<Vec<(Vec<u8>, Arc<Sign>)>> = Mutex::new(Vec::new());
+    /// The methods are created once and live as long as the process.
+    static EC_METHOD: OnceLock<usize> = OnceLock::new();
+    static RSA_METHOD: OnceLock<usize> = OnceLock::new();
+
+    fn ec_method() -> *const EC_KEY_METHOD {
+        *EC_METHOD.get_or_init(|| {
+            // SAFETY: the method is a copy of the default one with only the
+            // signing replaced, so setup and verification stay OpenSSL's.
+            unsafe {
+                let meth = EC_KEY_METHOD_new(EC_KEY_OpenSSL());
+                let mut sign_setup = std::ptr::null();
+                let mut sign_sig = std::ptr::null();
+                EC_KEY_METHOD_get_sign(meth, &mut None, &mut sign_setup, &mut sign_sig);
+                EC_KEY_METHOD_set_sign(meth, Some(ec_sign), sign_setup, sign_sig);
+                meth as usize
+            }
+        }) as *const EC_KEY_METHOD
+    }
+
+    fn rsa_method() -> *const RSA_METHOD {
+        *RSA_METHOD.get_or_init(|| {
+            // SAFETY: as for `ec_method`.
+            unsafe {
+                let meth = RSA_meth_dup(RSA_PKCS1_OpenSSL());
+                RSA_meth_set_sign(meth, Some(rsa_sign));
+                meth as usize
+            }
+        }) as *const RSA_METHOD
+    }
+
+    /// Signs with the signer of `public`, writing at most `max` bytes to `sig`.
+    ///
+    /// # Safety
+    ///
+    /// `dgst` must hold `dlen` bytes and `sig` room for `max` bytes.
+    unsafe fn sign_with(
+        public: std::result::Result<Vec<u8>, ErrorStack>,
+        nid: c_int,
+        dgst: *const c_uchar,
+        dlen: usize,
+        sig: *mut c_uchar,
+        siglen: *mut c_uint,
+        max: c_int,
+    ) -> c_int {
+        let signer = public.ok().and_then(|public| {
+            let signers = SIGNERS.lock().unwrap();
+            signers
+                .iter()
+                .find(|(key, _)| *key == public)
+                .map(|(_, signer)| signer.clone())
+        });
+        let signed = match signer {
+            Some(signer) => signer(Nid::from_raw(nid), slice::from_raw_parts(dgst, dlen)),
+            None => return 0,
+        };
+        match signed {
+            Ok(signature) if signature.len() <= max as usize => {
+                std::ptr::copy_nonoverlapping(signature.as_ptr(), sig, signature.len());
+                *siglen = signature.len() as c_uint;
+                1
+            }
+            Ok(_) => {
+                tracing::error!("The remote signature is longer than the key allows");
+                0
+            }
+            Err(err) => {
+                tracing::error!("{}", err);
+                0
+            }
+        }
+    }
+
+    unsafe extern "C" fn ec_sign(
+        nid: c_int,
+        dgst: *const c_uchar,
+        dlen: c_int,
+        sig: *mut c_uchar,
+        siglen: *mut c_uint,
+        _kinv: *const BIGNUM,
+        _r: *const BIGNUM,
+        eckey: *mut EC_KEY,
+    ) -> c_int {
+        let public = EcKeyRef::<Private>::from_ptr(eckey).public_key_to_der();
+        let max = ECDSA_size(eckey);
+        sign_with(public, nid, dgst, dlen as usize, sig, siglen, max)
+    }
+
+    unsafe extern "C" fn rsa_sign(
+        nid: c_int,
+        m: *const c_uchar,
+        m_length: c_uint,
+        sigret: *mut c_uchar,
+        siglen: *mut c_uint,
+        rsa: *const RSA,
+    ) -> c_int {
+        let public = RsaRef::<Private>::from_ptr(rsa as *mut RSA).public_key_to_der();
+        let max = RSA_size(rsa);
+        sign_with(public, nid, m, m_length as usize, sigret, siglen, max)
+    }
+
+    fn cvt(ret: c_int) -> std::result::Result<(), ErrorStack> {
+        if ret == 1 {
+            Ok(())
+        } else {
+            Err(ErrorStack::get())
+        }
+    }
+
+    /// A private key for `public` whose signatures are made by `sign`.
+    pub(crate) fn remote_key(public: &PKey<Public>, sign: Box<Sign>) -> Result<PKey<Private>> {
+        let pkey = match public.id() {
+            Id::EC => {
+                let ec = public.ec_key()?;
+                // SAFETY: the EC_KEY is owned by `ec` and handed on to the
+                // new `PKey`; the method outlives it.
+                let ec = unsafe {
+                    cvt(EC_KEY_set_method(ec.as_ptr(), ec_method()))?;
+                    EcKey::<Private>::from_ptr(ManuallyDrop::new(ec).as_ptr())
+                };
+                PKey::from_ec_key(ec)?
+            }
+            Id::RSA => {
+                let rsa = public.rsa()?;
+                // SAFETY: as above for the RSA key.
+                let rsa = unsafe {
+                    cvt(RSA_set_method(rsa.as_ptr(), rsa_method()))?;
+                    Rsa::<Private>::from_ptr(ManuallyDrop::new(rsa).as_ptr())
+                };
+                PKey::from_rsa(rsa)?
+            }
+            _ => Err(SimpleCAError::GenericError {
+                msg: "Only RSA and EC keys can sign remotely.",
+            })?,
+        };
+        let der = public.public_key_to_der()?;
+        let mut signers = SIGNERS.lock().unwrap();
+        signers.retain(|(key, _)| *key != der);
+        signers.push((der, Arc::from(sign)));
+        Ok(pkey)
+    }
+}
+
+#[cfg(not(feature = "kms"))]
+mod remote {
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private, Public};
+
+    use crate::err::{Result, SimpleCAError};
+
+    pub(crate) type Sign = dyn Fn(Nid, &[u8]) -> Result<Vec<u8>> + Send + Sync;
+
+    pub(crate) fn remote_key(_public: &PKey<Public>, _sign: Box<Sign>) -> Result<PKey<Private>> {
+        Err(SimpleCAError::GenericError {
+            msg: "simple-ca was built without the kms feature.",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let gcp =
+            "projects/dev/locations/global/keyRings/ca/cryptoKeys/intermediate/cryptoKeyVersions/1";
+        assert_eq!(KmsKey::parse(gcp).unwrap(), KmsKey::Gcp(gcp.to_string()));
+        assert_eq!(
+            KmsKey::parse("alias/dev-ca").unwrap(),
+            KmsKey::Aws("alias/dev-ca".to_string())
+        );
+        assert!(KmsKey::parse("projects/dev/locations/global").is_err());
+    }
+
+    #[cfg(feature = "kms")]
+    #[test]
+    fn test_remote_key() {
+        use crate::cert_params::CertParams;
+        use crate::certs::create_root_ca;
+        use crate::keys::KeyAlgorithm;
+        use crate::name::Name;
+        use openssl::md::Md;
+        use openssl::pkey_ctx::PkeyCtx;
+        use openssl::rsa::Padding;
+
+        for algorithm in [KeyAlgorithm::EcdsaP256, KeyAlgorithm::Rsa2048] {
+            let local = algorithm.generate().unwrap();
+            let public = PKey::public_key_from_der(&local.public_key_to_der().unwrap()).unwrap();
+            let signer = local.clone();
+            let remote = remote::remote_key(
+                &public,
+                Box::new(move |nid, digest| {
+                    let mut ctx = PkeyCtx::new(&signer)?;
+                    ctx.sign_init()?;
+                    if signer.id() == Id::RSA {
+                        ctx.set_rsa_padding(Padding::PKCS1)?;
+                    }
+                    ctx.set_signature_md(Md::from_nid(nid).unwrap())?;
+                    let mut signature = Vec::new();
+                    ctx.sign_to_vec(digest, &mut signature)?;
+                    Ok(signature)
+                }),
+            )
+            .unwrap();
+
+            let name = Name::new("Remote Root").to_x509_name().unwrap();
+            let params = CertParams::builder(name, remote)
+                .valid_days(1)
+                .self_signed()
+                .unwrap()
+                .build()
+                .unwrap();
+            let cert = create_root_ca(&params).unwrap();
+            assert!(cert.verify(&local).unwrap());
+        }
+    }
+}
@@ -1,29 +1,297 @@
+#[cfg(not(any(feature = "backend-openssl", feature = "backend-rcgen")))]
+compile_error!("Enable the backend-openssl or the backend-rcgen feature.");
+
+#[cfg(feature = "serve")]
+mod acme;
+#[cfg(feature = "serve")]
+mod api;
+#[cfg(feature = "backend-openssl")]
+mod atomic;
+#[cfg(feature = "backend-openssl")]
+mod backend;
+#[cfg(feature = "backend-openssl")]
+mod backup;
+#[cfg(feature = "backend-openssl")]
+mod batch;
+#[cfg(feature = "backend-openssl")]
 mod cert_params;
+#[cfg(feature = "backend-openssl")]
 mod certs;
+#[cfg(feature = "backend-openssl")]
+mod compose;
+#[cfg(feature = "backend-openssl")]
 mod conf;
+#[cfg(feature = "backend-openssl")]
+mod constraints;
+#[cfg(feature = "backend-openssl")]
+mod context;
+#[cfg(feature = "backend-openssl")]
+mod datetime;
+#[cfg(feature = "backend-openssl")]
+mod der;
+#[cfg(feature = "backend-openssl")]
+mod deterministic;
 mod err;
+#[cfg(feature = "backend-openssl")]
+mod export;
+#[cfg(feature = "backend-openssl")]
+mod guard;
+#[cfg(feature = "backend-openssl")]
+mod hardening;
+#[cfg(feature = "backend-openssl")]
+mod hooks;
+#[cfg(feature = "serve")]
+mod http;
+mod i18n;
+#[cfg(feature = "backend-openssl")]
+mod index;
+#[cfg(feature = "backend-openssl")]
+mod inspect;
+#[cfg(feature = "backend-openssl")]
+mod keypool;
+#[cfg(feature = "backend-openssl")]
+mod keys;
+#[cfg(feature = "backend-openssl")]
+mod keystore;
+#[cfg(feature = "backend-openssl")]
+mod lock;
+#[cfg(feature = "backend-openssl")]
+mod matching;
+#[cfg(feature = "backend-openssl")]
+mod memory;
+#[cfg(feature = "backend-openssl")]
 mod name;
+mod netif;
+#[cfg(feature = "backend-openssl")]
+mod out_dir;
+#[cfg(feature = "backend-openssl")]
+mod passphrase;
+#[cfg(feature = "backend-openssl")]
+pub mod paths;
+#[cfg(feature = "backend-openssl")]
+mod perms;
+#[cfg(feature = "backend-openssl")]
+mod policy;
+#[cfg(feature = "backend-rcgen")]
+pub mod rcgen_backend;
+#[cfg(feature = "backend-openssl")]
+mod receipt;
+#[cfg(feature = "backend-openssl")]
+mod repair;
+#[cfg(feature = "backend-openssl")]
+mod scan;
+#[cfg(feature = "backend-openssl")]
+mod serial;
+#[cfg(feature = "backend-openssl")]
+mod ssh;
+#[cfg(feature = "backend-openssl")]
+mod status;
+#[cfg(feature = "backend-openssl")]
+mod store;
+#[cfg(feature = "backend-openssl")]
+mod suggest;
+#[cfg(feature = "backend-openssl")]
+mod summary;
+#[cfg(feature = "backend-openssl")]
+mod trust;
+#[cfg(feature = "serve")]
+mod tsa;
+#[cfg(feature = "backend-openssl")]
+mod usage;
+#[cfg(feature = "backend-openssl")]
+mod vault;
+#[cfg(feature = "backend-openssl")]
+mod verify;
 
-pub use cert_params::CertParams;
-pub use conf::{CertAuthConf, Conf};
-pub use name::Name;
+#[cfg(feature = "backend-openssl")]
+pub use backend::KeyBackend;
+#[cfg(feature = "backend-openssl")]
+pub use backup::{backup_store, restore_store};
+#[cfg(feature = "backend-openssl")]
+pub use batch::{issue_manifest, read_cert_manifest, CertManifest, ManifestCert};
+#[cfg(feature = "backend-openssl")]
+pub use cert_params::{
+    parse_ttl, CertParams, CertParamsBuilder, SerialPolicy, DEFAULT_BACKDATE, DEFAULT_COMMENT,
+};
+#[cfg(feature = "backend-openssl")]
+pub use certs::{
+    cert_fingerprint, create_cert, create_client_cert, create_device_cert, create_intermediate_ca,
+    create_root_ca, create_server_cert, create_signer_cert, pubkey_fingerprint, ExtKeyUsage,
+};
+#[cfg(feature = "backend-openssl")]
+pub use compose::{
+    find_compose_file, issue_compose_certs, parse_compose_services, ComposeService, COMPOSE_FILES,
+};
+#[cfg(feature = "backend-openssl")]
+pub use conf::{
+    detect_local, ephemeral_store, profile, set_config_home, set_local, set_profile, BrandingConf,
+    CertAuthConf, Conf, HooksConf, KeysConf, KmsConf, NameConf, Pkcs11Conf, PolicyConf, RenewConf,
+    SansConf, SerialConf, TpmConf, ValidityConf, DEFAULT_PROFILE, HOME_ENV,
+};
+#[cfg(feature = "backend-openssl")]
+pub use constraints::NameConstraints;
+#[cfg(feature = "backend-openssl")]
+pub use context::CaContext;
+#[cfg(feature = "backend-openssl")]
+pub use deterministic::{CountingSerial, FixedKeys, KeySource, Sources};
+pub use err::{
+    Result, SimpleCAError, EXIT_CA_MISSING, EXIT_EXISTS, EXIT_FAILURE, EXIT_INVALID, EXIT_LOCKED,
+    EXIT_USAGE,
+};
+#[cfg(feature = "backend-openssl")]
+pub use export::{
+    android_network_security_config, chain_certs, export_all, export_android, export_der,
+    export_haproxy, export_java_stores, export_mobileconfig, export_p7b, export_pfx,
+    k8s_secret_name, k8s_tls_secret, pkcs7_certs_only, ExportLayout,
+};
+pub use fluent::FluentArgs;
+#[cfg(feature = "backend-openssl")]
+pub use guard::RepoGuard;
+#[cfg(feature = "backend-openssl")]
+pub use hardening::{generate_dhparam, generate_ticket_key, DHPARAM_BITS, TICKET_KEY_LEN};
+#[cfg(feature = "backend-openssl")]
+pub use hooks::{set_exec_hook, trust_hooks};
+pub use i18n::{display_width, language, message, pad, set_language, Language, LANG_ENV};
+#[cfg(feature = "backend-openssl")]
+pub use index::{issued_certs, issued_under_root, IssuedCert};
+#[cfg(feature = "backend-openssl")]
+pub use inspect::{inspect, CertInfo};
+#[cfg(feature = "backend-openssl")]
+pub use keypool::{prewarm, spawn_refill, DEFAULT_POOL_SIZE};
+#[cfg(feature = "backend-openssl")]
+pub use keys::{KeyAlgorithm, KeyProfile, SignatureDigest};
+#[cfg(feature = "backend-openssl")]
+pub use keystore::{JavaStoreFormat, DEFAULT_STORE_PASSWORD};
+#[cfg(feature = "backend-openssl")]
+pub use lock::StoreLock;
+#[cfg(feature = "backend-openssl")]
+pub use matching::{key_matches_cert, match_key, KeyMatch};
+#[cfg(feature = "backend-openssl")]
+pub use memory::{Ca, EphemeralCert};
+#[cfg(feature = "backend-openssl")]
+pub use name::{Name, NameBuilder, NameField, DEFAULT_NAME_ORDER};
+pub use netif::local_ips;
+#[cfg(feature = "backend-openssl")]
+pub use out_dir::OutDir;
+#[cfg(feature = "backend-openssl")]
+pub use passphrase::{set_passphrase_file, PASSPHRASE_ENV};
+#[cfg(feature = "backend-openssl")]
+pub use perms::exposed_keys;
+#[cfg(feature = "backend-openssl")]
+pub use policy::Rule;
+#[cfg(all(feature = "backend-rcgen", not(feature = "backend-openssl")))]
+pub use rcgen_backend::{create_intermediate_ca, create_root_ca, create_server_cert, CertParams};
+#[cfg(feature = "backend-openssl")]
+pub use receipt::{read_receipt, verify_receipt, Receipt};
+#[cfg(feature = "backend-openssl")]
+pub use repair::{repair, stale_certs, Repair, StaleCert};
+#[cfg(feature = "backend-openssl")]
+pub use scan::{scan_repo, LeakedKey};
+#[cfg(feature = "backend-openssl")]
+pub use serial::{MonotonicSerial, RandomSerial, SerialSource, SerialStrategy, TimestampSerial};
+#[cfg(feature = "backend-openssl")]
+pub use ssh::{
+    known_hosts_line, read_ssh_public_key, sign_ssh_key, ssh_ca, ssh_ca_public_key, ssh_cert_path,
+    sshd_config_snippet, SshCertType, DEFAULT_SSH_HOST_TTL, DEFAULT_SSH_USER_TTL,
+    TRUSTED_USER_CA_KEYS,
+};
+#[cfg(feature = "backend-openssl")]
+pub use status::{store_status, IssuerStatus, StoreStatus};
+#[cfg(feature = "backend-openssl")]
+pub use store::{
+    export_store, import_store, Role, StoreEntry, StoreManifest, STORE_LAYOUT_VERSION,
+};
+#[cfg(feature = "backend-openssl")]
+pub use summary::{IssuanceSummary, IssuedFile, PairReceipt};
+#[cfg(feature = "backend-openssl")]
+pub use trust::{trust_root, trust_stores, untrust_root, TrustChange, TrustStore};
+#[cfg(feature = "serve")]
+pub use tsa::DEFAULT_TSA_POLICY;
+#[cfg(feature = "backend-openssl")]
+pub use usage::{mark_used, unused_certs};
+#[cfg(feature = "backend-openssl")]
+pub use vault::{vault_pull, vault_push, VaultMount};
+#[cfg(feature = "backend-openssl")]
+pub use verify::{verify_cert, Purpose, Verification};
 
+#[cfg(feature = "backend-openssl")]
 macro_rules! write_file {
     ($content:expr, $dest:expr, $verbose:expr, $msg_fmt:expr) => {{
-        let mut file = File::create($dest).unwrap();
-        let _ = file.write_all($content)?;
+        crate::atomic::write_file($dest, $content)?;
         if $verbose {
-            println!($msg_fmt, $dest);
+            tracing::info!($msg_fmt, $dest);
         }
     }};
 }
 
-pub fn save_file(content: &Vec<u8>, dest: &std::path::Path) -> Result<(), std::io::Error> {
-    use std::io::Write;
-    let mut file = std::fs::File::create(dest).unwrap();
-    file.write_all(content)?;
-    Ok(())
+#[cfg(feature = "backend-openssl")]
+pub fn save_file(content: &[u8], dest: &std::path::Path) -> Result<(), std::io::Error> {
+    atomic::write_file(dest, content)
+}
+
+#[cfg(feature = "backend-openssl")]
+/// Like `save_file`, but readable by the owner only whatever the name of `dest`.
+pub fn save_private_file(content: &[u8], dest: &std::path::Path) -> Result<(), std::io::Error> {
+    atomic::write_private(dest, content)
 }
 
+#[cfg(feature = "backend-openssl")]
 mod commands;
-pub use commands::{generate_server_cert, load_ca};
+#[cfg(feature = "backend-openssl")]
+mod config_sig;
+#[cfg(feature = "backend-openssl")]
+mod csr;
+#[cfg(feature = "backend-openssl")]
+mod ct;
+#[cfg(feature = "backend-openssl")]
+mod device;
+#[cfg(feature = "backend-openssl")]
+mod email;
+#[cfg(feature = "backend-openssl")]
+mod kms;
+#[cfg(feature = "backend-openssl")]
+mod offline;
+#[cfg(feature = "backend-openssl")]
+mod renew;
+#[cfg(feature = "serve")]
+mod serve;
+#[cfg(feature = "backend-openssl")]
+mod signer;
+#[cfg(feature = "backend-openssl")]
+mod tpm;
+#[cfg(feature = "backend-openssl")]
+mod yubikey;
+#[cfg(feature = "backend-openssl")]
+pub use commands::{
+    create_intermediate, create_subordinate, cross_sign, generate_server_cert,
+    generate_server_pair, import_ca, load_ca, ServerExtras,
+};
+#[cfg(feature = "backend-openssl")]
+pub use config_sig::{config_signer, sign_config, trust_config_signer};
+#[cfg(feature = "backend-openssl")]
+pub use csr::{issue_csr_cert, read_csr};
+#[cfg(feature = "backend-openssl")]
+pub use ct::{log_id, PrecertPair};
+#[cfg(feature = "backend-openssl")]
+pub use device::{issue_devices, parse_devices, Device, DeviceEntry};
+#[cfg(feature = "backend-openssl")]
+pub use email::{check_email, issue_email_cert};
+#[cfg(feature = "backend-openssl")]
+pub use kms::{intermediate_to_kms, KmsKey};
+#[cfg(feature = "backend-openssl")]
+pub use offline::{
+    is_root_offline, offline_root, resign_intermediate, take_root_offline, OfflineRoot,
+};
+#[cfg(feature = "backend-openssl")]
+pub use renew::{
+    auto_renew_due, auto_renew_log, expiring_server_certs, get_or_issue, renew_all, renew_expiring,
+    renew_server_cert, CertSpec,
+};
+#[cfg(feature = "serve")]
+pub use serve::{serve_acme, serve_api, serve_tsa};
+#[cfg(feature = "backend-openssl")]
+pub use signer::{issue_signer_cert, SignerProfile};
+#[cfg(feature = "backend-openssl")]
+pub use tpm::{intermediate_to_tpm, issue_tpm_cert, parse_handle};
+#[cfg(feature = "backend-openssl")]
+pub use yubikey::{intermediate_to_yubikey, PivSlot, DEFAULT_YKCS11_MODULE};
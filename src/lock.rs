@@ -0,0 +1,123 @@
+use std::cell::Cell;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::conf::config_dir;
+use crate::err::{Result, SimpleCAError};
+
+const LOCK_FILE: &str = ".lock";
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+const LOCK_POLL: Duration = Duration::from_millis(100);
+
+thread_local! {
+    static LOCK_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Exclusive lock over the store, shared by every process writing to it.
+///
+/// The lock is an OS file lock on `.lock`, so it is released when its holder
+/// exits, however it exits. It is re-entrant within a thread, so public
+/// operations can take it even when called from another locked operation;
+/// the guard cannot leave the thread that took it.
+pub struct StoreLock {
+    /// The locked file, held by the outermost guard of the thread only.
+    file: Option<File>,
+    _thread: PhantomData<*const ()>,
+}
+
+fn holder_pid(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+impl StoreLock {
+    pub fn acquire() -> Result<StoreLock> {
+        StoreLock::acquire_in(&config_dir()?)
+    }
+
+    fn acquire_in(dir: &Path) -> Result<StoreLock> {
+        if LOCK_DEPTH.with(|depth| depth.get()) > 0 {
+            LOCK_DEPTH.with(|depth| depth.set(depth.get() + 1));
+            return Ok(StoreLock {
+                file: None,
+                _thread: PhantomData,
+            });
+        }
+
+        fs::create_dir_all(dir)?;
+        let path = dir.join(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        let started = SystemTime::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) => {
+                    if started.elapsed().unwrap_or_default() > LOCK_TIMEOUT {
+                        Err(SimpleCAError::StoreLocked {
+                            pid: holder_pid(&path).unwrap_or(0),
+                        })?;
+                    }
+                    thread::sleep(LOCK_POLL);
+                }
+                Err(TryLockError::Error(err)) => Err(err)?,
+            }
+        }
+        file.set_len(0)?;
+        write!(file, "{}", process::id())?;
+        LOCK_DEPTH.with(|depth| depth.set(1));
+        Ok(StoreLock {
+            file: Some(file),
+            _thread: PhantomData,
+        })
+    }
+}
+
+impl Drop for StoreLock {
+    fn drop(&mut self) {
+        LOCK_DEPTH.with(|depth| depth.set(depth.get().saturating_sub(1)));
+        // Closing the file releases the lock; the file stays so that no
+        // process ever locks a file another has just unlinked.
+        if let Some(file) = self.file.take() {
+            let _ = file.set_len(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_store_lock() {
+        let dir = std::env::temp_dir().join(format!("simple-ca-lock-{}", std::process::id()));
+        let outer = StoreLock::acquire_in(&dir).unwrap();
+        let inner = StoreLock::acquire_in(&dir).unwrap();
+        assert_eq!(holder_pid(&dir.join(LOCK_FILE)), Some(process::id()));
+
+        let (sender, receiver) = mpsc::channel();
+        let waiter = {
+            let dir = dir.clone();
+            thread::spawn(move || {
+                let _lock = StoreLock::acquire_in(&dir).unwrap();
+                sender.send(()).unwrap();
+            })
+        };
+        drop(inner);
+        assert!(receiver.recv_timeout(LOCK_POLL * 3).is_err());
+        drop(outer);
+        receiver.recv_timeout(LOCK_TIMEOUT).unwrap();
+        waiter.join().unwrap();
+        assert_eq!(LOCK_DEPTH.with(|depth| depth.get()), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
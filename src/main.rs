@@ -1,23 +1,630 @@
 extern crate clap;
 extern crate simple_ca;
 
-use clap::{App, Arg, SubCommand};
-use simple_ca::{generate_server_cert, load_ca, Name};
+use clap::{App, Arg, ArgGroup, ArgMatches, SubCommand};
+use serde::Serialize;
+use serde_json::json;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process;
+use tracing::Level;
 
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+use simple_ca::{
+    auto_renew_due, auto_renew_log, backup_store, config_signer, create_intermediate,
+    create_subordinate, cross_sign, detect_local, display_width, ephemeral_store, export_all,
+    export_android, export_der, export_haproxy, export_java_stores, export_mobileconfig,
+    export_p7b, export_pfx, export_store, find_compose_file, generate_server_cert,
+    generate_server_pair, import_ca, import_store, inspect, intermediate_to_kms,
+    intermediate_to_tpm, intermediate_to_yubikey, issue_compose_certs, issue_csr_cert,
+    issue_devices, issue_email_cert, issue_manifest, issue_signer_cert, issue_tpm_cert,
+    issued_certs, issued_under_root, k8s_tls_secret, known_hosts_line, load_ca, local_ips,
+    mark_used, match_key, pad, parse_handle, parse_ttl, prewarm, read_receipt, read_ssh_public_key,
+    renew_all, renew_expiring, renew_server_cert, repair, resign_intermediate, restore_store,
+    save_private_file, scan_repo, set_config_home, set_exec_hook, set_language, set_local,
+    set_passphrase_file, set_profile, sign_config, sign_ssh_key, ssh_ca, ssh_ca_public_key,
+    sshd_config_snippet, stale_certs, store_status, take_root_offline, tr, trust_config_signer,
+    trust_hooks, trust_root, untrust_root, unused_certs, vault_pull, vault_push, verify_cert,
+    CertAuthConf, Conf, ExportLayout, IssuanceSummary, IssuedCert, JavaStoreFormat, KeyAlgorithm,
+    Language, Name, OutDir, PivSlot, Purpose, RepoGuard, Result, Role, ServerExtras, SignerProfile,
+    SimpleCAError, Sources, SshCertType, StoreManifest, ValidityConf, VaultMount,
+    DEFAULT_STORE_PASSWORD, EXIT_FAILURE, EXIT_INVALID, EXIT_USAGE, TRUSTED_USER_CA_KEYS,
+};
+
+mod man;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const REPO_GUARD_ARGS: &str = "--allow-in-repo 'Allow writing private keys inside a git repository'
+     --gitignore 'Add written private keys to the repository .gitignore'";
+
+const CA_EXAMPLES: &str = "EXAMPLES:
+    simple-ca ca
+    simple-ca ca --force --days 3650 --intermediate-days 1825
+    simple-ca ca import --cert corp-test-root.pem --key corp-test-root.key.pem
+    simple-ca ca subordinate issuing --parent default
+    simple-ca ca offline --export /media/usb/root.key.pem --encrypt
+    simple-ca ca resign-intermediate --root-key /media/usb/root.key.pem
+    simple-ca ca yubikey --slot 9c
+    simple-ca ca kms alias/dev-ca-intermediate";
+
+const SERVER_EXAMPLES: &str = "EXAMPLES:
+    simple-ca server example.test example.test www.example.test
+    simple-ca server api.example.test api.example.test --ttl 6h
+    simple-ca server app.test app.test --out-dir ./certs --allow-in-repo --gitignore
+    simple-ca server example.test --pair
+    simple-ca server dev.test dev.test 192.168.1.20 --auto-ip
+    simple-ca server app.test app.test --with-dhparam --with-ticket-key
+    simple-ca server app.test app.test --haproxy
+    simple-ca server app.test app.test --format der
+    simple-ca server api.example.test api.example.test --issuer servers
+    simple-ca server app.test app.test --format k8s-secret | kubectl apply -f -
+    simple-ca server app.test app.test --exec 'systemctl reload nginx'";
+
+const ISSUE_EXAMPLES: &str = "EXAMPLES:
+    simple-ca issue --manifest certs.toml
+    simple-ca issue --manifest cluster/certs.toml --allow-in-repo --gitignore";
+
+const CSR_EXAMPLES: &str = "EXAMPLES:
+    simple-ca csr --csr tpm.csr.pem app.test
+    simple-ca csr --csr yubikey.csr.pem app.test --attestation slot9a.attest.pem";
+
+const TPM_EXAMPLES: &str = "EXAMPLES:
+    simple-ca tpm board.test board.test 10.0.0.7 --handle 0x81010002
+    simple-ca ca tpm --handle 0x81010001";
+
+const INTERMEDIATE_EXAMPLES: &str = "EXAMPLES:
+    simple-ca intermediate create servers
+    simple-ca intermediate create clients --days 365
+    simple-ca intermediate list
+    simple-ca server api.example.test api.example.test --issuer servers
+    simple-ca server app.test app.test --format k8s-secret | kubectl apply -f -";
+
+const CROSS_SIGN_EXAMPLES: &str = "EXAMPLES:
+    simple-ca cross-sign --root-cert new-root.cert.pem --root-key new-root.key.pem
+    simple-ca cross-sign --root-cert new-root.cert.pem --root-key new-root.key.pem --days 365";
+
+const DEVICE_EXAMPLES: &str = "EXAMPLES:
+    simple-ca device devices.csv --out-dir ./fleet
+    simple-ca device devices.csv --out-dir ./fleet --oid 1.3.6.1.4.1.55555.1 --days 90";
+
+const EMAIL_EXAMPLES: &str = "EXAMPLES:
+    simple-ca email alice@example.test --name 'Alice Example'
+    simple-ca email bob@example.test --out-dir ./smime --store-pass secret --days 30";
+
+const EXPORT_EXAMPLES: &str = "EXAMPLES:
+    simple-ca export --p7b
+    simple-ca export example.test --p7b --out example.p7b
+    simple-ca export example.test --k8s-secret --namespace dev | kubectl apply -f -
+    simple-ca export example.test --haproxy --out /etc/haproxy/certs/example.test.pem
+    simple-ca export --format der
+    simple-ca export example.test --format der --out device/example.crt
+    simple-ca export --format jks
+    simple-ca export example.test --format pkcs12-truststore --store-pass secret
+    simple-ca export example.test --format pfx --out C:\\inetpub\\example.pfx
+    simple-ca export android app/src/debug
+    simple-ca export mobileconfig";
+
+const TRUST_EXAMPLES: &str = "EXAMPLES:
+    simple-ca trust
+    simple-ca trust --system
+    simple-ca untrust";
+
+const TRUST_SYSTEM_ARG: &str =
+    "--system 'Use the stores of the machine, the macOS System keychain through sudo'";
+
+const EXPORT_ALL_EXAMPLES: &str = "EXAMPLES:
+    simple-ca export-all --layout traefik --dest ./traefik
+    simple-ca export-all --layout nginx --dest /etc/nginx/certs
+    simple-ca export-all --layout flat --dest ./certs --allow-in-repo";
+
+const COMPOSE_EXAMPLES: &str = "EXAMPLES:
+    simple-ca compose --gitignore
+    simple-ca compose deploy/docker-compose.yml --dest deploy/tls --allow-in-repo
+    simple-ca compose --days 30";
+
+const LIST_EXAMPLES: &str = "EXAMPLES:
+    simple-ca list
+    simple-ca list --unused 90d
+    simple-ca touch example.test";
+
+const INSPECT_EXAMPLES: &str = "EXAMPLES:
+    simple-ca inspect example.test
+    simple-ca inspect ~/.simple_ca/ca.cert.pem --json";
+
+const RENEW_EXAMPLES: &str = "EXAMPLES:
+    simple-ca renew example.test
+    simple-ca renew example.test --new-key
+    simple-ca renew --all";
+
+const REPAIR_EXAMPLES: &str = "EXAMPLES:
+    simple-ca repair
+    simple-ca repair --reissue";
+
+const SCAN_REPO_EXAMPLES: &str = "EXAMPLES:
+    simple-ca scan-repo
+    simple-ca scan-repo ~/src/project";
+
+const SIGNER_EXAMPLES: &str = "EXAMPLES:
+    simple-ca signer ocsp-signer
+    simple-ca signer ocsp-signer --issuer staging --days 30
+    simple-ca signer tsa";
+
+const SSH_SNIPPETS_EXAMPLES: &str = "EXAMPLES:
+    simple-ca ssh-snippets --hosts '*.dev.test'
+    simple-ca ssh-snippets --ca-key ~/.ssh/dev_ca.pub --hosts '*.dev.test'";
+
+const SSH_EXAMPLES: &str = "EXAMPLES:
+    simple-ca ssh-ca
+    simple-ca ssh sign ~/.ssh/id_ed25519.pub --principals alice --ttl 8h
+    simple-ca ssh sign /etc/ssh/ssh_host_ed25519_key.pub --host --principals build.dev.test";
+
+const VERIFY_EXAMPLES: &str = "EXAMPLES:
+    simple-ca verify example.test
+    simple-ca verify ./certs/fullchain.pem --host www.example.test
+    simple-ca verify ./fleet/SN-0001.cert.pem --purpose client";
+
+const MATCH_EXAMPLES: &str = "EXAMPLES:
+    simple-ca match ./certs/app.test.key.pem ./certs/app.test.cert.pem
+    simple-ca match ./certs/app.test.key.pem app.test --json";
+
+const STORE_EXAMPLES: &str = "EXAMPLES:
+    simple-ca store export ./dev-tools/ca --allow-in-repo
+    simple-ca store import ./dev-tools/ca --force
+    simple-ca store status";
+
+const BACKUP_EXAMPLES: &str = "EXAMPLES:
+    simple-ca backup team-ca.backup --encrypt
+    simple-ca restore team-ca.backup --force";
+
+const VAULT_EXAMPLES: &str = "EXAMPLES:
+    simple-ca vault push secret/dev-ca --encrypt
+    simple-ca vault pull secret/dev-ca --force
+    simple-ca vault push pki_dev --pki";
+
+const CONFIG_EXAMPLES: &str = "EXAMPLES:
+    simple-ca config trust --key ops.pub.pem --root-key /media/usb/root.key.pem
+    simple-ca config sign --key ops.key.pem
+    simple-ca config verify
+    simple-ca config trust-hooks";
+
+const PREWARM_EXAMPLES: &str = "EXAMPLES:
+    simple-ca prewarm
+    simple-ca prewarm --count 8 --algorithm rsa4096 --algorithm ecdsa-p256";
+
+#[cfg(feature = "serve")]
+const SERVE_EXAMPLES: &str = "EXAMPLES:
+    simple-ca serve acme
+    simple-ca serve acme --listen 0.0.0.0:14000 --host ca.dev.test
+    simple-ca serve api --token \"$TOKEN\"
+    simple-ca serve tsa --listen 127.0.0.1:14002";
+
+fn days(matches: &ArgMatches, name: &str) -> Result<Option<u32>> {
+    matches
+        .value_of(name)
+        .map(|days| match days.parse() {
+            Ok(days) if days > 0 => Ok(days),
+            _ => Err(SimpleCAError::InvalidDays {
+                value: days.to_string(),
+            }),
+        })
+        .transpose()
+}
+
+fn count(matches: &ArgMatches, name: &str) -> Result<usize> {
+    let count = matches.value_of(name).unwrap();
+    match count.parse() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(SimpleCAError::InvalidCount {
+            value: count.to_string(),
+        }),
+    }
+}
+
+fn repo_guard(matches: &ArgMatches) -> RepoGuard {
+    RepoGuard {
+        allow_in_repo: matches.is_present("allow-in-repo"),
+        gitignore: matches.is_present("gitignore"),
+        ..RepoGuard::default()
+    }
+}
+
+/// Logs the events of the library to stderr, warnings only unless raised
+/// with `-v`, and at least the startup of `serve`.
+fn init_logging(matches: &ArgMatches) {
+    let mut level = match matches.occurrences_of("v") {
+        _ if quiet(matches) => Level::ERROR,
+        0 => Level::WARN,
+        1 => Level::INFO,
+        2 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    if matches.subcommand_name() == Some("serve") {
+        level = level.max(Level::INFO);
+    }
+    let logger = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(level)
+        .with_target(false)
+        .with_ansi(std::io::stderr().is_terminal());
+    if matches.value_of("log-format") == Some("json") {
+        logger.json().init();
+    } else {
+        logger.without_time().init();
+    }
+}
+
+/// Whether `--quiet` asks for nothing but errors and the output the command
+/// is run for.
+fn quiet(matches: &ArgMatches) -> bool {
+    matches.is_present("quiet")
+}
+
+/// Prints an informational line, unless `--quiet`.
+fn info(matches: &ArgMatches, message: String) {
+    if !quiet(matches) {
+        println!("{}", message);
+    }
+}
+
+/// Prints a warning to stderr, unless `--quiet`.
+fn warn(matches: &ArgMatches, message: String) {
+    if !quiet(matches) {
+        eprintln!("{}", message);
+    }
+}
+
+/// Whether results are printed as JSON, with the global `--output json` or
+/// the `--json` flag of a command that has one.
+fn json_output(matches: &ArgMatches) -> bool {
+    matches.value_of("output") == Some("json")
+        || (matches.is_valid_arg("json") && matches.is_present("json"))
+}
+
+fn print_json<T: Serialize + ?Sized>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Prints the files a command wrote, a line each with the `message` or a
+/// JSON object listing them.
+fn print_written(matches: &ArgMatches, message: &str, paths: &[PathBuf]) -> Result<()> {
+    if json_output(matches) {
+        print_json(&json!({ "files": paths }))?;
+    } else {
+        for path in paths {
+            info(matches, tr!(message, path = format!("{:?}", path)));
+        }
+    }
+    Ok(())
+}
+
+fn print_summaries(matches: &ArgMatches, summaries: &[IssuanceSummary]) -> Result<()> {
+    if json_output(matches) {
+        print_json(summaries)?;
+    } else if !quiet(matches) {
+        let summaries: Vec<String> = summaries.iter().map(|s| s.to_string()).collect();
+        println!("{}", summaries.join("\n\n"));
+    }
+    Ok(())
+}
+
+fn print_issued(certs: &[IssuedCert]) -> Result<()> {
+    let rows = certs
+        .iter()
+        .map(|cert| {
+            let path = cert.cert_path()?;
+            Ok([
+                cert.serial.clone(),
+                cert.not_after.clone(),
+                cert.common_name.clone(),
+                cert.sans.join(","),
+                path.map(|path| path.display().to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+            ])
+        })
+        .collect::<Result<Vec<[String; 5]>>>()?;
+    let header = [
+        "list-serial",
+        "list-not-after",
+        "list-common-name",
+        "list-sans",
+        "list-file",
+    ]
+    .map(|id| tr!(id));
+    let mut widths = header.clone().map(|column| display_width(&column));
+    for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(display_width(column));
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(column, width)| pad(column, width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+    Ok(())
+}
+
+/// Points at `repair` when server certificates were issued by a replaced
+/// intermediate.
+fn warn_stale(matches: &ArgMatches) -> Result<()> {
+    let stale = stale_certs()?;
+    if !stale.is_empty() {
+        warn(matches, tr!("stale-certs", count = stale.len()));
+    }
+    Ok(())
+}
+
+/// Whether the existing files among `paths` may be replaced: with `--force`,
+/// or when the `question` is answered with yes on a terminal. The library
+/// refuses to replace them otherwise.
+fn confirm_replace(
+    matches: &ArgMatches,
+    paths: &[PathBuf],
+    question: impl FnOnce() -> Result<String>,
+) -> Result<bool> {
+    if matches.is_present("force") {
+        return Ok(true);
+    }
+    if !paths.iter().any(|path| path.exists()) || !std::io::stdin().is_terminal() {
+        return Ok(false);
+    }
+    confirm(&question()?)
+}
+
+/// Asks `question` on stderr and reads a yes or no from stdin, no by default.
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", question);
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(feature = "serve")]
+fn serve_command() -> App<'static> {
+    SubCommand::with_name("serve")
+        .about("Run a local service backed by the CA")
+        .after_help(SERVE_EXAMPLES)
+        .subcommand(
+            SubCommand::with_name("acme")
+                .about("Serve an ACME v2 directory issuing from the intermediate CA")
+                .args_from_usage(
+                    "--listen=[ADDR] 'Address to listen on'
+          --host=[HOST] 'Host name used in the directory URLs and TLS certificate'
+          --http 'Serve plain HTTP instead of HTTPS'",
+                )
+                .mut_arg("listen", |arg| arg.default_value("127.0.0.1:14000"))
+                .mut_arg("host", |arg| arg.default_value("localhost")),
+        )
+        .subcommand(
+            SubCommand::with_name("api")
+                .about("Serve a REST API issuing server certificates")
+                .args_from_usage(
+                    "--listen=[ADDR] 'Address to listen on'
+          --host=[HOST] 'Host name of the TLS certificate'
+          --http 'Serve plain HTTP instead of HTTPS'
+          --token=[TOKEN] 'Require this bearer token on every request'",
+                )
+                .mut_arg("listen", |arg| arg.default_value("127.0.0.1:14001"))
+                .mut_arg("host", |arg| arg.default_value("localhost")),
+        )
+        .subcommand(
+            SubCommand::with_name("tsa")
+                .about("Serve an RFC 3161 time-stamping authority over HTTP, signing with the tsa signer")
+                .args_from_usage(
+                    "--listen=[ADDR] 'Address to listen on'
+          --policy=[OID] 'Policy of the timestamps'",
+                )
+                .mut_arg("listen", |arg| arg.default_value("127.0.0.1:14002"))
+                .mut_arg("policy", |arg| arg.default_value(simple_ca::DEFAULT_TSA_POLICY)),
+        )
+}
+
+#[cfg(feature = "serve")]
+fn run_serve(matches: &ArgMatches) -> Result<()> {
+    use simple_ca::{serve_acme, serve_api, serve_tsa};
+
+    if let Some(matches) = matches.subcommand_matches("acme") {
+        serve_acme(
+            matches.value_of("listen").unwrap(),
+            matches.value_of("host").unwrap(),
+            matches.is_present("http"),
+            Sources::default(),
+        )?;
+    }
+    if let Some(matches) = matches.subcommand_matches("api") {
+        serve_api(
+            matches.value_of("listen").unwrap(),
+            matches.value_of("host").unwrap(),
+            matches.is_present("http"),
+            matches.value_of("token").map(|token| token.to_string()),
+            Sources::default(),
+        )?;
+    }
+    if let Some(matches) = matches.subcommand_matches("tsa") {
+        serve_tsa(
+            matches.value_of("listen").unwrap(),
+            matches.value_of("policy").unwrap(),
+            Sources::default(),
+        )?;
+    }
+    Ok(())
+}
 
 fn main() {
-    let matches = App::new("Simple CA")
+    let app = App::new("Simple CA")
         .version(VERSION)
         .about("Create certificates for dev environment easiliy.")
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .global(true)
+                .takes_value(true)
+                .value_name("NAME")
+                .help("CA profile to use, each kept in its own ~/.simple_ca/<NAME> directory"),
+        )
+        .arg(
+            Arg::with_name("home")
+                .long("home")
+                .global(true)
+                .takes_value(true)
+                .value_name("DIR")
+                .help("Directory used instead of ~/.simple_ca, also set by SIMPLE_CA_HOME"),
+        )
+        .arg(
+            Arg::with_name("passphrase-file")
+                .long("passphrase-file")
+                .global(true)
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Read the passphrase of encrypted CA keys from FILE, - for stdin"),
+        )
+        .arg(
+            Arg::with_name("exec")
+                .long("exec")
+                .global(true)
+                .takes_value(true)
+                .value_name("COMMAND")
+                .help("Run COMMAND after each server certificate is written, after [hooks] post_issue"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .global(true)
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(["text", "json"])
+                .help("Print results as text or as JSON for scripts"),
+        )
+        .arg(
+            Arg::with_name("lang")
+                .long("lang")
+                .global(true)
+                .takes_value(true)
+                .possible_values(["en", "zh"])
+                .help("Language of the output, also set by SIMPLE_CA_LANG or the locale"),
+        )
+        .arg(
+            Arg::with_name("v")
+                .short('v')
+                .long("verbose")
+                .global(true)
+                .multiple_occurrences(true)
+                .help("Log the written files, -vv also the steps taken"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short('q')
+                .long("quiet")
+                .global(true)
+                .help("Print nothing but errors and the output asked for, e.g. JSON"),
+        )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .global(true)
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(["text", "json"])
+                .help("Log to stderr as text or as JSON lines for CI"),
+        )
+        .arg(Arg::with_name("local").long("local").global(true).help(
+            "Keep the CA in ./.simple_ca of the current project, used automatically when present",
+        ))
         .subcommand(
             SubCommand::with_name("ca")
                 .about("Regenerate CA certificates")
-                .arg(Arg::with_name("v").short('v').help("Sets verbose mode")),
+                .after_help(CA_EXAMPLES)
+                .args_from_usage(REPO_GUARD_ARGS)
+                .args_from_usage(
+                    "--days=[DAYS] 'Validity of the root CA certificate in days'
+          --intermediate-days=[DAYS] 'Validity of the intermediate CA certificate in days'
+          --force 'Replace an existing CA without asking'
+          ",
+                )
+                .mut_arg("force", |arg| arg.visible_alias("yes"))
+                .subcommand(
+                    SubCommand::with_name("import")
+                        .about("Install an existing root CA and issue a new intermediate from it")
+                        .args_from_usage(
+                            "--cert=<FILE> 'Certificate of the root CA, PEM or DER'
+          --key=<FILE> 'Private key of the root CA'
+          --intermediate-days=[DAYS] 'Validity of the intermediate CA certificate in days'
+          --force 'Replace an existing CA'",
+                        )
+                        .args_from_usage(REPO_GUARD_ARGS),
+                )
+                .subcommand(
+                    SubCommand::with_name("offline")
+                        .about("Export the root key and remove it from the store")
+                        .args_from_usage(
+                            "--export=<FILE> 'File to export the root key to'
+          --encrypt 'Encrypt the exported key with the passphrase'",
+                        )
+                        .args_from_usage(REPO_GUARD_ARGS),
+                )
+                .subcommand(
+                    SubCommand::with_name("resign-intermediate")
+                        .about("Re-sign the intermediate with its key using the offline root key")
+                        .args_from_usage(
+                            "--root-key=<FILE> 'Private key of the root, as exported by ca offline'
+          --intermediate-days=[DAYS] 'Validity of the intermediate CA certificate in days'",
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("yubikey")
+                        .about("Move the intermediate key into a PIV slot of a YubiKey")
+                        .args_from_usage(
+                            "--slot=[SLOT] 'PIV slot of the key'
+          --import 'Import the current intermediate key instead of generating one on the device'
+          --module=[FILE] 'PKCS#11 module of the YubiKey, libykcs11 by default'
+          --intermediate-days=[DAYS] 'Validity of the new intermediate CA certificate in days'",
+                        )
+                        .mut_arg("slot", |arg| {
+                            arg.default_value("9c")
+                                .possible_values(PivSlot::ALL.map(|slot| slot.name()))
+                        })
+                        .mut_arg("intermediate-days", |arg| arg.conflicts_with("import")),
+                )
+                .subcommand(
+                    SubCommand::with_name("tpm")
+                        .about("Move the intermediate key into the TPM")
+                        .args_from_usage(
+                            "--handle=<HANDLE> 'Persistent handle of the key, e.g. 0x81010001, reused when it holds one'
+          --intermediate-days=[DAYS] 'Validity of the new intermediate CA certificate in days'",
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("kms")
+                        .about("Move the intermediate key into AWS KMS or GCP Cloud KMS")
+                        .args_from_usage(
+                            "<KEY> 'AWS KMS key ID, alias or ARN, or Cloud KMS key version name'
+          --intermediate-days=[DAYS] 'Validity of the new intermediate CA certificate in days'",
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("subordinate")
+                        .about("Issue a subordinate CA from an intermediate for a deeper hierarchy")
+                        .arg(
+                            Arg::with_name("NAME")
+                                .help("Name of the subordinate CA, used with server --issuer")
+                                .required(true),
+                        )
+                        .args_from_usage(
+                            "--parent=<NAME> 'Named intermediate issuing it, or default for the default one'
+          --days=[DAYS] 'Validity of the subordinate CA certificate in days'",
+                        )
+                        .args_from_usage(REPO_GUARD_ARGS),
+                ),
         )
         .subcommand(
             SubCommand::with_name("server")
                 .about("Create server certificate")
+                .after_help(SERVER_EXAMPLES)
                 .arg(
                     Arg::with_name("COMMON_NAME")
                         .help("Common name field of the certificate")
@@ -26,7 +633,8 @@ fn main() {
                 .arg(
                     Arg::with_name("subjectAltName")
                         .help("DNS entry in the SubjectAltName extension of the certificate")
-                        .required(true)
+                        .required_unless_present("pair")
+                        .conflicts_with("pair")
                         .multiple(true)
                         .takes_value(true),
                 )
@@ -36,34 +644,1630 @@ fn main() {
           --locality=[NAME] 'Locality field of the certificate'
           --org=[NAME] 'Orgnaization field of the certificate'
           --org-unit=[NAME] 'Organization unit field of the certificate'
+          --days=[DAYS] 'Validity of the certificate in days'
+          --ttl=[TTL] 'Validity of the certificate as a lifetime like 90m or 6h'
+          --not-before=[TIME] 'Start of validity as an RFC 3339 time like 2024-01-31T08:00:00Z'
+          --not-after=[TIME] 'End of validity as an RFC 3339 time, overriding --days and --ttl'
+          --out-dir=[DIR] 'Also copy the key, certificate and chains into this directory'
+          --key-name=[TEMPLATE] 'File name of the key in --out-dir, {domain} is replaced'
+          --cert-name=[TEMPLATE] 'File name of the certificate in --out-dir'
+          --fullchain-name=[TEMPLATE] 'File name of the full chain in --out-dir'
+          --chain-name=[TEMPLATE] 'File name of the CA chain in --out-dir'
+          --receipt 'Also write a CMS receipt of the issuance signed by the CA'
+          --precert 'Also write a CT precertificate and embed a simulated SCT for it'
+          --with-dhparam 'Also write 2048-bit DH parameters, which can take a while'
+          --with-ticket-key 'Also write a random TLS session ticket key'
+          --haproxy 'Also write the key, certificate and intermediates into one PEM for HAProxy'
+          --issuer=[NAME] 'Sign with the named intermediate instead of the default one'
+          --auto-ip 'Also add the addresses of the local network interfaces as IP SANs'
+          --pair 'Issue COMMON_NAME and *.COMMON_NAME with separate keys and a combined JSON receipt'
+          --format=[FORMAT] 'k8s-secret prints a Secret manifest instead of a summary, der also writes .crt and .key in DER'
+          --secret-name=[NAME] 'Name of the Kubernetes Secret, <domain>-tls by default'
+          --namespace=[NAMESPACE] 'Namespace of the Kubernetes Secret'
+          --force 'Replace an existing key and certificate'
           ",
                 )
-                .arg(Arg::with_name("v").short('v').help("Sets verbose put mode")),
+                .mut_arg("auto-ip", |arg| arg.conflicts_with("pair"))
+                .mut_arg("format", |arg| arg.possible_values(["k8s-secret", "der"]))
+                .mut_arg("secret-name", |arg| {
+                    arg.requires("format").conflicts_with("pair")
+                })
+                .mut_arg("namespace", |arg| arg.requires("format"))
+                .args_from_usage(REPO_GUARD_ARGS),
+        )
+        .subcommand(
+            SubCommand::with_name("issue")
+                .about("Issue every server certificate listed in a TOML manifest")
+                .after_help(ISSUE_EXAMPLES)
+                .args_from_usage(
+                    "--manifest=<FILE> 'Manifest with a [[cert]] table per certificate and optional [defaults]'",
+                )
+                .args_from_usage(REPO_GUARD_ARGS),
+        )
+        .subcommand(
+            SubCommand::with_name("csr")
+                .about("Create a server certificate for a CSR, e.g. of a key in a TPM or YubiKey")
+                .after_help(CSR_EXAMPLES)
+                .arg(
+                    Arg::with_name("subjectAltName")
+                        .help("DNS entry in the SubjectAltName extension of the certificate")
+                        .multiple(true)
+                        .takes_value(true),
+                )
+                .args_from_usage(
+                    "--csr=<FILE> 'CSR in PEM or DER, its common name names the certificate'
+          --attestation=[FILE] 'Attestation statement of the key, kept with the certificate'
+          --days=[DAYS] 'Validity of the certificate in days'
+          --ttl=[TTL] 'Validity of the certificate as a lifetime like 90m or 6h'
+          --issuer=[NAME] 'Sign with the named intermediate instead of the default one'",
+                )
+                .args_from_usage(REPO_GUARD_ARGS),
         )
-        .get_matches();
+        .subcommand(
+            SubCommand::with_name("tpm")
+                .about("Create a server key inside the TPM and a certificate for it")
+                .after_help(TPM_EXAMPLES)
+                .arg(
+                    Arg::with_name("CN")
+                        .help("Common name of the certificate")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("subjectAltName")
+                        .help("DNS entry in the SubjectAltName extension of the certificate")
+                        .multiple(true)
+                        .takes_value(true),
+                )
+                .args_from_usage(
+                    "--handle=<HANDLE> 'Persistent handle of the key, e.g. 0x81010002, reused when it holds one'
+          --days=[DAYS] 'Validity of the certificate in days'
+          --ttl=[TTL] 'Validity of the certificate as a lifetime like 90m or 6h'
+          --issuer=[NAME] 'Sign with the named intermediate instead of the default one'",
+                )
+                .args_from_usage(REPO_GUARD_ARGS),
+        )
+        .subcommand(
+            SubCommand::with_name("intermediate")
+                .about("Manage additional named intermediate CAs under the root")
+                .after_help(INTERMEDIATE_EXAMPLES)
+                .subcommand(
+                    SubCommand::with_name("create")
+                        .about("Create or replace a named intermediate CA")
+                        .arg(
+                            Arg::with_name("NAME")
+                                .help("Name of the intermediate, e.g. servers")
+                                .required(true),
+                        )
+                        .args_from_usage(
+                            "--days=[DAYS] 'Validity of the intermediate CA certificate in days'",
+                        )
+                        .args_from_usage(REPO_GUARD_ARGS),
+                )
+                .subcommand(
+                    SubCommand::with_name("list").about("List the named intermediate CAs"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cross-sign")
+                .about("Sign the intermediate CA with a second root for an alternate chain")
+                .after_help(CROSS_SIGN_EXAMPLES)
+                .args_from_usage(
+                    "--root-cert=<FILE> 'Certificate of the second root, PEM or DER'
+          --root-key=<FILE> 'Private key of the second root'
+          --days=[DAYS] 'Validity of the cross-signed certificate in days'",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("device")
+                .about("Issue device certificates in bulk from a CSV of device IDs")
+                .after_help(DEVICE_EXAMPLES)
+                .arg(
+                    Arg::with_name("CSV")
+                        .help("File with one device ID per line, optionally followed by ,ATTRIBUTE")
+                        .required(true),
+                )
+                .args_from_usage(
+                    "--out-dir=<DIR> 'Directory receiving the keys, certificates and manifest.json'
+          --oid=[OID] 'Also put the ATTRIBUTE column (or the ID) into the subject under this OID'
+          --days=[DAYS] 'Validity of the certificates in days'
+          --ttl=[TTL] 'Validity of the certificates as a lifetime like 90m or 6h'
+          --not-before=[TIME] 'Start of validity as an RFC 3339 time like 2024-01-31T08:00:00Z'
+          --not-after=[TIME] 'End of validity as an RFC 3339 time, overriding --days and --ttl'
+          ",
+                )
+                .args_from_usage(REPO_GUARD_ARGS),
+        )
+        .subcommand(
+            SubCommand::with_name("email")
+                .about("Issue an S/MIME certificate for an email address, with a .p12 for mail clients")
+                .after_help(EMAIL_EXAMPLES)
+                .arg(
+                    Arg::with_name("ADDRESS")
+                        .help("Email address put into the SAN and subject")
+                        .required(true),
+                )
+                .args_from_usage(
+                    "--name=[NAME] 'Common name of the subject, the address by default'
+          --out-dir=[DIR] 'Directory receiving the key, certificate and .p12'
+          --store-pass=[PASSWORD] 'Password of the .p12, changeit by default'
+          --issuer=[NAME] 'Named intermediate issuing the certificate'
+          --days=[DAYS] 'Validity of the certificate in days'
+          --ttl=[TTL] 'Validity of the certificate as a lifetime like 90m or 6h'",
+                )
+                .mut_arg("out-dir", |arg| arg.default_value("."))
+                .args_from_usage(REPO_GUARD_ARGS),
+        )
+        .subcommand(
+            SubCommand::with_name("signer")
+                .about("Issue the certificate of a signer profile, like an OCSP signer, from the intermediate")
+                .after_help(SIGNER_EXAMPLES)
+                .arg(
+                    Arg::with_name("PROFILE")
+                        .help("Kind of signer")
+                        .possible_values(SignerProfile::ALL.map(|profile| profile.name()))
+                        .required(true),
+                )
+                .args_from_usage(
+                    "--issuer=[NAME] 'Named intermediate issuing the certificate'
+          --days=[DAYS] 'Validity of the certificate in days'
+          --ttl=[TTL] 'Validity of the certificate as a lifetime like 90m or 6h'",
+                )
+                .args_from_usage(REPO_GUARD_ARGS),
+        )
+        .subcommand(
+            SubCommand::with_name("verify-receipt")
+                .about("Verify an issuance receipt against the root CA and print it")
+                .arg(
+                    Arg::with_name("FILE")
+                        .help("Receipt written by server --receipt")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("List every certificate issued by the CA")
+                .after_help(LIST_EXAMPLES)
+                .args_from_usage(
+                    "--unused=[AGE] 'Only list certificates in the store not used for AGE, like 90d'
+          --touched-only 'Ignore file access times and only count uses recorded with touch'",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("touch")
+                .about("Record that server certificates were just used, for list --unused")
+                .arg(
+                    Arg::with_name("DOMAIN")
+                        .help("Common name of a server certificate in the store")
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("trust")
+                .about("Add the root to the trust stores of the OS")
+                .after_help(TRUST_EXAMPLES)
+                .args_from_usage(TRUST_SYSTEM_ARG),
+        )
+        .subcommand(
+            SubCommand::with_name("untrust")
+                .about("Remove the root from the trust stores of the OS")
+                .args_from_usage(TRUST_SYSTEM_ARG),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Export a certificate chain for other tools")
+                .after_help(EXPORT_EXAMPLES)
+                .arg(
+                    Arg::with_name("DOMAIN")
+                        .help("Server certificate to export, the CA chain if omitted"),
+                )
+                .args_from_usage(
+                    "--p7b 'Write a PKCS#7 certs-only bundle of the chain'
+          --k8s-secret 'Print a kubernetes.io/tls Secret manifest of the certificate'
+          --haproxy 'Write the key, certificate and intermediates into one PEM for HAProxy'
+          --format=[FORMAT] 'Write the certificate, or the root without a domain, and the key in this encoding, or a Java truststore with the root and a keystore for the domain'
+          --out=[FILE] 'Output file, named after the domain or the CA by default'
+          --store-pass=[PASSWORD] 'Password of the Java truststore and keystore or the PFX, changeit by default'
+          --secret-name=[NAME] 'Name of the Kubernetes Secret, <domain>-tls by default'
+          --namespace=[NAMESPACE] 'Namespace of the Kubernetes Secret'",
+                )
+                .mut_arg("k8s-secret", |arg| arg.requires("DOMAIN"))
+                .mut_arg("haproxy", |arg| arg.requires("DOMAIN"))
+                .mut_arg("format", |arg| {
+                    arg.possible_values(["der", "jks", "pkcs12-truststore", "pfx"])
+                        .requires_if("pfx", "DOMAIN")
+                })
+                .mut_arg("store-pass", |arg| arg.requires("format"))
+                .mut_arg("secret-name", |arg| arg.requires("k8s-secret"))
+                .mut_arg("namespace", |arg| arg.requires("k8s-secret"))
+                .group(
+                    ArgGroup::new("artifact")
+                        .args(&["p7b", "k8s-secret", "haproxy", "format"])
+                        .required(true),
+                )
+                .args_from_usage(REPO_GUARD_ARGS)
+                .subcommand_negates_reqs(true)
+                .subcommand(
+                    SubCommand::with_name("android")
+                        .about("Write the root and a network security config trusting it in debug builds")
+                        .arg(
+                            Arg::with_name("DIR")
+                                .help("Source set of the app module")
+                                .default_value("app/src/debug"),
+                        )
+                        .arg(
+                            Arg::with_name("force")
+                                .long("force")
+                                .help("Replace an existing network security config"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("mobileconfig")
+                        .about("Write an iOS/macOS configuration profile installing the root")
+                        .arg(
+                            Arg::with_name("FILE")
+                                .help("Profile to write")
+                                .default_value("simple-ca.mobileconfig"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export-all")
+                .about("Copy every current server certificate with its key and chains into a directory")
+                .after_help(EXPORT_ALL_EXAMPLES)
+                .args_from_usage(
+                    "--layout=<LAYOUT> 'Directory convention to write the files in'
+          --dest=<DIR> 'Directory to write the files into'",
+                )
+                .mut_arg("layout", |arg| {
+                    arg.possible_values(["traefik", "nginx", "flat"])
+                })
+                .args_from_usage(REPO_GUARD_ARGS),
+        )
+        .subcommand(
+            SubCommand::with_name("compose")
+                .about("Issue a certificate for every service of a docker-compose file")
+                .after_help(COMPOSE_EXAMPLES)
+                .arg(
+                    Arg::with_name("FILE")
+                        .help("Compose file, compose.yaml or docker-compose.yml in the current directory by default"),
+                )
+                .args_from_usage(
+                    "--dest=[DIR] 'Directory to write the files into, certs/ next to the compose file by default'
+          --days=[DAYS] 'Validity of the certificates in days'
+          --ttl=[TTL] 'Validity of the certificates as a lifetime like 90m or 6h'
+          --issuer=[NAME] 'Sign with the named intermediate instead of the default one'",
+                )
+                .args_from_usage(REPO_GUARD_ARGS),
+        )
+        .subcommand(
+            SubCommand::with_name("inspect")
+                .about("Show the details of a stored or given certificate")
+                .after_help(INSPECT_EXAMPLES)
+                .arg(
+                    Arg::with_name("TARGET")
+                        .help("Common name of a stored server certificate, or a certificate file")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the details as JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("renew")
+                .about("Re-issue server certificates keeping their names and SANs")
+                .after_help(RENEW_EXAMPLES)
+                .arg(
+                    Arg::with_name("DOMAIN")
+                        .help("Common name of the certificate to renew")
+                        .required_unless_present_any(["all", "expiring"])
+                        .conflicts_with("all"),
+                )
+                .arg(
+                    Arg::with_name("expiring")
+                        .long("expiring")
+                        .takes_value(true)
+                        .value_name("DAYS")
+                        .conflicts_with_all(&["DOMAIN", "all", "new-key"])
+                        .hide(true)
+                        .help("Renew the certificates expiring within DAYS, for automatic renewal"),
+                )
+                .args_from_usage(
+                    "--all 'Renew every server certificate in the store'
+          --new-key 'Generate a new key instead of reusing the current one'",
+                )
+                .args_from_usage(REPO_GUARD_ARGS),
+        )
+        .subcommand(
+            SubCommand::with_name("repair")
+                .about("Fix the chain bundles of certificates issued by a previous intermediate")
+                .after_help(REPAIR_EXAMPLES)
+                .args_from_usage(
+                    "--reissue 'Re-issue the certificates from the current intermediate instead'",
+                )
+                .args_from_usage(REPO_GUARD_ARGS),
+        )
+        .subcommand(
+            SubCommand::with_name("scan-repo")
+                .about("Search a working tree for leaked private keys issued by this CA")
+                .after_help(SCAN_REPO_EXAMPLES)
+                .arg(
+                    Arg::with_name("PATH")
+                        .help("Root of the working tree to scan")
+                        .default_value("."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("ssh-snippets")
+                .about("Print known_hosts and sshd_config lines trusting an SSH CA key")
+                .after_help(SSH_SNIPPETS_EXAMPLES)
+                .args_from_usage(
+                    "--ca-key=[FILE] 'OpenSSH public key of the SSH CA, the one of ssh-ca by default'
+          --hosts=[PATTERNS] 'Comma separated host patterns the CA is trusted for'",
+                )
+                .mut_arg("hosts", |arg| arg.default_value("*")),
+        )
+        .subcommand(
+            SubCommand::with_name("ssh-ca")
+                .about("Create the SSH CA key and print its public key")
+                .after_help(SSH_EXAMPLES)
+                .args_from_usage(
+                    "--force 'Replace an existing SSH CA key, its certificates stop being trusted'",
+                )
+                .args_from_usage(REPO_GUARD_ARGS),
+        )
+        .subcommand(
+            SubCommand::with_name("ssh")
+                .about("Issue OpenSSH certificates from the SSH CA")
+                .after_help(SSH_EXAMPLES)
+                .subcommand(
+                    SubCommand::with_name("sign")
+                        .about("Sign an OpenSSH public key as a user or host certificate")
+                        .args_from_usage(
+                            "<PUBKEY> 'OpenSSH public key to certify, e.g. ~/.ssh/id_ed25519.pub'
+                             --principals=<LIST> 'Comma separated user names, or host names with --host'
+                             --host 'Issue a host certificate instead of a user certificate'
+                             --id=[ID] 'Key ID logged by sshd, the first principal by default'
+                             --ttl=[TTL] 'Validity like 8h or 30d, 1d for users and 365d for hosts by default'
+                             --out=[FILE] 'Where to write the certificate, next to the key by default'",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Validate a certificate against the stored CA chain")
+                .after_help(VERIFY_EXAMPLES)
+                .arg(
+                    Arg::with_name("TARGET")
+                        .help("Common name of a stored server certificate, or a certificate file")
+                        .required(true),
+                )
+                .args_from_usage(
+                    "--purpose=[PURPOSE] 'Usage to check the key usages for'
+          --host=[HOST] 'Also check that the certificate is valid for this host name'
+          --json 'Print the result as JSON'",
+                )
+                .mut_arg("purpose", |arg| {
+                    arg.possible_values(["server", "client", "any"])
+                        .default_value("server")
+                }),
+        )
+        .subcommand(
+            SubCommand::with_name("match")
+                .about("Check that a private key belongs to a certificate")
+                .after_help(MATCH_EXAMPLES)
+                .arg(
+                    Arg::with_name("KEY")
+                        .help("Private key file")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("CERT")
+                        .help("Certificate file, or common name of a stored server certificate")
+                        .required(true),
+                )
+                .args_from_usage("--json 'Print the result as JSON'"),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Sign the config so its issuance policy cannot be edited unnoticed")
+                .after_help(CONFIG_EXAMPLES)
+                .subcommand(
+                    SubCommand::with_name("sign")
+                        .about("Sign the config, it is verified on every load from then on")
+                        .args_from_usage(
+                            "--key=<FILE> 'Root key or private key of a trusted signer'",
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("trust")
+                        .about("Trust a public key to sign the config")
+                        .args_from_usage(
+                            "--key=<FILE> 'Public key of the signer, in PEM'
+                             --root-key=<FILE> 'Private key of the root CA'",
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("verify").about("Check the signature of the config"),
+                )
+                .subcommand(
+                    SubCommand::with_name("trust-hooks")
+                        .about("Let the post_issue hook of the config run from now on"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("backup")
+                .about("Write keys, certificates, config and index into a single file")
+                .after_help(BACKUP_EXAMPLES)
+                .arg(
+                    Arg::with_name("FILE")
+                        .help("Backup file to write")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("encrypt")
+                        .long("encrypt")
+                        .help("Encrypt the backup with a passphrase"),
+                )
+                .args_from_usage(REPO_GUARD_ARGS),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("Restore the CA state from a file written by backup")
+                .after_help(BACKUP_EXAMPLES)
+                .arg(
+                    Arg::with_name("FILE")
+                        .help("Backup file to read")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Replace an existing CA"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("vault")
+                .about("Push the CA to HashiCorp Vault or pull it from there")
+                .after_help(VAULT_EXAMPLES)
+                .subcommand(
+                    SubCommand::with_name("push")
+                        .about("Store the CA in a KV secret, or as the CA of a PKI mount")
+                        .args_from_usage(
+                            "<PATH> 'KV secret, e.g. secret/dev-ca, or PKI mount with --pki'
+                             --pki 'Import the intermediate into the PKI mount at PATH'
+                             --encrypt 'Encrypt the backup with a passphrase'",
+                        )
+                        .mut_arg("encrypt", |arg| arg.conflicts_with("pki")),
+                )
+                .subcommand(
+                    SubCommand::with_name("pull")
+                        .about("Restore the CA from a KV secret written by vault push")
+                        .args_from_usage(
+                            "<PATH> 'KV secret, e.g. secret/dev-ca'
+                             --force 'Replace an existing CA'",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("store")
+                .about("Export, import or summarize the whole CA store")
+                .after_help(STORE_EXAMPLES)
+                .subcommand(
+                    SubCommand::with_name("export")
+                        .about("Copy the store into a relocatable directory")
+                        .arg(
+                            Arg::with_name("DIR")
+                                .help("Destination directory")
+                                .required(true),
+                        )
+                        .args_from_usage(REPO_GUARD_ARGS),
+                )
+                .subcommand(
+                    SubCommand::with_name("import")
+                        .about("Replace the store with an exported directory")
+                        .arg(
+                            Arg::with_name("DIR")
+                                .help("Directory written by store export")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("force")
+                                .long("force")
+                                .help("Replace an existing CA"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("status")
+                        .about("Summarize certificates, disk usage and issuers of the store")
+                        .args_from_usage("--json 'Print the status as JSON'"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("prewarm")
+                .about("Pre-generate keys so the next certificates are issued at once")
+                .after_help(PREWARM_EXAMPLES)
+                .args_from_usage(
+                    "--count=[COUNT] 'Keys of each algorithm to keep in the pool'
+          --algorithm=[ALGORITHM]... 'Algorithm to pre-generate keys of, by default those of the config'",
+                )
+                .mut_arg("count", |arg| arg.default_value("4"))
+                .mut_arg("algorithm", |arg| {
+                    arg.possible_values(KeyAlgorithm::ALL.map(|algorithm| algorithm.name()))
+                        .multiple_occurrences(true)
+                }),
+        )
+        .subcommand(
+            SubCommand::with_name("gen-man")
+                .about("Write man pages of every command, for packagers")
+                .hide(true)
+                .arg(
+                    Arg::with_name("DIR")
+                        .help("Destination directory")
+                        .default_value("man"),
+                ),
+        );
+    #[cfg(feature = "serve")]
+    let app = app.subcommand(serve_command());
+    let matches = match app.clone().try_get_matches() {
+        Ok(matches) => matches,
+        Err(err) if err.use_stderr() => {
+            let _ = err.print();
+            process::exit(EXIT_USAGE);
+        }
+        Err(err) => err.exit(),
+    };
+    init_logging(&matches);
+
+    let code = run(app, &matches).unwrap_or_else(|err| {
+        eprintln!("{}", tr!("error", error = format!("{:#}", err)));
+        err.exit_code()
+    });
+    if let Some(dir) = ephemeral_store() {
+        let _ = fs::remove_dir_all(dir);
+    }
+    if code != 0 {
+        process::exit(code);
+    }
+}
+
+/// Applies the global flags, runs the command and then starts the background
+/// renewal. Returns the exit status, non-zero when a check the command made
+/// failed.
+fn run(app: App, matches: &ArgMatches) -> Result<i32> {
+    if let Some(language) = matches.value_of("lang").and_then(Language::from_tag) {
+        set_language(language);
+    }
+    if let Some(home) = matches.value_of("home") {
+        set_config_home(Path::new(home))?;
+    }
+    if matches.is_present("local") {
+        set_local(&std::env::current_dir()?);
+    } else {
+        detect_local()?;
+    }
+    if let Some(path) = matches.value_of("passphrase-file") {
+        set_passphrase_file(Path::new(path));
+    }
+    if let Some(command) = matches.value_of("exec") {
+        set_exec_hook(command);
+    }
+    if let Some(profile) = matches.value_of("profile") {
+        set_profile(profile)?;
+    }
+
+    let code = run_command(app, matches);
+
+    if !changes_store(matches) {
+        match auto_renew_due() {
+            Ok(Some(days)) if ephemeral_store().is_none() => {
+                if let Err(err) = spawn_renewal(matches, days) {
+                    warn(
+                        matches,
+                        tr!("auto-renew-failed-with", error = err.to_string()),
+                    );
+                }
+            }
+            Ok(_) => {}
+            Err(err) => warn(matches, tr!("auto-renew-skipped", error = err.to_string())),
+        }
+    }
+    code
+}
 
-    if let Some(matches) = matches.subcommand_matches("ca") {
+/// Whether the command replaces the CA or rewrites the store, which the
+/// automatic renewal must not run alongside of.
+fn changes_store(matches: &ArgMatches) -> bool {
+    match matches.subcommand() {
+        Some(("ca" | "intermediate" | "cross-sign" | "renew" | "repair" | "restore", _)) => true,
+        Some(("store", matches)) => matches.subcommand_name() == Some("import"),
+        Some(("vault", matches)) => matches.subcommand_name() == Some("pull"),
+        _ => false,
+    }
+}
+
+/// Renews the certificates expiring within `days` in a detached
+/// `renew --expiring` process on the same store, so the command returns
+/// without waiting for it. The process logs to `auto_renew_log` and runs
+/// without a terminal, so an encrypted CA key fails the renewal instead of
+/// prompting for the passphrase after the command has returned.
+fn spawn_renewal(matches: &ArgMatches, days: u32) -> Result<()> {
+    let mut renewal = process::Command::new(std::env::current_exe()?);
+    for global in ["profile", "home", "passphrase-file", "lang"] {
+        // The renewal cannot read a passphrase from the caller's stdin.
+        if let Some(value) = matches.value_of(global).filter(|value| *value != "-") {
+            renewal.arg(format!("--{}", global)).arg(value);
+        }
+    }
+    if matches.is_present("local") {
+        renewal.arg("--local");
+    }
+    let log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(auto_renew_log()?)?;
+    renewal
+        .args(["renew", "--expiring", &days.to_string()])
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::null())
+        .stderr(log);
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+
+        // SAFETY: setsid is async-signal-safe. A new session has no
+        // controlling terminal, so `/dev/tty` cannot be opened to prompt.
+        unsafe {
+            renewal.pre_exec(|| {
+                libc::setsid();
+                Ok(())
+            });
+        }
+    }
+    renewal.spawn()?;
+    Ok(())
+}
+
+fn run_command(app: App, matches: &ArgMatches) -> Result<i32> {
+    let sources = Sources::default();
+    if let Some(matches) = matches
+        .subcommand_matches("ca")
+        .and_then(|matches| matches.subcommand_matches("import"))
+    {
+        let validity = ValidityConf {
+            intermediate_days: days(matches, "intermediate-days")?,
+            ..ValidityConf::default()
+        };
+        import_ca(
+            Path::new(matches.value_of("cert").unwrap()),
+            Path::new(matches.value_of("key").unwrap()),
+            matches.is_present("force"),
+            &validity,
+            &repo_guard(matches),
+            &sources,
+            matches.is_present("v"),
+        )?;
+        print_summaries(matches, &IssuanceSummary::ca()?)?;
+        warn_stale(matches)?;
+    } else if let Some(matches) = matches
+        .subcommand_matches("ca")
+        .and_then(|matches| matches.subcommand_matches("offline"))
+    {
+        let offline = take_root_offline(
+            Path::new(matches.value_of("export").unwrap()),
+            matches.is_present("encrypt"),
+            &repo_guard(matches),
+            matches.is_present("v"),
+        )?;
+        info(
+            matches,
+            tr!(
+                "root-offline",
+                path = offline.exported_to.display().to_string()
+            ),
+        );
+    } else if let Some(matches) = matches
+        .subcommand_matches("ca")
+        .and_then(|matches| matches.subcommand_matches("resign-intermediate"))
+    {
+        let validity = ValidityConf {
+            intermediate_days: days(matches, "intermediate-days")?,
+            ..ValidityConf::default()
+        };
+        let summary = resign_intermediate(
+            Path::new(matches.value_of("root-key").unwrap()),
+            &validity,
+            &sources,
+            matches.is_present("v"),
+        )?;
+        print_summaries(matches, &[summary])?;
+    } else if let Some(matches) = matches
+        .subcommand_matches("ca")
+        .and_then(|matches| matches.subcommand_matches("yubikey"))
+    {
+        let validity = ValidityConf {
+            intermediate_days: days(matches, "intermediate-days")?,
+            ..ValidityConf::default()
+        };
+        let summary = intermediate_to_yubikey(
+            PivSlot::from_name(matches.value_of("slot").unwrap()).unwrap(),
+            matches.is_present("import"),
+            matches.value_of("module").map(Path::new),
+            &validity,
+            &sources,
+            matches.is_present("v"),
+        )?;
+        print_summaries(matches, &[summary])?;
+    } else if let Some(matches) = matches
+        .subcommand_matches("ca")
+        .and_then(|matches| matches.subcommand_matches("tpm"))
+    {
+        let validity = ValidityConf {
+            intermediate_days: days(matches, "intermediate-days")?,
+            ..ValidityConf::default()
+        };
+        let summary = intermediate_to_tpm(
+            parse_handle(matches.value_of("handle").unwrap())?,
+            &validity,
+            &sources,
+            matches.is_present("v"),
+        )?;
+        print_summaries(matches, &[summary])?;
+    } else if let Some(matches) = matches
+        .subcommand_matches("ca")
+        .and_then(|matches| matches.subcommand_matches("kms"))
+    {
+        let validity = ValidityConf {
+            intermediate_days: days(matches, "intermediate-days")?,
+            ..ValidityConf::default()
+        };
+        let summary = intermediate_to_kms(
+            matches.value_of("KEY").unwrap(),
+            &validity,
+            &sources,
+            matches.is_present("v"),
+        )?;
+        print_summaries(matches, &[summary])?;
+    } else if let Some(matches) = matches
+        .subcommand_matches("ca")
+        .and_then(|matches| matches.subcommand_matches("subordinate"))
+    {
+        let validity = ValidityConf {
+            intermediate_days: days(matches, "days")?,
+            ..ValidityConf::default()
+        };
+        let summary = create_subordinate(
+            matches.value_of("NAME").unwrap(),
+            matches.value_of("parent").unwrap(),
+            &validity,
+            &repo_guard(matches),
+            &sources,
+            matches.is_present("v"),
+        )?;
+        print_summaries(matches, &[summary])?;
+    } else if let Some(matches) = matches.subcommand_matches("ca") {
         let verbose = matches.is_present("v");
-        load_ca(true, verbose).unwrap();
+        let validity = ValidityConf {
+            ca_days: days(matches, "days")?,
+            intermediate_days: days(matches, "intermediate-days")?,
+            ..ValidityConf::default()
+        };
+        let mut guard = repo_guard(matches);
+        guard.replace = confirm_replace(
+            matches,
+            &[
+                CertAuthConf::ca_key()?,
+                CertAuthConf::ca_cert()?,
+                CertAuthConf::intermediate_key()?,
+                CertAuthConf::intermediate_cert()?,
+            ],
+            || {
+                Ok(tr!(
+                    "confirm-ca-reset",
+                    count = issued_under_root(sources.now())?.len()
+                ))
+            },
+        )?;
+        load_ca(true, &validity, &guard, &sources, verbose)?;
+        print_summaries(matches, &IssuanceSummary::ca()?)?;
+        warn_stale(matches)?;
     }
 
     if let Some(matches) = matches.subcommand_matches("server") {
         let verbose = matches.is_present("v");
-        let sans = matches
+        let mut sans = matches
             .values_of("subjectAltName")
             .map(|values| values.collect::<Vec<&str>>())
             .unwrap_or_else(|| Vec::with_capacity(0));
+        let local_ips: Vec<String> = if matches.is_present("auto-ip") {
+            local_ips()?.iter().map(|ip| ip.to_string()).collect()
+        } else {
+            Vec::new()
+        };
+        sans.extend(local_ips.iter().map(|ip| ip.as_str()));
 
         if let Some(common_name) = matches.value_of("COMMON_NAME") {
+            let mut domains = vec![common_name.to_string()];
+            if matches.is_present("pair") {
+                domains.push(format!("*.{}", common_name));
+            }
+            // Without --force the library refuses to replace the certificate.
+            let existing = StoreManifest::load()?;
+            if matches.is_present("force") {
+                for domain in &domains {
+                    if existing.entry(Role::ServerCert, Some(domain)).is_some() {
+                        warn(matches, tr!("replacing-server-cert", domain = domain));
+                    }
+                }
+            }
             let name = Name {
-                country: matches.value_of("country").unwrap_or("").to_string(),
-                province: matches.value_of("state").unwrap_or("").to_string(),
-                locality: matches.value_of("locality").unwrap_or("").to_string(),
-                org: matches.value_of("org").unwrap_or("").to_string(),
-                org_unit: matches.value_of("org-unit").unwrap_or("").to_string(),
-                common_name: common_name.to_string(),
+                country: matches.value_of("country").map(String::from),
+                province: matches.value_of("state").map(String::from),
+                locality: matches.value_of("locality").map(String::from),
+                org: matches.value_of("org").map(String::from),
+                org_unit: matches.value_of("org-unit").map(String::from),
+                common_name: Some(common_name.to_string()),
+            };
+            let validity = ValidityConf {
+                server_days: days(matches, "days")?,
+                server_ttl: matches.value_of("ttl").map(|ttl| ttl.to_string()),
+                not_before: matches.value_of("not-before").map(|time| time.to_string()),
+                not_after: matches.value_of("not-after").map(|time| time.to_string()),
+                ..ValidityConf::default()
+            };
+            let out_dir = matches.value_of("out-dir").map(|dir| {
+                let mut out_dir = OutDir::new(Path::new(dir));
+                let names = [
+                    ("key-name", &mut out_dir.key_name),
+                    ("cert-name", &mut out_dir.cert_name),
+                    ("fullchain-name", &mut out_dir.fullchain_name),
+                    ("chain-name", &mut out_dir.chain_name),
+                ];
+                for (arg, name) in names {
+                    if let Some(value) = matches.value_of(arg) {
+                        *name = value.to_string();
+                    }
+                }
+                out_dir
+            });
+            let extras = ServerExtras {
+                receipt: matches.is_present("receipt"),
+                precert: matches.is_present("precert"),
+                dhparam: matches.is_present("with-dhparam"),
+                ticket_key: matches.is_present("with-ticket-key"),
+                haproxy: matches.is_present("haproxy"),
+                der: matches.value_of("format") == Some("der"),
+                ..ServerExtras::default()
             };
-            generate_server_cert(&name, &sans, verbose).unwrap();
+            let guard = RepoGuard {
+                replace: matches.is_present("force"),
+                ..repo_guard(matches)
+            };
+            let issuer = matches.value_of("issuer");
+            let summaries = if matches.is_present("pair") {
+                generate_server_pair(
+                    &name,
+                    &validity,
+                    issuer,
+                    &guard,
+                    out_dir.as_ref(),
+                    &extras,
+                    &sources,
+                    verbose,
+                )
+            } else {
+                generate_server_cert(
+                    &name,
+                    &sans,
+                    &validity,
+                    issuer,
+                    &guard,
+                    out_dir.as_ref(),
+                    &extras,
+                    &sources,
+                    verbose,
+                )
+                .map(|summary| vec![summary])
+            };
+            let summaries = summaries?;
+            if matches.value_of("format") == Some("k8s-secret") {
+                let secrets = domains
+                    .iter()
+                    .map(|domain| {
+                        k8s_tls_secret(
+                            domain,
+                            matches.value_of("secret-name"),
+                            matches.value_of("namespace"),
+                        )
+                    })
+                    .collect::<Result<Vec<String>>>()?;
+                print!("{}", secrets.join("---\n"));
+            } else {
+                print_summaries(matches, &summaries)?;
+            }
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("issue") {
+        // Applying a manifest again re-issues its certificates.
+        let summaries = issue_manifest(
+            Path::new(matches.value_of("manifest").unwrap()),
+            &RepoGuard {
+                replace: true,
+                ..repo_guard(matches)
+            },
+            &sources,
+            matches.is_present("v"),
+        )?;
+        print_summaries(matches, &summaries)?;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("csr") {
+        let sans = matches
+            .values_of("subjectAltName")
+            .map(|values| values.collect::<Vec<&str>>())
+            .unwrap_or_default();
+        let validity = ValidityConf {
+            server_days: days(matches, "days")?,
+            server_ttl: matches.value_of("ttl").map(|ttl| ttl.to_string()),
+            ..ValidityConf::default()
+        };
+        let csr = fs::read(matches.value_of("csr").unwrap())?;
+        let attestation = matches.value_of("attestation").map(fs::read).transpose()?;
+        let summary = issue_csr_cert(
+            &csr,
+            &sans,
+            &validity,
+            matches.value_of("issuer"),
+            attestation.as_deref(),
+            &repo_guard(matches),
+            &sources,
+            matches.is_present("v"),
+        )?;
+        print_summaries(matches, &[summary])?;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("tpm") {
+        let domain = matches.value_of("CN").unwrap();
+        let sans = matches
+            .values_of("subjectAltName")
+            .map(|values| values.collect::<Vec<&str>>())
+            .unwrap_or_default();
+        let validity = ValidityConf {
+            server_days: days(matches, "days")?,
+            server_ttl: matches.value_of("ttl").map(|ttl| ttl.to_string()),
+            ..ValidityConf::default()
+        };
+        let summary = issue_tpm_cert(
+            domain,
+            &sans,
+            parse_handle(matches.value_of("handle").unwrap())?,
+            &validity,
+            matches.value_of("issuer"),
+            &repo_guard(matches),
+            &sources,
+            matches.is_present("v"),
+        )?;
+        print_summaries(matches, &[summary])?;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("intermediate") {
+        if let Some(matches) = matches.subcommand_matches("create") {
+            let validity = ValidityConf {
+                intermediate_days: days(matches, "days")?,
+                ..ValidityConf::default()
+            };
+            let summary = create_intermediate(
+                matches.value_of("NAME").unwrap(),
+                &validity,
+                &repo_guard(matches),
+                &sources,
+                matches.is_present("v"),
+            )?;
+            print_summaries(matches, &[summary])?;
+        } else if let Some(matches) = matches.subcommand_matches("list") {
+            let manifest = StoreManifest::load()?;
+            let names = manifest.intermediate_names();
+            if json_output(matches) {
+                print_json(&names)?;
+            } else {
+                for name in names {
+                    println!("{}", name);
+                }
+            }
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("cross-sign") {
+        let summary = cross_sign(
+            Path::new(matches.value_of("root-cert").unwrap()),
+            Path::new(matches.value_of("root-key").unwrap()),
+            days(matches, "days")?,
+            &sources,
+            matches.is_present("v"),
+        )?;
+        print_summaries(matches, &[summary])?;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("device") {
+        let validity = ValidityConf {
+            server_days: days(matches, "days")?,
+            server_ttl: matches.value_of("ttl").map(|ttl| ttl.to_string()),
+            not_before: matches.value_of("not-before").map(|time| time.to_string()),
+            not_after: matches.value_of("not-after").map(|time| time.to_string()),
+            ..ValidityConf::default()
+        };
+        let devices = issue_devices(
+            Path::new(matches.value_of("CSV").unwrap()),
+            Path::new(matches.value_of("out-dir").unwrap()),
+            matches.value_of("oid"),
+            &validity,
+            &repo_guard(matches),
+            &sources,
+            matches.is_present("v"),
+        )?;
+        if json_output(matches) {
+            print_json(&devices)?;
+        } else {
+            info(matches, tr!("issued-devices", count = devices.len()));
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("email") {
+        let validity = ValidityConf {
+            server_days: days(matches, "days")?,
+            server_ttl: matches.value_of("ttl").map(|ttl| ttl.to_string()),
+            ..ValidityConf::default()
+        };
+        let summary = issue_email_cert(
+            matches.value_of("ADDRESS").unwrap(),
+            matches.value_of("name"),
+            Path::new(matches.value_of("out-dir").unwrap()),
+            matches
+                .value_of("store-pass")
+                .unwrap_or(DEFAULT_STORE_PASSWORD),
+            &validity,
+            matches.value_of("issuer"),
+            &repo_guard(matches),
+            &sources,
+            matches.is_present("v"),
+        )?;
+        print_summaries(matches, &[summary])?;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("signer") {
+        let validity = ValidityConf {
+            server_days: days(matches, "days")?,
+            server_ttl: matches.value_of("ttl").map(|ttl| ttl.to_string()),
+            ..ValidityConf::default()
+        };
+        let summary = issue_signer_cert(
+            SignerProfile::from_name(matches.value_of("PROFILE").unwrap()).unwrap(),
+            &validity,
+            matches.value_of("issuer"),
+            &repo_guard(matches),
+            &sources,
+            matches.is_present("v"),
+        )?;
+        print_summaries(matches, &[summary])?;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("verify-receipt") {
+        let receipt = read_receipt(Path::new(matches.value_of("FILE").unwrap()))?;
+        print_json(&receipt)?;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("list") {
+        let certs = match matches.value_of("unused") {
+            Some(age) => unused_certs(parse_ttl(age)?, matches.is_present("touched-only")),
+            None => issued_certs(),
+        };
+        if json_output(matches) {
+            print_json(&certs?)?;
+        } else {
+            print_issued(&certs?)?;
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("touch") {
+        for domain in matches.values_of("DOMAIN").unwrap() {
+            mark_used(domain)?;
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("prewarm") {
+        let algorithms = match matches.values_of("algorithm") {
+            Some(names) => names.filter_map(KeyAlgorithm::from_name).collect(),
+            None => Conf::load()?.keys().algorithms(),
+        };
+        let keys = prewarm(&algorithms, count(matches, "count")?)?;
+        print_written(matches, "saved-pool-key", &keys)?;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("gen-man") {
+        let dir = Path::new(matches.value_of("DIR").unwrap());
+        let pages = man::write_man_pages(app, dir, VERSION)?;
+        print_written(matches, "saved-man-page", &pages)?;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("trust") {
+        let changes = trust_root(matches.is_present("system"))?;
+        if json_output(matches) {
+            print_json(&changes)?;
+        } else {
+            for change in changes {
+                let key = if change.changed {
+                    "trusted-root"
+                } else {
+                    "root-already-trusted"
+                };
+                info(matches, tr!(key, store = change.store.to_string()));
+            }
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("untrust") {
+        let changes = untrust_root(matches.is_present("system"))?;
+        if json_output(matches) {
+            print_json(&changes)?;
+        } else {
+            for change in changes {
+                let key = if change.changed {
+                    "untrusted-root"
+                } else {
+                    "root-not-trusted"
+                };
+                info(matches, tr!(key, store = change.store.to_string()));
+            }
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("export") {
+        let domain = matches.value_of("DOMAIN");
+        if let Some(matches) = matches.subcommand_matches("android") {
+            let dest = Path::new(matches.value_of("DIR").unwrap());
+            let written = export_android(dest, matches.is_present("force"))?;
+            print_written(matches, "saved-android", &written)?;
+            if !json_output(matches) {
+                info(matches, tr!("android-manifest-hint"));
+            }
+        } else if let Some(matches) = matches.subcommand_matches("mobileconfig") {
+            let dest = Path::new(matches.value_of("FILE").unwrap());
+            export_mobileconfig(dest)?;
+            print_written(matches, "saved-mobileconfig", &[dest.to_path_buf()])?;
+            if !json_output(matches) {
+                info(matches, tr!("mobileconfig-trust-hint"));
+            }
+        } else if matches.is_present("k8s-secret") {
+            let secret = k8s_tls_secret(
+                domain.unwrap(),
+                matches.value_of("secret-name"),
+                matches.value_of("namespace"),
+            )?;
+            match matches.value_of("out") {
+                Some(out) => save_private_file(secret.as_bytes(), Path::new(out))?,
+                None => print!("{}", secret),
+            }
+        } else if matches.is_present("haproxy") {
+            let domain = domain.unwrap();
+            let default_out = format!("{}.haproxy.pem", domain.replace('*', "_"));
+            let out = Path::new(matches.value_of("out").unwrap_or(&default_out));
+            export_haproxy(domain, out, &repo_guard(matches))?;
+            print_written(matches, "saved-haproxy", &[out.to_path_buf()])?;
+        } else if let Some(format) = matches
+            .value_of("format")
+            .and_then(JavaStoreFormat::from_name)
+        {
+            let default_out = format!("truststore.{}", format.extension());
+            let out = Path::new(matches.value_of("out").unwrap_or(&default_out));
+            let password = matches
+                .value_of("store-pass")
+                .unwrap_or(DEFAULT_STORE_PASSWORD);
+            let written = export_java_stores(domain, format, out, password, &repo_guard(matches))?;
+            print_written(matches, "saved-java-store", &written)?;
+        } else if matches.value_of("format") == Some("pfx") {
+            let domain = domain.unwrap();
+            let default_out = format!("{}.pfx", domain.replace('*', "_"));
+            let out = Path::new(matches.value_of("out").unwrap_or(&default_out));
+            let password = matches
+                .value_of("store-pass")
+                .unwrap_or(DEFAULT_STORE_PASSWORD);
+            export_pfx(domain, out, password, &repo_guard(matches))?;
+            print_written(matches, "saved-pfx", &[out.to_path_buf()])?;
+        } else if matches.is_present("format") {
+            let default_out = format!("{}.crt", domain.unwrap_or("ca").replace('*', "_"));
+            let out = Path::new(matches.value_of("out").unwrap_or(&default_out));
+            let written = export_der(domain, out, &repo_guard(matches))?;
+            print_written(matches, "saved-der", &written)?;
+        } else {
+            let default_out = format!("{}.p7b", domain.unwrap_or("ca-chain"));
+            let out = Path::new(matches.value_of("out").unwrap_or(&default_out));
+            export_p7b(domain, out)?;
+            print_written(matches, "saved-bundle", &[out.to_path_buf()])?;
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("export-all") {
+        let layout = ExportLayout::from_name(matches.value_of("layout").unwrap()).unwrap();
+        let dest = Path::new(matches.value_of("dest").unwrap());
+        let domains = export_all(layout, dest, &repo_guard(matches), matches.is_present("v"))?;
+        if json_output(matches) {
+            print_json(&json!({ "dest": dest, "domains": domains }))?;
+        } else {
+            info(
+                matches,
+                tr!(
+                    "exported-all",
+                    count = domains.len(),
+                    path = format!("{:?}", dest)
+                ),
+            );
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("compose") {
+        let compose_file = match matches.value_of("FILE") {
+            Some(file) => Some(PathBuf::from(file)),
+            None => find_compose_file(Path::new(".")),
+        };
+        let compose_file = match compose_file {
+            Some(compose_file) => compose_file,
+            None => {
+                eprintln!("{}", tr!("no-compose-file"));
+                return Ok(EXIT_FAILURE);
+            }
+        };
+        let validity = ValidityConf {
+            server_days: days(matches, "days")?,
+            server_ttl: matches.value_of("ttl").map(|ttl| ttl.to_string()),
+            ..ValidityConf::default()
+        };
+        let dest = match matches.value_of("dest") {
+            Some(dest) => PathBuf::from(dest),
+            None => compose_file.with_file_name("certs"),
+        };
+        let summaries = issue_compose_certs(
+            &compose_file,
+            &dest,
+            &validity,
+            matches.value_of("issuer"),
+            // Like a manifest, the compose file is issued again on every run.
+            &RepoGuard {
+                replace: true,
+                ..repo_guard(matches)
+            },
+            &sources,
+            matches.is_present("v"),
+        )?;
+        print_summaries(matches, &summaries)?;
+        if !json_output(matches) {
+            info(
+                matches,
+                tr!(
+                    "compose-issued",
+                    count = summaries.len(),
+                    path = format!("{:?}", dest)
+                ),
+            );
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("inspect") {
+        let certs = inspect(matches.value_of("TARGET").unwrap())?;
+        if json_output(matches) {
+            print_json(&certs)?;
+        } else {
+            let certs: Vec<String> = certs.iter().map(|cert| cert.to_string()).collect();
+            println!("{}", certs.join("\n\n"));
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("renew") {
+        let new_key = matches.is_present("new-key");
+        let guard = repo_guard(matches);
+        let verbose = matches.is_present("v");
+        if let Some(days) = days(matches, "expiring")? {
+            for domain in renew_expiring(days, &sources, verbose)? {
+                warn(matches, tr!("auto-renewed", domain = domain));
+            }
+        } else {
+            let renewed = match matches.value_of("DOMAIN") {
+                Some(domain) => renew_server_cert(domain, new_key, &guard, &sources, verbose)
+                    .map(|summary| vec![summary]),
+                _ => renew_all(new_key, &guard, &sources, verbose),
+            };
+            print_summaries(matches, &renewed?)?;
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("repair") {
+        let repairs = repair(
+            matches.is_present("reissue"),
+            &repo_guard(matches),
+            &sources,
+            matches.is_present("v"),
+        )?;
+        if json_output(matches) {
+            print_json(&repairs)?;
+        } else {
+            if repairs.is_empty() {
+                info(matches, tr!("repair-nothing"));
+            }
+            let repairs: Vec<String> = repairs.iter().map(|repair| repair.to_string()).collect();
+            info(matches, repairs.join("\n\n"));
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("scan-repo") {
+        let root = Path::new(matches.value_of("PATH").unwrap());
+        let leaked = scan_repo(root)?;
+        for key in &leaked {
+            warn(
+                matches,
+                tr!(
+                    "leaked-key",
+                    path = format!("{:?}", key.path),
+                    issued_as = format!("{:?}", key.issued_as)
+                ),
+            );
+        }
+        if !leaked.is_empty() {
+            return Ok(EXIT_INVALID);
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("ssh-snippets") {
+        let ca_key_path = match matches.value_of("ca-key") {
+            Some(path) => PathBuf::from(path),
+            None => ssh_ca_public_key()?,
+        };
+        let ca_key = read_ssh_public_key(&ca_key_path)?;
+        let hosts: Vec<&str> = matches.value_of("hosts").unwrap().split(',').collect();
+        println!("# ~/.ssh/known_hosts");
+        println!("{}", known_hosts_line(&ca_key, &hosts));
+        println!();
+        println!(
+            "# /etc/ssh/sshd_config, after copying {:?} to {}",
+            ca_key_path, TRUSTED_USER_CA_KEYS
+        );
+        println!("{}", sshd_config_snippet());
+    }
+
+    if let Some(matches) = matches.subcommand_matches("ssh-ca") {
+        println!(
+            "{}",
+            ssh_ca(
+                matches.is_present("force"),
+                &repo_guard(matches),
+                matches.is_present("v")
+            )?
+        );
+    }
+
+    if let Some(matches) = matches
+        .subcommand_matches("ssh")
+        .and_then(|matches| matches.subcommand_matches("sign"))
+    {
+        let cert_type = if matches.is_present("host") {
+            SshCertType::Host
+        } else {
+            SshCertType::User
+        };
+        let principals: Vec<&str> = matches
+            .value_of("principals")
+            .unwrap()
+            .split(',')
+            .map(str::trim)
+            .filter(|principal| !principal.is_empty())
+            .collect();
+        let ttl = matches.value_of("ttl").map(parse_ttl).transpose()?;
+        let cert = sign_ssh_key(
+            Path::new(matches.value_of("PUBKEY").unwrap()),
+            cert_type,
+            matches.value_of("id"),
+            &principals,
+            ttl,
+            matches.value_of("out").map(Path::new),
+            &sources,
+        )?;
+        if json_output(matches) {
+            print_json(&json!({ "cert": cert }))?;
+        } else {
+            info(matches, tr!("ssh-signed", path = format!("{:?}", cert)));
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("verify") {
+        let purpose = Purpose::from_name(matches.value_of("purpose").unwrap()).unwrap();
+        let verification = verify_cert(
+            matches.value_of("TARGET").unwrap(),
+            purpose,
+            matches.value_of("host"),
+        )?;
+        if json_output(matches) {
+            print_json(&verification)?;
+        } else {
+            info(matches, verification.to_string());
+            if let Some(issuer) = &verification.archived_issuer {
+                warn(matches, tr!("verify-repair-hint", issuer = issuer.as_str()));
+            }
+        }
+        if !verification.valid {
+            return Ok(EXIT_INVALID);
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("match") {
+        let key_match = match_key(
+            Path::new(matches.value_of("KEY").unwrap()),
+            matches.value_of("CERT").unwrap(),
+        )?;
+        if json_output(matches) {
+            print_json(&key_match)?;
+        } else {
+            info(matches, key_match.to_string());
+        }
+        if !key_match.matches {
+            return Ok(EXIT_INVALID);
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("config") {
+        if let Some(matches) = matches.subcommand_matches("sign") {
+            let key = Path::new(matches.value_of("key").unwrap());
+            sign_config(key, matches.is_present("v"))?;
+        }
+        if let Some(matches) = matches.subcommand_matches("trust") {
+            let fingerprint = trust_config_signer(
+                Path::new(matches.value_of("key").unwrap()),
+                Path::new(matches.value_of("root-key").unwrap()),
+                matches.is_present("v"),
+            )?;
+            info(
+                matches,
+                tr!("config-signer-trusted", fingerprint = fingerprint),
+            );
+        }
+        if matches.subcommand_matches("verify").is_some() {
+            match config_signer()? {
+                Some(signer) => info(matches, tr!("config-signed", signer = signer)),
+                None => info(matches, tr!("config-unsigned")),
+            }
+        }
+        if matches.subcommand_matches("trust-hooks").is_some() {
+            match trust_hooks()? {
+                Some(command) => info(matches, tr!("hooks-trusted", command = command)),
+                None => info(matches, tr!("hooks-none")),
+            }
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("backup") {
+        let dest = Path::new(matches.value_of("FILE").unwrap());
+        let count = backup_store(dest, matches.is_present("encrypt"), &repo_guard(matches))?;
+        if json_output(matches) {
+            print_json(&json!({ "path": dest, "archived": count }))?;
+        } else {
+            info(
+                matches,
+                tr!(
+                    "backup-written",
+                    count = count,
+                    path = format!("{:?}", dest)
+                ),
+            );
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("restore") {
+        let src = Path::new(matches.value_of("FILE").unwrap());
+        let restored = restore_store(src, matches.is_present("force"))?;
+        if json_output(matches) {
+            print_json(&json!({ "files": restored }))?;
+        } else {
+            info(matches, tr!("backup-restored", count = restored.len()));
+        }
+    }
+
+    if let Some(matches) = matches
+        .subcommand_matches("vault")
+        .and_then(|matches| matches.subcommand_matches("push"))
+    {
+        let path = matches.value_of("PATH").unwrap();
+        let mount = if matches.is_present("pki") {
+            VaultMount::Pki
+        } else {
+            VaultMount::Kv
+        };
+        let count = vault_push(path, mount, matches.is_present("encrypt"))?;
+        if json_output(matches) {
+            print_json(&json!({ "path": path, "pushed": count }))?;
+        } else {
+            info(matches, tr!("vault-pushed", count = count, path = path));
+        }
+    }
+
+    if let Some(matches) = matches
+        .subcommand_matches("vault")
+        .and_then(|matches| matches.subcommand_matches("pull"))
+    {
+        let path = matches.value_of("PATH").unwrap();
+        let restored = vault_pull(path, matches.is_present("force"))?;
+        if json_output(matches) {
+            print_json(&json!({ "files": restored }))?;
+        } else {
+            info(
+                matches,
+                tr!("vault-pulled", count = restored.len(), path = path),
+            );
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("store") {
+        if let Some(matches) = matches.subcommand_matches("export") {
+            let dest = Path::new(matches.value_of("DIR").unwrap());
+            export_store(dest, &repo_guard(matches))?;
+        }
+        if let Some(matches) = matches.subcommand_matches("import") {
+            let src = Path::new(matches.value_of("DIR").unwrap());
+            import_store(src, matches.is_present("force"))?;
+        }
+        if let Some(matches) = matches.subcommand_matches("status") {
+            let status = store_status()?;
+            if json_output(matches) {
+                print_json(&status)?;
+            } else {
+                println!("{}", status);
+            }
+            for path in &status.exposed_keys {
+                warn(
+                    matches,
+                    tr!("exposed-key", path = path.display().to_string()),
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "serve")]
+    if let Some(matches) = matches.subcommand_matches("serve") {
+        run_serve(matches)?;
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn days_of(args: &[&str]) -> Result<Option<u32>> {
+        let app = App::new("test").arg(Arg::from_usage("--days [DAYS] 'days'"));
+        days(&app.get_matches_from(args), "days")
+    }
+
+    #[test]
+    fn test_days() {
+        assert_eq!(days_of(&["test"]).unwrap(), None);
+        assert_eq!(days_of(&["test", "--days", "30"]).unwrap(), Some(30));
+        for value in ["abc", "0", "-1", "1.5"] {
+            let arg = format!("--days={}", value);
+            let err = days_of(&["test", &arg]).unwrap_err();
+            assert_eq!(err.exit_code(), EXIT_USAGE);
+        }
+    }
+
+    #[test]
+    fn test_count() {
+        let count_of = |value: &str| {
+            let app = App::new("test").arg(Arg::from_usage("--count=[COUNT] 'count'"));
+            let arg = format!("--count={}", value);
+            count(&app.get_matches_from(["test", &arg]), "count")
+        };
+        assert_eq!(count_of("8").unwrap(), 8);
+        for value in ["abc", "0", "-1", "1.5"] {
+            let err = count_of(value).unwrap_err();
+            assert_eq!(err.exit_code(), EXIT_USAGE);
         }
     }
 }
@@ -0,0 +1,196 @@
+//! Man pages of the CLI in roff, a page per command like `git`, for
+//! distribution packages. Rendered from the clap definition so they never
+//! drift from `--help`.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::{App, Arg};
+
+/// Escapes text for roff: backslashes, and control characters at the start
+/// of a line.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\e")
+        .lines()
+        .map(|line| {
+            if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{}", line)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn bold(text: &str) -> String {
+    format!("\\fB{}\\fR", escape(text).replace('-', "\\-"))
+}
+
+fn italic(text: &str) -> String {
+    format!("\\fI{}\\fR", escape(text))
+}
+
+/// The flags and value names of an option, e.g. `-q, --quiet` or
+/// `--days <DAYS>`.
+fn option_label(arg: &Arg) -> String {
+    let mut flags = Vec::new();
+    if let Some(short) = arg.get_short() {
+        flags.push(bold(&format!("-{}", short)));
+    }
+    if let Some(long) = arg.get_long() {
+        flags.push(bold(&format!("--{}", long)));
+    }
+    let mut label = flags.join(", ");
+    if arg.is_takes_value_set() {
+        let names = match arg.get_value_names() {
+            Some(names) => names.to_vec(),
+            None => vec![arg.get_id()],
+        };
+        for name in names {
+            label.push_str(&format!(" <{}>", italic(name)));
+        }
+    }
+    label
+}
+
+fn arg_help(arg: &Arg) -> String {
+    let mut help = escape(arg.get_long_help().or_else(|| arg.get_help()).unwrap_or(""));
+    if let Some(values) = arg.get_possible_values() {
+        let values: Vec<&str> = values
+            .iter()
+            .filter(|value| !value.is_hide_set())
+            .map(|value| value.get_name())
+            .collect();
+        help.push_str(&format!(" [possible values: {}]", values.join(", ")));
+    }
+    let defaults: Vec<String> = arg
+        .get_default_values()
+        .iter()
+        .map(|value| value.to_string_lossy().to_string())
+        .collect();
+    if !defaults.is_empty() {
+        help.push_str(&format!(" [default: {}]", escape(&defaults.join(", "))));
+    }
+    help
+}
+
+fn synopsis(app: &App, command: &str) -> String {
+    let mut synopsis = bold(command);
+    if app
+        .get_arguments()
+        .any(|arg| !arg.is_hide_set() && !arg.is_positional())
+    {
+        synopsis.push_str(" [OPTIONS]");
+    }
+    for arg in app.get_positionals().filter(|arg| !arg.is_hide_set()) {
+        let name = italic(arg.get_id());
+        let dots = if arg.is_multiple_values_set() || arg.is_multiple_occurrences_set() {
+            "..."
+        } else {
+            ""
+        };
+        if arg.is_required_set() {
+            synopsis.push_str(&format!(" <{}>{}", name, dots));
+        } else {
+            synopsis.push_str(&format!(" [{}]{}", name, dots));
+        }
+    }
+    if app.get_subcommands().any(visible) {
+        synopsis.push_str(" <SUBCOMMAND>");
+    }
+    synopsis
+}
+
+/// Whether `sub` gets a page, which the `help` subcommand clap adds does not.
+fn visible(sub: &App) -> bool {
+    !sub.is_hide_set() && sub.get_name() != "help"
+}
+
+/// Renders the page of `app`, invoked as `command`, e.g. `simple-ca ca import`.
+pub fn render(app: &App, command: &str, version: &str) -> String {
+    let page = command.replace(' ', "-");
+    let mut out = format!(
+        ".TH {} 1 \"\" \"simple-ca {}\"\n",
+        page.to_uppercase(),
+        escape(version)
+    );
+    out.push_str(&format!(
+        ".SH NAME\n{} \\- {}\n",
+        page,
+        escape(app.get_about().unwrap_or(""))
+    ));
+    out.push_str(&format!(".SH SYNOPSIS\n{}\n", synopsis(app, command)));
+    if let Some(about) = app.get_long_about() {
+        out.push_str(&format!(".SH DESCRIPTION\n{}\n", escape(about)));
+    }
+
+    let positionals: Vec<&Arg> = app
+        .get_positionals()
+        .filter(|arg| !arg.is_hide_set())
+        .collect();
+    if !positionals.is_empty() {
+        out.push_str(".SH ARGUMENTS\n");
+        for arg in positionals {
+            out.push_str(&format!(
+                ".TP\n{}\n{}\n",
+                italic(arg.get_id()),
+                arg_help(arg)
+            ));
+        }
+    }
+    // Global options are listed once, on the page of `simple-ca`.
+    let top = !command.contains(' ');
+    let options: Vec<&Arg> = app
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set() && !arg.is_positional())
+        .filter(|arg| top || !arg.is_global_set())
+        .collect();
+    if !options.is_empty() {
+        out.push_str(".SH OPTIONS\n");
+        for arg in options {
+            out.push_str(&format!(".TP\n{}\n{}\n", option_label(arg), arg_help(arg)));
+        }
+    }
+    let subcommands: Vec<&App> = app.get_subcommands().filter(|sub| visible(sub)).collect();
+    if !subcommands.is_empty() {
+        out.push_str(".SH SUBCOMMANDS\n");
+        for sub in subcommands {
+            out.push_str(&format!(
+                ".TP\n{}(1)\n{}\n",
+                bold(&format!("{}-{}", page, sub.get_name())),
+                escape(sub.get_about().unwrap_or(""))
+            ));
+        }
+    }
+    if let Some(examples) = app.get_after_help() {
+        let examples = examples.trim_start_matches("EXAMPLES:").trim_matches('\n');
+        out.push_str(&format!(".SH EXAMPLES\n.nf\n{}\n.fi\n", escape(examples)));
+    }
+    if !top {
+        out.push_str(&format!(".SH SEE ALSO\n{}(1)\n", bold("simple-ca")));
+    }
+    out
+}
+
+/// Writes the page of `app` and of each of its visible subcommands, recursively,
+/// into `dir` as `simple-ca.1`, `simple-ca-server.1`, `simple-ca-ca-import.1`
+/// and so on. Returns the written pages.
+pub fn write_man_pages(mut app: App, dir: &Path, version: &str) -> io::Result<Vec<PathBuf>> {
+    // Adds the help and version flags clap otherwise only adds when parsing.
+    app.build();
+    fs::create_dir_all(dir)?;
+    let mut written = Vec::new();
+    let mut pending = vec![("simple-ca".to_string(), &app)];
+    while let Some((command, app)) = pending.pop() {
+        let path = dir.join(format!("{}.1", command.replace(' ', "-")));
+        fs::write(&path, render(app, &command, version))?;
+        written.push(path);
+        for sub in app.get_subcommands().filter(|sub| visible(sub)) {
+            pending.push((format!("{} {}", command, sub.get_name()), sub));
+        }
+    }
+    written.sort();
+    Ok(written)
+}
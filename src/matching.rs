@@ -0,0 +1,102 @@
+//! Checks whether a private key belongs to a certificate, for when key and
+//! certificate files got shuffled between directories.
+
+use std::fmt;
+use std::path::Path;
+
+use serde::Serialize;
+
+use openssl::pkey::{HasPublic, PKeyRef};
+use openssl::x509::X509Ref;
+
+use crate::certs::pubkey_fingerprint;
+use crate::err::{Result, SimpleCAError};
+use crate::inspect::{describe_key, format_name, load_certs};
+use crate::passphrase::read_private_key;
+
+/// Outcome of comparing a private key with a certificate.
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyMatch {
+    pub matches: bool,
+    /// Algorithm and size of the private key, like `RSA 2048`.
+    pub key: String,
+    /// SHA-256 fingerprint of the public half of the private key.
+    pub key_fingerprint: String,
+    pub subject: String,
+    /// Algorithm and size of the certificate's public key.
+    pub cert_key: String,
+    /// SHA-256 fingerprint of the certificate's public key.
+    pub cert_key_fingerprint: String,
+}
+
+impl fmt::Display for KeyMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.matches {
+            write!(f, "OK: {} key matches {}", self.key, self.subject)
+        } else {
+            write!(
+                f,
+                "MISMATCH: {} key {} does not match {}, whose {} key is {}",
+                self.key,
+                self.key_fingerprint,
+                self.subject,
+                self.cert_key,
+                self.cert_key_fingerprint
+            )
+        }
+    }
+}
+
+/// Whether `cert` certifies the public half of `pkey`.
+pub fn key_matches_cert<T: HasPublic>(pkey: &PKeyRef<T>, cert: &X509Ref) -> Result<bool> {
+    Ok(cert.public_key()?.public_eq(pkey))
+}
+
+impl KeyMatch {
+    pub fn new<T: HasPublic>(pkey: &PKeyRef<T>, cert: &X509Ref) -> Result<KeyMatch> {
+        let cert_pkey = cert.public_key()?;
+        Ok(KeyMatch {
+            matches: key_matches_cert(pkey, cert)?,
+            key: describe_key(pkey),
+            key_fingerprint: pubkey_fingerprint(pkey)?,
+            subject: format_name(cert.subject_name())?,
+            cert_key: describe_key(&cert_pkey),
+            cert_key_fingerprint: pubkey_fingerprint(&cert_pkey)?,
+        })
+    }
+}
+
+/// Compares the private key at `key` with the first certificate at `cert`,
+/// a certificate file or the common name of a stored server certificate.
+pub fn match_key(key: &Path, cert: &str) -> Result<KeyMatch> {
+    let pkey = read_private_key(key)?;
+    let cert = match load_certs(cert)?.into_iter().next() {
+        Some(cert) => cert,
+        None => Err(SimpleCAError::GenericError {
+            msg: "No certificate found",
+        })?,
+    };
+    KeyMatch::new(&pkey, &cert)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::certs::fixtures::test_root_ca;
+    use crate::keys::KeyAlgorithm;
+
+    #[test]
+    fn test_key_match() {
+        let (_, key, cert) = test_root_ca();
+        let other = KeyAlgorithm::EcdsaP256.generate().unwrap();
+
+        let matched = KeyMatch::new(&key, &cert).unwrap();
+        assert!(matched.matches);
+        assert_eq!(matched.key_fingerprint, matched.cert_key_fingerprint);
+
+        let mismatched = KeyMatch::new(&other, &cert).unwrap();
+        assert!(!mismatched.matches);
+        assert!(mismatched.to_string().starts_with("MISMATCH: EC 256 key"));
+    }
+}
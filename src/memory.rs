@@ -0,0 +1,179 @@
+//! Throwaway CAs held in memory only, for test suites that want a fresh CA
+//! per test without touching the store, `target/` or `$HOME`.
+
+use std::time::Duration;
+
+use openssl::pkey::{PKey, Private};
+use openssl::x509::X509;
+
+use crate::cert_params::{CertParams, DEFAULT_BACKDATE};
+use crate::certs::{create_intermediate_ca, create_root_ca, create_server_cert};
+use crate::err::Result;
+use crate::keys::KeyAlgorithm;
+use crate::name::Name;
+
+/// Lifetimes in days, each shorter than its issuer's so issuance never fails
+/// on `OutlivesIssuer`.
+const ROOT_DAYS: u32 = 3;
+const INTERMEDIATE_DAYS: u32 = 2;
+const SERVER_DAYS: u32 = 1;
+/// Fast to generate, which matters when every test makes its own CA.
+const ALGORITHM: KeyAlgorithm = KeyAlgorithm::EcdsaP256;
+
+/// A root and an intermediate that exist only as long as the value.
+pub struct Ca {
+    root: X509,
+    intermediate: X509,
+    intermediate_key: PKey<Private>,
+    backdate: Duration,
+}
+
+/// A certificate issued in memory, by a `Ca` or a `CaContext`, with its key
+/// and chain.
+pub struct EphemeralCert {
+    pub cert: X509,
+    pub key: PKey<Private>,
+    /// The issuer up to the root.
+    pub chain: Vec<X509>,
+}
+
+impl Ca {
+    /// Creates a root and an intermediate with fresh keys, valid for a few
+    /// days.
+    pub fn ephemeral() -> Result<Ca> {
+        let root_name = Name::new("Simple CA Ephemeral Root").to_x509_name()?;
+        let root_key = ALGORITHM.generate()?;
+        let root_params = CertParams::builder(root_name.to_owned()?, root_key.clone())
+            .valid_days(ROOT_DAYS)
+            .self_signed()?
+            .build()?;
+        let root = create_root_ca(&root_params)?;
+
+        let name = Name::new("Simple CA Ephemeral Intermediate").to_x509_name()?;
+        let intermediate_key = ALGORITHM.generate()?;
+        let params = CertParams::builder(name, intermediate_key.clone())
+            .issuer(root_name, root_key)
+            .valid_days(INTERMEDIATE_DAYS)
+            .path_len(0)
+            .build()?;
+        let intermediate = create_intermediate_ca(&params, &root)?;
+        Ok(Ca {
+            root,
+            intermediate,
+            intermediate_key,
+            backdate: DEFAULT_BACKDATE,
+        })
+    }
+
+    /// How far before issuance server certificates start, an hour unless
+    /// set, as with `backdate` in the config.
+    pub fn with_backdate(mut self, backdate: Duration) -> Ca {
+        self.backdate = backdate;
+        self
+    }
+
+    /// The root, for the trust store of the client under test.
+    pub fn root(&self) -> &X509 {
+        &self.root
+    }
+
+    pub fn root_pem(&self) -> Result<Vec<u8>> {
+        Ok(self.root.to_pem()?)
+    }
+
+    pub fn intermediate(&self) -> &X509 {
+        &self.intermediate
+    }
+
+    /// Issues a server certificate for `name`, which is also its first SAN,
+    /// followed by `sans`.
+    pub fn issue_server(&self, name: &str, sans: &[&str]) -> Result<EphemeralCert> {
+        let key = ALGORITHM.generate()?;
+        let mut alt_names = vec![name];
+        alt_names.extend(sans.iter().filter(|san| **san != name));
+        let params = CertParams::builder(Name::new(name).to_x509_name()?, key.clone())
+            .issuer(
+                self.intermediate.subject_name().to_owned()?,
+                self.intermediate_key.clone(),
+            )
+            .valid_days(SERVER_DAYS)
+            .backdate(self.backdate)
+            .sub_alt_names(alt_names)
+            .build()?;
+        let cert = create_server_cert(&params, &self.intermediate)?;
+        Ok(EphemeralCert {
+            cert,
+            key,
+            chain: vec![self.intermediate.clone(), self.root.clone()],
+        })
+    }
+}
+
+impl EphemeralCert {
+    pub fn cert_pem(&self) -> Result<Vec<u8>> {
+        Ok(self.cert.to_pem()?)
+    }
+
+    pub fn cert_der(&self) -> Result<Vec<u8>> {
+        Ok(self.cert.to_der()?)
+    }
+
+    /// The key as unencrypted PKCS#8.
+    pub fn key_pem(&self) -> Result<Vec<u8>> {
+        Ok(self.key.private_key_to_pem_pkcs8()?)
+    }
+
+    pub fn key_der(&self) -> Result<Vec<u8>> {
+        Ok(self.key.private_key_to_pkcs8()?)
+    }
+
+    /// The certificate followed by the intermediate, what a server sends.
+    pub fn fullchain_pem(&self) -> Result<Vec<u8>> {
+        let mut pem = self.cert.to_pem()?;
+        pem.extend(self.chain[0].to_pem()?);
+        Ok(pem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::stack::Stack;
+    use openssl::x509::store::X509StoreBuilder;
+    use openssl::x509::X509StoreContext;
+
+    #[test]
+    fn test_issue_server() {
+        let ca = Ca::ephemeral().unwrap();
+        let issued = ca.issue_server("app.test", &["127.0.0.1"]).unwrap();
+
+        let mut store = X509StoreBuilder::new().unwrap();
+        store.add_cert(ca.root().clone()).unwrap();
+        let store = store.build();
+        let mut chain = Stack::new().unwrap();
+        chain.push(ca.intermediate().clone()).unwrap();
+        let mut ctx = X509StoreContext::new().unwrap();
+        assert!(ctx
+            .init(&store, &issued.cert, &chain, |ctx| ctx.verify_cert())
+            .unwrap());
+
+        let text = String::from_utf8(issued.cert.to_text().unwrap()).unwrap();
+        assert!(text.contains("DNS:app.test, IP Address:127.0.0.1"));
+        assert!(PKey::private_key_from_pem(&issued.key_pem().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_backdate() {
+        let ca = Ca::ephemeral().unwrap().with_backdate(Duration::ZERO);
+        let issued = ca.issue_server("app.test", &[]).unwrap();
+        let now = openssl::asn1::Asn1Time::days_from_now(0).unwrap();
+        let skew = issued.cert.not_before().diff(&now).unwrap();
+        assert_eq!(skew.days, 0);
+        assert!(skew.secs < 60);
+
+        let ca = ca.with_backdate(Duration::from_secs(2 * 60 * 60));
+        let issued = ca.issue_server("app.test", &[]).unwrap();
+        let skew = issued.cert.not_before().diff(&now).unwrap();
+        assert!(skew.secs >= 2 * 60 * 60 - 60);
+    }
+}
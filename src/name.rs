@@ -3,58 +3,192 @@ extern crate openssl;
 use openssl::error::ErrorStack;
 use openssl::nid::Nid;
 use openssl::x509::{X509Name, X509NameBuilder};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
-pub struct Name {
-    pub country: String,
-    pub province: String,
-    pub locality: String,
-    pub org: String,
-    pub org_unit: String,
-    pub common_name: String,
+/// An attribute of a distinguished name, as used in `[name] order`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum NameField {
+    C,
+    ST,
+    L,
+    O,
+    OU,
+    CN,
 }
 
-macro_rules! append {
-    ($b:expr, $n:expr, $v:expr) => {{
-        if !$v.is_empty() {
-            $b.append_entry_by_nid($n, $v)?;
+/// Order of the RDNs emitted by `Name::to_x509_name`, most significant first.
+pub const DEFAULT_NAME_ORDER: [NameField; 6] = [
+    NameField::C,
+    NameField::ST,
+    NameField::L,
+    NameField::O,
+    NameField::OU,
+    NameField::CN,
+];
+
+impl NameField {
+    fn nid(&self) -> Nid {
+        match self {
+            NameField::C => Nid::COUNTRYNAME,
+            NameField::ST => Nid::STATEORPROVINCENAME,
+            NameField::L => Nid::LOCALITYNAME,
+            NameField::O => Nid::ORGANIZATIONNAME,
+            NameField::OU => Nid::ORGANIZATIONALUNITNAME,
+            NameField::CN => Nid::COMMONNAME,
         }
-    }};
+    }
+}
+
+/// A distinguished name; fields left `None` or empty are not written.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Name {
+    pub country: Option<String>,
+    pub province: Option<String>,
+    pub locality: Option<String>,
+    pub org: Option<String>,
+    pub org_unit: Option<String>,
+    pub common_name: Option<String>,
 }
 
 impl Name {
+    /// A name of only a common name.
+    pub fn new(common_name: impl Into<String>) -> Name {
+        Name {
+            common_name: Some(common_name.into()),
+            ..Name::default()
+        }
+    }
+
+    pub fn builder() -> NameBuilder {
+        NameBuilder::default()
+    }
+
+    /// This name with another common name.
     pub fn copy(&self, common_name: &str) -> Self {
-        let mut new = self.clone();
-        new.country = self.country.clone();
-        new.province = self.province.clone();
-        new.locality = self.locality.clone();
-        new.org = self.org.clone();
-        new.org_unit = self.org_unit.clone();
-        new.common_name = common_name.to_string();
-        new
+        Name {
+            common_name: Some(common_name.to_string()),
+            ..self.clone()
+        }
+    }
+
+    /// The common name, empty when unset.
+    pub fn cn(&self) -> &str {
+        self.common_name.as_deref().unwrap_or_default()
+    }
+
+    fn field(&self, field: NameField) -> &str {
+        let value = match field {
+            NameField::C => &self.country,
+            NameField::ST => &self.province,
+            NameField::L => &self.locality,
+            NameField::O => &self.org,
+            NameField::OU => &self.org_unit,
+            NameField::CN => &self.common_name,
+        };
+        value.as_deref().unwrap_or_default()
     }
 
     pub fn to_x509_name(&self) -> Result<X509Name, ErrorStack> {
+        self.to_x509_name_ordered(&DEFAULT_NAME_ORDER)
+    }
+
+    /// Builds the name with its RDNs in `order`. Fields missing from `order`
+    /// follow in their default order, empty ones are left out.
+    pub fn to_x509_name_ordered(&self, order: &[NameField]) -> Result<X509Name, ErrorStack> {
         let mut builder = X509NameBuilder::new()?;
-        append!(builder, Nid::COUNTRYNAME, &self.country);
-        append!(builder, Nid::STATEORPROVINCENAME, &self.country);
-        append!(builder, Nid::LOCALITYNAME, &self.locality);
-        append!(builder, Nid::ORGANIZATIONNAME, &self.org);
-        append!(builder, Nid::ORGANIZATIONALUNITNAME, &self.org_unit);
-        append!(builder, Nid::COMMONNAME, &self.common_name);
+        let rest = DEFAULT_NAME_ORDER
+            .iter()
+            .filter(|field| !order.contains(field));
+        let mut seen = Vec::new();
+        for &field in order.iter().chain(rest) {
+            let value = self.field(field);
+            if !value.is_empty() && !seen.contains(&field) {
+                builder.append_entry_by_nid(field.nid(), value)?;
+            }
+            seen.push(field);
+        }
         Ok(builder.build())
     }
 }
 
-impl Clone for Name {
-    fn clone(&self) -> Self {
-        Name {
-            common_name: self.common_name.clone(),
-            country: self.common_name.clone(),
-            province: self.common_name.clone(),
-            locality: self.common_name.clone(),
-            org: self.common_name.clone(),
-            org_unit: self.common_name.clone(),
-        }
+/// Builds a `Name` field by field:
+/// `Name::builder().org("Acme").common_name("app.test").build()`.
+#[derive(Debug, Default)]
+pub struct NameBuilder {
+    name: Name,
+}
+
+impl NameBuilder {
+    pub fn country(mut self, country: impl Into<String>) -> Self {
+        self.name.country = Some(country.into());
+        self
+    }
+
+    pub fn province(mut self, province: impl Into<String>) -> Self {
+        self.name.province = Some(province.into());
+        self
+    }
+
+    pub fn locality(mut self, locality: impl Into<String>) -> Self {
+        self.name.locality = Some(locality.into());
+        self
+    }
+
+    pub fn org(mut self, org: impl Into<String>) -> Self {
+        self.name.org = Some(org.into());
+        self
+    }
+
+    pub fn org_unit(mut self, org_unit: impl Into<String>) -> Self {
+        self.name.org_unit = Some(org_unit.into());
+        self
+    }
+
+    pub fn common_name(mut self, common_name: impl Into<String>) -> Self {
+        self.name.common_name = Some(common_name.into());
+        self
+    }
+
+    pub fn build(self) -> Name {
+        self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inspect::format_name;
+
+    #[test]
+    fn test_name_order() {
+        let name = Name::builder()
+            .country("NZ")
+            .province("Auckland")
+            .org("Dev")
+            .common_name("app.test")
+            .build();
+        let default = name.to_x509_name().unwrap();
+        assert_eq!(
+            format_name(&default).unwrap(),
+            "C=NZ, ST=Auckland, O=Dev, CN=app.test"
+        );
+        let cn_first = name
+            .to_x509_name_ordered(&[NameField::CN, NameField::O, NameField::CN])
+            .unwrap();
+        assert_eq!(
+            format_name(&cn_first).unwrap(),
+            "CN=app.test, O=Dev, C=NZ, ST=Auckland"
+        );
+    }
+
+    #[test]
+    fn test_copy() {
+        let name = Name::builder().country("NZ").org("Dev").build();
+        let copy = name.copy("app.test");
+        assert_eq!(copy.country.as_deref(), Some("NZ"));
+        assert_eq!(copy.org.as_deref(), Some("Dev"));
+        assert_eq!(copy.province, None);
+        assert_eq!(copy.cn(), "app.test");
+        assert_eq!(name.clone(), name);
     }
 }
@@ -0,0 +1,62 @@
+//! Addresses of the local network interfaces, for IP SANs that let other
+//! machines on the LAN reach dev services by IP.
+
+use std::net::IpAddr;
+
+use crate::err::Result;
+
+#[cfg(unix)]
+fn is_reachable(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => !ip.is_loopback() && !ip.is_unspecified() && !ip.is_link_local(),
+        IpAddr::V6(ip) => {
+            !ip.is_loopback() && !ip.is_unspecified() && (ip.segments()[0] & 0xffc0) != 0xfe80
+        }
+    }
+}
+
+/// The addresses of the interfaces that are up, without loopback and
+/// link-local addresses.
+#[cfg(unix)]
+pub fn local_ips() -> Result<Vec<IpAddr>> {
+    use std::io;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: getifaddrs fills in a list that is freed below, and every
+    // sockaddr is only read as the type its family names.
+    unsafe {
+        if libc::getifaddrs(&mut addrs) != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let mut ips = Vec::new();
+        let mut cursor = addrs;
+        while let Some(ifa) = cursor.as_ref() {
+            cursor = ifa.ifa_next;
+            if ifa.ifa_addr.is_null() || ifa.ifa_flags & libc::IFF_UP as libc::c_uint == 0 {
+                continue;
+            }
+            let ip = match i32::from((*ifa.ifa_addr).sa_family) {
+                libc::AF_INET => {
+                    let addr = &*(ifa.ifa_addr as *const libc::sockaddr_in);
+                    IpAddr::V4(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)))
+                }
+                libc::AF_INET6 => {
+                    let addr = &*(ifa.ifa_addr as *const libc::sockaddr_in6);
+                    IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr))
+                }
+                _ => continue,
+            };
+            if is_reachable(&ip) && !ips.contains(&ip) {
+                ips.push(ip);
+            }
+        }
+        libc::freeifaddrs(addrs);
+        Ok(ips)
+    }
+}
+
+#[cfg(not(unix))]
+pub fn local_ips() -> Result<Vec<IpAddr>> {
+    Ok(Vec::new())
+}
@@ -0,0 +1,284 @@
+//! Offline-root mode: the root key is exported once and removed from the
+//! store, so routine issuance only ever touches the intermediate. Re-issuing
+//! the intermediate is the only operation that needs the root key back.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use openssl::x509::X509;
+
+use crate::atomic::write_private;
+use crate::backend::KeyBackend;
+use crate::cert_params::CertParams;
+use crate::certs::{cert_fingerprint, create_intermediate_ca};
+use crate::commands::{read_file, read_root_cert, write_chain_files};
+use crate::conf::{file_in_conf, CertAuthConf, Conf, ValidityConf};
+use crate::datetime::format_rfc3339;
+use crate::deterministic::Sources;
+use crate::err::{Result, SimpleCAError};
+use crate::guard::RepoGuard;
+use crate::index::record_issued;
+use crate::lock::StoreLock;
+use crate::matching::key_matches_cert;
+use crate::passphrase::{private_key_to_pem, read_private_key};
+use crate::store::{self, issued_by, Role, StoreManifest};
+use crate::summary::IssuanceSummary;
+
+/// Kept in the store in place of the root key while the root is offline.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OfflineRoot {
+    /// Where the root key was exported to.
+    pub exported_to: PathBuf,
+    pub exported_at: String,
+    pub encrypted: bool,
+    /// SHA-256 fingerprint of the root certificate the key belongs to.
+    pub root_fingerprint: String,
+}
+
+/// Whether the root key of the store was taken offline.
+pub fn is_root_offline() -> Result<bool> {
+    Ok(CertAuthConf::offline_root()?.exists())
+}
+
+/// Details of the offline root, `None` while the root key is in the store.
+pub fn offline_root() -> Result<Option<OfflineRoot>> {
+    let path = CertAuthConf::offline_root()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_slice(&read_file(&path)?)?))
+}
+
+/// Exports the root key to `dest`, encrypted with the passphrase when
+/// `encrypt` is set, and removes it from the store. The key is only removed
+/// once the exported copy was read back and found to match the root.
+pub fn take_root_offline(
+    dest: &Path,
+    encrypt: bool,
+    guard: &RepoGuard,
+    verbose: bool,
+) -> Result<OfflineRoot> {
+    let _lock = StoreLock::acquire()?;
+    if is_root_offline()? {
+        Err(SimpleCAError::RootOffline)?;
+    }
+    let ca_key_path = CertAuthConf::ca_key()?;
+    let ca = read_root_cert()?;
+    let ca_pkey = read_private_key(&ca_key_path)?;
+    guard.check(&[dest])?;
+    if dest.exists() {
+        Err(SimpleCAError::GenericError {
+            msg: "Refusing to overwrite an existing file with the root key.",
+        })?;
+    }
+    write_private(dest, private_key_to_pem(&ca_pkey, encrypt)?)?;
+    if verbose {
+        tracing::info!("Exported CA private key to: {:?}", dest);
+    }
+    retire_root_key(dest, &ca, encrypt, verbose)
+}
+
+/// Removes the root key from the store once the copy exported to `exported`
+/// was read back and found to match `ca`, keeping it otherwise.
+fn retire_root_key(
+    exported: &Path,
+    ca: &X509,
+    encrypt: bool,
+    verbose: bool,
+) -> Result<OfflineRoot> {
+    let exported_key = read_private_key(exported)?;
+    if !key_matches_cert(&exported_key, ca)? {
+        Err(SimpleCAError::GenericError {
+            msg: "The exported root key could not be read back, it was kept in the store.",
+        })?;
+    }
+
+    let offline = OfflineRoot {
+        exported_to: exported.canonicalize()?,
+        exported_at: format_rfc3339(SystemTime::now()),
+        encrypted: encrypt,
+        root_fingerprint: cert_fingerprint(ca)?,
+    };
+    let marker_path = CertAuthConf::offline_root()?;
+    write_file!(
+        &serde_json::to_vec_pretty(&offline)?,
+        &marker_path,
+        verbose,
+        "Saved offline root details at: {:?}"
+    );
+    store::record(Role::OfflineRoot, None, &marker_path, Some(ca))?;
+    let ca_key_path = CertAuthConf::ca_key()?;
+    fs::remove_file(&ca_key_path)?;
+    store::forget(Role::CaKey, None)?;
+    if verbose {
+        tracing::info!("Removed CA private key from: {:?}", ca_key_path);
+    }
+    Ok(offline)
+}
+
+/// Re-signs the intermediate with the root key at `root_key`, keeping its key
+/// and subject so the certificates it issued stay valid, e.g. before it
+/// expires while the root is offline. Their chain bundles are rewritten with
+/// the new intermediate certificate.
+pub fn resign_intermediate(
+    root_key: &Path,
+    validity: &ValidityConf,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<IssuanceSummary> {
+    let _lock = StoreLock::acquire()?;
+    let conf = Conf::load()?;
+    let validity = validity.or(&conf.validity());
+    let ca_cert_path = CertAuthConf::ca_cert()?;
+    let ca = X509::from_pem(&read_file(&ca_cert_path)?)?;
+    let ca_pkey = read_private_key(root_key)?;
+    if !key_matches_cert(&ca_pkey, &ca)? {
+        Err(SimpleCAError::WrongRootKey {
+            path: root_key.to_path_buf(),
+        })?;
+    }
+
+    let intermediate_key = KeyBackend::intermediate(&conf)?;
+    let intermediate_cert_path = CertAuthConf::intermediate_cert()?;
+    let intermediate_pkey = intermediate_key.load()?;
+    let current = X509::from_pem(&read_file(&intermediate_cert_path)?)?;
+    let intermediate_name = current.subject_name().to_owned()?;
+    let ca_name = ca.subject_name().to_owned()?;
+    let mut params = CertParams::intermediate_ca_params(
+        &intermediate_name,
+        &intermediate_pkey,
+        &ca_name,
+        &ca_pkey,
+        validity.intermediate_days(),
+    )?;
+    params.name_constraints = conf.name_constraints();
+    params.path_len = conf.ca().intermediate_path_len();
+    params.backdate = validity.backdate()?;
+    params.digest = conf.keys().digest();
+    sources.configure(&mut params, conf.serials().strategy())?;
+    let intermediate = create_intermediate_ca(&params, &ca)?;
+
+    store::archive_cert(Role::ArchivedIntermediateCert, &intermediate_cert_path)?;
+    write_file!(
+        &intermediate.to_pem()?,
+        &intermediate_cert_path,
+        verbose,
+        "Saved intermediate certicate at: {:?}"
+    );
+    store::record(
+        Role::IntermediateCert,
+        None,
+        &intermediate_cert_path,
+        Some(&ca),
+    )?;
+    record_issued(
+        &intermediate,
+        intermediate_key.path().map(PathBuf::as_path),
+        Some(&intermediate_cert_path),
+        sources.now(),
+    )?;
+
+    // Certificates issued with the same key are bundled with the new copy.
+    for entry in StoreManifest::load()?.entries(Role::ServerCert) {
+        let domain = match &entry.name {
+            Some(domain) => domain,
+            None => continue,
+        };
+        let cert_path = file_in_conf(&entry.path)?;
+        let cert = X509::from_pem(&read_file(&cert_path)?)?;
+        if issued_by(&cert, &intermediate) {
+            store::record(
+                Role::ServerCert,
+                Some(domain),
+                &cert_path,
+                Some(&intermediate),
+            )?;
+            write_chain_files(domain, &cert, &intermediate, verbose)?;
+        }
+    }
+    let mut summary = IssuanceSummary::new(&intermediate)?;
+    if let Some(key_path) = intermediate_key.path() {
+        summary = summary.file("key", key_path);
+    }
+    Ok(summary.file("cert", &intermediate_cert_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fixtures::issue_test_server;
+    use crate::commands::{load_ca, ServerExtras};
+    use crate::conf::with_test_store;
+
+    #[test]
+    fn test_take_root_offline() {
+        with_test_store("offline", |home| {
+            issue_test_server("app.test", &[], &ServerExtras::default());
+            let ca_key_path = CertAuthConf::ca_key().unwrap();
+            let intermediate_path = CertAuthConf::intermediate_cert().unwrap();
+            let intermediate = X509::from_pem(&read_file(&intermediate_path).unwrap()).unwrap();
+            let dest = home.join("exported-root.pem");
+
+            let offline = take_root_offline(&dest, false, &RepoGuard::default(), false).unwrap();
+            assert!(!offline.encrypted);
+            assert!(!ca_key_path.exists());
+            assert!(is_root_offline().unwrap());
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = fs::metadata(&dest).unwrap().permissions().mode();
+                assert_eq!(mode & 0o777, 0o600);
+            }
+
+            let sources = Sources::default();
+            fs::remove_file(&intermediate_path).unwrap();
+            assert!(matches!(
+                load_ca(
+                    false,
+                    &ValidityConf::default(),
+                    &RepoGuard::default(),
+                    &sources,
+                    false
+                ),
+                Err(SimpleCAError::RootOffline)
+            ));
+            fs::write(&intermediate_path, intermediate.to_pem().unwrap()).unwrap();
+
+            resign_intermediate(&dest, &ValidityConf::default(), &sources, false).unwrap();
+            let resigned = X509::from_pem(&read_file(&intermediate_path).unwrap()).unwrap();
+            assert_ne!(
+                resigned.serial_number().to_bn().unwrap(),
+                intermediate.serial_number().to_bn().unwrap()
+            );
+            let ca = read_root_cert().unwrap();
+            assert!(resigned.verify(&ca.public_key().unwrap()).unwrap());
+            assert!(load_ca(
+                false,
+                &ValidityConf::default(),
+                &RepoGuard::default(),
+                &sources,
+                false
+            )
+            .is_ok());
+        });
+    }
+
+    #[test]
+    fn test_retire_root_key_mismatch() {
+        with_test_store("offline-mismatch", |home| {
+            issue_test_server("app.test", &[], &ServerExtras::default());
+            let ca_key_path = CertAuthConf::ca_key().unwrap();
+            let intermediate_key = read_file(&CertAuthConf::intermediate_key().unwrap()).unwrap();
+            let dest = home.join("exported-root.pem");
+            fs::write(&dest, intermediate_key).unwrap();
+
+            let ca = read_root_cert().unwrap();
+            assert!(retire_root_key(&dest, &ca, false, false).is_err());
+            assert!(ca_key_path.exists());
+            assert!(!is_root_offline().unwrap());
+        });
+    }
+}
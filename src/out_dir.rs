@@ -0,0 +1,171 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::err::Result;
+
+use crate::conf::CertAuthConf;
+use crate::guard::RepoGuard;
+
+/// A directory outside the store that issued server files are copied into.
+///
+/// File names are templates where `{domain}` is replaced with the common name.
+#[derive(Debug, Clone)]
+pub struct OutDir {
+    pub dir: PathBuf,
+    pub key_name: String,
+    pub cert_name: String,
+    pub fullchain_name: String,
+    pub chain_name: String,
+    pub dhparam_name: String,
+    pub ticket_key_name: String,
+    pub haproxy_name: String,
+    pub cert_der_name: String,
+    pub key_der_name: String,
+}
+
+impl OutDir {
+    pub fn new(dir: &Path) -> OutDir {
+        OutDir {
+            dir: dir.to_path_buf(),
+            key_name: "{domain}.key.pem".to_string(),
+            cert_name: "{domain}.cert.pem".to_string(),
+            fullchain_name: "{domain}.fullchain.pem".to_string(),
+            chain_name: "{domain}.chain.pem".to_string(),
+            dhparam_name: "{domain}.dhparam.pem".to_string(),
+            ticket_key_name: "{domain}.ticket.key".to_string(),
+            haproxy_name: "{domain}.haproxy.pem".to_string(),
+            cert_der_name: "{domain}.crt".to_string(),
+            key_der_name: "{domain}.key".to_string(),
+        }
+    }
+
+    fn path(&self, template: &str, domain: &str) -> PathBuf {
+        let domain = domain.replace('*', "_");
+        self.dir.join(template.replace("{domain}", &domain))
+    }
+
+    pub fn key_path(&self, domain: &str) -> PathBuf {
+        self.path(&self.key_name, domain)
+    }
+
+    pub fn fullchain_path(&self, domain: &str) -> PathBuf {
+        self.path(&self.fullchain_name, domain)
+    }
+
+    /// Copies the key, certificate and chains issued for `domain` into the
+    /// directory, along with its DH parameters, ticket key, HAProxy PEM and
+    /// DER files when present.
+    pub fn copy_server_files(
+        &self,
+        domain: &str,
+        guard: &RepoGuard,
+        verbose: bool,
+    ) -> Result<Vec<PathBuf>> {
+        fs::create_dir_all(&self.dir)?;
+        let ticket_key = CertAuthConf::server_ticket_key(domain)?;
+        let haproxy = CertAuthConf::server_haproxy(domain)?;
+        let mut private_dests = vec![self.key_path(domain)];
+        if ticket_key.exists() {
+            private_dests.push(self.path(&self.ticket_key_name, domain));
+        }
+        if haproxy.exists() {
+            private_dests.push(self.path(&self.haproxy_name, domain));
+        }
+        let key_der = CertAuthConf::server_key_der(domain)?;
+        if key_der.exists() {
+            private_dests.push(self.path(&self.key_der_name, domain));
+        }
+        guard.check(
+            &private_dests
+                .iter()
+                .map(PathBuf::as_path)
+                .collect::<Vec<_>>(),
+        )?;
+
+        let mut files = Vec::new();
+        // Keys held in hardware have no file to copy.
+        let key = CertAuthConf::server_key(domain)?;
+        if key.exists() {
+            files.push((key, &self.key_name));
+        }
+        files.extend([
+            (CertAuthConf::server_cert(domain)?, &self.cert_name),
+            (
+                CertAuthConf::server_fullchain(domain)?,
+                &self.fullchain_name,
+            ),
+            (CertAuthConf::server_chain(domain)?, &self.chain_name),
+        ]);
+        let dhparam = CertAuthConf::server_dhparam(domain)?;
+        if dhparam.exists() {
+            files.push((dhparam, &self.dhparam_name));
+        }
+        if ticket_key.exists() {
+            files.push((ticket_key, &self.ticket_key_name));
+        }
+        if haproxy.exists() {
+            files.push((haproxy, &self.haproxy_name));
+        }
+        let cert_der = CertAuthConf::server_cert_der(domain)?;
+        if cert_der.exists() {
+            files.push((cert_der, &self.cert_der_name));
+        }
+        if key_der.exists() {
+            files.push((key_der, &self.key_der_name));
+        }
+        let mut copied = Vec::with_capacity(files.len());
+        for (src, template) in files.iter() {
+            let dest = self.path(template, domain);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(src, &dest)?;
+            if verbose {
+                tracing::info!("Copied {:?} to: {:?}", src, dest);
+            }
+            copied.push(dest);
+        }
+        Ok(copied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{generate_server_cert, ServerExtras};
+    use crate::conf::{with_test_store, ValidityConf};
+    use crate::deterministic::Sources;
+    use crate::name::Name;
+
+    #[test]
+    fn test_copy_server_files() {
+        with_test_store("out-dir", |home| {
+            let mut out_dir = OutDir::new(&home.join("deploy"));
+            out_dir.cert_name = "certs/{domain}.pem".to_string();
+            generate_server_cert(
+                &Name::new("*.app.test"),
+                &vec![],
+                &ValidityConf::default(),
+                None,
+                &RepoGuard::default(),
+                Some(&out_dir),
+                &ServerExtras::default(),
+                &Sources::default(),
+                false,
+            )
+            .unwrap();
+
+            let cert = home.join("deploy/certs/_.app.test.pem");
+            assert_eq!(
+                fs::read(&cert).unwrap(),
+                fs::read(CertAuthConf::server_cert("*.app.test").unwrap()).unwrap()
+            );
+            for name in ["key", "fullchain", "chain"] {
+                assert!(home
+                    .join(format!("deploy/_.app.test.{}.pem", name))
+                    .exists());
+            }
+            assert!(!home.join("deploy/_.app.test.dhparam.pem").exists());
+        });
+    }
+}
@@ -0,0 +1,212 @@
+//! Where simple-ca keeps the files issued for a name, for editors and deploy
+//! scripts that need to find them.
+//!
+//! Store files are named after the name they were issued for, turned into a
+//! file name stem by [`reversed_domain`] so that related domains sort
+//! together:
+//!
+//! | Name                | Stem                |
+//! |---------------------|---------------------|
+//! | `www.example.test`  | `test.example.www`  |
+//! | `*.example.test`    | `test.example._`    |
+//! | `example.test:8443` | `test.example@8443` |
+//! | `192.168.1.20`      | `192.168.1.20`      |
+//! | `fe80::1`           | `fe80--1`           |
+//! | `[::1]:8443`        | `--1@8443`          |
+//!
+//! [`domain_from_reversed`] maps a stem back to its name. Stems contain no
+//! characters that are special in file names on Windows or in shells; path
+//! separators and `%` in a name are escaped as `%2F`, `%5C` and `%25`, so
+//! no name reaches outside the store.
+//!
+//! The manifest of the store has the final say on where a file is, so files
+//! written by older versions with other stems are still found by
+//! [`server_files`] and [`identify`].
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use crate::err::Result;
+
+use crate::conf::file_in_conf;
+use crate::store::{store_path, Role, StoreEntry, StoreManifest};
+
+/// Label standing in for the `*` of a wildcard.
+const WILDCARD_LABEL: &str = "_";
+/// Separates a port from the host in a stem.
+const PORT_SEPARATOR: char = '@';
+/// Characters of a name escaped in its stem, with their escapes.
+const ESCAPES: [(char, &str); 3] = [('%', "%25"), ('/', "%2F"), ('\\', "%5C")];
+
+/// Splits a trailing port off `name`, accepting `[v6]:port` for IPv6.
+fn split_port(name: &str) -> (String, Option<u16>) {
+    if let Ok(addr) = name.parse::<SocketAddr>() {
+        return (addr.ip().to_string(), Some(addr.port()));
+    }
+    if name.parse::<Ipv6Addr>().is_err() {
+        if let Some((host, port)) = name.rsplit_once(':') {
+            if let Ok(port) = port.parse() {
+                return (host.to_string(), Some(port));
+            }
+        }
+    }
+    (
+        name.trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string(),
+        None,
+    )
+}
+
+/// The file name stem of the files issued for `name`: the labels of a
+/// domain in reverse order with `_` for a wildcard, an IPv4 address as is,
+/// and an IPv6 address with `-` for `:`, followed by `@port` for a port.
+pub fn reversed_domain(name: &str) -> String {
+    let (host, port) = split_port(name);
+    let mut stem = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => ip.to_string(),
+        Ok(IpAddr::V6(ip)) => ip.to_string().replace(':', "-"),
+        Err(_) => {
+            let host = host
+                .split('.')
+                .rev()
+                .map(|label| if label == "*" { WILDCARD_LABEL } else { label })
+                .collect::<Vec<&str>>()
+                .join(".");
+            ESCAPES
+                .iter()
+                .fold(host, |host, (c, escape)| host.replace(*c, escape))
+        }
+    };
+    if let Some(port) = port {
+        stem.push(PORT_SEPARATOR);
+        stem.push_str(&port.to_string());
+    }
+    stem
+}
+
+/// The name of a store file written before `store.json` existed, when
+/// IPv4 addresses were reversed like domains and a port followed a `:`.
+pub(crate) fn domain_from_legacy(stem: &str) -> String {
+    let (host, port) = match stem.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => (host, Some(port)),
+        _ => (stem, None),
+    };
+    if host.parse::<Ipv4Addr>().is_err() {
+        return domain_from_reversed(stem);
+    }
+    let host = host.split('.').rev().collect::<Vec<&str>>().join(".");
+    match port {
+        Some(port) => format!("{}:{}", host, port),
+        None => host,
+    }
+}
+
+/// The name whose files are named `stem`, undoing [`reversed_domain`].
+///
+/// Stems of older versions, which reversed IPv4 addresses and kept `*` and
+/// `:port` as they were, map back to the same names except for IPv4
+/// addresses.
+pub fn domain_from_reversed(stem: &str) -> String {
+    let (host, port) = match stem.rsplit_once(PORT_SEPARATOR) {
+        Some((host, port)) if port.parse::<u16>().is_ok() => (host, Some(port)),
+        _ => match stem.rsplit_once(':') {
+            Some((host, port)) if port.parse::<u16>().is_ok() && !host.contains(':') => {
+                (host, Some(port))
+            }
+            _ => (stem, None),
+        },
+    };
+    let v6 = host.replace('-', ":").parse::<Ipv6Addr>().ok();
+    let host = match (host.parse::<IpAddr>(), v6) {
+        (Ok(ip), _) => ip.to_string(),
+        (Err(_), Some(ip)) if host.contains("--") || host.matches('-').count() == 7 => {
+            ip.to_string()
+        }
+        _ => {
+            let host = host
+                .split('.')
+                .rev()
+                .map(|label| if label == WILDCARD_LABEL { "*" } else { label })
+                .collect::<Vec<&str>>()
+                .join(".");
+            ESCAPES.iter().rev().fold(host, |host, (c, escape)| {
+                host.replace(escape, &c.to_string())
+            })
+        }
+    };
+    match (port, host.parse::<Ipv6Addr>()) {
+        (Some(port), Ok(_)) => format!("[{}]:{}", host, port),
+        (Some(port), Err(_)) => format!("{}:{}", host, port),
+        (None, _) => host,
+    }
+}
+
+/// Paths of the files issued for a server certificate, which exist only
+/// once it was issued.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerFiles {
+    pub key: PathBuf,
+    pub cert: PathBuf,
+    pub fullchain: PathBuf,
+    pub chain: PathBuf,
+}
+
+/// Where the files of the server certificate for `domain` are, or will be
+/// written to.
+pub fn server_files(domain: &str) -> Result<ServerFiles> {
+    Ok(ServerFiles {
+        key: store_path(Role::ServerKey, Some(domain))?,
+        cert: store_path(Role::ServerCert, Some(domain))?,
+        fullchain: store_path(Role::ServerFullchain, Some(domain))?,
+        chain: store_path(Role::ServerChain, Some(domain))?,
+    })
+}
+
+/// The store entry describing the existing file at `path`, telling its role
+/// and the name it was issued for. `None` when the file is not part of the
+/// store.
+pub fn identify(path: &Path) -> Result<Option<StoreEntry>> {
+    let path = path.canonicalize()?;
+    for entry in StoreManifest::load()?.files {
+        let entry_path = file_in_conf(&entry.path)?.canonicalize().ok();
+        if entry_path.as_deref() == Some(path.as_path()) {
+            return Ok(Some(entry));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reversed_domain() {
+        let names = [
+            ("www.example.test", "test.example.www"),
+            ("*.example.test", "test.example._"),
+            ("example.test:8443", "test.example@8443"),
+            ("192.168.1.20", "192.168.1.20"),
+            ("192.168.1.20:443", "192.168.1.20@443"),
+            ("fe80::1", "fe80--1"),
+            ("[::1]:8443", "--1@8443"),
+            ("localhost", "localhost"),
+            ("../../etc/passwd", "%2Fetc%2Fpasswd..%2F.."),
+            ("..\\a%2F.test", "test.%5Ca%252F.."),
+        ];
+        for (name, stem) in names {
+            assert_eq!(reversed_domain(name), stem);
+            assert_eq!(domain_from_reversed(stem), name);
+        }
+        assert_eq!(domain_from_reversed("test.example.*"), "*.example.test");
+        assert_eq!(
+            domain_from_reversed("test.example:8443"),
+            "example.test:8443"
+        );
+        assert_eq!(domain_from_reversed("dead-beef.test"), "test.dead-beef");
+        assert_eq!(domain_from_legacy("20.1.168.192"), "192.168.1.20");
+        assert_eq!(domain_from_legacy("20.1.168.192:443"), "192.168.1.20:443");
+        assert_eq!(domain_from_legacy("test.example"), "example.test");
+    }
+}
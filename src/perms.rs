@@ -0,0 +1,125 @@
+//! Files holding private keys are readable by their owner only: they are
+//! created with mode 0600 on Unix, and on Windows `icacls` replaces their
+//! inherited ACL with one granting the current user alone.
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::conf::file_in_conf;
+use crate::err::Result;
+use crate::store::StoreManifest;
+
+/// Suffixes of the files in the store and exports that hold key material.
+const PRIVATE_SUFFIXES: &[&str] = &[".key.pem", ".key", ".ticket.key", ".haproxy.pem"];
+
+/// Whether `path` is named like a file holding a private key.
+pub(crate) fn is_private(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    PRIVATE_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+}
+
+/// Creates or truncates `path`, restricted to its owner.
+pub(crate) fn create_private(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let file = options.open(path)?;
+    // The mode only applies to new files, an existing one keeps its own.
+    restrict(path)?;
+    Ok(file)
+}
+
+/// Creates the directory `path`, accessible by its owner only, failing when
+/// it exists already so nobody else can have prepared it.
+pub(crate) fn create_private_dir(path: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(path)
+}
+
+#[cfg(unix)]
+fn restrict(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+}
+
+#[cfg(windows)]
+fn restrict(path: &Path) -> io::Result<()> {
+    let user = std::env::var("USERNAME").map_err(|_| io::Error::other("USERNAME is not set"))?;
+    let output = std::process::Command::new("icacls")
+        .arg(path)
+        .args(["/inheritance:r", "/grant:r", &format!("{}:F", user)])
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "icacls could not restrict {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stdout).trim()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn restrict(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_exposed(meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o077 != 0
+}
+
+#[cfg(not(unix))]
+fn is_exposed(_meta: &fs::Metadata) -> bool {
+    false
+}
+
+/// The keys in the store that users other than the owner can access, e.g.
+/// written by an older version or copied in with a permissive umask. Always
+/// empty outside Unix.
+pub fn exposed_keys() -> Result<Vec<PathBuf>> {
+    let mut exposed = Vec::new();
+    for entry in StoreManifest::load()?.files {
+        if !entry.role.is_key() {
+            continue;
+        }
+        let path = file_in_conf(&entry.path)?;
+        if let Ok(meta) = fs::metadata(&path) {
+            if is_exposed(&meta) {
+                exposed.push(path);
+            }
+        }
+    }
+    Ok(exposed)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::atomic::write_file;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_private_mode() {
+        let dir = std::env::temp_dir().join(format!("simple-ca-perms-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let key = dir.join("test.key.pem");
+        let cert = dir.join("test.cert.pem");
+        fs::write(&key, b"old").unwrap();
+        fs::set_permissions(&key, fs::Permissions::from_mode(0o644)).unwrap();
+        write_file(&key, b"key").unwrap();
+        write_file(&cert, b"cert").unwrap();
+
+        let key_meta = fs::metadata(&key).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(key_meta.permissions().mode() & 0o777, 0o600);
+        assert!(!is_exposed(&key_meta));
+        assert_eq!(key_meta.len(), 3);
+        assert!(!is_private(&cert));
+    }
+}
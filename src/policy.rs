@@ -0,0 +1,410 @@
+//! Issuance policies written as small expressions, e.g.
+//! `deny when san.ends_with(".com") && !san.ends_with(".dev.com")`.
+//!
+//! Rules are checked in order against every SAN of a certificate and the first
+//! one whose condition holds decides; certificates no rule matches are allowed.
+//! Conditions can use `san`, `cn` and `days`, string literals, integers,
+//! `true`/`false`, the methods `ends_with`, `starts_with`, `contains` and
+//! `len`, `!`, `&&`, `||`, comparisons and parentheses.
+
+use crate::err::{Result, SimpleCAError};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Int(i64),
+    Op(&'static str),
+}
+
+const OPERATORS: [&str; 14] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "(", ")", ".", ",", "-",
+];
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(i, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, c)) => value.push(c),
+                        None => return Err("unterminated string".to_string()),
+                    },
+                    Some((_, c)) => value.push(c),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+            tokens.push(Token::Str(value));
+        } else if c.is_ascii_digit() {
+            let mut value = String::new();
+            while let Some(&(_, c)) = chars.peek().filter(|(_, c)| c.is_ascii_digit()) {
+                value.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Int(value.parse().map_err(|_| "number too large")?));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut value = String::new();
+            while let Some(&(_, c)) = chars
+                .peek()
+                .filter(|(_, c)| c.is_alphanumeric() || *c == '_')
+            {
+                value.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Ident(value));
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| input[i..].starts_with(*op))
+                .ok_or_else(|| format!("unexpected {:?}", c))?;
+            for _ in 0..op.len() {
+                chars.next();
+            }
+            tokens.push(Token::Op(op));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    Var(String),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, String, Vec<Expr>),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Op(o)) if *o == op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if self.eat(op) {
+            Ok(())
+        } else {
+            Err(format!("expected {:?}", op))
+        }
+    }
+
+    fn binary<F>(&mut self, ops: &[&'static str], operand: F) -> Result<Expr, String>
+    where
+        F: Fn(&mut Parser) -> Result<Expr, String>,
+    {
+        let mut left = operand(self)?;
+        'outer: loop {
+            for op in ops {
+                if self.eat(op) {
+                    left = Expr::Binary(op, Box::new(left), Box::new(operand(self)?));
+                    continue 'outer;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        self.binary(&["||"], Parser::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        self.binary(&["&&"], Parser::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        self.binary(&["==", "!=", "<=", ">=", "<", ">"], Parser::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            Ok(Expr::Not(Box::new(self.unary()?)))
+        } else if self.eat("-") {
+            Ok(Expr::Neg(Box::new(self.unary()?)))
+        } else {
+            self.postfix()
+        }
+    }
+
+    fn postfix(&mut self) -> Result<Expr, String> {
+        let mut expr = self.primary()?;
+        while self.eat(".") {
+            let method = match self.next() {
+                Some(Token::Ident(method)) => method,
+                _ => return Err("expected a method name".to_string()),
+            };
+            self.expect("(")?;
+            let mut args = Vec::new();
+            if !self.eat(")") {
+                loop {
+                    args.push(self.or()?);
+                    if self.eat(")") {
+                        break;
+                    }
+                    self.expect(",")?;
+                }
+            }
+            expr = Expr::Call(Box::new(expr), method, args);
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Str(value)) => Ok(Expr::Str(value)),
+            Some(Token::Int(value)) => Ok(Expr::Int(value)),
+            Some(Token::Ident(name)) => Ok(match name.as_str() {
+                "true" => Expr::Bool(true),
+                "false" => Expr::Bool(false),
+                _ => Expr::Var(name),
+            }),
+            Some(Token::Op("(")) => {
+                let expr = self.or()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("unexpected end of rule".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+}
+
+/// What a rule is evaluated against.
+struct Subject<'a> {
+    san: &'a str,
+    cn: &'a str,
+    days: i64,
+}
+
+impl Expr {
+    fn eval(&self, subject: &Subject) -> Result<Value, String> {
+        Ok(match self {
+            Expr::Str(value) => Value::Str(value.clone()),
+            Expr::Int(value) => Value::Int(*value),
+            Expr::Bool(value) => Value::Bool(*value),
+            Expr::Var(name) => match name.as_str() {
+                "san" => Value::Str(subject.san.to_string()),
+                "cn" => Value::Str(subject.cn.to_string()),
+                "days" => Value::Int(subject.days),
+                _ => return Err(format!("unknown variable {}", name)),
+            },
+            Expr::Not(expr) => match expr.eval(subject)? {
+                Value::Bool(value) => Value::Bool(!value),
+                value => return Err(format!("cannot negate {:?}", value)),
+            },
+            Expr::Neg(expr) => match expr.eval(subject)? {
+                Value::Int(value) => Value::Int(-value),
+                value => return Err(format!("cannot negate {:?}", value)),
+            },
+            Expr::Binary(op, left, right) => {
+                let left = left.eval(subject)?;
+                match (*op, left) {
+                    ("&&", Value::Bool(false)) => Value::Bool(false),
+                    ("||", Value::Bool(true)) => Value::Bool(true),
+                    ("&&", Value::Bool(true)) | ("||", Value::Bool(false)) => {
+                        match right.eval(subject)? {
+                            Value::Bool(value) => Value::Bool(value),
+                            value => return Err(format!("expected a boolean, got {:?}", value)),
+                        }
+                    }
+                    ("==", left) => Value::Bool(left == right.eval(subject)?),
+                    ("!=", left) => Value::Bool(left != right.eval(subject)?),
+                    (op, Value::Int(left)) => match right.eval(subject)? {
+                        Value::Int(right) => Value::Bool(match op {
+                            "<" => left < right,
+                            "<=" => left <= right,
+                            ">" => left > right,
+                            ">=" => left >= right,
+                            _ => return Err(format!("cannot apply {} to numbers", op)),
+                        }),
+                        value => return Err(format!("cannot compare a number to {:?}", value)),
+                    },
+                    (op, value) => return Err(format!("cannot apply {} to {:?}", op, value)),
+                }
+            }
+            Expr::Call(target, method, args) => {
+                let target = match target.eval(subject)? {
+                    Value::Str(target) => target,
+                    value => return Err(format!("cannot call {} on {:?}", method, value)),
+                };
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(subject))
+                    .collect::<Result<Vec<_>, _>>()?;
+                match (method.as_str(), args.as_slice()) {
+                    ("ends_with", [Value::Str(arg)]) => Value::Bool(target.ends_with(arg.as_str())),
+                    ("starts_with", [Value::Str(arg)]) => {
+                        Value::Bool(target.starts_with(arg.as_str()))
+                    }
+                    ("contains", [Value::Str(arg)]) => Value::Bool(target.contains(arg.as_str())),
+                    ("len", []) => Value::Int(target.chars().count() as i64),
+                    _ => return Err(format!("unknown method {} for {:?}", method, args)),
+                }
+            }
+        })
+    }
+}
+
+/// A single `allow when ...` or `deny when ...` rule.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    source: String,
+    allow: bool,
+    condition: Expr,
+}
+
+impl Rule {
+    pub fn parse(source: &str) -> Result<Rule> {
+        let invalid = |msg: String| SimpleCAError::InvalidPolicy {
+            rule: source.to_string(),
+            msg,
+        };
+        let mut parser = Parser {
+            tokens: tokenize(source).map_err(invalid)?,
+            pos: 0,
+        };
+        let allow = match parser.next() {
+            Some(Token::Ident(action)) if action == "allow" => true,
+            Some(Token::Ident(action)) if action == "deny" => false,
+            _ => Err(invalid("expected allow or deny".to_string()))?,
+        };
+        match parser.next() {
+            Some(Token::Ident(when)) if when == "when" => {}
+            _ => Err(invalid("expected when".to_string()))?,
+        }
+        let condition = parser.or().map_err(invalid)?;
+        if let Some(token) = parser.peek() {
+            Err(invalid(format!("unexpected {:?}", token)))?;
+        }
+        Ok(Rule {
+            source: source.to_string(),
+            allow,
+            condition,
+        })
+    }
+
+    fn matches(&self, subject: &Subject) -> Result<bool> {
+        match self.condition.eval(subject) {
+            Ok(Value::Bool(matched)) => Ok(matched),
+            Ok(value) => Err(SimpleCAError::InvalidPolicy {
+                rule: self.source.clone(),
+                msg: format!("expected a boolean, got {:?}", value),
+            })?,
+            Err(msg) => Err(SimpleCAError::InvalidPolicy {
+                rule: self.source.clone(),
+                msg,
+            })?,
+        }
+    }
+}
+
+/// `name` as rules see it: DNS names are case-insensitive and a trailing dot
+/// names the same host, so `APP.Example.COM.` is `app.example.com`.
+pub(crate) fn normalize_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Checks a certificate for `cn` with `sans`, valid for `days`, against `rules`.
+/// Names are normalized first, so a rule cannot be bypassed by changing
+/// their case.
+pub(crate) fn check_policy(rules: &[String], cn: &str, sans: &[&str], days: i64) -> Result<()> {
+    if rules.is_empty() {
+        return Ok(());
+    }
+    let rules = rules
+        .iter()
+        .map(|rule| Rule::parse(rule))
+        .collect::<Result<Vec<_>>>()?;
+    let cn = &normalize_name(cn);
+    let sans = if sans.is_empty() { &[""][..] } else { sans };
+    for san in sans {
+        let subject = Subject {
+            san: &normalize_name(san),
+            cn,
+            days,
+        };
+        for rule in &rules {
+            if rule.matches(&subject)? {
+                if !rule.allow {
+                    Err(SimpleCAError::PolicyDenied {
+                        name: san.to_string(),
+                        rule: rule.source.clone(),
+                    })?;
+                }
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_policy() {
+        let rules = vec![
+            "deny when days > 400".to_string(),
+            "allow when san == \"example.com\"".to_string(),
+            "deny when san.ends_with(\".com\") && !san.ends_with(\".dev.com\")".to_string(),
+        ];
+        assert!(check_policy(&rules, "a.dev.com", &["a.dev.com"], 30).is_ok());
+        assert!(check_policy(&rules, "example.com", &["example.com"], 30).is_ok());
+        assert!(check_policy(&rules, "x", &["a.dev.com", "b.com"], 30).is_err());
+        assert!(check_policy(&rules, "a.test", &["a.test"], 401).is_err());
+        assert!(Rule::parse("deny when (san.len() >= 3").is_err());
+        assert!(Rule::parse("maybe when true").is_err());
+        assert!(check_policy(&["deny when nope".to_string()], "a", &["a"], 1).is_err());
+    }
+
+    #[test]
+    fn test_check_policy_mixed_case() {
+        let rules = vec![
+            "deny when san.ends_with(\".com\")".to_string(),
+            "allow when cn == \"intranet.test\"".to_string(),
+        ];
+        assert!(check_policy(&rules, "x", &["APP.EXAMPLE.COM"], 30).is_err());
+        assert!(check_policy(&rules, "x", &["app.example.com."], 30).is_err());
+        assert!(check_policy(&rules, "x", &["App.Example.Com.."], 30).is_err());
+        assert!(check_policy(&rules, "Intranet.TEST.", &["a.test"], 30).is_ok());
+        assert_eq!(normalize_name("APP.Example.COM."), "app.example.com");
+    }
+}
@@ -0,0 +1,324 @@
+//! Certificates built with rcgen and ring instead of OpenSSL.
+//!
+//! Mirrors `create_root_ca`, `create_intermediate_ca` and `create_server_cert`
+//! from `certs`, but keys, names and certificates are rcgen types, so nothing
+//! on this path links OpenSSL. Built without `backend-openssl`, these are the
+//! functions the crate exports at its root.
+//!
+//! Only the extensions the OpenSSL counterparts write are supported: name
+//! constraints and path length on CAs, SANs and the Netscape comment on
+//! server certificates. There is no way to add arbitrary extensions, and the
+//! signature digest follows the key. RSA keys can be loaded but not
+//! generated here, ring only generates ECDSA and Ed25519 keys.
+
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, CertifiedIssuer, CustomExtension,
+    ExtendedKeyUsagePurpose, IsCa, KeyUsagePurpose, PublicKeyData, SanType, SerialNumber,
+};
+use ring::rand::{SecureRandom, SystemRandom};
+use time::OffsetDateTime;
+
+use crate::err::{Result, SimpleCAError};
+
+pub use rcgen::{DistinguishedName, DnType, GeneralSubtree, KeyPair, NameConstraints};
+
+const NETSCAPE_CERT_TYPE: &[u64] = &[2, 16, 840, 1, 113730, 1, 1];
+const NETSCAPE_COMMENT: &[u64] = &[2, 16, 840, 1, 113730, 1, 13];
+/// DER of the Netscape cert type "SSL Server", bit 1 of a BIT STRING.
+const SSL_SERVER: &[u8] = &[0x03, 0x02, 0x06, 0x40];
+const DEFAULT_COMMENT: &str = "Simple CA Generated Server Certificate";
+const DEFAULT_BACKDATE: Duration = Duration::from_secs(60 * 60);
+
+/// Parameters of a certificate, the rcgen counterpart of `CertParams`.
+///
+/// `key` is the subject key: a key pair for CAs, which sign later
+/// certificates, and any public key for server certificates.
+pub struct CertParams<K = KeyPair> {
+    pub name: DistinguishedName,
+    pub key: K,
+    pub valid: u32,
+    pub ttl: Option<Duration>,
+    /// Explicit start as a Unix timestamp, the time of signing when `None`.
+    pub not_before: Option<i64>,
+    /// Explicit end as a Unix timestamp, overriding `valid` and `ttl`.
+    pub not_after: Option<i64>,
+    /// How far before the time of signing the certificate starts, unless
+    /// `not_before` is set.
+    pub backdate: Duration,
+    /// Random when `None`.
+    pub serial: Option<SerialNumber>,
+    pub sub_alt_names: Vec<String>,
+    /// Written into CA certificates only.
+    pub name_constraints: Option<NameConstraints>,
+    /// How many CA certificates may follow this CA certificate in a chain,
+    /// unlimited when `None`.
+    pub path_len: Option<u8>,
+    /// Netscape comment, written into server certificates only and left out
+    /// when `None`.
+    pub comment: Option<String>,
+}
+
+impl<K: PublicKeyData> CertParams<K> {
+    pub fn new(name: DistinguishedName, key: K, valid: u32) -> Self {
+        CertParams {
+            name,
+            key,
+            valid,
+            ttl: None,
+            not_before: None,
+            not_after: None,
+            backdate: DEFAULT_BACKDATE,
+            serial: None,
+            sub_alt_names: Vec::new(),
+            name_constraints: None,
+            path_len: None,
+            comment: Some(DEFAULT_COMMENT.to_string()),
+        }
+    }
+
+    /// Start and end of validity, computed like `CertParams::valid_from` and
+    /// `CertParams::valid_to`.
+    fn validity(&self) -> Result<(OffsetDateTime, OffsetDateTime)> {
+        let now = i64::try_from(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+            .unwrap_or(i64::MAX);
+        let not_before = match self.not_before {
+            Some(not_before) => not_before,
+            None => now.saturating_sub(i64::try_from(self.backdate.as_secs()).unwrap_or(i64::MAX)),
+        };
+        let not_after = match self.not_after {
+            Some(not_after) => not_after,
+            None => {
+                let lifetime = match self.ttl {
+                    Some(ttl) => i64::try_from(ttl.as_secs()).unwrap_or(i64::MAX),
+                    None => i64::from(self.valid) * 86_400,
+                };
+                self.not_before.unwrap_or(now).saturating_add(lifetime)
+            }
+        };
+        Ok((timestamp(not_before)?, timestamp(not_after)?))
+    }
+
+    fn certificate_params(&self) -> Result<CertificateParams> {
+        let (not_before, not_after) = self.validity()?;
+        let mut params = CertificateParams::default();
+        params.distinguished_name = self.name.clone();
+        params.not_before = not_before;
+        params.not_after = not_after;
+        params.serial_number = Some(match &self.serial {
+            Some(serial) => serial.clone(),
+            None => create_serial_number()?,
+        });
+        params.use_authority_key_identifier_extension = true;
+        Ok(params)
+    }
+}
+
+/// A CA certificate together with its key, able to sign further certificates.
+pub struct CaCert {
+    issuer: CertifiedIssuer<'static, KeyPair>,
+    not_after: OffsetDateTime,
+}
+
+impl CaCert {
+    pub fn der(&self) -> &[u8] {
+        self.issuer.der()
+    }
+
+    pub fn pem(&self) -> String {
+        self.issuer.pem()
+    }
+
+    pub fn key(&self) -> &KeyPair {
+        self.issuer.key()
+    }
+
+    pub fn not_after(&self) -> OffsetDateTime {
+        self.not_after
+    }
+}
+
+fn timestamp(unix: i64) -> Result<OffsetDateTime> {
+    OffsetDateTime::from_unix_timestamp(unix).map_err(|_| SimpleCAError::GenericError {
+        msg: "Validity is out of the range of X.509 times.",
+    })
+}
+
+/// Random positive 20 byte serial number, the most RFC 5280 allows.
+fn create_serial_number() -> Result<SerialNumber> {
+    let mut serial = [0u8; 20];
+    SystemRandom::new()
+        .fill(&mut serial)
+        .map_err(|_| SimpleCAError::GenericError {
+            msg: "Could not generate a random serial number.",
+        })?;
+    serial[0] &= 0x7f;
+    Ok(SerialNumber::from_slice(&serial))
+}
+
+/// Ensures a certificate valid until `not_after` does not outlive `issuer`.
+fn check_issuer_validity(not_after: OffsetDateTime, issuer: &CaCert) -> Result<()> {
+    if not_after > issuer.not_after {
+        return Err(SimpleCAError::OutlivesIssuer {
+            not_after: not_after.to_string(),
+            issuer_not_after: issuer.not_after.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn sub_alt_names(names: &[String]) -> Result<Vec<SanType>> {
+    names
+        .iter()
+        .map(|name| {
+            Ok(match name.parse::<IpAddr>() {
+                Ok(ip) => SanType::IpAddress(ip),
+                Err(_) if name.contains('@') => SanType::Rfc822Name(name.clone().try_into()?),
+                Err(_) => SanType::DnsName(name.clone().try_into()?),
+            })
+        })
+        .collect()
+}
+
+fn ca_params(params: &CertParams) -> Result<CertificateParams> {
+    let mut ca = params.certificate_params()?;
+    ca.is_ca = IsCa::Ca(match params.path_len {
+        Some(path_len) => BasicConstraints::Constrained(path_len),
+        None => BasicConstraints::Unconstrained,
+    });
+    ca.key_usages = vec![
+        KeyUsagePurpose::DigitalSignature,
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+    ];
+    ca.name_constraints = params.name_constraints.clone();
+    Ok(ca)
+}
+
+pub fn create_root_ca(params: CertParams) -> Result<CaCert> {
+    let root = ca_params(&params)?;
+    let not_after = root.not_after;
+    let issuer = CertifiedIssuer::self_signed(root, params.key)?;
+    Ok(CaCert { issuer, not_after })
+}
+
+/// Creates a CA certificate signed by `issuer`, which is the root or, for
+/// deeper hierarchies, another intermediate.
+pub fn create_intermediate_ca(params: CertParams, issuer: &CaCert) -> Result<CaCert> {
+    let intermediate = ca_params(&params)?;
+    let not_after = intermediate.not_after;
+    check_issuer_validity(not_after, issuer)?;
+    let issuer = CertifiedIssuer::signed_by(intermediate, params.key, &issuer.issuer)?;
+    Ok(CaCert { issuer, not_after })
+}
+
+pub fn create_server_cert<K: PublicKeyData>(
+    params: &CertParams<K>,
+    intermediate: &CaCert,
+) -> Result<Certificate> {
+    let mut server = params.certificate_params()?;
+    check_issuer_validity(server.not_after, intermediate)?;
+    server.is_ca = IsCa::ExplicitNoCa;
+    server.key_usages = vec![
+        KeyUsagePurpose::DigitalSignature,
+        KeyUsagePurpose::ContentCommitment,
+        KeyUsagePurpose::KeyEncipherment,
+    ];
+    server.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    server.subject_alt_names = sub_alt_names(&params.sub_alt_names)?;
+    server.custom_extensions = vec![CustomExtension::from_oid_content(
+        NETSCAPE_CERT_TYPE,
+        SSL_SERVER.to_vec(),
+    )];
+    if let Some(comment) = &params.comment {
+        // IA5String, which rcgen has no encoder for in custom extensions.
+        if comment.len() > 127 || !comment.is_ascii() {
+            return Err(SimpleCAError::GenericError {
+                msg: "The certificate comment must be ASCII and at most 127 bytes long.",
+            });
+        }
+        let content = [&[0x16, comment.len() as u8], comment.as_bytes()].concat();
+        server
+            .custom_extensions
+            .push(CustomExtension::from_oid_content(NETSCAPE_COMMENT, content));
+    }
+    Ok(server.signed_by(&params.key, &intermediate.issuer)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{PKCS_ECDSA_P256_SHA256, PKCS_ED25519};
+
+    fn name(common_name: &str) -> DistinguishedName {
+        let mut name = DistinguishedName::new();
+        name.push(DnType::CommonName, common_name);
+        name
+    }
+
+    #[test]
+    #[cfg(feature = "backend-openssl")]
+    fn test_rcgen_chain() {
+        let root_key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+        let mut params = CertParams::new(name("Root CA"), root_key, 3650);
+        params.path_len = Some(1);
+        params.name_constraints = Some(NameConstraints {
+            permitted_subtrees: vec![GeneralSubtree::DnsName("example.com".into())],
+            excluded_subtrees: Vec::new(),
+        });
+        let root_ca = create_root_ca(params).unwrap();
+
+        let key = KeyPair::generate_for(&PKCS_ED25519).unwrap();
+        let intermediate = create_intermediate_ca(
+            CertParams::new(name("Intermediate CA"), key, 1825),
+            &root_ca,
+        )
+        .unwrap();
+
+        let key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+        let mut params = CertParams::new(name("example.com"), key, 365);
+        params.sub_alt_names = vec!["*.example.com".into(), "10.0.0.1".into()];
+        let server = create_server_cert(&params, &intermediate).unwrap();
+
+        let root = openssl::x509::X509::from_der(root_ca.der()).unwrap();
+        let intermediate = openssl::x509::X509::from_der(intermediate.der()).unwrap();
+        let server = openssl::x509::X509::from_der(server.der()).unwrap();
+        assert!(root.verify(&root.public_key().unwrap()).unwrap());
+        assert!(intermediate.verify(&root.public_key().unwrap()).unwrap());
+        assert!(server.verify(&intermediate.public_key().unwrap()).unwrap());
+        assert_eq!(
+            server.authority_key_id().unwrap().as_slice(),
+            intermediate.subject_key_id().unwrap().as_slice()
+        );
+        assert_eq!(root.pathlen(), Some(1));
+        let names = server.subject_alt_names().unwrap();
+        assert_eq!(names.get(0).unwrap().dnsname(), Some("*.example.com"));
+        assert_eq!(names.get(1).unwrap().ipaddress(), Some(&[10, 0, 0, 1][..]));
+        assert_ne!(
+            root.serial_number().to_bn().unwrap(),
+            intermediate.serial_number().to_bn().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_rcgen_validity() {
+        let key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+        let mut params = CertParams::new(name("Root CA"), key, 30);
+        params.not_before = Some(1_700_000_000);
+        let root_ca = create_root_ca(params).unwrap();
+        assert_eq!(
+            root_ca.not_after().unix_timestamp(),
+            1_700_000_000 + 30 * 86_400
+        );
+        assert!(root_ca.pem().starts_with("-----BEGIN CERTIFICATE-----"));
+
+        let key = KeyPair::generate_for(&PKCS_ED25519).unwrap();
+        let params = CertParams::new(name("example.com"), key, 365);
+        assert!(matches!(
+            create_server_cert(&params, &root_ca),
+            Err(SimpleCAError::OutlivesIssuer { .. })
+        ));
+    }
+}
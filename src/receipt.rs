@@ -0,0 +1,123 @@
+use std::path::Path;
+
+use crate::err::Result;
+use serde::{Deserialize, Serialize};
+
+use openssl::cms::{CMSOptions, CmsContentInfo};
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::X509Ref;
+
+use crate::certs::cert_fingerprint;
+use crate::commands::{load_ca, read_file, read_root_cert};
+use crate::conf::ValidityConf;
+use crate::datetime::format_rfc3339;
+use crate::deterministic::Sources;
+use crate::guard::RepoGuard;
+use crate::index::IssuedCert;
+
+/// Issuance metadata signed by the intermediate CA into a CMS receipt.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Receipt {
+    pub serial: String,
+    pub common_name: String,
+    pub sans: Vec<String>,
+    pub not_before: String,
+    pub not_after: String,
+    /// SHA-256 fingerprint of the certificate.
+    pub fingerprint: String,
+    pub issued_at: String,
+}
+
+/// Creates a PEM encoded CMS SignedData over the receipt for `cert`.
+pub(crate) fn sign_receipt(cert: &X509Ref, sources: &Sources) -> Result<Vec<u8>> {
+    let issued = IssuedCert::from_cert(cert, None, None)?;
+    let receipt = Receipt {
+        serial: issued.serial,
+        common_name: issued.common_name,
+        sans: issued.sans,
+        not_before: issued.not_before,
+        not_after: issued.not_after,
+        fingerprint: cert_fingerprint(cert)?,
+        issued_at: format_rfc3339(sources.now()),
+    };
+    let (intermediate, pkey, _) = load_ca(
+        false,
+        &ValidityConf::default(),
+        &RepoGuard::default(),
+        sources,
+        false,
+    )?;
+    let cms = CmsContentInfo::sign(
+        Some(&intermediate),
+        Some(&pkey),
+        None,
+        Some(&serde_json::to_vec_pretty(&receipt)?),
+        CMSOptions::BINARY,
+    )?;
+    Ok(cms.to_pem()?)
+}
+
+/// Reads and verifies the receipt stored at `path`.
+pub fn read_receipt(path: &Path) -> Result<Receipt> {
+    verify_receipt(&read_file(path)?)
+}
+
+/// Verifies a receipt against the root CA and returns its contents.
+pub fn verify_receipt(pem: &[u8]) -> Result<Receipt> {
+    let root = read_root_cert()?;
+    let mut store = X509StoreBuilder::new()?;
+    store.add_cert(root)?;
+    let store = store.build();
+
+    let mut cms = CmsContentInfo::from_pem(pem)?;
+    let mut content = Vec::new();
+    cms.verify(
+        None,
+        Some(&store),
+        None,
+        Some(&mut content),
+        CMSOptions::BINARY,
+    )?;
+    Ok(serde_json::from_slice(&content)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fixtures::issue_test_server;
+    use crate::commands::ServerExtras;
+    use crate::conf::{with_test_store, CertAuthConf};
+    use openssl::x509::X509;
+
+    #[test]
+    fn test_receipt() {
+        with_test_store("receipt", |_| {
+            let extras = ServerExtras {
+                receipt: true,
+                ..ServerExtras::default()
+            };
+            let summary = issue_test_server("app.test", &["api.app.test"], &extras);
+            let path = CertAuthConf::server_receipt("app.test").unwrap();
+            let receipt = read_receipt(&path).unwrap();
+            assert_eq!(receipt.serial, summary.serial);
+            assert_eq!(receipt.sans, ["app.test", "api.app.test"]);
+            let cert = X509::from_pem(
+                &read_file(&CertAuthConf::server_cert("app.test").unwrap()).unwrap(),
+            )
+            .unwrap();
+            assert_eq!(receipt.fingerprint, cert_fingerprint(&cert).unwrap());
+
+            // Any change to the signed content breaks the signature.
+            let pem = read_file(&path).unwrap();
+            let signed = serde_json::to_vec_pretty(&receipt).unwrap();
+            let mut cms = CmsContentInfo::from_pem(&pem).unwrap().to_der().unwrap();
+            let at = cms
+                .windows(signed.len())
+                .position(|window| window == &signed[..])
+                .unwrap();
+            cms[at + signed.len() - 2] ^= 1;
+            let tampered = CmsContentInfo::from_der(&cms).unwrap().to_pem().unwrap();
+            assert!(verify_receipt(&tampered).is_err());
+        });
+    }
+}
@@ -0,0 +1,381 @@
+use std::fs::{self, File};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use openssl::nid::Nid;
+use openssl::pkey::PKey;
+use openssl::x509::{X509Ref, X509};
+
+use crate::certs::cert_fingerprint;
+use crate::commands::{
+    generate_server_cert, issue_server_cert, issuer_name, read_file, write_chain_files,
+    write_precert, write_receipt, ServerExtras,
+};
+use crate::conf::{file_in_conf, with_config_dir, CertAuthConf, Conf, ValidityConf};
+use crate::csr::{is_hardware_bound, renew_csr_cert};
+use crate::ct::issue_precert_pair;
+use crate::deterministic::Sources;
+use crate::err::{Result, SimpleCAError};
+use crate::guard::RepoGuard;
+use crate::hooks::run_post_issue_hooks;
+use crate::index::{record_issued, IssuedCert};
+use crate::inspect::format_ip;
+use crate::lock::StoreLock;
+use crate::name::Name;
+use crate::store::{self, Role, StoreManifest};
+use crate::summary::IssuanceSummary;
+
+const AUTO_RENEW_STAMP: &str = ".auto_renew";
+const AUTO_RENEW_LOG: &str = ".auto_renew.log";
+const AUTO_RENEW_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// DNS names and IP addresses in the subjectAltName of `cert`.
+fn alt_names(cert: &X509Ref) -> Vec<String> {
+    cert.subject_alt_names()
+        .map(|names| {
+            names
+                .iter()
+                .filter_map(|name| {
+                    name.dnsname()
+                        .map(|dns| dns.to_string())
+                        .or_else(|| name.ipaddress().map(format_ip))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Lists the domains of server certificates in the store expiring within `days`
+/// of the time of `sources`.
+pub fn expiring_server_certs(days: u32, sources: &Sources) -> Result<Vec<String>> {
+    let deadline = sources.days_from_now(days)?;
+    let mut expiring = Vec::new();
+    for entry in StoreManifest::load()?.entries(Role::ServerCert) {
+        let domain = match &entry.name {
+            Some(domain) => domain,
+            None => continue,
+        };
+        let cert = X509::from_pem(&read_file(&file_in_conf(&entry.path)?)?)?;
+        if cert.not_after() < deadline {
+            expiring.push(domain.clone());
+        }
+    }
+    Ok(expiring)
+}
+
+/// Re-issues the server certificate for `domain` reusing its subject and SANs,
+/// and its key unless `new_key` is set.
+pub fn renew_server_cert(
+    domain: &str,
+    new_key: bool,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<IssuanceSummary> {
+    let _lock = StoreLock::acquire()?;
+    let manifest = StoreManifest::load()?;
+    manifest.check_domain(domain)?;
+    let cert_path = CertAuthConf::server_cert(domain)?;
+    let cert = X509::from_pem(&read_file(&cert_path)?)?;
+    if is_hardware_bound(domain)? {
+        if new_key {
+            Err(SimpleCAError::HardwareKey {
+                domain: domain.to_string(),
+            })?;
+        }
+        let alt_names = alt_names(&cert);
+        let alt_names: Vec<&str> = alt_names.iter().map(|x| x.as_str()).collect();
+        let issuer = issuer_name(&cert)?;
+        return renew_csr_cert(
+            domain,
+            &alt_names,
+            issuer.as_deref(),
+            guard,
+            sources,
+            verbose,
+        );
+    }
+    let key_path = CertAuthConf::server_key(domain)?;
+    let pkey = if new_key {
+        guard.check(&[&key_path])?;
+        sources.new_key(Conf::load()?.keys().server_algorithm())?
+    } else {
+        PKey::private_key_from_pem(&read_file(&key_path)?)?
+    };
+
+    let name = cert.subject_name().to_owned()?;
+    let common_name = match name.entries_by_nid(Nid::COMMONNAME).next() {
+        Some(entry) => entry.data().to_string()?,
+        None => String::new(),
+    };
+    let alt_names: Vec<String> = alt_names(&cert)
+        .into_iter()
+        .filter(|dns| *dns != common_name)
+        .collect();
+    let alt_names: Vec<&str> = alt_names.iter().map(|x| x.as_str()).collect();
+
+    let (mut renewed, ca) = issue_server_cert(
+        &name,
+        &pkey,
+        &alt_names,
+        &ValidityConf::default(),
+        issuer_name(&cert)?.as_deref(),
+        guard,
+        sources,
+        verbose,
+    )?;
+    let mut precert = None;
+    if manifest.entry(Role::ServerPrecert, Some(domain)).is_some() {
+        let pair = issue_precert_pair(&renewed, guard, sources, verbose)?;
+        renewed = pair.cert;
+        precert = Some(pair.precert);
+    }
+    if new_key {
+        write_file!(
+            &pkey.private_key_to_pem_pkcs8()?,
+            &key_path,
+            verbose,
+            "Saved new server key at: {:?}"
+        );
+        store::record(Role::ServerKey, Some(domain), &key_path, None)?;
+    }
+    write_file!(
+        &renewed.to_pem()?,
+        &cert_path,
+        verbose,
+        "Renewed server certificate at: {:?}"
+    );
+    store::record(Role::ServerCert, Some(domain), &cert_path, Some(&ca))?;
+    record_issued(&renewed, Some(&key_path), Some(&cert_path), sources.now())?;
+    write_chain_files(domain, &renewed, &ca, verbose)?;
+    let mut summary = IssuanceSummary::server(domain, &renewed)?;
+    if manifest.entry(Role::ServerReceipt, Some(domain)).is_some() {
+        write_receipt(domain, &renewed, sources, verbose)?;
+        summary = summary.file("receipt", &CertAuthConf::server_receipt(domain)?);
+    }
+    if let Some(precert) = precert {
+        write_precert(domain, &precert, &ca, verbose)?;
+        summary = summary.file("precert", &CertAuthConf::server_precert(domain)?);
+    }
+    if store::is_recorded(Role::ServerHaproxy, Some(domain))? {
+        summary = summary.file("haproxy", &CertAuthConf::server_haproxy(domain)?);
+    }
+    if store::is_recorded(Role::ServerCertDer, Some(domain))? {
+        summary = summary
+            .file("cert-der", &CertAuthConf::server_cert_der(domain)?)
+            .file("key-der", &CertAuthConf::server_key_der(domain)?);
+    }
+    run_post_issue_hooks(domain, None)?;
+    Ok(summary)
+}
+
+/// A server certificate wanted by an embedding app, see `get_or_issue`.
+#[derive(Debug, Clone, Default)]
+pub struct CertSpec {
+    pub common_name: String,
+    /// DNS names and IP addresses the certificate must cover besides the
+    /// common name.
+    pub alt_names: Vec<String>,
+    /// Validity of a newly issued certificate.
+    pub validity: ValidityConf,
+    /// Days a stored certificate must remain valid to be returned as is,
+    /// defaulting to the renewal window of the config.
+    pub min_days_left: Option<u32>,
+}
+
+impl CertSpec {
+    pub fn new(common_name: &str) -> Self {
+        CertSpec {
+            common_name: common_name.to_string(),
+            ..CertSpec::default()
+        }
+    }
+
+    pub fn alt_name(mut self, name: &str) -> Self {
+        self.alt_names.push(name.to_string());
+        self
+    }
+}
+
+/// Whether `sans` covers `name`, comparing IP addresses by value.
+fn covers(sans: &[String], name: &str) -> bool {
+    match name.parse::<IpAddr>() {
+        Ok(ip) => sans.iter().any(|san| san.parse() == Ok(ip)),
+        Err(_) => sans.iter().any(|san| san.eq_ignore_ascii_case(name)),
+    }
+}
+
+/// Returns the stored server certificate for `spec.common_name` when it
+/// covers every name of the spec, was issued by the current intermediate CA
+/// and stays valid for `spec.min_days_left`. A certificate that only fails
+/// the last two checks is renewed with its key, and anything else is issued
+/// from scratch, creating the CA first if needed.
+pub fn get_or_issue(spec: &CertSpec, sources: &Sources) -> Result<IssuedCert> {
+    let _lock = StoreLock::acquire()?;
+    let domain = spec.common_name.as_str();
+    let manifest = StoreManifest::load()?;
+    let key_path = CertAuthConf::server_key(domain)?;
+    let cert_path = CertAuthConf::server_cert(domain)?;
+    let stored = match manifest.entry(Role::ServerCert, Some(domain)) {
+        Some(entry) if key_path.exists() && cert_path.exists() => {
+            Some((entry, X509::from_pem(&read_file(&cert_path)?)?))
+        }
+        _ => None,
+    };
+    // A certificate that does not cover the spec is replaced.
+    let guard = RepoGuard {
+        replace: true,
+        ..RepoGuard::default()
+    };
+    if let Some((entry, cert)) = stored {
+        let sans = alt_names(&cert);
+        if spec.alt_names.iter().all(|name| covers(&sans, name)) {
+            let days = match spec.min_days_left {
+                Some(days) => days,
+                None => Conf::load()?.renew().within_days(),
+            };
+            let intermediate_path = CertAuthConf::intermediate_cert()?;
+            let current_issuer = match &entry.issuer {
+                Some(issuer) if intermediate_path.exists() => {
+                    let intermediate = X509::from_pem(&read_file(&intermediate_path)?)?;
+                    *issuer == cert_fingerprint(&intermediate)?
+                }
+                _ => false,
+            } || issuer_name(&cert)?.is_some();
+            if current_issuer && cert.not_after() >= sources.days_from_now(days)? {
+                return IssuedCert::from_cert(&cert, Some(&key_path), Some(&cert_path));
+            }
+            renew_server_cert(domain, false, &guard, sources, false)?;
+            let cert = X509::from_pem(&read_file(&cert_path)?)?;
+            return IssuedCert::from_cert(&cert, Some(&key_path), Some(&cert_path));
+        }
+    }
+
+    let name = Name::new(domain.to_string());
+    let alt_names: Vec<&str> = spec.alt_names.iter().map(String::as_str).collect();
+    generate_server_cert(
+        &name,
+        &alt_names,
+        &spec.validity,
+        None,
+        &guard,
+        None,
+        &ServerExtras::default(),
+        sources,
+        false,
+    )?;
+    let cert = X509::from_pem(&read_file(&cert_path)?)?;
+    IssuedCert::from_cert(&cert, Some(&key_path), Some(&cert_path))
+}
+
+/// Renews every server certificate in the store.
+pub fn renew_all(
+    new_key: bool,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<Vec<IssuanceSummary>> {
+    let _lock = StoreLock::acquire()?;
+    let manifest = StoreManifest::load()?;
+    manifest
+        .server_domains()
+        .into_iter()
+        .map(|domain| renew_server_cert(domain, new_key, guard, sources, verbose))
+        .collect()
+}
+
+/// Renews every server certificate expiring within `days`.
+pub fn renew_expiring(days: u32, sources: &Sources, verbose: bool) -> Result<Vec<String>> {
+    let expiring = expiring_server_certs(days, sources)?;
+    for domain in &expiring {
+        renew_server_cert(domain, false, &RepoGuard::default(), sources, verbose)?;
+    }
+    Ok(expiring)
+}
+
+/// Records an auto-renewal attempt, returning `false` if one ran within the last hour.
+fn claim_auto_renew() -> Result<bool> {
+    // Held so that commands started together cannot both claim the renewal.
+    let _lock = StoreLock::acquire()?;
+    with_config_dir(|mut dir| {
+        dir.push(AUTO_RENEW_STAMP);
+        let stamp = dir;
+        if let Ok(modified) = fs::metadata(&stamp).and_then(|meta| meta.modified()) {
+            let elapsed = SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or_default();
+            if elapsed < AUTO_RENEW_INTERVAL {
+                return Ok(false);
+            }
+        }
+        File::create(&stamp)?.set_modified(SystemTime::now())?;
+        Ok(true)
+    })
+}
+
+/// The file the background renewal appends its output to.
+pub fn auto_renew_log() -> Result<PathBuf> {
+    with_config_dir(|dir| Ok(dir.join(AUTO_RENEW_LOG)))
+}
+
+/// Claims this hour's automatic renewal when it is enabled in the config,
+/// returning the `within_days` window to renew, or `None` when renewal is
+/// disabled or already ran within the last hour.
+pub fn auto_renew_due() -> Result<Option<u32>> {
+    let renew = Conf::load()?.renew();
+    if !renew.auto() || !claim_auto_renew()? {
+        return Ok(None);
+    }
+    Ok(Some(renew.within_days()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::with_test_store;
+
+    #[test]
+    fn test_get_or_issue() {
+        with_test_store("get-or-issue", |_| {
+            let spec = CertSpec::new("app.test").alt_name("127.0.0.1");
+            let issued = get_or_issue(&spec, &Sources::default()).unwrap();
+            assert!(issued.key_path().unwrap().unwrap().exists());
+            assert_eq!(
+                get_or_issue(&spec, &Sources::default()).unwrap().serial,
+                issued.serial
+            );
+
+            let renewed = get_or_issue(
+                &CertSpec {
+                    min_days_left: Some(10_000),
+                    ..spec.clone()
+                },
+                &Sources::default(),
+            )
+            .unwrap();
+            assert_ne!(renewed.serial, issued.serial);
+
+            let wider = get_or_issue(&spec.alt_name("api.app.test"), &Sources::default()).unwrap();
+            assert_ne!(wider.serial, renewed.serial);
+            assert_eq!(wider.sans, vec!["app.test", "api.app.test"]);
+        });
+    }
+    #[test]
+    fn test_claim_auto_renew() {
+        with_test_store("claim-auto-renew", |home| {
+            assert!(claim_auto_renew().unwrap());
+            assert!(!claim_auto_renew().unwrap());
+
+            let stamp = File::options()
+                .write(true)
+                .open(home.join(AUTO_RENEW_STAMP))
+                .unwrap();
+            stamp
+                .set_modified(SystemTime::now() - AUTO_RENEW_INTERVAL)
+                .unwrap();
+            assert!(claim_auto_renew().unwrap());
+            assert!(!claim_auto_renew().unwrap());
+        });
+    }
+}
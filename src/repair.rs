@@ -0,0 +1,243 @@
+//! Finds server certificates issued by a replaced intermediate CA and repairs
+//! their chain bundles, e.g. after `ca` regenerated the CA.
+
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::deterministic::Sources;
+use crate::err::Result;
+use serde::Serialize;
+
+use openssl::x509::X509;
+
+use crate::certs::cert_fingerprint;
+use crate::commands::{read_file, read_root_cert, write_bundles};
+use crate::conf::{file_in_conf, CertAuthConf};
+use crate::guard::RepoGuard;
+use crate::lock::StoreLock;
+use crate::renew::renew_server_cert;
+use crate::store::{issued_by, Role, StoreManifest};
+use crate::summary::IssuanceSummary;
+use crate::tr;
+
+/// A stored server certificate that does not chain to a current
+/// intermediate CA.
+#[derive(Debug, Clone, Serialize)]
+pub struct StaleCert {
+    pub domain: String,
+    /// The archived intermediate that issued it, when it is still known.
+    pub archived_issuer: Option<PathBuf>,
+}
+
+/// What `repair` did for a stale certificate.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case", tag = "action")]
+pub enum Repair {
+    /// The chain bundles were rewritten with the archived intermediate.
+    Rebundled { domain: String, issuer: PathBuf },
+    /// The certificate was re-issued by the current intermediate.
+    Reissued { summary: IssuanceSummary },
+    /// No archived intermediate issued the certificate, so only re-issuing
+    /// can repair it.
+    Unrepairable { domain: String },
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Repair::Rebundled { domain, issuer } => write!(
+                f,
+                "{}",
+                tr!(
+                    "repair-rebundled",
+                    domain = domain.as_str(),
+                    issuer = issuer.display().to_string()
+                )
+            ),
+            Repair::Reissued { summary } => write!(f, "{}", summary),
+            Repair::Unrepairable { domain } => {
+                write!(
+                    f,
+                    "{}",
+                    tr!("repair-unrepairable", domain = domain.as_str())
+                )
+            }
+        }
+    }
+}
+
+/// Loads the stored certificates of `role` along with their paths.
+fn load_certs(manifest: &StoreManifest, role: Role) -> Result<Vec<(X509, PathBuf)>> {
+    let mut certs = Vec::new();
+    for entry in manifest.entries(role) {
+        let path = file_in_conf(&entry.path)?;
+        if let Ok(Ok(cert)) = read_file(&path).map(|pem| X509::from_pem(&pem)) {
+            certs.push((cert, path));
+        }
+    }
+    Ok(certs)
+}
+
+/// The archived intermediate that issued `cert`, if any.
+pub(crate) fn archived_issuer(cert: &X509) -> Result<Option<(X509, PathBuf)>> {
+    let manifest = StoreManifest::load()?;
+    Ok(load_certs(&manifest, Role::ArchivedIntermediateCert)?
+        .into_iter()
+        .find(|(issuer, _)| issued_by(cert, issuer)))
+}
+
+/// Every stored server certificate not issued by the current intermediate or
+/// one of the named intermediates.
+pub fn stale_certs() -> Result<Vec<StaleCert>> {
+    let manifest = StoreManifest::load()?;
+    let intermediate_path = CertAuthConf::intermediate_cert()?;
+    if !intermediate_path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut current = vec![X509::from_pem(&read_file(&intermediate_path)?)?];
+    current.extend(
+        load_certs(&manifest, Role::NamedIntermediateCert)?
+            .into_iter()
+            .map(|(intermediate, _)| intermediate),
+    );
+    let fingerprints = current
+        .iter()
+        .map(|intermediate| cert_fingerprint(intermediate))
+        .collect::<Result<Vec<String>>>()?;
+
+    let mut stale = Vec::new();
+    for entry in manifest.entries(Role::ServerCert) {
+        let domain = match &entry.name {
+            Some(domain) => domain,
+            None => continue,
+        };
+        if fingerprints
+            .iter()
+            .any(|fp| entry.issuer.as_deref() == Some(fp))
+        {
+            continue;
+        }
+        let cert = X509::from_pem(&read_file(&file_in_conf(&entry.path)?)?)?;
+        if current
+            .iter()
+            .any(|intermediate| issued_by(&cert, intermediate))
+        {
+            continue;
+        }
+        stale.push(StaleCert {
+            domain: domain.clone(),
+            archived_issuer: archived_issuer(&cert)?.map(|(_, path)| path),
+        });
+    }
+    Ok(stale)
+}
+
+/// Repairs every stale server certificate, rewriting its chain bundles with
+/// the archived intermediate that issued it, or re-issuing it with its key
+/// from the current intermediate when `reissue` is set.
+pub fn repair(
+    reissue: bool,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<Vec<Repair>> {
+    let _lock = StoreLock::acquire()?;
+    let manifest = StoreManifest::load()?;
+    let mut roots = vec![read_root_cert()?];
+    roots.extend(
+        load_certs(&manifest, Role::ArchivedCaCert)?
+            .into_iter()
+            .map(|(root, _)| root),
+    );
+
+    let mut repairs = Vec::new();
+    for stale in stale_certs()? {
+        let domain = stale.domain;
+        if reissue {
+            let summary = renew_server_cert(&domain, false, guard, sources, verbose)?;
+            repairs.push(Repair::Reissued { summary });
+            continue;
+        }
+        let cert = X509::from_pem(&read_file(&CertAuthConf::server_cert(&domain)?)?)?;
+        let issuer = archived_issuer(&cert)?.and_then(|(intermediate, path)| {
+            let root = roots.iter().find(|root| issued_by(&intermediate, root))?;
+            Some((intermediate, root.clone(), path))
+        });
+        match issuer {
+            Some((intermediate, root, path)) => {
+                write_bundles(&domain, &cert, &[intermediate, root], verbose)?;
+                repairs.push(Repair::Rebundled {
+                    domain,
+                    issuer: path,
+                });
+            }
+            None => repairs.push(Repair::Unrepairable { domain }),
+        }
+    }
+    Ok(repairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use crate::commands::fixtures::issue_test_server;
+    use crate::commands::{load_ca, ServerExtras};
+    use crate::conf::{with_test_store, ValidityConf};
+    use crate::paths::server_files;
+    use crate::store::{self, ARCHIVE_DIR};
+
+    #[test]
+    fn test_repair() {
+        with_test_store("repair", |home| {
+            let guard = RepoGuard {
+                replace: true,
+                ..RepoGuard::default()
+            };
+            let sources = Sources::default();
+            let regenerate = || {
+                load_ca(true, &ValidityConf::default(), &guard, &sources, false).unwrap();
+            };
+            issue_test_server("app.test", &[], &ServerExtras::default());
+            let files = server_files("app.test").unwrap();
+            let old_intermediate = read_file(&CertAuthConf::intermediate_cert().unwrap()).unwrap();
+            regenerate();
+
+            let stale = stale_certs().unwrap();
+            assert_eq!(stale.len(), 1);
+            assert_eq!(stale[0].domain, "app.test");
+            let archived = stale[0].archived_issuer.clone().unwrap();
+            assert_eq!(read_file(&archived).unwrap(), old_intermediate);
+
+            let repairs = repair(false, &guard, &sources, false).unwrap();
+            assert!(matches!(
+                repairs.as_slice(),
+                [Repair::Rebundled { domain, issuer }] if domain == "app.test" && *issuer == archived
+            ));
+            let cert = read_file(&files.cert).unwrap();
+            let mut fullchain = cert.clone();
+            fullchain.extend(&old_intermediate);
+            assert_eq!(read_file(&files.fullchain).unwrap(), fullchain);
+
+            let repairs = repair(true, &guard, &sources, false).unwrap();
+            assert!(matches!(repairs.as_slice(), [Repair::Reissued { .. }]));
+            assert_ne!(read_file(&files.cert).unwrap(), cert);
+            assert!(stale_certs().unwrap().is_empty());
+
+            regenerate();
+            fs::remove_dir_all(home.join(ARCHIVE_DIR)).unwrap();
+            let manifest = StoreManifest::load().unwrap();
+            for role in [Role::ArchivedCaCert, Role::ArchivedIntermediateCert] {
+                for entry in manifest.entries(role) {
+                    store::forget(role, entry.name.as_deref()).unwrap();
+                }
+            }
+            let repairs = repair(false, &guard, &sources, false).unwrap();
+            assert!(matches!(
+                repairs.as_slice(),
+                [Repair::Unrepairable { domain }] if domain == "app.test"
+            ));
+        });
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::err::Result;
+
+use openssl::error::ErrorStack;
+use openssl::pkey::{PKey, Private};
+use openssl::x509::X509;
+
+use crate::certs::pubkey_fingerprint;
+use crate::conf::{config_dir, file_in_conf};
+use crate::store::{Role, StoreManifest};
+
+const MAX_SCAN_SIZE: u64 = 1024 * 1024;
+const PEM_BEGIN: &str = "-----BEGIN ";
+const PEM_END: &str = "-----END ";
+
+/// A private key found in a scanned tree that was issued by this CA.
+#[derive(Debug)]
+pub struct LeakedKey {
+    pub path: PathBuf,
+    pub issued_as: PathBuf,
+}
+
+/// Parses a PEM private key without a passphrase. Encrypted keys fail
+/// instead of OpenSSL prompting for their passphrase on the terminal.
+fn unencrypted_key(pem: &[u8]) -> Option<PKey<Private>> {
+    PKey::private_key_from_pem_callback(pem, |_| Err(ErrorStack::get())).ok()
+}
+
+/// The role of the certificate issued for the key of `role`.
+fn cert_role(role: Role) -> Option<Role> {
+    match role {
+        Role::CaKey => Some(Role::CaCert),
+        Role::IntermediateKey => Some(Role::IntermediateCert),
+        Role::NamedIntermediateKey => Some(Role::NamedIntermediateCert),
+        Role::ServerKey => Some(Role::ServerCert),
+        Role::SignerKey => Some(Role::SignerCert),
+        _ => None,
+    }
+}
+
+/// Collects the public key fingerprints of every private key in the store.
+/// They are taken from the certificates issued for the keys where there are
+/// some, so encrypted keys count without their passphrase.
+fn issued_fingerprints() -> Result<HashMap<String, PathBuf>> {
+    let manifest = StoreManifest::load()?;
+    let mut fingerprints = HashMap::new();
+    for entry in &manifest.files {
+        if !entry.role.is_key() {
+            continue;
+        }
+        let path = file_in_conf(&entry.path)?;
+        let cert = cert_role(entry.role)
+            .and_then(|role| manifest.entry(role, entry.name.as_deref()))
+            .and_then(|cert| fs::read(file_in_conf(&cert.path).ok()?).ok())
+            .and_then(|pem| X509::from_pem(&pem).ok());
+        if let Some(public_key) = cert.and_then(|cert| cert.public_key().ok()) {
+            fingerprints.insert(pubkey_fingerprint(&public_key)?, path.clone());
+        }
+        if let Some(pkey) = fs::read(&path).ok().and_then(|pem| unencrypted_key(&pem)) {
+            fingerprints.insert(pubkey_fingerprint(&pkey)?, path);
+        }
+    }
+    Ok(fingerprints)
+}
+
+/// Extracts every PEM private key block contained in `content`.
+fn private_key_blocks(content: &str) -> Vec<&str> {
+    let mut blocks = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find(PEM_BEGIN) {
+        let block = &rest[start..];
+        let end = match block.find(PEM_END) {
+            Some(end) => end + PEM_END.len(),
+            None => break,
+        };
+        let end = match block[end..].find("-----") {
+            Some(i) => end + i + 5,
+            None => break,
+        };
+        let header = block.lines().next().unwrap_or("");
+        if header.ends_with("PRIVATE KEY-----") {
+            blocks.push(&block[..end]);
+        }
+        rest = &block[end..];
+    }
+    blocks
+}
+
+fn scan_dir(
+    dir: &Path,
+    store: &Path,
+    fingerprints: &HashMap<String, PathBuf>,
+    found: &mut Vec<LeakedKey>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            // A directory that vanished or cannot be resolved is skipped
+            // rather than ending the scan.
+            let is_store = match path.canonicalize() {
+                Ok(dir) => dir == store,
+                Err(_) => continue,
+            };
+            if entry.file_name() != ".git" && !is_store {
+                scan_dir(&path, store, fingerprints, found)?;
+            }
+            continue;
+        }
+        if !file_type.is_file() || entry.metadata()?.len() > MAX_SCAN_SIZE {
+            continue;
+        }
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        let content = String::from_utf8_lossy(&content);
+        for block in private_key_blocks(&content) {
+            // Encrypted blocks cannot be matched without their passphrase.
+            let pkey = match unencrypted_key(block.as_bytes()) {
+                Some(pkey) => pkey,
+                None => continue,
+            };
+            if let Some(issued_as) = fingerprints.get(&pubkey_fingerprint(&pkey)?) {
+                found.push(LeakedKey {
+                    path: path.clone(),
+                    issued_as: issued_as.clone(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Searches the tree under `root` for PEM private keys that belong to this CA,
+/// skipping the store itself when it is kept in the tree.
+pub fn scan_repo(root: &Path) -> Result<Vec<LeakedKey>> {
+    let fingerprints = issued_fingerprints()?;
+    let mut found = Vec::new();
+    if !fingerprints.is_empty() {
+        let store = config_dir()?.canonicalize()?;
+        scan_dir(root, &store, &fingerprints, &mut found)?;
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certs::fixtures::test_root_ca;
+    use crate::conf::with_test_home;
+    use crate::keys::KeyAlgorithm;
+    use crate::store;
+    use openssl::symm::Cipher;
+
+    #[test]
+    fn test_scan_repo() {
+        let repo = std::env::temp_dir().join(format!("simple-ca-scan-{}", std::process::id()));
+        // The store is kept in the tree, as with a project-local store.
+        let home = repo.join(".simple_ca");
+        fs::create_dir_all(&home).unwrap();
+        with_test_home(&home, || {
+            let key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+            let pem = key.private_key_to_pem_pkcs8().unwrap();
+            let key_path = home.join("app.test.key.pem");
+            fs::write(&key_path, &pem).unwrap();
+            store::record(Role::ServerKey, Some("app.test"), &key_path, None).unwrap();
+
+            let other = KeyAlgorithm::EcdsaP256.generate().unwrap();
+            fs::create_dir_all(repo.join("deploy")).unwrap();
+            let mut leaked = b"tls:\n  key: |\n".to_vec();
+            leaked.extend(&pem);
+            fs::write(repo.join("deploy/values.yaml"), leaked).unwrap();
+            fs::write(
+                repo.join("other.key"),
+                other.private_key_to_pem_pkcs8().unwrap(),
+            )
+            .unwrap();
+
+            let found = scan_repo(&repo).unwrap();
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].path, repo.join("deploy/values.yaml"));
+            assert_eq!(found[0].issued_as, key_path);
+        });
+        fs::remove_dir_all(&repo).unwrap();
+    }
+    #[test]
+    fn test_scan_repo_encrypted_ca() {
+        let repo = std::env::temp_dir().join(format!("simple-ca-scan-enc-{}", std::process::id()));
+        let home = repo.join(".simple_ca");
+        fs::create_dir_all(&home).unwrap();
+        with_test_home(&home, || {
+            let (_, ca_key, ca) = test_root_ca();
+            let key_path = home.join("ca.key.pem");
+            let encrypted = ca_key
+                .private_key_to_pem_pkcs8_passphrase(Cipher::aes_256_cbc(), b"secret")
+                .unwrap();
+            fs::write(&key_path, &encrypted).unwrap();
+            store::record(Role::CaKey, None, &key_path, None).unwrap();
+            let cert_path = home.join("ca.cert.pem");
+            fs::write(&cert_path, ca.to_pem().unwrap()).unwrap();
+            store::record(Role::CaCert, None, &cert_path, Some(&ca)).unwrap();
+
+            fs::write(
+                repo.join("root.pem"),
+                ca_key.private_key_to_pem_pkcs8().unwrap(),
+            )
+            .unwrap();
+            // Skipped without asking for its passphrase.
+            fs::write(repo.join("backup.pem"), &encrypted).unwrap();
+
+            let found = scan_repo(&repo).unwrap();
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].path, repo.join("root.pem"));
+            assert_eq!(found[0].issued_as, key_path);
+        });
+        fs::remove_dir_all(&repo).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+//! Serial numbers of issued certificates, allocated by one of several
+//! strategies for downstream systems that key off serial formats.
+
+use std::fs;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::err::Result;
+use serde::{Deserialize, Serialize};
+
+use openssl::bn::BigNum;
+use openssl::error::ErrorStack;
+use openssl::rand::rand_bytes;
+
+use crate::atomic::write_file;
+use crate::conf::file_in_conf;
+use crate::lock::StoreLock;
+
+pub(crate) const SERIALS_FILE: &str = "serials.json";
+
+/// Hands out the serial numbers of new certificates.
+pub trait SerialSource {
+    fn next_serial(&self) -> Result<BigNum>;
+}
+
+impl<S: SerialSource + ?Sized> SerialSource for Arc<S> {
+    fn next_serial(&self) -> Result<BigNum> {
+        (**self).next_serial()
+    }
+}
+
+/// How serials are allocated, set by `strategy` in the `[serials]` config.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SerialStrategy {
+    /// 159 random bits, the largest positive serial RFC 5280 allows.
+    #[serde(rename = "random-160bit")]
+    Random,
+    /// 1, 2, 3, ... counted in the serial registry.
+    #[serde(rename = "monotonic-persisted")]
+    Monotonic,
+    /// A registry counter based on the time in nanoseconds, followed by 64
+    /// random bits.
+    #[default]
+    #[serde(rename = "timestamp-prefixed")]
+    TimestampPrefixed,
+}
+
+impl SerialStrategy {
+    /// The source of the strategy, timestamps taken from `clock` or the
+    /// system clock when `None`.
+    pub fn source(&self, clock: Option<SystemTime>) -> Box<dyn SerialSource + Send + Sync> {
+        match self {
+            SerialStrategy::Random => Box::new(RandomSerial),
+            SerialStrategy::Monotonic => Box::new(MonotonicSerial),
+            SerialStrategy::TimestampPrefixed => Box::new(TimestampSerial { clock }),
+        }
+    }
+}
+
+/// The registry kept in `serials.json`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct SerialRegistry {
+    /// The counter of the last allocated serial.
+    last: u64,
+}
+
+/// Advances the counter of the registry in the config dir with `next`, which
+/// gets the last counter. Regenerating the CA keeps the registry, so counters
+/// are never handed out twice.
+fn advance_registry(next: impl Fn(u64) -> u64) -> Result<u64> {
+    let _lock = StoreLock::acquire()?;
+    let path = file_in_conf(SERIALS_FILE)?;
+    let mut registry: SerialRegistry = if path.exists() {
+        serde_json::from_slice(&fs::read(&path)?)?
+    } else {
+        SerialRegistry::default()
+    };
+    registry.last = next(registry.last);
+    write_file(&path, serde_json::to_vec_pretty(&registry)?)?;
+    Ok(registry.last)
+}
+
+/// Random serials, unique with overwhelming probability but not recorded.
+pub struct RandomSerial;
+
+impl SerialSource for RandomSerial {
+    fn next_serial(&self) -> Result<BigNum> {
+        Ok(random_serial()?)
+    }
+}
+
+/// Sequential serials that never repeat within the config dir.
+pub struct MonotonicSerial;
+
+impl SerialSource for MonotonicSerial {
+    fn next_serial(&self) -> Result<BigNum> {
+        let counter = advance_registry(|last| last + 1)?;
+        Ok(BigNum::from_slice(&counter.to_be_bytes())?)
+    }
+}
+
+/// Serials whose counter is the current time in nanoseconds, or one past the
+/// last counter when the clock is behind it.
+#[derive(Default)]
+pub struct TimestampSerial {
+    /// The current time, the system clock when `None`.
+    pub clock: Option<SystemTime>,
+}
+
+impl SerialSource for TimestampSerial {
+    fn next_serial(&self) -> Result<BigNum> {
+        let now = self.clock.unwrap_or_else(SystemTime::now);
+        let counter = advance_registry(|last| now_nanos(now).max(last + 1))?;
+        Ok(compose(counter)?)
+    }
+}
+
+/// Combines a counter with 64 random bits, keeping the serial positive and
+/// within the 20 octets RFC 5280 allows.
+fn compose(counter: u64) -> Result<BigNum, ErrorStack> {
+    let mut random = [0u8; 8];
+    rand_bytes(&mut random)?;
+    let mut bytes = counter.to_be_bytes().to_vec();
+    bytes.extend(random);
+    BigNum::from_slice(&bytes)
+}
+
+/// Nanoseconds since the epoch, 0 for a clock set before it.
+fn now_nanos(now: SystemTime) -> u64 {
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    since_epoch.as_nanos() as u64
+}
+
+/// A serial that is unique with overwhelming probability, for certificates
+/// created without a store.
+pub(crate) fn random_serial() -> Result<BigNum, ErrorStack> {
+    let mut bytes = [0u8; 20];
+    rand_bytes(&mut bytes)?;
+    bytes[0] &= 0x7f;
+    bytes[19] |= 1;
+    BigNum::from_slice(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compose() {
+        let first = compose(u64::MAX - 1).unwrap();
+        let second = compose(u64::MAX).unwrap();
+        assert!(first < second);
+        assert!(second.num_bytes() <= 16);
+        assert!(!random_serial().unwrap().is_negative());
+    }
+
+    #[test]
+    fn test_random_serial() {
+        let serial = RandomSerial.next_serial().unwrap();
+        assert!(!serial.is_negative());
+        assert!(serial.num_bytes() <= 20);
+        assert_ne!(serial, RandomSerial.next_serial().unwrap());
+    }
+}
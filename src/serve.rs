@@ -0,0 +1,84 @@
+use crate::err::Result;
+
+use openssl::nid::Nid;
+use openssl::ssl::{SslAcceptor, SslMethod};
+use openssl::x509::X509Name;
+
+use crate::acme::AcmeServer;
+use crate::api::ApiServer;
+use crate::commands::issue_server_cert;
+use crate::conf::ValidityConf;
+use crate::deterministic::Sources;
+use crate::guard::RepoGuard;
+use crate::http;
+use crate::keypool::spawn_refill;
+use crate::keys::KeyAlgorithm;
+use crate::tsa::TsaServer;
+
+/// Builds a TLS acceptor for `host` with a certificate issued by the
+/// intermediate CA. The key never leaves memory.
+pub(crate) fn tls_acceptor(host: &str, sources: &Sources) -> Result<SslAcceptor> {
+    let pkey = sources.new_key(KeyAlgorithm::EcdsaP256)?;
+    let mut name = X509Name::builder()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, host)?;
+    let name = name.build();
+    let (cert, ca) = issue_server_cert(
+        &name,
+        &pkey,
+        &[],
+        &ValidityConf::default(),
+        None,
+        &RepoGuard::default(),
+        sources,
+        false,
+    )?;
+
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
+    acceptor.set_private_key(&pkey)?;
+    acceptor.set_certificate(&cert)?;
+    acceptor.add_extra_chain_cert(ca)?;
+    acceptor.check_private_key()?;
+    Ok(acceptor.build())
+}
+
+/// Runs the ACME server on `listen`, advertising URLs under `host`.
+pub fn serve_acme(listen: &str, host: &str, plain_http: bool, sources: Sources) -> Result<()> {
+    let port = listen.rsplit(':').next().unwrap_or("443");
+    let (scheme, tls) = if plain_http {
+        ("http", None)
+    } else {
+        ("https", Some(tls_acceptor(host, &sources)?))
+    };
+    let base_url = format!("{}://{}:{}", scheme, host, port);
+    let server = AcmeServer::new(&base_url, ValidityConf::default(), sources);
+    spawn_refill()?;
+    tracing::info!("ACME directory: {}/directory", base_url);
+    http::serve(listen, tls, move |request| server.handle(request))
+}
+
+/// Runs the REST API on `listen`, requiring `token` as a bearer token if set.
+pub fn serve_api(
+    listen: &str,
+    host: &str,
+    plain_http: bool,
+    token: Option<String>,
+    sources: Sources,
+) -> Result<()> {
+    let tls = if plain_http {
+        None
+    } else {
+        Some(tls_acceptor(host, &sources)?)
+    };
+    let server = ApiServer::new(token, sources);
+    spawn_refill()?;
+    tracing::info!("Serving API on {}", listen);
+    http::serve(listen, tls, move |request| server.handle(request))
+}
+
+/// Runs a time-stamping authority on `listen`, answering RFC 3161 requests
+/// over plain HTTP like public TSAs; the timestamps are signed either way.
+pub fn serve_tsa(listen: &str, policy: &str, sources: Sources) -> Result<()> {
+    let server = TsaServer::new(policy, sources)?;
+    tracing::info!("Serving timestamps on http://{}/", listen);
+    http::serve(listen, None, move |request| server.handle(request))
+}
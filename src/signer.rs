@@ -0,0 +1,196 @@
+//! Certificates of signers next to the CA rather than of servers: an OCSP
+//! responder signing the status of the certificates its issuer issued, or a
+//! time-stamping authority signing RFC 3161 timestamps, see `tsa`. Each
+//! profile fixes the extended key usage and extensions, and its key and
+//! certificate live in the store as `signer.<profile>.key.pem` and
+//! `signer.<profile>.cert.pem`.
+
+use openssl::asn1::{Asn1Object, Asn1OctetString};
+use openssl::x509::X509Extension;
+
+use crate::cert_params::CertParams;
+use crate::certs::{create_signer_cert, ExtKeyUsage};
+use crate::commands::load_issuer;
+use crate::conf::{Conf, ValidityConf};
+use crate::deterministic::Sources;
+use crate::err::Result;
+use crate::guard::RepoGuard;
+use crate::index::record_issued;
+use crate::lock::StoreLock;
+use crate::store::{self, store_path, Role};
+use crate::summary::IssuanceSummary;
+
+/// id-pkix-ocsp-nocheck of RFC 6960, telling clients not to check the
+/// revocation of the OCSP signer itself.
+const OID_OCSP_NOCHECK: &str = "1.3.6.1.5.5.7.48.1.5";
+/// DER of the NULL the no-check extension holds.
+const DER_NULL: [u8; 2] = [0x05, 0x00];
+
+/// The kinds of signer certificates, each issued by the intermediate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignerProfile {
+    /// Signs OCSP responses, with the ocspSigning usage and no-check
+    /// extension.
+    OcspSigner,
+    /// Signs RFC 3161 timestamps, with the timeStamping usage as the only and
+    /// critical extended key usage the RFC asks for.
+    Tsa,
+}
+
+impl SignerProfile {
+    pub const ALL: [SignerProfile; 2] = [SignerProfile::OcspSigner, SignerProfile::Tsa];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SignerProfile::OcspSigner => "ocsp-signer",
+            SignerProfile::Tsa => "tsa",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SignerProfile> {
+        SignerProfile::ALL
+            .into_iter()
+            .find(|profile| profile.name() == name)
+    }
+
+    /// Appended to the organization in the subject CN.
+    fn title(&self) -> &'static str {
+        match self {
+            SignerProfile::OcspSigner => "OCSP Signer",
+            SignerProfile::Tsa => "Time Stamping Authority",
+        }
+    }
+
+    fn ext_key_usage(&self) -> ExtKeyUsage {
+        match self {
+            SignerProfile::OcspSigner => ExtKeyUsage::OcspSigning,
+            SignerProfile::Tsa => ExtKeyUsage::TimeStamping,
+        }
+    }
+
+    /// Whether the extended key usage is critical.
+    fn critical_usage(&self) -> bool {
+        match self {
+            SignerProfile::OcspSigner => false,
+            SignerProfile::Tsa => true,
+        }
+    }
+
+    /// Extensions of the profile besides the key usages.
+    fn extensions(&self) -> Result<Vec<X509Extension>> {
+        match self {
+            SignerProfile::OcspSigner => {
+                let oid = Asn1Object::from_str(OID_OCSP_NOCHECK)?;
+                let value = Asn1OctetString::new_from_bytes(&DER_NULL)?;
+                Ok(vec![X509Extension::new_from_der(&oid, false, &value)?])
+            }
+            SignerProfile::Tsa => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Issues the certificate of `profile` from the intermediate CA, or the named
+/// one of `issuer`, with a new key, both written into the store in place of
+/// any earlier ones.
+pub fn issue_signer_cert(
+    profile: SignerProfile,
+    validity: &ValidityConf,
+    issuer: Option<&str>,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<IssuanceSummary> {
+    let _lock = StoreLock::acquire()?;
+    let key_path = store_path(Role::SignerKey, Some(profile.name()))?;
+    let cert_path = store_path(Role::SignerCert, Some(profile.name()))?;
+    guard.check(&[&key_path])?;
+    let conf = Conf::load()?;
+    let validity = validity.or(&conf.validity());
+    let (ca, ca_pkey, ca_name) = load_issuer(issuer, &validity, guard, sources, verbose)?;
+
+    let branding = conf.branding();
+    let name = conf.ca().ca_name(&branding);
+    let common_name = format!(
+        "{} {}",
+        name.org.as_deref().unwrap_or_default(),
+        profile.title()
+    );
+    let subject = name
+        .copy(common_name.trim())
+        .to_x509_name_ordered(&conf.name().order())?;
+    let pkey = sources.new_key(conf.keys().server_algorithm())?;
+    let mut builder = CertParams::builder(subject, pkey.clone())
+        .issuer(ca_name, ca_pkey)
+        .valid_days(validity.server_days())
+        .ext_key_usage([profile.ext_key_usage()])
+        .digest(conf.keys().digest());
+    for extension in profile.extensions()? {
+        builder = builder.extension(extension);
+    }
+    let mut params = builder.build()?;
+    params.ttl = validity.server_ttl()?;
+    (params.not_before, params.not_after) = validity.server_window()?;
+    params.backdate = validity.backdate()?;
+    sources.configure(&mut params, conf.serials().strategy())?;
+    let cert = create_signer_cert(&params, &ca, profile.critical_usage())?;
+
+    write_file!(
+        &pkey.private_key_to_pem_pkcs8()?,
+        &key_path,
+        verbose,
+        "Saved signer key at: {:?}"
+    );
+    write_file!(
+        &cert.to_pem()?,
+        &cert_path,
+        verbose,
+        "Saved signer certificate at: {:?}"
+    );
+    store::record(Role::SignerKey, Some(profile.name()), &key_path, None)?;
+    store::record(
+        Role::SignerCert,
+        Some(profile.name()),
+        &cert_path,
+        Some(&ca),
+    )?;
+    record_issued(&cert, Some(&key_path), Some(&cert_path), sources.now())?;
+
+    Ok(IssuanceSummary::new(&cert)?
+        .file("key", &key_path)
+        .file("cert", &cert_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certs::fixtures::test_root_ca;
+    use crate::keys::KeyAlgorithm;
+    use crate::Name;
+
+    fn signer_text(profile: SignerProfile) -> String {
+        let (ca_name, ca_key, ca) = test_root_ca();
+        let key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let mut builder = CertParams::builder(Name::new("Signer").to_x509_name().unwrap(), key)
+            .issuer(ca_name, ca_key)
+            .valid_days(7)
+            .ext_key_usage([profile.ext_key_usage()]);
+        for extension in profile.extensions().unwrap() {
+            builder = builder.extension(extension);
+        }
+        let params = builder.build().unwrap();
+        let cert = create_signer_cert(&params, &ca, profile.critical_usage()).unwrap();
+        String::from_utf8(cert.to_text().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_signer_profiles() {
+        let text = signer_text(SignerProfile::from_name("ocsp-signer").unwrap());
+        assert!(text.contains("X509v3 Extended Key Usage: \n                OCSP Signing"));
+        assert!(text.contains("OCSP No Check"));
+        assert!(text.contains("Digital Signature\n"));
+
+        let text = signer_text(SignerProfile::from_name("tsa").unwrap());
+        assert!(text.contains("X509v3 Extended Key Usage: critical\n                Time Stamping"));
+        assert!(!text.contains("OCSP No Check"));
+    }
+}
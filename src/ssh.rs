@@ -0,0 +1,327 @@
+//! An SSH CA next to the X.509 one: an Ed25519 key in the store signing
+//! OpenSSH user and host certificates (`PROTOCOL.certkeys`), plus the
+//! `known_hosts` and `sshd_config` lines trusting it.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use openssl::base64;
+use openssl::pkey::{PKey, Private};
+use openssl::rand::rand_bytes;
+use openssl::sign::Signer;
+
+use crate::atomic::write_file;
+use crate::commands::read_file;
+use crate::conf::{config_dir, Conf};
+use crate::deterministic::Sources;
+use crate::err::{Result, SimpleCAError};
+use crate::guard::RepoGuard;
+use crate::lock::StoreLock;
+use crate::passphrase::{private_key_to_pem, read_private_key};
+use crate::store::{self, store_path, Role};
+
+/// Where sshd is told to find the user CA key in the generated config.
+pub const TRUSTED_USER_CA_KEYS: &str = "/etc/ssh/simple_ca_user_ca.pub";
+/// Lifetime of a user certificate unless given, a working day and then some.
+pub const DEFAULT_SSH_USER_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Lifetime of a host certificate unless given.
+pub const DEFAULT_SSH_HOST_TTL: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+const ED25519: &str = "ssh-ed25519";
+const CERT_SUFFIX: &str = "-cert-v01@openssh.com";
+/// What user certificates allow, as `ssh-keygen` grants by default, sorted
+/// as the format requires.
+const USER_EXTENSIONS: &[&str] = &[
+    "permit-X11-forwarding",
+    "permit-agent-forwarding",
+    "permit-port-forwarding",
+    "permit-pty",
+    "permit-user-rc",
+];
+
+/// Whom an SSH certificate authenticates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SshCertType {
+    /// A user logging in, principals are user names.
+    User,
+    /// A server, principals are host names.
+    Host,
+}
+
+impl SshCertType {
+    fn code(&self) -> u32 {
+        match self {
+            SshCertType::User => 1,
+            SshCertType::Host => 2,
+        }
+    }
+
+    pub fn default_ttl(&self) -> Duration {
+        match self {
+            SshCertType::User => DEFAULT_SSH_USER_TTL,
+            SshCertType::Host => DEFAULT_SSH_HOST_TTL,
+        }
+    }
+}
+
+fn put_string(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.extend((data.len() as u32).to_be_bytes());
+    buf.extend(data);
+}
+
+fn put_strings(buf: &mut Vec<u8>, items: &[&str]) {
+    let mut list = Vec::new();
+    for item in items {
+        put_string(&mut list, item.as_bytes());
+    }
+    put_string(buf, &list);
+}
+
+/// The wire encoding of an Ed25519 public key.
+fn ed25519_blob(pkey: &PKey<Private>) -> Result<Vec<u8>> {
+    let mut blob = Vec::new();
+    put_string(&mut blob, ED25519.as_bytes());
+    put_string(&mut blob, &pkey.raw_public_key()?);
+    Ok(blob)
+}
+
+/// Reads an OpenSSH public key (`<type> <base64> [comment]`) and returns it
+/// without its comment.
+pub fn read_ssh_public_key(path: &Path) -> Result<String> {
+    let content = String::from_utf8(read_file(path)?)?;
+    let invalid = || SimpleCAError::InvalidSshKey {
+        path: path.to_path_buf(),
+    };
+    let mut fields = content.split_whitespace();
+    let (kind, blob) = match (fields.next(), fields.next()) {
+        (Some(kind), Some(blob)) => (kind, blob),
+        _ => Err(invalid())?,
+    };
+    let decoded = base64::decode_block(blob).map_err(|_| invalid())?;
+    // The blob starts with the length-prefixed key type.
+    if decoded.len() < 4 + kind.len() || &decoded[4..4 + kind.len()] != kind.as_bytes() {
+        Err(invalid())?;
+    }
+    Ok(format!("{} {}", kind, blob))
+}
+
+/// A `known_hosts` line trusting host certificates signed by `ca_key` for `hosts`.
+pub fn known_hosts_line(ca_key: &str, hosts: &[&str]) -> String {
+    format!("@cert-authority {} {} simple-ca", hosts.join(","), ca_key)
+}
+
+/// sshd_config lines trusting user certificates signed by the CA key
+/// installed at `TRUSTED_USER_CA_KEYS`.
+pub fn sshd_config_snippet() -> String {
+    format!("TrustedUserCAKeys {}", TRUSTED_USER_CA_KEYS)
+}
+
+/// The SSH CA key of the store, created on first use or again with `reset`,
+/// and its public key line.
+pub fn ssh_ca(reset: bool, guard: &RepoGuard, verbose: bool) -> Result<String> {
+    let _lock = StoreLock::acquire()?;
+    let key_path = store_path(Role::SshCaKey, None)?;
+    let pub_path = store_path(Role::SshCaPub, None)?;
+    if !reset && key_path.exists() {
+        return Ok(String::from_utf8(read_file(&pub_path)?)?.trim().to_string());
+    }
+    guard.check(&[&key_path])?;
+    let pkey = PKey::generate_ed25519()?;
+    let encrypt = Conf::load()?.keys().encrypt_ca();
+    write_file(&key_path, private_key_to_pem(&pkey, encrypt)?)?;
+    store::record(Role::SshCaKey, None, &key_path, None)?;
+    let line = format!(
+        "{} {} simple-ca",
+        ED25519,
+        base64::encode_block(&ed25519_blob(&pkey)?)
+    );
+    write_file(&pub_path, format!("{}\n", line))?;
+    store::record(Role::SshCaPub, None, &pub_path, None)?;
+    if verbose {
+        tracing::info!("Saved SSH CA key at: {:?}", key_path);
+    }
+    Ok(line)
+}
+
+/// Path of the SSH CA public key, failing before `ssh_ca` created it.
+pub fn ssh_ca_public_key() -> Result<PathBuf> {
+    let path = store_path(Role::SshCaPub, None)?;
+    if !path.exists() {
+        Err(SimpleCAError::SshCaMissing { dir: config_dir()? })?;
+    }
+    Ok(path)
+}
+
+/// Where `ssh-keygen` puts the certificate of `key`: `id_ed25519.pub` gets
+/// `id_ed25519-cert.pub`.
+pub fn ssh_cert_path(key: &Path) -> PathBuf {
+    let name = key.file_name().unwrap_or_default().to_string_lossy();
+    let stem = name.strip_suffix(".pub").unwrap_or(&name);
+    key.with_file_name(format!("{}-cert.pub", stem))
+}
+
+/// Encodes and signs the certificate for the public key line `key`.
+fn sign_cert(
+    ca: &PKey<Private>,
+    key: &str,
+    cert_type: SshCertType,
+    key_id: &str,
+    principals: &[&str],
+    valid_after: u64,
+    valid_before: u64,
+) -> Result<String> {
+    let mut fields = key.split_whitespace();
+    let kind = fields.next().unwrap_or_default();
+    let blob = base64::decode_block(fields.next().unwrap_or_default())?;
+    let cert_kind = format!("{}{}", kind, CERT_SUFFIX);
+    let mut nonce = [0; 32];
+    rand_bytes(&mut nonce)?;
+    let mut serial = [0; 8];
+    rand_bytes(&mut serial)?;
+
+    let mut cert = Vec::new();
+    put_string(&mut cert, cert_kind.as_bytes());
+    put_string(&mut cert, &nonce);
+    // The key fields follow the type in the public key blob, in the order
+    // the certificate wants them.
+    cert.extend(&blob[4 + kind.len()..]);
+    cert.extend(serial);
+    cert.extend(cert_type.code().to_be_bytes());
+    put_string(&mut cert, key_id.as_bytes());
+    put_strings(&mut cert, principals);
+    cert.extend(valid_after.to_be_bytes());
+    cert.extend(valid_before.to_be_bytes());
+    // No critical options.
+    put_string(&mut cert, &[]);
+    let mut extensions = Vec::new();
+    if cert_type == SshCertType::User {
+        for extension in USER_EXTENSIONS {
+            put_string(&mut extensions, extension.as_bytes());
+            put_string(&mut extensions, &[]);
+        }
+    }
+    put_string(&mut cert, &extensions);
+    // Reserved.
+    put_string(&mut cert, &[]);
+    put_string(&mut cert, &ed25519_blob(ca)?);
+
+    let mut signer = Signer::new_without_digest(ca)?;
+    let mut signature = Vec::new();
+    put_string(&mut signature, ED25519.as_bytes());
+    put_string(&mut signature, &signer.sign_oneshot_to_vec(&cert)?);
+    put_string(&mut cert, &signature);
+    Ok(format!(
+        "{} {} {}",
+        cert_kind,
+        base64::encode_block(&cert),
+        key_id
+    ))
+}
+
+/// Signs a certificate of `cert_type` for the OpenSSH public key at
+/// `key_path`, valid for `principals` from the time of `sources` for `ttl`,
+/// and writes it to `dest`, by default next to the key. The key ID defaults
+/// to the first principal. At least one principal is required, as a
+/// certificate without any is valid for every user or host. Returns the path
+/// of the certificate.
+pub fn sign_ssh_key(
+    key_path: &Path,
+    cert_type: SshCertType,
+    key_id: Option<&str>,
+    principals: &[&str],
+    ttl: Option<Duration>,
+    dest: Option<&Path>,
+    sources: &Sources,
+) -> Result<PathBuf> {
+    if principals.iter().all(|principal| principal.is_empty()) {
+        Err(SimpleCAError::NoSshPrincipals)?;
+    }
+    let key = read_ssh_public_key(key_path)?;
+    if key
+        .split(' ')
+        .next()
+        .unwrap_or_default()
+        .ends_with(CERT_SUFFIX)
+    {
+        Err(SimpleCAError::InvalidSshKey {
+            path: key_path.to_path_buf(),
+        })?;
+    }
+    ssh_ca_public_key()?;
+    let ca = read_private_key(&store_path(Role::SshCaKey, None)?)?;
+    let valid_after = sources.now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let valid_before = valid_after.saturating_add(ttl.unwrap_or_else(|| cert_type.default_ttl()));
+    let key_id = key_id.or(principals.first().copied()).unwrap_or_default();
+    let cert = sign_cert(
+        &ca,
+        &key,
+        cert_type,
+        key_id,
+        principals,
+        valid_after.as_secs(),
+        valid_before.as_secs(),
+    )?;
+    let dest = dest
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| ssh_cert_path(key_path));
+    write_file(&dest, format!("{}\n", cert))?;
+    Ok(dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::sign::Verifier;
+
+    #[test]
+    fn test_sign_cert() {
+        let ca = PKey::generate_ed25519().unwrap();
+        let user = PKey::generate_ed25519().unwrap();
+        let key = format!(
+            "{} {}",
+            ED25519,
+            base64::encode_block(&ed25519_blob(&user).unwrap())
+        );
+        let line = sign_cert(&ca, &key, SshCertType::User, "alice", &["alice"], 0, 60).unwrap();
+        let fields: Vec<&str> = line.split(' ').collect();
+        assert_eq!(fields[0], "ssh-ed25519-cert-v01@openssh.com");
+        assert_eq!(fields[2], "alice");
+
+        // The signature, the last string, covers everything before it.
+        let cert = base64::decode_block(fields[1]).unwrap();
+        let signature_len = 4 + 4 + ED25519.len() + 4 + 64;
+        let (signed, signature) = cert.split_at(cert.len() - signature_len);
+        let mut verifier = Verifier::new_without_digest(&ca).unwrap();
+        assert!(verifier
+            .verify_oneshot(&signature[signature_len - 64..], signed)
+            .unwrap());
+        assert_eq!(
+            ssh_cert_path(Path::new("keys/id_ed25519.pub")),
+            Path::new("keys/id_ed25519-cert.pub")
+        );
+    }
+
+    #[test]
+    fn test_sign_ssh_key_needs_principals() {
+        let err = sign_ssh_key(
+            Path::new("id_ed25519.pub"),
+            SshCertType::User,
+            None,
+            &[],
+            None,
+            None,
+            &Sources::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, SimpleCAError::NoSshPrincipals));
+    }
+
+    #[test]
+    fn test_known_hosts_line() {
+        let key = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIE";
+        assert_eq!(
+            known_hosts_line(key, &["*.dev.test", "10.0.0.*"]),
+            "@cert-authority *.dev.test,10.0.0.* ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIE simple-ca"
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::err::Result;
+use serde::Serialize;
+
+use openssl::x509::X509;
+
+use crate::certs::cert_fingerprint;
+use crate::commands::read_file;
+use crate::conf::{config_dir, file_in_conf, Conf};
+use crate::datetime::{format_asn1, format_rfc3339};
+use crate::i18n::pad;
+use crate::index::issued_certs;
+use crate::inspect::format_name;
+use crate::perms::exposed_keys;
+use crate::store::{Role, StoreManifest, ARCHIVE_DIR};
+use crate::tr;
+
+/// A CA certificate in the store and how many stored certificates it issued.
+#[derive(Debug, Clone, Serialize)]
+pub struct IssuerStatus {
+    pub subject: String,
+    pub sha256_fingerprint: String,
+    pub not_after: String,
+    pub issued: usize,
+}
+
+/// Health overview of the store, printed by `store status`.
+#[derive(Debug, Clone, Serialize)]
+pub struct StoreStatus {
+    pub dir: PathBuf,
+    /// Current certificates by state, from the issuance index.
+    pub valid: usize,
+    pub expiring: usize,
+    pub expired: usize,
+    /// Index entries whose files were overwritten by a later issuance.
+    pub superseded: usize,
+    /// Superseded certificates that have not expired. Nothing revokes them,
+    /// so clients keep trusting them until they expire.
+    pub pending_revocations: usize,
+    /// Files of the store and their size, archived certificates included but
+    /// not the other profiles or the key pool.
+    pub files: usize,
+    pub disk_bytes: u64,
+    /// Issuance times from the index, or the start of validity of entries
+    /// recorded before the index kept them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oldest_issuance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub newest_issuance: Option<String>,
+    pub issuers: Vec<IssuerStatus>,
+    /// Keys other users can access, see `exposed_keys`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exposed_keys: Vec<PathBuf>,
+}
+
+/// Collects the files under `dir`, recursing into subdirectories and
+/// skipping symlinks.
+fn tree_files(dir: &Path, files: &mut HashSet<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            tree_files(&entry.path(), files)?;
+        } else if file_type.is_file() {
+            files.insert(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Counts the files of the store in `dir` and adds up their size: the files
+/// directly in it, those the manifest records and everything in `archive/`.
+/// Other subdirectories hold the other profiles and the key pool.
+fn disk_usage(dir: &Path, manifest: &StoreManifest) -> Result<(usize, u64)> {
+    let mut paths = HashSet::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            paths.insert(entry.path());
+        }
+    }
+    for entry in &manifest.files {
+        paths.insert(file_in_conf(&entry.path)?);
+    }
+    let archive = dir.join(ARCHIVE_DIR);
+    if archive.is_dir() {
+        tree_files(&archive, &mut paths)?;
+    }
+    let (mut files, mut bytes) = (0, 0);
+    for path in paths {
+        match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.is_file() => {
+                files += 1;
+                bytes += meta.len();
+            }
+            _ => {}
+        }
+    }
+    Ok((files, bytes))
+}
+
+/// Gathers the status of the store, counting certificates expiring within
+/// the renewal window as expiring.
+pub fn store_status() -> Result<StoreStatus> {
+    let dir = config_dir()?;
+    let now = SystemTime::now();
+    let within = Duration::from_secs(86_400 * u64::from(Conf::load()?.renew().within_days()));
+    let (now, soon) = (format_rfc3339(now), format_rfc3339(now + within));
+
+    let certs = issued_certs()?;
+    let mut seen = HashSet::new();
+    let (mut valid, mut expiring, mut expired, mut superseded) = (0, 0, 0, 0);
+    let mut pending_revocations = 0;
+    for cert in certs.iter().rev() {
+        // Issuing again for the same name overwrites the files of the earlier entry.
+        if let Some(path) = &cert.cert {
+            if !seen.insert(path) {
+                superseded += 1;
+                if cert.not_after > now {
+                    pending_revocations += 1;
+                }
+                continue;
+            }
+        }
+        if cert.not_after <= now {
+            expired += 1;
+        } else if cert.not_after <= soon {
+            expiring += 1;
+        } else {
+            valid += 1;
+        }
+    }
+
+    let manifest = StoreManifest::load()?;
+    let (files, disk_bytes) = if dir.exists() {
+        disk_usage(&dir, &manifest)?
+    } else {
+        (0, 0)
+    };
+
+    let issuance_times = || {
+        certs
+            .iter()
+            .map(|cert| cert.issued_at.as_ref().unwrap_or(&cert.not_before))
+    };
+
+    let mut issued: BTreeMap<&str, usize> = BTreeMap::new();
+    for entry in &manifest.files {
+        if matches!(
+            entry.role,
+            Role::IntermediateCert | Role::NamedIntermediateCert | Role::ServerCert
+        ) {
+            if let Some(issuer) = &entry.issuer {
+                *issued.entry(issuer).or_default() += 1;
+            }
+        }
+    }
+    let mut issuers = Vec::new();
+    for role in [
+        Role::CaCert,
+        Role::IntermediateCert,
+        Role::NamedIntermediateCert,
+    ] {
+        for entry in manifest.entries(role) {
+            let cert = X509::from_pem(&read_file(&file_in_conf(&entry.path)?)?)?;
+            let fingerprint = cert_fingerprint(&cert)?;
+            issuers.push(IssuerStatus {
+                subject: format_name(cert.subject_name())?,
+                issued: issued.get(fingerprint.as_str()).copied().unwrap_or(0),
+                sha256_fingerprint: fingerprint,
+                not_after: format_asn1(cert.not_after())?,
+            });
+        }
+    }
+
+    Ok(StoreStatus {
+        dir,
+        valid,
+        expiring,
+        expired,
+        superseded,
+        pending_revocations,
+        files,
+        disk_bytes,
+        oldest_issuance: issuance_times().min().cloned(),
+        newest_issuance: issuance_times().max().cloned(),
+        issuers,
+        exposed_keys: exposed_keys()?,
+    })
+}
+
+impl fmt::Display for StoreStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = |id: &str| pad(&format!("{}:", tr!(id)), 13);
+        writeln!(f, "{} {}", label("status-store"), self.dir.display())?;
+        let counts = tr!(
+            "status-counts",
+            valid = self.valid,
+            expiring = self.expiring,
+            expired = self.expired,
+            superseded = self.superseded
+        );
+        writeln!(f, "{} {}", label("status-certificates"), counts)?;
+        let pending = tr!(
+            "status-revocations-value",
+            pending = self.pending_revocations
+        );
+        writeln!(f, "{} {}", label("status-revocations"), pending)?;
+        let usage = tr!(
+            "status-disk-usage-value",
+            bytes = self.disk_bytes,
+            files = self.files
+        );
+        write!(f, "{} {}", label("status-disk-usage"), usage)?;
+        if let (Some(oldest), Some(newest)) = (&self.oldest_issuance, &self.newest_issuance) {
+            let range = tr!(
+                "status-issued-range",
+                oldest = oldest.as_str(),
+                newest = newest.as_str()
+            );
+            write!(f, "\n{} {}", label("status-issued"), range)?;
+        }
+        for issuer in &self.issuers {
+            let value = tr!(
+                "status-issuer-value",
+                subject = issuer.subject.as_str(),
+                not_after = issuer.not_after.as_str(),
+                issued = issuer.issued
+            );
+            write!(f, "\n{} {}", label("status-issuer"), value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fixtures::issue_test_server;
+    use crate::commands::ServerExtras;
+    use crate::conf::with_test_store;
+
+    #[test]
+    fn test_store_status() {
+        with_test_store("status", |home| {
+            for domain in ["app.test", "api.test", "app.test"] {
+                issue_test_server(domain, &[], &ServerExtras::default());
+            }
+            let status = store_status().unwrap();
+            assert_eq!(status.dir, home);
+            assert_eq!((status.expiring, status.expired), (0, 0));
+            assert_eq!(status.superseded, 1);
+            // The root and the intermediate are in the index too.
+            assert_eq!(status.valid, 4);
+            let issued: Vec<usize> = status.issuers.iter().map(|i| i.issued).collect();
+            assert_eq!(issued, [1, 2]);
+            assert!(status.files > 0 && status.disk_bytes > 0);
+            assert!(status.exposed_keys.is_empty());
+            // The earlier app.test certificate is superseded but unexpired.
+            assert_eq!(status.pending_revocations, 1);
+            let certs = issued_certs().unwrap();
+            assert_eq!(status.oldest_issuance, certs[0].issued_at);
+            assert_eq!(status.newest_issuance, certs[certs.len() - 1].issued_at);
+
+            // Archived files count, other profiles and the key pool do not.
+            let nested = home.join(ARCHIVE_DIR).join("nested");
+            fs::create_dir_all(&nested).unwrap();
+            fs::write(nested.join("old.cert.pem"), [0; 100]).unwrap();
+            for dir in ["other-profile", "key-pool"] {
+                fs::create_dir_all(home.join(dir)).unwrap();
+                fs::write(home.join(dir).join("ca.key.pem"), [0; 50]).unwrap();
+            }
+            let nested = store_status().unwrap();
+            assert_eq!(nested.files, status.files + 1);
+            assert_eq!(nested.disk_bytes, status.disk_bytes + 100);
+        });
+    }
+}
@@ -0,0 +1,488 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use openssl::x509::X509;
+
+use crate::atomic::write_file;
+use crate::backup::is_contained;
+use crate::certs::cert_fingerprint;
+use crate::conf::{config_dir, file_in_conf};
+use crate::err::{Result, SimpleCAError};
+use crate::guard::RepoGuard;
+use crate::hooks::warn_unapproved_hook;
+use crate::index::INDEX_FILE;
+use crate::lock::StoreLock;
+use crate::paths::{domain_from_legacy, reversed_domain};
+use crate::suggest::{did_you_mean, hint};
+
+pub(crate) const STORE_MANIFEST: &str = "store.json";
+pub(crate) const CONFIG_FILE: &str = "config";
+/// Where replaced CA certificates are kept, under the store.
+pub(crate) const ARCHIVE_DIR: &str = "archive";
+
+/// Version of the on-disk layout described by `store.json`.
+pub const STORE_LAYOUT_VERSION: u32 = 1;
+
+/// What a file in the store is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    CaKey,
+    CaCert,
+    IntermediateKey,
+    IntermediateCert,
+    /// The key of an additional intermediate, keyed by its name.
+    NamedIntermediateKey,
+    /// An additional intermediate created with `intermediate create`, keyed
+    /// by its name.
+    NamedIntermediateCert,
+    /// A named intermediate followed by its issuers up to the root.
+    NamedIntermediateChain,
+    /// The intermediate certificate signed by a second root.
+    CrossIntermediateCert,
+    /// The cross-signed intermediate followed by the second root.
+    CrossChain,
+    ServerKey,
+    ServerCert,
+    ServerFullchain,
+    ServerChain,
+    ServerReceipt,
+    ServerPrecert,
+    /// CSR of a server key held in hardware, re-signed on renewal.
+    ServerCsr,
+    /// Attestation statement submitted with the CSR.
+    ServerAttestation,
+    ServerPairReceipt,
+    ServerDhparam,
+    ServerTicketKey,
+    /// Key, certificate and intermediates in one file, as HAProxy loads them.
+    ServerHaproxy,
+    /// The certificate in DER, for Java tooling and embedded stacks.
+    ServerCertDer,
+    /// The key as DER encoded PKCS#8.
+    ServerKeyDer,
+    CtLogKey,
+    /// Where the root key went while the root is offline.
+    OfflineRoot,
+    /// Signature over the config, by the root key or a trusted signer.
+    ConfigSignature,
+    /// Public keys trusted to sign the config besides the root key.
+    ConfigSigners,
+    /// Signature of the root key over the trusted signers.
+    ConfigSignersSignature,
+    /// The key of the SSH CA.
+    SshCaKey,
+    /// The SSH CA public key, in OpenSSH format.
+    SshCaPub,
+    /// The key of a signer like the OCSP signer, named by its profile.
+    SignerKey,
+    /// The certificate of a signer, named by its profile.
+    SignerCert,
+    /// A replaced root certificate, named by its SHA-256 fingerprint.
+    ArchivedCaCert,
+    /// A replaced intermediate certificate, named by its SHA-256 fingerprint.
+    ArchivedIntermediateCert,
+}
+
+impl Role {
+    pub fn is_key(&self) -> bool {
+        matches!(
+            self,
+            Role::CaKey
+                | Role::IntermediateKey
+                | Role::NamedIntermediateKey
+                | Role::ServerKey
+                | Role::ServerTicketKey
+                | Role::ServerHaproxy
+                | Role::ServerKeyDer
+                | Role::CtLogKey
+                | Role::SshCaKey
+                | Role::SignerKey
+        )
+    }
+
+    /// File name used for the role before any manifest entry exists.
+    pub fn default_file(&self, name: Option<&str>) -> String {
+        let name = name.map(reversed_domain).unwrap_or_default();
+        match self {
+            Role::CaKey => "ca.key.pem".to_string(),
+            Role::CaCert => "ca.cert.pem".to_string(),
+            Role::IntermediateKey => "intermediate.key.pem".to_string(),
+            Role::IntermediateCert => "intermediate.cert.pem".to_string(),
+            Role::NamedIntermediateKey => format!("intermediate.{}.key.pem", name),
+            Role::NamedIntermediateCert => format!("intermediate.{}.cert.pem", name),
+            Role::NamedIntermediateChain => format!("intermediate.{}.chain.pem", name),
+            Role::CrossIntermediateCert => "intermediate.cross.cert.pem".to_string(),
+            Role::CrossChain => "intermediate.cross.chain.pem".to_string(),
+            Role::ServerKey => format!("{}.key.pem", name),
+            Role::ServerCert => format!("{}.cert.pem", name),
+            Role::ServerFullchain => format!("{}.fullchain.pem", name),
+            Role::ServerChain => format!("{}.chain.pem", name),
+            Role::ServerReceipt => format!("{}.receipt.pem", name),
+            Role::ServerPrecert => format!("{}.precert.pem", name),
+            Role::ServerCsr => format!("{}.csr.pem", name),
+            Role::ServerAttestation => format!("{}.attestation", name),
+            Role::ServerPairReceipt => format!("{}.pair.json", name),
+            Role::ServerDhparam => format!("{}.dhparam.pem", name),
+            Role::ServerTicketKey => format!("{}.ticket.key", name),
+            Role::ServerHaproxy => format!("{}.haproxy.pem", name),
+            Role::ServerCertDer => format!("{}.crt", name),
+            Role::ServerKeyDer => format!("{}.key", name),
+            Role::CtLogKey => "ct-log.key.pem".to_string(),
+            Role::OfflineRoot => "ca.key.offline.json".to_string(),
+            Role::ConfigSignature => "config.sig".to_string(),
+            Role::ConfigSigners => "config.signers.pem".to_string(),
+            Role::ConfigSignersSignature => "config.signers.sig".to_string(),
+            Role::SshCaKey => "ssh_ca.key.pem".to_string(),
+            Role::SshCaPub => "ssh_ca.pub".to_string(),
+            Role::SignerKey => format!("signer.{}.key.pem", name),
+            Role::SignerCert => format!("signer.{}.cert.pem", name),
+            Role::ArchivedCaCert => format!("{}/ca.{}.cert.pem", ARCHIVE_DIR, short(&name)),
+            Role::ArchivedIntermediateCert => {
+                format!("{}/intermediate.{}.cert.pem", ARCHIVE_DIR, short(&name))
+            }
+        }
+    }
+}
+
+/// The first 16 characters of a fingerprint, enough to tell archived
+/// certificates apart in file names.
+fn short(fingerprint: &str) -> &str {
+    fingerprint.get(..16).unwrap_or(fingerprint)
+}
+
+/// A single file in the store, with paths relative to the config dir.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StoreEntry {
+    pub role: Role,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub path: String,
+    /// SHA-256 fingerprint of the certificate that issued this one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+}
+
+/// The `store.json` manifest describing the layout of the config dir.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StoreManifest {
+    pub version: u32,
+    pub files: Vec<StoreEntry>,
+}
+
+impl Default for StoreManifest {
+    fn default() -> StoreManifest {
+        StoreManifest {
+            version: STORE_LAYOUT_VERSION,
+            files: Vec::new(),
+        }
+    }
+}
+
+fn read_cert(path: &Path) -> Option<X509> {
+    X509::from_pem(&fs::read(path).ok()?).ok()
+}
+
+pub(crate) fn issued_by(cert: &X509, issuer: &X509) -> bool {
+    issuer
+        .public_key()
+        .and_then(|key| cert.verify(&key))
+        .unwrap_or(false)
+}
+
+impl StoreManifest {
+    /// Loads the manifest, migrating older layouts when necessary.
+    pub fn load() -> Result<StoreManifest> {
+        let dir = config_dir()?;
+        if dir.join(STORE_MANIFEST).exists() {
+            StoreManifest::load_from(&dir)
+        } else if dir.exists() {
+            let _lock = StoreLock::acquire()?;
+            let manifest = StoreManifest::migrate_unversioned(&dir)?;
+            manifest.save()?;
+            Ok(manifest)
+        } else {
+            Ok(StoreManifest::default())
+        }
+    }
+
+    /// Reads the manifest of the store located in `dir`.
+    pub fn load_from(dir: &Path) -> Result<StoreManifest> {
+        let manifest: StoreManifest = serde_json::from_slice(&fs::read(dir.join(STORE_MANIFEST))?)?;
+        if manifest.version > STORE_LAYOUT_VERSION {
+            Err(SimpleCAError::UnsupportedStoreVersion {
+                version: manifest.version,
+            })?;
+        }
+        Ok(manifest)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        self.save_to(&config_dir()?)
+    }
+
+    pub fn save_to(&self, dir: &Path) -> Result<()> {
+        write_file(dir.join(STORE_MANIFEST), serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Entries stored inside the store directory, which can be relocated with it.
+    pub(crate) fn relocatable(&self) -> StoreManifest {
+        StoreManifest {
+            version: self.version,
+            files: self
+                .files
+                .iter()
+                .filter(|entry| Path::new(&entry.path).is_relative())
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Builds a manifest for a config dir written before `store.json` existed.
+    fn migrate_unversioned(dir: &Path) -> Result<StoreManifest> {
+        let mut manifest = StoreManifest::default();
+        let ca = read_cert(&dir.join(Role::CaCert.default_file(None)));
+        let intermediate = read_cert(&dir.join(Role::IntermediateCert.default_file(None)));
+
+        for entry in fs::read_dir(dir)? {
+            let file_name = entry?.file_name().to_string_lossy().to_string();
+            let (role, name) = match file_name.as_str() {
+                "ca.key.pem" => (Role::CaKey, None),
+                "ca.cert.pem" => (Role::CaCert, None),
+                "intermediate.key.pem" => (Role::IntermediateKey, None),
+                "intermediate.cert.pem" => (Role::IntermediateCert, None),
+                _ => match file_name.strip_suffix(".key.pem") {
+                    Some(stem) => (Role::ServerKey, Some(domain_from_legacy(stem))),
+                    None => match file_name.strip_suffix(".cert.pem") {
+                        Some(stem) => (Role::ServerCert, Some(domain_from_legacy(stem))),
+                        None => continue,
+                    },
+                },
+            };
+            let issuer = if role.is_key() {
+                None
+            } else {
+                let cert = read_cert(&dir.join(&file_name));
+                let issuer = match role {
+                    Role::CaCert | Role::IntermediateCert => ca.as_ref(),
+                    _ => intermediate.as_ref(),
+                };
+                match (cert, issuer) {
+                    (Some(cert), Some(issuer)) if issued_by(&cert, issuer) => {
+                        Some(cert_fingerprint(issuer)?)
+                    }
+                    _ => None,
+                }
+            };
+            manifest.files.push(StoreEntry {
+                role,
+                name,
+                path: file_name,
+                issuer,
+            });
+        }
+        Ok(manifest)
+    }
+
+    pub fn entry(&self, role: Role, name: Option<&str>) -> Option<&StoreEntry> {
+        self.files
+            .iter()
+            .find(|entry| entry.role == role && entry.name.as_deref() == name)
+    }
+
+    pub fn entries(&self, role: Role) -> impl Iterator<Item = &StoreEntry> {
+        self.files.iter().filter(move |entry| entry.role == role)
+    }
+
+    /// Names of the domains with a server certificate in the store.
+    pub fn server_domains(&self) -> Vec<&str> {
+        self.entries(Role::ServerCert)
+            .filter_map(|entry| entry.name.as_deref())
+            .collect()
+    }
+
+    /// Names of the intermediates created with `intermediate create`.
+    pub fn intermediate_names(&self) -> Vec<&str> {
+        self.entries(Role::NamedIntermediateCert)
+            .filter_map(|entry| entry.name.as_deref())
+            .collect()
+    }
+
+    /// Fails with a suggestion when `domain` has no server certificate.
+    pub(crate) fn check_domain(&self, domain: &str) -> Result<()> {
+        if self.entry(Role::ServerCert, Some(domain)).is_none() {
+            Err(SimpleCAError::UnknownDomain {
+                domain: domain.to_string(),
+                hint: hint(did_you_mean(domain, self.server_domains())),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Adds or replaces the entry for `role`/`name`.
+    pub fn insert(&mut self, entry: StoreEntry) {
+        self.remove(entry.role, entry.name.as_deref());
+        self.files.push(entry);
+    }
+
+    /// Removes the entry for `role`/`name`, if any.
+    pub fn remove(&mut self, role: Role, name: Option<&str>) {
+        self.files
+            .retain(|entry| !(entry.role == role && entry.name.as_deref() == name));
+    }
+}
+
+/// Resolves the path of a store file, preferring the manifest over the default layout.
+pub fn store_path(role: Role, name: Option<&str>) -> Result<PathBuf> {
+    let manifest = StoreManifest::load()?;
+    match manifest.entry(role, name) {
+        Some(entry) => file_in_conf(&entry.path),
+        None => file_in_conf(&role.default_file(name)),
+    }
+}
+
+/// Whether the manifest has an entry for the file, without migrating or
+/// creating a manifest that does not exist yet.
+pub(crate) fn is_recorded(role: Role, name: Option<&str>) -> Result<bool> {
+    let dir = config_dir()?;
+    if !dir.join(STORE_MANIFEST).exists() {
+        return Ok(false);
+    }
+    Ok(StoreManifest::load_from(&dir)?.entry(role, name).is_some())
+}
+
+/// Records a file written into the store, along with the certificate that issued it.
+pub fn record(role: Role, name: Option<&str>, path: &Path, issuer: Option<&X509>) -> Result<()> {
+    let _lock = StoreLock::acquire()?;
+    let dir = config_dir()?;
+    let path = path.strip_prefix(&dir).unwrap_or(path);
+    tracing::debug!(
+        "Recording {:?} of {} at {:?}",
+        role,
+        name.unwrap_or("the CA"),
+        path
+    );
+    let mut manifest = StoreManifest::load()?;
+    manifest.insert(StoreEntry {
+        role,
+        name: name.map(|name| name.to_string()),
+        path: path.to_string_lossy().to_string(),
+        issuer: issuer.map(|cert| cert_fingerprint(cert)).transpose()?,
+    });
+    manifest.save()
+}
+
+/// Removes the entry of a file deleted from the store.
+pub(crate) fn forget(role: Role, name: Option<&str>) -> Result<()> {
+    let _lock = StoreLock::acquire()?;
+    let mut manifest = StoreManifest::load()?;
+    manifest.remove(role, name);
+    manifest.save()
+}
+
+/// Keeps a copy of the root or intermediate certificate at `path` before it
+/// is replaced, so the certificates it issued can still be bundled with it.
+pub(crate) fn archive_cert(role: Role, path: &Path) -> Result<()> {
+    let cert = match read_cert(path) {
+        Some(cert) => cert,
+        None => return Ok(()),
+    };
+    let fingerprint = cert_fingerprint(&cert)?;
+    let archived = file_in_conf(&role.default_file(Some(&fingerprint)))?;
+    if let Some(parent) = archived.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(path, &archived)?;
+    record(role, Some(&fingerprint), &archived, None)
+}
+
+fn copy_store(src: &Path, dest: &Path, manifest: &StoreManifest) -> Result<()> {
+    // The manifest of an imported store is untrusted, none of its paths may
+    // leave either store.
+    if let Some(entry) = manifest
+        .files
+        .iter()
+        .find(|entry| !is_contained(&entry.path))
+    {
+        Err(SimpleCAError::InvalidStore {
+            dir: src.to_path_buf(),
+            msg: format!("{:?} is outside of it", entry.path),
+        })?;
+    }
+    fs::create_dir_all(dest)?;
+    for entry in &manifest.files {
+        let target = dest.join(&entry.path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(src.join(&entry.path), target)?;
+    }
+    for file in [CONFIG_FILE, INDEX_FILE] {
+        if src.join(file).exists() {
+            fs::copy(src.join(file), dest.join(file))?;
+        }
+    }
+    manifest.save_to(dest)
+}
+
+/// Copies the store, its manifest and config into `dest` as a relocatable directory.
+pub fn export_store(dest: &Path, guard: &RepoGuard) -> Result<()> {
+    let _lock = StoreLock::acquire()?;
+    let manifest = StoreManifest::load()?.relocatable();
+    let keys: Vec<PathBuf> = manifest
+        .files
+        .iter()
+        .filter(|entry| entry.role.is_key())
+        .map(|entry| dest.join(&entry.path))
+        .collect();
+    fs::create_dir_all(dest)?;
+    guard.check(&keys.iter().map(|key| key.as_path()).collect::<Vec<_>>())?;
+    copy_store(&config_dir()?, dest, &manifest)
+}
+
+/// Replaces the current store with one previously written by `export_store`.
+pub fn import_store(src: &Path, force: bool) -> Result<()> {
+    let _lock = StoreLock::acquire()?;
+    let manifest = StoreManifest::load_from(src)?.relocatable();
+    check_replace(force)?;
+    copy_store(src, &config_dir()?, &manifest)?;
+    warn_unapproved_hook();
+    Ok(())
+}
+
+/// Fails unless the store has no CA yet or `force` allows replacing it.
+pub(crate) fn check_replace(force: bool) -> Result<()> {
+    let current = StoreManifest::load()?;
+    let has_ca = [Role::CaKey, Role::OfflineRoot]
+        .iter()
+        .any(|role| current.entry(*role, None).is_some());
+    if !force && has_ca {
+        Err(SimpleCAError::StoreExists { dir: config_dir()? })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_store_stays_inside() {
+        let dir = std::env::temp_dir().join(format!("simple-ca-copy-{}", std::process::id()));
+        let manifest = StoreManifest {
+            version: STORE_LAYOUT_VERSION,
+            files: vec![StoreEntry {
+                role: Role::ServerKey,
+                name: Some("app.test".to_string()),
+                path: "../app.key.pem".to_string(),
+                issuer: None,
+            }],
+        };
+        let err = copy_store(&dir.join("src"), &dir.join("dest"), &manifest).unwrap_err();
+        assert!(matches!(err, SimpleCAError::InvalidStore { .. }));
+        assert!(!dir.exists());
+    }
+}
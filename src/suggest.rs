@@ -0,0 +1,35 @@
+/// Picks the candidate closest to a misspelled `input`, if any is close enough.
+pub(crate) fn did_you_mean<'a, I>(input: &str, candidates: I) -> Option<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    candidates
+        .into_iter()
+        .map(|candidate| (strsim::jaro_winkler(input, candidate), candidate))
+        .filter(|(confidence, _)| *confidence > 0.8)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+/// Formats a suggestion as a sentence to append to an error message.
+pub(crate) fn hint(suggestion: Option<String>) -> String {
+    match suggestion {
+        Some(suggestion) => format!(" Did you mean {:?}?", suggestion),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_did_you_mean() {
+        let domains = ["api.example.test", "www.example.test", "localhost"];
+        assert_eq!(
+            did_you_mean("api.exmaple.test", domains),
+            Some("api.example.test".to_string())
+        );
+        assert_eq!(did_you_mean("db.internal", domains), None);
+    }
+}
@@ -0,0 +1,137 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::err::Result;
+use serde::Serialize;
+
+use openssl::x509::{X509Ref, X509};
+
+use crate::backend::KeyBackend;
+use crate::commands::read_file;
+use crate::conf::{CertAuthConf, Conf};
+use crate::i18n::pad;
+use crate::inspect::CertInfo;
+use crate::tr;
+
+/// A file written while issuing a certificate.
+#[derive(Debug, Clone, Serialize)]
+pub struct IssuedFile {
+    /// What the file holds, e.g. `key`, `cert` or `fullchain`.
+    pub kind: String,
+    pub path: PathBuf,
+}
+
+/// What a single issuance produced, printed after `ca`, `server` and `renew`.
+#[derive(Debug, Clone, Serialize)]
+pub struct IssuanceSummary {
+    pub subject: String,
+    pub serial: String,
+    pub sans: Vec<String>,
+    pub not_before: String,
+    pub not_after: String,
+    pub sha256_fingerprint: String,
+    pub files: Vec<IssuedFile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chain_hint: Option<String>,
+}
+
+/// The combined JSON receipt of an apex and wildcard pair.
+#[derive(Debug, Clone, Serialize)]
+pub struct PairReceipt {
+    pub apex: String,
+    pub issued_at: String,
+    pub certificates: Vec<IssuanceSummary>,
+}
+
+impl IssuanceSummary {
+    pub fn new(cert: &X509Ref) -> Result<IssuanceSummary> {
+        let info = CertInfo::from_cert(cert)?;
+        Ok(IssuanceSummary {
+            subject: info.subject,
+            serial: info.serial,
+            sans: info.sans,
+            not_before: info.not_before,
+            not_after: info.not_after,
+            sha256_fingerprint: info.sha256_fingerprint,
+            files: Vec::new(),
+            chain_hint: None,
+        })
+    }
+
+    pub fn file(mut self, kind: &str, path: &Path) -> IssuanceSummary {
+        self.files.push(IssuedFile {
+            kind: kind.to_string(),
+            path: path.to_path_buf(),
+        });
+        self
+    }
+
+    pub fn chain_hint(mut self, hint: String) -> IssuanceSummary {
+        self.chain_hint = Some(hint);
+        self
+    }
+
+    /// Summary of a server certificate written to the store for `domain`.
+    pub(crate) fn server(domain: &str, cert: &X509Ref) -> Result<IssuanceSummary> {
+        let fullchain = CertAuthConf::server_fullchain(domain)?;
+        Ok(IssuanceSummary::new(cert)?
+            .file("key", &CertAuthConf::server_key(domain)?)
+            .file("cert", &CertAuthConf::server_cert(domain)?)
+            .file("fullchain", &fullchain)
+            .file("chain", &CertAuthConf::server_chain(domain)?)
+            .chain_hint(tr!(
+                "summary-serve-hint",
+                fullchain = format!("{:?}", fullchain),
+                ca = format!("{:?}", CertAuthConf::ca_cert()?)
+            )))
+    }
+
+    /// Summaries of the root and intermediate certificates in the store.
+    pub fn ca() -> Result<Vec<IssuanceSummary>> {
+        let root_path = CertAuthConf::ca_cert()?;
+        let intermediate_path = CertAuthConf::intermediate_cert()?;
+        let root = X509::from_pem(&read_file(&root_path)?)?;
+        let intermediate = X509::from_pem(&read_file(&intermediate_path)?)?;
+        let conf = Conf::load()?;
+        // Keys held in a token have no file to name.
+        let with_key = |summary: IssuanceSummary, key: KeyBackend| match key.path() {
+            Some(path) => summary.file("key", path),
+            None => summary,
+        };
+        Ok(vec![
+            with_key(IssuanceSummary::new(&root)?, KeyBackend::ca(&conf)?)
+                .file("cert", &root_path)
+                .chain_hint(tr!("summary-trust-hint", ca = format!("{:?}", root_path))),
+            with_key(
+                IssuanceSummary::new(&intermediate)?,
+                KeyBackend::intermediate(&conf)?,
+            )
+            .file("cert", &intermediate_path),
+        ])
+    }
+}
+
+impl fmt::Display for IssuanceSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = |id: &str| pad(&format!("{}:", tr!(id)), 12);
+        writeln!(f, "{} {}", label("summary-subject"), self.subject)?;
+        if !self.sans.is_empty() {
+            writeln!(f, "{} {}", label("summary-sans"), self.sans.join(", "))?;
+        }
+        let valid = tr!(
+            "summary-valid-range",
+            not_before = self.not_before.as_str(),
+            not_after = self.not_after.as_str()
+        );
+        writeln!(f, "{} {}", label("summary-valid"), valid)?;
+        write!(f, "{} {}", label("summary-sha256"), self.sha256_fingerprint)?;
+        for file in &self.files {
+            let kind = label(&format!("summary-file-{}", file.kind));
+            write!(f, "\n{} {}", kind, file.path.display())?;
+        }
+        if let Some(hint) = &self.chain_hint {
+            write!(f, "\n{}", hint)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+//! Keys created inside a TPM 2.0 on Linux, e.g. for IoT and dev-board
+//! provisioning from the same CA. `tpm2-tools` creates a signing key and
+//! makes it persistent at a handle, then the tpm2 OpenSSL provider opens it
+//! by that handle: a server key signs its own CSR, which is issued like any
+//! other, and an intermediate key signs certificates. The private key never
+//! leaves the TPM.
+
+use std::fs;
+use std::ops::RangeInclusive;
+use std::process::Command;
+
+use openssl::pkey::PKey;
+use openssl::x509::X509ReqBuilder;
+
+use crate::backend::KeyBackend;
+use crate::commands::{remove_intermediate_key, replace_intermediate};
+use crate::conf::{CertAuthConf, Conf, ValidityConf};
+use crate::csr::issue_csr_cert;
+use crate::deterministic::Sources;
+use crate::err::{Result, SimpleCAError};
+use crate::guard::RepoGuard;
+use crate::keys::KeyAlgorithm;
+use crate::lock::StoreLock;
+use crate::name::Name;
+use crate::summary::IssuanceSummary;
+
+/// OpenSSL provider reaching the TPM.
+pub(crate) const TPM2_PROVIDER: &str = "tpm2";
+/// Persistent handles of the owner hierarchy.
+const PERSISTENT_HANDLES: RangeInclusive<u32> = 0x8100_0000..=0x81ff_ffff;
+/// An unrestricted signing key that cannot be exported.
+const KEY_ATTRIBUTES: &str = "fixedtpm|fixedparent|sensitivedataorigin|userwithauth|sign";
+
+/// Parses a persistent handle like `0x81010002`.
+pub fn parse_handle(handle: &str) -> Result<u32> {
+    let digits = handle
+        .strip_prefix("0x")
+        .or_else(|| handle.strip_prefix("0X"))
+        .unwrap_or(handle);
+    match u32::from_str_radix(digits, 16) {
+        Ok(value) if PERSISTENT_HANDLES.contains(&value) => Ok(value),
+        _ => Err(SimpleCAError::TpmFailed {
+            msg: format!(
+                "{} is not a persistent handle, 0x81000000 to 0x81ffffff",
+                handle
+            ),
+        })?,
+    }
+}
+
+/// Runs one of the `tpm2-tools`, which reach the TPM through `TPM2TOOLS_TCTI`
+/// or the resource manager.
+fn tpm2(tool: &str, args: &[&str]) -> Result<()> {
+    let output =
+        Command::new(tool)
+            .args(args)
+            .output()
+            .map_err(|err| SimpleCAError::TpmFailed {
+                msg: format!("{} could not be run: {}", tool, err),
+            })?;
+    if !output.status.success() {
+        Err(SimpleCAError::TpmFailed {
+            msg: format!(
+                "{} failed: {}",
+                tool,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        })?;
+    }
+    Ok(())
+}
+
+/// The `tpm2-tools` name of `algorithm`.
+fn tpm_algorithm(algorithm: KeyAlgorithm) -> &'static str {
+    match algorithm {
+        KeyAlgorithm::Rsa2048 => "rsa2048",
+        KeyAlgorithm::Rsa3072 => "rsa3072",
+        KeyAlgorithm::Rsa4096 => "rsa4096",
+        KeyAlgorithm::EcdsaP256 => "ecc256",
+        KeyAlgorithm::EcdsaP384 => "ecc384",
+    }
+}
+
+/// Creates a signing key at `handle` unless one is there already, which is
+/// kept so a certificate can be renewed for the same key.
+fn ensure_key(handle: u32, algorithm: KeyAlgorithm, verbose: bool) -> Result<()> {
+    let handle = format!("0x{:08x}", handle);
+    if tpm2("tpm2_readpublic", &["-c", &handle]).is_ok() {
+        return Ok(());
+    }
+    let context = std::env::temp_dir().join(format!("simple-ca-tpm-{}.ctx", std::process::id()));
+    let context_file = context.to_string_lossy();
+    let created = tpm2(
+        "tpm2_createprimary",
+        &[
+            "-C",
+            "o",
+            "-G",
+            tpm_algorithm(algorithm),
+            "-a",
+            KEY_ATTRIBUTES,
+            "-c",
+            &context_file,
+        ],
+    )
+    .and_then(|_| {
+        tpm2(
+            "tpm2_evictcontrol",
+            &["-C", "o", "-c", &context_file, &handle],
+        )
+    });
+    let _ = fs::remove_file(&context);
+    created?;
+    if verbose {
+        tracing::info!("Created TPM key at handle {}", handle);
+    }
+    Ok(())
+}
+
+/// Creates a server key for `domain` in the TPM at `handle`, or reuses the
+/// key already there, and signs a server certificate for it through a CSR
+/// the key signs itself. The CSR is kept, so the certificate renews like
+/// that of any hardware-bound key.
+#[allow(clippy::too_many_arguments)]
+pub fn issue_tpm_cert(
+    domain: &str,
+    alt_names: &[&str],
+    handle: u32,
+    validity: &ValidityConf,
+    issuer: Option<&str>,
+    guard: &RepoGuard,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<IssuanceSummary> {
+    let _lock = StoreLock::acquire()?;
+    let conf = Conf::load()?;
+    ensure_key(handle, conf.keys().server_algorithm(), verbose)?;
+    let key = KeyBackend::Tpm { handle }.load()?;
+
+    let mut builder = X509ReqBuilder::new()?;
+    let name = Name::new(domain).to_x509_name()?;
+    builder.set_subject_name(&name)?;
+    builder.set_pubkey(&key)?;
+    builder.sign(&key, conf.keys().digest().message_digest())?;
+    let csr = builder.build().to_pem()?;
+    issue_csr_cert(
+        &csr, alt_names, validity, issuer, None, guard, sources, verbose,
+    )
+}
+
+/// Moves the default intermediate into the TPM at `handle`: the root issues a
+/// new intermediate for the key there, created unless it exists, the key file
+/// is deleted and the config points at the handle.
+pub fn intermediate_to_tpm(
+    handle: u32,
+    validity: &ValidityConf,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<IssuanceSummary> {
+    let _lock = StoreLock::acquire()?;
+    let mut conf = Conf::load()?;
+    let validity = validity.or(&conf.validity());
+    ensure_key(handle, conf.keys().ca_algorithm(), verbose)?;
+    let key = KeyBackend::Tpm { handle }.load()?;
+    let public = PKey::public_key_from_der(&key.public_key_to_der()?)?;
+    let intermediate = replace_intermediate(&conf, public, &validity, sources, verbose)?;
+    remove_intermediate_key(verbose)?;
+
+    conf.clear_intermediate_key();
+    let mut tpm = conf.tpm();
+    tpm.intermediate_handle = Some(format!("0x{:08x}", handle));
+    conf.set_tpm(tpm);
+    conf.save(&Conf::path()?)?;
+
+    let cert_path = CertAuthConf::intermediate_cert()?;
+    IssuanceSummary::new(&intermediate).map(|summary| summary.file("cert", &cert_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_handle() {
+        assert_eq!(parse_handle("0x81010002").unwrap(), 0x8101_0002);
+        assert_eq!(parse_handle("81000001").unwrap(), 0x8100_0001);
+        assert!(parse_handle("0x80000000").is_err());
+        assert!(parse_handle("persistent").is_err());
+    }
+}
@@ -0,0 +1,346 @@
+//! Installing the root into the trust stores of the OS, so browsers and
+//! clients accept the issued certificates without per-tool configuration.
+//! Stores are driven through the vendor tools, `certutil` on Windows,
+//! `security` on macOS and NSS's `certutil` for the databases of Firefox and
+//! Chromium, which ignore the system store on Linux.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use serde::{Serialize, Serializer};
+
+use openssl::hash::MessageDigest;
+use openssl::x509::X509;
+
+use crate::certs::cert_fingerprint;
+use crate::commands::read_root_cert;
+use crate::conf::{home_dir, CertAuthConf};
+use crate::err::{Result, SimpleCAError};
+
+/// A trust store the root can be added to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrustStore {
+    /// The ROOT store of the current user on Windows. Windows asks for
+    /// confirmation before a root is added to it.
+    WindowsUser,
+    /// The ROOT store of the machine on Windows, which needs an elevated prompt.
+    WindowsMachine,
+    /// The login keychain on macOS, trusted for the current user.
+    MacLogin,
+    /// The System keychain on macOS, trusted for every user through `sudo`.
+    MacSystem,
+    /// An NSS database directory holding `cert9.db`, of a Firefox profile or
+    /// the one Chromium shares on Linux.
+    Nss(PathBuf),
+}
+
+const MAC_SYSTEM_KEYCHAIN: &str = "/Library/Keychains/System.keychain";
+const NSS_DB: &str = "cert9.db";
+/// Directories below the home directory holding one Firefox profile each.
+const FIREFOX_PROFILE_DIRS: &[&str] = &[
+    ".mozilla/firefox",
+    "snap/firefox/common/.mozilla/firefox",
+    ".var/app/org.mozilla.firefox/.mozilla/firefox",
+    "Library/Application Support/Firefox/Profiles",
+];
+/// The database Chromium and Chrome use on Linux.
+const SHARED_NSS_DIR: &str = ".pki/nssdb";
+
+impl fmt::Display for TrustStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrustStore::WindowsUser => write!(f, "Windows CurrentUser ROOT store"),
+            TrustStore::WindowsMachine => write!(f, "Windows LocalMachine ROOT store"),
+            TrustStore::MacLogin => write!(f, "macOS login keychain"),
+            TrustStore::MacSystem => write!(f, "macOS System keychain"),
+            TrustStore::Nss(dir) => write!(f, "NSS database {}", dir.display()),
+        }
+    }
+}
+
+impl Serialize for TrustStore {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Outcome of `trust_root` or `untrust_root` for one store.
+#[derive(Debug, Clone, Serialize)]
+pub struct TrustChange {
+    pub store: TrustStore,
+    /// Whether the store was changed, false when it already was as asked.
+    pub changed: bool,
+}
+
+/// SHA-1 thumbprint, the certificate id `certutil` and `security` take.
+fn thumbprint(cert: &X509) -> Result<String> {
+    let digest = cert.digest(MessageDigest::sha1())?;
+    Ok(digest.iter().map(|b| format!("{:02X}", b)).collect())
+}
+
+/// Nickname of the root in NSS databases, unique per root.
+fn nss_nickname(cert: &X509) -> Result<String> {
+    Ok(format!("simple-ca {}", &cert_fingerprint(cert)?[..16]))
+}
+
+/// The NSS databases below `home`: the shared one and one per Firefox profile.
+pub fn nss_databases(home: &Path) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    for profiles in FIREFOX_PROFILE_DIRS {
+        if let Ok(entries) = fs::read_dir(home.join(profiles)) {
+            dirs.extend(entries.flatten().map(|entry| entry.path()));
+        }
+    }
+    dirs.sort();
+    dirs.insert(0, home.join(SHARED_NSS_DIR));
+    dirs.retain(|dir| dir.join(NSS_DB).is_file());
+    dirs
+}
+
+impl TrustStore {
+    fn run(&self, program: &str, args: &[&str]) -> Result<Output> {
+        Command::new(program)
+            .args(args)
+            .output()
+            .map_err(|err| SimpleCAError::TrustStoreFailed {
+                store: self.to_string(),
+                msg: format!("{} could not be run: {}", program, err),
+            })
+    }
+
+    fn check(&self, output: Output) -> Result<()> {
+        if !output.status.success() {
+            Err(SimpleCAError::TrustStoreFailed {
+                store: self.to_string(),
+                msg: String::from_utf8_lossy(&output.stdout).trim().to_string(),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Runs `certutil` on the user's or the machine's stores.
+    fn certutil(&self, args: &[&str]) -> Result<Output> {
+        match self {
+            TrustStore::WindowsUser => self.run("certutil", &[&["-user"], args].concat()),
+            _ => self.run("certutil", args),
+        }
+    }
+
+    /// Runs `security`, through `sudo` for the System keychain.
+    fn security(&self, args: &[&str]) -> Result<Output> {
+        match self {
+            TrustStore::MacSystem => self.run("sudo", &[&["security"], args].concat()),
+            _ => self.run("security", args),
+        }
+    }
+
+    /// Runs NSS's `certutil` on the database.
+    fn nss_certutil(&self, dir: &Path, args: &[&str]) -> Result<Output> {
+        let db = format!("sql:{}", dir.display());
+        self.run("certutil", &[&["-d", &db], args].concat())
+    }
+
+    fn keychain(&self) -> Result<String> {
+        if *self == TrustStore::MacSystem {
+            return Ok(MAC_SYSTEM_KEYCHAIN.to_string());
+        }
+        let output = self.security(&["login-keychain"])?;
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .trim_matches('"')
+            .to_string())
+    }
+
+    /// Whether the store holds `root`, with or without trust settings.
+    fn contains(&self, root: &X509) -> Result<bool> {
+        let id = thumbprint(root)?;
+        match self {
+            TrustStore::WindowsUser | TrustStore::WindowsMachine => {
+                Ok(self.certutil(&["-store", "Root", &id])?.status.success())
+            }
+            TrustStore::MacLogin | TrustStore::MacSystem => {
+                let output = self.security(&["find-certificate", "-a", "-Z", &self.keychain()?])?;
+                let hashes = String::from_utf8_lossy(&output.stdout);
+                Ok(hashes.contains(&format!("SHA-1 hash: {}", id)))
+            }
+            TrustStore::Nss(dir) => {
+                let output = self.nss_certutil(dir, &["-L", "-n", &nss_nickname(root)?])?;
+                Ok(output.status.success())
+            }
+        }
+    }
+
+    /// Whether `root` is in the store and, on macOS, has trust settings making
+    /// it an anchor.
+    pub fn is_trusted(&self, root: &X509, root_path: &Path) -> Result<bool> {
+        if !self.contains(root)? {
+            return Ok(false);
+        }
+        match self {
+            TrustStore::MacLogin | TrustStore::MacSystem => {
+                let path = root_path.to_string_lossy();
+                let output = self.run(
+                    "security",
+                    &["verify-cert", "-c", &path, "-p", "basic", "-L"],
+                )?;
+                Ok(output.status.success())
+            }
+            _ => Ok(true),
+        }
+    }
+
+    fn add(&self, root: &X509, root_path: &Path) -> Result<()> {
+        let path = root_path.to_string_lossy();
+        match self {
+            TrustStore::WindowsUser | TrustStore::WindowsMachine => {
+                self.check(self.certutil(&["-addstore", "Root", &path])?)
+            }
+            TrustStore::MacLogin => {
+                let keychain = self.keychain()?;
+                self.check(self.security(&[
+                    "add-trusted-cert",
+                    "-r",
+                    "trustRoot",
+                    "-k",
+                    &keychain,
+                    &path,
+                ])?)
+            }
+            TrustStore::MacSystem => self.check(self.security(&[
+                "add-trusted-cert",
+                "-d",
+                "-r",
+                "trustRoot",
+                "-k",
+                MAC_SYSTEM_KEYCHAIN,
+                &path,
+            ])?),
+            TrustStore::Nss(dir) => {
+                let nickname = nss_nickname(root)?;
+                self.check(
+                    self.nss_certutil(dir, &["-A", "-t", "C,,", "-n", &nickname, "-i", &path])?,
+                )
+            }
+        }
+    }
+
+    fn remove(&self, root: &X509, root_path: &Path) -> Result<()> {
+        let id = thumbprint(root)?;
+        match self {
+            TrustStore::WindowsUser | TrustStore::WindowsMachine => {
+                self.check(self.certutil(&["-delstore", "Root", &id])?)
+            }
+            TrustStore::MacLogin | TrustStore::MacSystem => {
+                let path = root_path.to_string_lossy();
+                // Fails when the certificate has no trust settings, which the
+                // deletion below makes moot anyway.
+                let mut args = vec!["remove-trusted-cert"];
+                if *self == TrustStore::MacSystem {
+                    args.push("-d");
+                }
+                args.push(&path);
+                self.security(&args)?;
+                let keychain = self.keychain()?;
+                self.check(self.security(&["delete-certificate", "-Z", &id, &keychain])?)
+            }
+            TrustStore::Nss(dir) => {
+                let nickname = nss_nickname(root)?;
+                self.check(self.nss_certutil(dir, &["-D", "-n", &nickname])?)
+            }
+        }
+    }
+}
+
+/// The trust stores of this platform, of the machine rather than the current
+/// user with `system`. NSS databases belong to the user and are left out then.
+pub fn trust_stores(system: bool) -> Vec<TrustStore> {
+    let mut stores = trust_stores_of_os(system);
+    if !system && !cfg!(windows) {
+        if let Ok(home) = home_dir() {
+            stores.extend(nss_databases(&home).into_iter().map(TrustStore::Nss));
+        }
+    }
+    stores
+}
+
+fn trust_stores_of_os(system: bool) -> Vec<TrustStore> {
+    if cfg!(windows) {
+        vec![if system {
+            TrustStore::WindowsMachine
+        } else {
+            TrustStore::WindowsUser
+        }]
+    } else if cfg!(target_os = "macos") {
+        vec![if system {
+            TrustStore::MacSystem
+        } else {
+            TrustStore::MacLogin
+        }]
+    } else {
+        Vec::new()
+    }
+}
+
+fn stores_and_root(system: bool) -> Result<(Vec<TrustStore>, X509)> {
+    let stores = trust_stores(system);
+    if stores.is_empty() {
+        Err(SimpleCAError::TrustUnsupported {
+            platform: std::env::consts::OS,
+        })?;
+    }
+    let root = read_root_cert()?;
+    Ok((stores, root))
+}
+
+/// Adds the root to every trust store of the platform that does not trust
+/// it yet.
+pub fn trust_root(system: bool) -> Result<Vec<TrustChange>> {
+    let (stores, root) = stores_and_root(system)?;
+    let root_path = CertAuthConf::ca_cert()?;
+    let mut changes = Vec::new();
+    for store in stores {
+        let changed = !store.is_trusted(&root, &root_path)?;
+        if changed {
+            store.add(&root, &root_path)?;
+        }
+        changes.push(TrustChange { store, changed });
+    }
+    Ok(changes)
+}
+
+/// Removes the root from every trust store of the platform it is in.
+pub fn untrust_root(system: bool) -> Result<Vec<TrustChange>> {
+    let (stores, root) = stores_and_root(system)?;
+    let root_path = CertAuthConf::ca_cert()?;
+    let mut changes = Vec::new();
+    for store in stores {
+        let changed = store.contains(&root)?;
+        if changed {
+            store.remove(&root, &root_path)?;
+        }
+        changes.push(TrustChange { store, changed });
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nss_databases() {
+        let home = std::env::temp_dir().join(format!("simple-ca-nss-{}", std::process::id()));
+        let profile = home.join(".mozilla/firefox/abcd.default-release");
+        fs::create_dir_all(&profile).unwrap();
+        fs::create_dir_all(home.join(".mozilla/firefox/Crash Reports")).unwrap();
+        fs::create_dir_all(home.join(SHARED_NSS_DIR)).unwrap();
+        fs::write(profile.join(NSS_DB), b"").unwrap();
+        fs::write(home.join(SHARED_NSS_DIR).join(NSS_DB), b"").unwrap();
+
+        let dirs = nss_databases(&home);
+        fs::remove_dir_all(&home).unwrap();
+        assert_eq!(dirs, vec![home.join(SHARED_NSS_DIR), profile]);
+    }
+}
@@ -0,0 +1,285 @@
+//! A time-stamping authority for testing signed-artifact pipelines: answers
+//! RFC 3161 requests posted over HTTP with a timestamp token signed by the
+//! `tsa` signer of the store. Requests and responses are handled by the TS
+//! module of OpenSSL, which the openssl crate does not wrap.
+
+use std::ffi::{c_int, c_long, c_uchar, c_void};
+use std::ptr;
+use std::time::UNIX_EPOCH;
+
+use foreign_types::ForeignType;
+use openssl::asn1::{Asn1Integer, Asn1Object};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::stack::Stack;
+use openssl::x509::X509;
+use openssl_sys::{ASN1_INTEGER, ASN1_OBJECT, BIO, EVP_MD, EVP_PKEY, X509 as X509_RAW};
+
+use crate::commands::{ca_chain, read_file};
+use crate::conf::{Conf, ValidityConf};
+use crate::deterministic::Sources;
+use crate::err::Result;
+use crate::guard::RepoGuard;
+use crate::http::{Request, Response};
+use crate::lock::StoreLock;
+use crate::passphrase::read_private_key;
+use crate::serial::SerialSource;
+use crate::signer::{issue_signer_cert, SignerProfile};
+use crate::store::{self, store_path, Role};
+
+/// Policy of the timestamps unless given, that of the example TSA section
+/// of OpenSSL's config, as no real policy stands behind a dev TSA.
+pub const DEFAULT_TSA_POLICY: &str = "1.2.3.4.1";
+
+const CONTENT_TYPE_QUERY: &str = "application/timestamp-query";
+const CONTENT_TYPE_REPLY: &str = "application/timestamp-reply";
+
+#[allow(non_camel_case_types)]
+enum TS_RESP_CTX {}
+#[allow(non_camel_case_types)]
+enum TS_RESP {}
+
+type SerialCb = unsafe extern "C" fn(*mut TS_RESP_CTX, *mut c_void) -> *mut ASN1_INTEGER;
+type TimeCb =
+    unsafe extern "C" fn(*mut TS_RESP_CTX, *mut c_void, *mut c_long, *mut c_long) -> c_int;
+
+extern "C" {
+    fn TS_RESP_CTX_new() -> *mut TS_RESP_CTX;
+    fn TS_RESP_CTX_free(ctx: *mut TS_RESP_CTX);
+    fn TS_RESP_CTX_set_signer_cert(ctx: *mut TS_RESP_CTX, signer: *mut X509_RAW) -> c_int;
+    fn TS_RESP_CTX_set_signer_key(ctx: *mut TS_RESP_CTX, key: *mut EVP_PKEY) -> c_int;
+    fn TS_RESP_CTX_set_signer_digest(ctx: *mut TS_RESP_CTX, md: *const EVP_MD) -> c_int;
+    fn TS_RESP_CTX_set_certs(
+        ctx: *mut TS_RESP_CTX,
+        certs: *mut openssl_sys::stack_st_X509,
+    ) -> c_int;
+    fn TS_RESP_CTX_set_def_policy(ctx: *mut TS_RESP_CTX, policy: *const ASN1_OBJECT) -> c_int;
+    fn TS_RESP_CTX_add_md(ctx: *mut TS_RESP_CTX, md: *const EVP_MD) -> c_int;
+    fn TS_RESP_CTX_set_serial_cb(ctx: *mut TS_RESP_CTX, cb: SerialCb, data: *mut c_void);
+    fn TS_RESP_CTX_set_time_cb(ctx: *mut TS_RESP_CTX, cb: TimeCb, data: *mut c_void);
+    fn TS_RESP_create_response(ctx: *mut TS_RESP_CTX, req_bio: *mut BIO) -> *mut TS_RESP;
+    fn TS_RESP_free(resp: *mut TS_RESP);
+    fn i2d_TS_RESP(resp: *const TS_RESP, out: *mut *mut c_uchar) -> c_int;
+}
+
+/// Hands OpenSSL a copy of the serial allocated for the response.
+unsafe extern "C" fn serial_cb(_ctx: *mut TS_RESP_CTX, data: *mut c_void) -> *mut ASN1_INTEGER {
+    openssl_sys::ASN1_INTEGER_dup(data as *const ASN1_INTEGER)
+}
+
+/// Hands OpenSSL the time of the response, which tests may have injected.
+unsafe extern "C" fn time_cb(
+    _ctx: *mut TS_RESP_CTX,
+    data: *mut c_void,
+    sec: *mut c_long,
+    usec: *mut c_long,
+) -> c_int {
+    let (secs, micros) = *(data as *const (c_long, c_long));
+    *sec = secs;
+    *usec = micros;
+    1
+}
+
+/// The key and certificate of the `tsa` signer in the store, issued from
+/// the intermediate first when the store has none.
+fn load_signer(sources: &Sources) -> Result<(X509, PKey<Private>)> {
+    let name = SignerProfile::Tsa.name();
+    let _lock = StoreLock::acquire()?;
+    if !store::is_recorded(Role::SignerCert, Some(name))? {
+        issue_signer_cert(
+            SignerProfile::Tsa,
+            &ValidityConf::default(),
+            None,
+            &RepoGuard::default(),
+            sources,
+            false,
+        )?;
+    }
+    let cert = X509::from_pem(&read_file(&store_path(Role::SignerCert, Some(name))?)?)?;
+    let key = read_private_key(&store_path(Role::SignerKey, Some(name))?)?;
+    Ok((cert, key))
+}
+
+fn check(result: c_int) -> Result<()> {
+    if result <= 0 {
+        Err(ErrorStack::get())?;
+    }
+    Ok(())
+}
+
+/// Answers RFC 3161 requests with tokens signed by a TSA certificate.
+pub struct TsaServer {
+    cert: X509,
+    key: PKey<Private>,
+    /// The CAs above the TSA certificate, sent when a request asks for it.
+    chain: Vec<X509>,
+    policy: Asn1Object,
+    digest: MessageDigest,
+    serials: Box<dyn SerialSource + Send + Sync>,
+    sources: Sources,
+}
+
+impl TsaServer {
+    /// A TSA signing with the `tsa` signer of the store, issued from the
+    /// intermediate first when the store has none, under `policy`.
+    pub fn new(policy: &str, sources: Sources) -> Result<TsaServer> {
+        let (cert, key) = load_signer(&sources)?;
+        let chain = ca_chain(&cert)?.split_off(1);
+        let conf = Conf::load()?;
+        Ok(TsaServer {
+            cert,
+            key,
+            chain,
+            policy: Asn1Object::from_str(policy)?,
+            digest: conf.keys().digest().message_digest(),
+            serials: sources.serials(conf.serials().strategy()),
+            sources,
+        })
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        if request.method != "POST" {
+            return Response::text(405, "text/plain", b"Post a timestamp query\n");
+        }
+        if let Some(content_type) = request.header("Content-Type") {
+            if content_type != CONTENT_TYPE_QUERY {
+                return Response::text(415, "text/plain", b"Expected a timestamp query\n");
+            }
+        }
+        match self.respond(&request.body) {
+            Ok(reply) => Response::text(200, CONTENT_TYPE_REPLY, &reply),
+            Err(err) => Response::text(500, "text/plain", err.to_string().as_bytes()),
+        }
+    }
+
+    /// The DER encoded TimeStampResp to the DER encoded `query`. Malformed
+    /// or unsupported queries get a response rejecting them.
+    pub fn respond(&self, query: &[u8]) -> Result<Vec<u8>> {
+        let serial: Asn1Integer = self.serials.next_serial()?.to_asn1_integer()?;
+        let time = self
+            .sources
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let time: (c_long, c_long) = (time.as_secs() as c_long, time.subsec_micros() as c_long);
+        let mut certs = Stack::new()?;
+        for cert in &self.chain {
+            certs.push(cert.clone())?;
+        }
+        let md = |md: MessageDigest| md.as_ptr();
+
+        // SAFETY: the context takes its own references to the certificates,
+        // key and policy, the callbacks only read `serial` and `time`, which
+        // outlive the context, and everything allocated here is freed before
+        // returning.
+        unsafe {
+            let ctx = TS_RESP_CTX_new();
+            if ctx.is_null() {
+                Err(ErrorStack::get())?;
+            }
+            let mut bio = ptr::null_mut();
+            let mut resp = ptr::null_mut();
+            let result = (|| {
+                check(TS_RESP_CTX_set_signer_cert(ctx, self.cert.as_ptr()))?;
+                check(TS_RESP_CTX_set_signer_key(ctx, self.key.as_ptr()))?;
+                check(TS_RESP_CTX_set_signer_digest(ctx, md(self.digest)))?;
+                check(TS_RESP_CTX_set_certs(ctx, certs.as_ptr()))?;
+                check(TS_RESP_CTX_set_def_policy(ctx, self.policy.as_ptr()))?;
+                for digest in [
+                    MessageDigest::sha1(),
+                    MessageDigest::sha256(),
+                    MessageDigest::sha384(),
+                    MessageDigest::sha512(),
+                ] {
+                    check(TS_RESP_CTX_add_md(ctx, md(digest)))?;
+                }
+                TS_RESP_CTX_set_serial_cb(ctx, serial_cb, serial.as_ptr() as *mut c_void);
+                TS_RESP_CTX_set_time_cb(ctx, time_cb, &time as *const _ as *mut c_void);
+
+                bio = openssl_sys::BIO_new_mem_buf(
+                    query.as_ptr() as *const c_void,
+                    query.len() as c_int,
+                );
+                if bio.is_null() {
+                    Err(ErrorStack::get())?;
+                }
+                resp = TS_RESP_create_response(ctx, bio);
+                if resp.is_null() {
+                    Err(ErrorStack::get())?;
+                }
+                let len = i2d_TS_RESP(resp, ptr::null_mut());
+                check(len)?;
+                let mut der = vec![0; len as usize];
+                let mut out = der.as_mut_ptr();
+                check(i2d_TS_RESP(resp, &mut out))?;
+                Ok(der)
+            })();
+            if !resp.is_null() {
+                TS_RESP_free(resp);
+            }
+            if !bio.is_null() {
+                openssl_sys::BIO_free_all(bio);
+            }
+            TS_RESP_CTX_free(ctx);
+            result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cert_params::CertParams;
+    use crate::certs::fixtures::test_root_ca;
+    use crate::certs::{create_signer_cert, ExtKeyUsage};
+    use crate::der;
+    use crate::keys::KeyAlgorithm;
+    use crate::serial::RandomSerial;
+    use crate::Name;
+    use openssl::hash::hash;
+
+    #[test]
+    fn test_respond() {
+        let (ca_name, ca_key, ca) = test_root_ca();
+        let key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let params = CertParams::builder(Name::new("TSA").to_x509_name().unwrap(), key.clone())
+            .issuer(ca_name, ca_key)
+            .valid_days(7)
+            .ext_key_usage([ExtKeyUsage::TimeStamping])
+            .build()
+            .unwrap();
+        let cert = create_signer_cert(&params, &ca, true).unwrap();
+        let server = TsaServer {
+            cert,
+            key,
+            chain: vec![ca],
+            policy: Asn1Object::from_str(DEFAULT_TSA_POLICY).unwrap(),
+            digest: MessageDigest::sha256(),
+            serials: Box::new(RandomSerial),
+            sources: Sources::default(),
+        };
+
+        let digest = hash(MessageDigest::sha256(), b"artifact").unwrap();
+        let query = der::sequence(&[
+            der::small_integer(1),
+            der::sequence(&[
+                der::sequence(&[der::oid(der::OID_SHA256), der::null()]),
+                der::octet_string(&digest),
+            ]),
+            der::boolean(true),
+        ]);
+        let reply = server.respond(&query).unwrap();
+        let (_, content, _) = der::read_tlv(&reply).unwrap();
+        let fields = der::children(content).unwrap();
+        // PKIStatusInfo granted, followed by the token.
+        let (_, status, _) = der::read_tlv(fields[0]).unwrap();
+        assert_eq!(der::children(status).unwrap()[0], der::small_integer(0));
+        assert_eq!(fields.len(), 2);
+
+        let reply = server.respond(b"not a query").unwrap();
+        let (_, content, _) = der::read_tlv(&reply).unwrap();
+        let (_, status, _) = der::read_tlv(der::children(content).unwrap()[0]).unwrap();
+        assert_eq!(der::children(status).unwrap()[0], der::small_integer(2));
+    }
+}
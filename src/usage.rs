@@ -0,0 +1,108 @@
+//! When the certificates in the store were last used, from the access times
+//! of their files and from uses recorded explicitly with `touch`.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::err::Result;
+
+use crate::atomic::write_file;
+use crate::conf::{file_in_conf, CertAuthConf};
+use crate::index::{issued_certs, stored_path, IssuedCert};
+use crate::lock::StoreLock;
+use crate::store::StoreManifest;
+
+pub(crate) const USAGE_FILE: &str = "usage.json";
+
+/// Last recorded use of each certificate file in Unix seconds, keyed like
+/// `IssuedCert::cert`.
+type Usage = BTreeMap<String, u64>;
+
+fn load_usage() -> Result<Usage> {
+    let path = file_in_conf(USAGE_FILE)?;
+    if !path.exists() {
+        return Ok(Usage::new());
+    }
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// Records that the server certificate of `domain` was just used, for stores
+/// on file systems mounted without access times.
+pub fn mark_used(domain: &str) -> Result<()> {
+    let _lock = StoreLock::acquire()?;
+    StoreManifest::load()?.check_domain(domain)?;
+    let mut usage = load_usage()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    usage.insert(stored_path(&CertAuthConf::server_cert(domain)?)?, now);
+    write_file(
+        file_in_conf(USAGE_FILE)?,
+        serde_json::to_vec_pretty(&usage)?,
+    )?;
+    Ok(())
+}
+
+/// The key, certificate and, for server certificates in the store, the chain
+/// files a server would read.
+fn used_files(cert: &IssuedCert) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if let Some(key) = &cert.key {
+        files.push(file_in_conf(key)?);
+    }
+    if let Some(path) = cert.cert_path()? {
+        if path == CertAuthConf::server_cert(&cert.common_name)? {
+            files.push(CertAuthConf::server_fullchain(&cert.common_name)?);
+            files.push(CertAuthConf::server_chain(&cert.common_name)?);
+        }
+        files.push(path);
+    }
+    Ok(files)
+}
+
+fn accessed(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.accessed()).ok()
+}
+
+/// The last use of `cert`, or `None` when nothing records one. File access
+/// times are ignored with `touched_only`.
+fn last_used(cert: &IssuedCert, usage: &Usage, touched_only: bool) -> Result<Option<SystemTime>> {
+    let touched = cert
+        .cert
+        .as_ref()
+        .and_then(|cert| usage.get(cert))
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(*secs));
+    if touched_only {
+        return Ok(touched);
+    }
+    let files = used_files(cert)?;
+    Ok(files
+        .iter()
+        .filter_map(|file| accessed(file))
+        .chain(touched)
+        .max())
+}
+
+/// Certificates still in the store whose last use is older than `idle`. The
+/// CA certificates are never reported.
+pub fn unused_certs(idle: Duration, touched_only: bool) -> Result<Vec<IssuedCert>> {
+    let usage = load_usage()?;
+    let cutoff = SystemTime::now().checked_sub(idle).unwrap_or(UNIX_EPOCH);
+    let mut seen = HashSet::from([CertAuthConf::ca_cert()?, CertAuthConf::intermediate_cert()?]);
+    let mut unused = Vec::new();
+    // Re-issuing overwrites the files, so only the latest entry per file counts.
+    for cert in issued_certs()?.into_iter().rev() {
+        let path = match cert.cert_path()? {
+            Some(path) if path.exists() => path,
+            _ => continue,
+        };
+        if !seen.insert(path) {
+            continue;
+        }
+        if last_used(&cert, &usage, touched_only)?.is_none_or(|used| used < cutoff) {
+            unused.push(cert);
+        }
+    }
+    unused.reverse();
+    Ok(unused)
+}
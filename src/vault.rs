@@ -0,0 +1,124 @@
+//! A shared dev CA kept in HashiCorp Vault, so ephemeral CI runners hydrate
+//! it without copying PEM files around. `vault_push` stores a backup of the
+//! store in a KV secret, or hands the intermediate to a PKI mount so Vault
+//! issues from the same chain; `vault_pull` restores the KV secret. Both run
+//! the `vault` CLI, which takes the address and token from `VAULT_ADDR` and
+//! `VAULT_TOKEN` or its own login.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use serde_json::{json, Value};
+
+use crate::backend::KeyBackend;
+use crate::backup::{backup_content, restore_content};
+use crate::commands::{read_file, read_root_cert};
+use crate::conf::{CertAuthConf, Conf};
+use crate::err::{Result, SimpleCAError};
+use crate::lock::StoreLock;
+
+/// Field of the KV secret holding the backup.
+const BACKUP_FIELD: &str = "backup";
+/// Field of the KV secret holding the root certificate, for runners that
+/// only need to trust the CA.
+const CA_CERT_FIELD: &str = "ca_cert";
+
+/// The kind of secrets engine mounted at a Vault path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VaultMount {
+    /// A KV secret, version 1 or 2, e.g. `secret/dev-ca`.
+    Kv,
+    /// A PKI secrets engine, e.g. `pki_dev`.
+    Pki,
+}
+
+fn vault_error(path: &str, msg: impl ToString) -> SimpleCAError {
+    SimpleCAError::VaultFailed {
+        path: path.to_string(),
+        msg: msg.to_string(),
+    }
+}
+
+/// Runs `vault` with `input` on its stdin, which keeps keys off its command
+/// line, and returns its stdout.
+fn vault(path: &str, args: &[&str], input: &[u8]) -> Result<Vec<u8>> {
+    let mut child = Command::new("vault")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| vault_error(path, format!("vault could not be run: {}", err)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        Err(vault_error(
+            path,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        ))?;
+    }
+    Ok(output.stdout)
+}
+
+/// Pushes the CA to `path`. A KV secret gets a backup of the whole store,
+/// encrypted with the passphrase when `encrypt` is set, and the root
+/// certificate; a PKI mount gets the intermediate key and its chain as its
+/// CA. Returns the number of files pushed.
+pub fn vault_push(path: &str, mount: VaultMount, encrypt: bool) -> Result<usize> {
+    let _lock = StoreLock::acquire()?;
+    let root = read_root_cert()?;
+    match mount {
+        VaultMount::Kv => {
+            let (backup, count) = backup_content(encrypt)?;
+            let secret = json!({
+                BACKUP_FIELD: String::from_utf8_lossy(&backup),
+                CA_CERT_FIELD: String::from_utf8_lossy(&root.to_pem()?),
+            });
+            vault(
+                path,
+                &["kv", "put", path, "-"],
+                secret.to_string().as_bytes(),
+            )?;
+            Ok(count)
+        }
+        VaultMount::Pki => {
+            let conf = Conf::load()?;
+            // Keys in a token or KMS cannot be handed over.
+            let key = match KeyBackend::intermediate(&conf)? {
+                backend @ KeyBackend::File(_) => backend.load()?,
+                _ => Err(vault_error(
+                    path,
+                    "the intermediate key is not in the store",
+                ))?,
+            };
+            let mut bundle = key.private_key_to_pem_pkcs8()?;
+            bundle.extend(read_file(&CertAuthConf::intermediate_cert()?)?);
+            bundle.extend(root.to_pem()?);
+            let config = format!("{}/config/ca", path.trim_end_matches('/'));
+            vault(path, &["write", &config, "pem_bundle=-"], &bundle)?;
+            Ok(3)
+        }
+    }
+}
+
+/// Restores the backup in the KV secret at `path` into the store. An
+/// existing CA is only replaced with `force`. Returns the restored files.
+pub fn vault_pull(path: &str, force: bool) -> Result<Vec<PathBuf>> {
+    let _lock = StoreLock::acquire()?;
+    let output = vault(path, &["kv", "get", "-format=json", path], &[])?;
+    let secret: Value = serde_json::from_slice(&output).map_err(|err| vault_error(path, err))?;
+    // KV version 2 nests the fields one level deeper than version 1.
+    let data = &secret["data"];
+    let backup = data["data"][BACKUP_FIELD]
+        .as_str()
+        .or_else(|| data[BACKUP_FIELD].as_str())
+        .ok_or_else(|| vault_error(path, "the secret holds no simple-ca backup"))?;
+    restore_content(
+        Path::new(&format!("vault:{}", path)),
+        backup.as_bytes(),
+        force,
+    )
+}
@@ -0,0 +1,179 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use openssl::stack::Stack;
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::verify::X509VerifyParam;
+use openssl::x509::{X509PurposeId, X509StoreContext, X509};
+
+use crate::commands::{read_file, read_root_cert};
+use crate::conf::{file_in_conf, CertAuthConf};
+use crate::err::{Result, SimpleCAError};
+use crate::inspect::{format_name, load_certs};
+use crate::repair::archived_issuer;
+use crate::store::{Role, StoreManifest};
+
+/// What a certificate is checked to be usable for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Purpose {
+    Server,
+    Client,
+    Any,
+}
+
+impl Purpose {
+    pub fn from_name(name: &str) -> Option<Purpose> {
+        match name {
+            "server" => Some(Purpose::Server),
+            "client" => Some(Purpose::Client),
+            "any" => Some(Purpose::Any),
+            _ => None,
+        }
+    }
+
+    fn id(&self) -> X509PurposeId {
+        match self {
+            Purpose::Server => X509PurposeId::SSL_SERVER,
+            Purpose::Client => X509PurposeId::SSL_CLIENT,
+            Purpose::Any => X509PurposeId::ANY,
+        }
+    }
+}
+
+/// Outcome of validating a certificate against the stored CA chain.
+#[derive(Debug, Clone, Serialize)]
+pub struct Verification {
+    pub subject: String,
+    pub valid: bool,
+    /// OpenSSL's reason when validation failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Position in the chain of the certificate that failed, 0 being the leaf.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depth: Option<u32>,
+    /// Subject of the certificate that failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed_subject: Option<String>,
+    /// Subjects of the verified chain, from the leaf up to the root.
+    pub chain: Vec<String>,
+    /// Archived intermediate that issued a certificate failing validation,
+    /// which `repair` can bundle it with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archived_issuer: Option<String>,
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.valid {
+            write!(f, "OK: {}", self.chain.join(" <- "))
+        } else {
+            write!(
+                f,
+                "FAILED: {} at depth {} ({})",
+                self.reason.as_deref().unwrap_or_default(),
+                self.depth.unwrap_or_default(),
+                self.failed_subject.as_deref().unwrap_or_default()
+            )
+        }
+    }
+}
+
+/// Validates the first certificate at `target` (a stored domain or a file)
+/// against the stored root, using the stored intermediates and any further
+/// certificates in the file as the untrusted chain.
+pub fn verify_cert(target: &str, purpose: Purpose, host: Option<&str>) -> Result<Verification> {
+    let mut certs = load_certs(target)?.into_iter();
+    let leaf = match certs.next() {
+        Some(leaf) => leaf,
+        None => Err(SimpleCAError::GenericError {
+            msg: "No certificate found",
+        })?,
+    };
+    let mut untrusted = Stack::new()?;
+    for cert in certs {
+        untrusted.push(cert)?;
+    }
+    untrusted.push(X509::from_pem(&read_file(
+        &CertAuthConf::intermediate_cert()?,
+    )?)?)?;
+    for entry in StoreManifest::load()?.entries(Role::NamedIntermediateCert) {
+        untrusted.push(X509::from_pem(&read_file(&file_in_conf(&entry.path)?)?)?)?;
+    }
+
+    let mut store = X509StoreBuilder::new()?;
+    store.add_cert(read_root_cert()?)?;
+    store.set_purpose(purpose.id())?;
+    if let Some(host) = host {
+        let mut param = X509VerifyParam::new()?;
+        param.set_host(host)?;
+        store.set_param(&param)?;
+    }
+    let store = store.build();
+
+    let mut context = X509StoreContext::new()?;
+    let mut verification = context.init(&store, &leaf, &untrusted, |context| {
+        let valid = context.verify_cert()?;
+        let chain = match context.chain() {
+            Some(chain) if valid => chain
+                .iter()
+                .map(|cert| format_name(cert.subject_name()).unwrap_or_default())
+                .collect(),
+            _ => Vec::new(),
+        };
+        let failed_subject = context
+            .current_cert()
+            .map(|cert| format_name(cert.subject_name()).unwrap_or_default());
+        Ok(Verification {
+            subject: format_name(leaf.subject_name()).unwrap_or_default(),
+            valid,
+            reason: (!valid).then(|| context.error().error_string().to_string()),
+            depth: (!valid).then(|| context.error_depth()),
+            failed_subject: if valid { None } else { failed_subject },
+            chain,
+            archived_issuer: None,
+        })
+    })?;
+    if !verification.valid {
+        verification.archived_issuer =
+            archived_issuer(&leaf)?.map(|(_, path)| path.display().to_string());
+    }
+    Ok(verification)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certs::fixtures::test_root_ca;
+    use crate::commands::fixtures::issue_test_server;
+    use crate::commands::ServerExtras;
+    use crate::conf::with_test_store;
+
+    #[test]
+    fn test_verify_cert() {
+        with_test_store("verify", |home| {
+            issue_test_server("app.test", &[], &ServerExtras::default());
+
+            let verification = verify_cert("app.test", Purpose::Server, Some("app.test")).unwrap();
+            assert!(verification.valid, "{}", verification);
+            assert_eq!(verification.chain.len(), 3);
+            assert!(
+                !verify_cert("app.test", Purpose::Server, Some("other.test"))
+                    .unwrap()
+                    .valid
+            );
+            assert!(
+                !verify_cert("app.test", Purpose::Client, None)
+                    .unwrap()
+                    .valid
+            );
+
+            let (_, _, foreign) = test_root_ca();
+            let path = home.join("foreign.pem");
+            std::fs::write(&path, foreign.to_pem().unwrap()).unwrap();
+            let verification = verify_cert(path.to_str().unwrap(), Purpose::Any, None).unwrap();
+            assert!(!verification.valid);
+            assert_eq!(verification.depth, Some(0));
+        });
+    }
+}
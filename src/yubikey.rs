@@ -0,0 +1,177 @@
+//! The intermediate key on a YubiKey: generated in a PIV slot, or imported
+//! into one from the store, with `ykman`. The key is then used through the
+//! YubiKey's PKCS#11 module like any other token key, so every certificate
+//! the intermediate issues is signed on the device after asking for the PIN.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use openssl::pkey::PKey;
+use openssl::x509::X509;
+
+use crate::commands::{read_file, remove_intermediate_key, replace_intermediate};
+use crate::conf::{CertAuthConf, Conf, ValidityConf};
+use crate::deterministic::Sources;
+use crate::err::{Result, SimpleCAError};
+use crate::keys::KeyAlgorithm;
+use crate::lock::StoreLock;
+use crate::summary::IssuanceSummary;
+
+/// The YubiKey's PKCS#11 module, found on the library path of the platform.
+#[cfg(windows)]
+pub const DEFAULT_YKCS11_MODULE: &str = "libykcs11.dll";
+#[cfg(target_os = "macos")]
+pub const DEFAULT_YKCS11_MODULE: &str = "libykcs11.dylib";
+#[cfg(not(any(windows, target_os = "macos")))]
+pub const DEFAULT_YKCS11_MODULE: &str = "libykcs11.so";
+
+/// A PIV slot holding a key and its certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PivSlot {
+    Authentication,
+    Signature,
+    KeyManagement,
+    CardAuthentication,
+}
+
+impl PivSlot {
+    pub const ALL: [PivSlot; 4] = [
+        PivSlot::Authentication,
+        PivSlot::Signature,
+        PivSlot::KeyManagement,
+        PivSlot::CardAuthentication,
+    ];
+
+    /// The slot number `ykman` takes, e.g. `9c`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PivSlot::Authentication => "9a",
+            PivSlot::Signature => "9c",
+            PivSlot::KeyManagement => "9d",
+            PivSlot::CardAuthentication => "9e",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PivSlot> {
+        PivSlot::ALL
+            .into_iter()
+            .find(|slot| slot.name() == name.to_lowercase())
+    }
+
+    /// The URI of the slot's private key in the YubiKey's PKCS#11 module,
+    /// which numbers the slots from 1 in the order above.
+    pub fn key_uri(&self) -> String {
+        let id = PivSlot::ALL.iter().position(|slot| slot == self).unwrap() + 1;
+        format!("pkcs11:id=%{:02x};type=private", id)
+    }
+}
+
+/// The `ykman` name of `algorithm`.
+fn piv_algorithm(algorithm: KeyAlgorithm) -> &'static str {
+    match algorithm {
+        KeyAlgorithm::Rsa2048 => "RSA2048",
+        KeyAlgorithm::Rsa3072 => "RSA3072",
+        KeyAlgorithm::Rsa4096 => "RSA4096",
+        KeyAlgorithm::EcdsaP256 => "ECCP256",
+        KeyAlgorithm::EcdsaP384 => "ECCP384",
+    }
+}
+
+/// Runs `ykman` attached to the terminal, which asks for the management key
+/// and PIN itself.
+fn ykman(args: &[&str]) -> Result<()> {
+    let status = Command::new("ykman")
+        .arg("piv")
+        .args(args)
+        .status()
+        .map_err(|err| SimpleCAError::YubiKeyFailed {
+            msg: format!("ykman could not be run: {}", err),
+        })?;
+    if !status.success() {
+        Err(SimpleCAError::YubiKeyFailed {
+            msg: format!("`ykman piv {}` failed with {}", args.join(" "), status),
+        })?;
+    }
+    Ok(())
+}
+
+/// Moves the default intermediate onto the YubiKey in `slot` and points the
+/// config at it. With `import` the key in the store is imported and then
+/// deleted from disk, so its certificates stay valid; otherwise a new key is
+/// generated on the device and the root issues a new intermediate for it.
+/// `module` is the YubiKey's PKCS#11 module, `DEFAULT_YKCS11_MODULE` unless
+/// the config names one.
+pub fn intermediate_to_yubikey(
+    slot: PivSlot,
+    import: bool,
+    module: Option<&Path>,
+    validity: &ValidityConf,
+    sources: &Sources,
+    verbose: bool,
+) -> Result<IssuanceSummary> {
+    let _lock = StoreLock::acquire()?;
+    let mut conf = Conf::load()?;
+    let validity = validity.or(&conf.validity());
+    let key_path = CertAuthConf::intermediate_key()?;
+    let cert_path = CertAuthConf::intermediate_cert()?;
+
+    let intermediate = if import {
+        let key_file = key_path.to_string_lossy();
+        ykman(&["keys", "import", slot.name(), &key_file])?;
+        X509::from_pem(&read_file(&cert_path)?)?
+    } else {
+        let public_path = cert_path.with_file_name(format!("yubikey-{}.pub.pem", slot.name()));
+        let public_file = public_path.to_string_lossy();
+        let algorithm = piv_algorithm(conf.keys().ca_algorithm());
+        ykman(&[
+            "keys",
+            "generate",
+            "--algorithm",
+            algorithm,
+            slot.name(),
+            &public_file,
+        ])?;
+        let public = PKey::public_key_from_pem(&read_file(&public_path)?);
+        fs::remove_file(&public_path)?;
+        replace_intermediate(&conf, public?, &validity, sources, verbose)?
+    };
+    // The PKCS#11 module only shows keys of slots that hold a certificate.
+    ykman(&[
+        "certificates",
+        "import",
+        slot.name(),
+        &cert_path.to_string_lossy(),
+    ])?;
+
+    remove_intermediate_key(verbose)?;
+
+    conf.clear_intermediate_key();
+    let mut pkcs11 = conf.pkcs11();
+    pkcs11.intermediate_key = Some(slot.key_uri());
+    if let Some(module) = module {
+        pkcs11.module = Some(module.to_path_buf());
+    } else if pkcs11.module.is_none() {
+        pkcs11.module = Some(PathBuf::from(DEFAULT_YKCS11_MODULE));
+    }
+    conf.set_pkcs11(pkcs11);
+    conf.save(&Conf::path()?)?;
+
+    IssuanceSummary::new(&intermediate).map(|summary| summary.file("cert", &cert_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_uri() {
+        assert_eq!(PivSlot::from_name("9C"), Some(PivSlot::Signature));
+        assert_eq!(PivSlot::Signature.key_uri(), "pkcs11:id=%02;type=private");
+        assert_eq!(
+            PivSlot::CardAuthentication.key_uri(),
+            "pkcs11:id=%04;type=private"
+        );
+        assert_eq!(PivSlot::from_name("82"), None);
+    }
+}